@@ -32,10 +32,23 @@ impl FuzzData {
                 FuzzInstruction::PlaceOrder { .. }
                     | FuzzInstruction::PlaceOrderPegged { .. }
                     | FuzzInstruction::PlaceTakeOrder { .. }
+                    | FuzzInstruction::Swap { .. }
+                    | FuzzInstruction::SwapExactOut { .. }
                     | FuzzInstruction::CancelAllAndPlaceOrders { .. }
             )
         })
     }
+
+    fn has_affordable_penalty(&self) -> bool {
+        // users would run out of lamports long before the fuzzer runs out of instructions
+        self.market.event_heap_penalty <= INITIAL_BALANCE / 1_000
+    }
+
+    fn has_affordable_posting_bond(&self) -> bool {
+        // open orders accounts would run out of lamports, bonds stay locked until the orders
+        // leave the book and the funds are settled
+        self.market.posting_bond <= INITIAL_BALANCE / 1_000
+    }
 }
 
 #[derive(Debug, Arbitrary, Clone)]
@@ -63,6 +76,16 @@ enum FuzzInstruction {
         data: openbook_v2::instruction::PlaceTakeOrder,
         makers: Option<HashSet<UserId>>,
     },
+    Swap {
+        user_id: UserId,
+        data: openbook_v2::instruction::Swap,
+        makers: Option<HashSet<UserId>>,
+    },
+    SwapExactOut {
+        user_id: UserId,
+        data: openbook_v2::instruction::SwapExactOut,
+        makers: Option<HashSet<UserId>>,
+    },
     EditOrder {
         user_id: UserId,
         data: openbook_v2::instruction::EditOrder,
@@ -98,6 +121,10 @@ enum FuzzInstruction {
         user_ids: HashSet<UserId>,
         data: openbook_v2::instruction::ConsumeGivenEvents,
     },
+    PurgeExpiredOrders {
+        user_id: UserId,
+        data: openbook_v2::instruction::PurgeExpiredOrders,
+    },
     SettleFunds {
         user_id: UserId,
         data: openbook_v2::instruction::SettleFunds,
@@ -154,6 +181,22 @@ impl FuzzRunner for FuzzContext {
                 .place_take_order(user_id, data, makers.as_ref())
                 .map_or_else(error_parser::place_take_order, keep),
 
+            FuzzInstruction::Swap {
+                user_id,
+                data,
+                makers,
+            } => self
+                .swap(user_id, data, makers.as_ref())
+                .map_or_else(error_parser::swap, keep),
+
+            FuzzInstruction::SwapExactOut {
+                user_id,
+                data,
+                makers,
+            } => self
+                .swap_exact_out(user_id, data, makers.as_ref())
+                .map_or_else(error_parser::swap_exact_out, keep),
+
             FuzzInstruction::EditOrder {
                 user_id,
                 data,
@@ -198,6 +241,10 @@ impl FuzzRunner for FuzzContext {
                 .consume_given_events(user_ids, data)
                 .map_or_else(error_parser::consume_given_events, keep),
 
+            FuzzInstruction::PurgeExpiredOrders { user_id, data } => self
+                .purge_expired_orders(user_id, data)
+                .map_or_else(error_parser::purge_expired_orders, keep),
+
             FuzzInstruction::SettleFunds {
                 user_id,
                 data,
@@ -225,7 +272,11 @@ fuzz_target!(|fuzz_data: FuzzData| -> Corpus {
 });
 
 fn run_fuzz(fuzz_data: FuzzData) -> Corpus {
-    if !fuzz_data.is_borsh_serializable() || !fuzz_data.contains_place_order_ixs() {
+    if !fuzz_data.is_borsh_serializable()
+        || !fuzz_data.contains_place_order_ixs()
+        || !fuzz_data.has_affordable_penalty()
+        || !fuzz_data.has_affordable_posting_bond()
+    {
         return Corpus::Reject;
     }
 
@@ -295,10 +346,7 @@ fn run_fuzz(fuzz_data: FuzzData) -> Corpus {
         };
 
         let event_heap_len = |ctx: &FuzzContext| -> usize {
-            let event_heap = ctx
-                .state
-                .get_account::<openbook_v2::state::EventHeap>(&ctx.event_heap)
-                .unwrap();
+            let event_heap = ctx.state.get_event_heap(&ctx.event_heap).unwrap();
             event_heap.len()
         };
 
@@ -377,10 +425,7 @@ fn run_fuzz(fuzz_data: FuzzData) -> Corpus {
 
     {
         let is_empty = |pubkey| -> bool {
-            let book_side = ctx
-                .state
-                .get_account::<openbook_v2::state::BookSide>(pubkey)
-                .unwrap();
+            let book_side = ctx.state.get_book_side(pubkey).unwrap();
             book_side.is_empty()
         };
 
@@ -479,6 +524,8 @@ mod error_parser {
             e if e == OpenBookError::InvalidPostAmount.into() => Corpus::Keep,
             e if e == OpenBookError::InvalidPriceLots.into() => Corpus::Keep,
             e if e == OpenBookError::OpenOrdersFull.into() => Corpus::Keep,
+            e if e == OpenBookError::ExposureLimitExceeded.into() => Corpus::Keep,
+            e if e == OpenBookError::OrderBelowMinimumSize.into() => Corpus::Keep,
             e if e == OpenBookError::WouldSelfTrade.into() => Corpus::Keep,
             e if e == OpenBookError::WouldExecutePartially.into() => Corpus::Keep,
             e if e == TokenError::InsufficientFunds.into() => Corpus::Keep,
@@ -498,6 +545,8 @@ mod error_parser {
             e if e == OpenBookError::InvalidPostAmount.into() => Corpus::Keep,
             e if e == OpenBookError::InvalidPriceLots.into() => Corpus::Keep,
             e if e == OpenBookError::OpenOrdersFull.into() => Corpus::Keep,
+            e if e == OpenBookError::ExposureLimitExceeded.into() => Corpus::Keep,
+            e if e == OpenBookError::OrderBelowMinimumSize.into() => Corpus::Keep,
             e if e == OpenBookError::OraclePegInvalidOracleState.into() => Corpus::Keep,
             e if e == OpenBookError::WouldSelfTrade.into() => Corpus::Keep,
             e if e == OpenBookError::WouldExecutePartially.into() => Corpus::Keep,
@@ -519,6 +568,26 @@ mod error_parser {
         }
     }
 
+    pub fn swap(err: ProgramError) -> Corpus {
+        match err {
+            e if e == OpenBookError::InvalidOraclePrice.into() => Corpus::Keep,
+            e if e == OpenBookError::SwapSlippageExceeded.into() => Corpus::Keep,
+            e if e == TokenError::InsufficientFunds.into() => Corpus::Keep,
+            _ => panic!("{}", err),
+        }
+    }
+
+    pub fn swap_exact_out(err: ProgramError) -> Corpus {
+        match err {
+            e if e == OpenBookError::InvalidInputLotsSize.into() => Corpus::Reject,
+            e if e == OpenBookError::InvalidOraclePrice.into() => Corpus::Keep,
+            e if e == OpenBookError::SwapSlippageExceeded.into() => Corpus::Keep,
+            e if e == OpenBookError::WouldExecutePartially.into() => Corpus::Keep,
+            e if e == TokenError::InsufficientFunds.into() => Corpus::Keep,
+            _ => panic!("{}", err),
+        }
+    }
+
     pub fn edit_order(err: ProgramError) -> Corpus {
         match err {
             e if e == OpenBookError::InvalidInputCancelSize.into() => Corpus::Reject,
@@ -547,6 +616,8 @@ mod error_parser {
             e if e == OpenBookError::InvalidPostAmount.into() => Corpus::Keep,
             e if e == OpenBookError::InvalidPriceLots.into() => Corpus::Keep,
             e if e == OpenBookError::OpenOrdersFull.into() => Corpus::Keep,
+            e if e == OpenBookError::ExposureLimitExceeded.into() => Corpus::Keep,
+            e if e == OpenBookError::OrderBelowMinimumSize.into() => Corpus::Keep,
             e if e == OpenBookError::WouldSelfTrade.into() => Corpus::Keep,
             e if e == OpenBookError::WouldExecutePartially.into() => Corpus::Keep,
             e if e == TokenError::InsufficientFunds.into() => Corpus::Keep,
@@ -581,6 +652,10 @@ mod error_parser {
         }
     }
 
+    pub fn purge_expired_orders(err: ProgramError) -> Corpus {
+        panic!("{}", err);
+    }
+
     pub fn cancel_all_orders(err: ProgramError) -> Corpus {
         panic!("{}", err);
     }
@@ -606,6 +681,7 @@ use anchor_spl::token::spl_token::{
     state::{Account as TokenAccount, AccountState, Mint},
 };
 use bumpalo::Bump;
+use openbook_v2::state::{BookSideValue, EventHeapValue};
 use solana_program::{
     account_info::AccountInfo, bpf_loader, clock::Epoch, instruction::AccountMeta,
     program_pack::Pack, pubkey::Pubkey, rent::Rent, system_program,
@@ -643,6 +719,19 @@ impl AccountsState {
             .and_then(|acc| AccountDeserialize::try_deserialize(&mut &acc.data[..]).ok())
     }
 
+    // Book sides and event heaps have nodes after the fixed part, which get_account can't decode
+    pub fn get_book_side(&self, pubkey: &Pubkey) -> Option<BookSideValue> {
+        self.0
+            .get(pubkey)
+            .and_then(|acc| BookSideValue::from_bytes(&acc.data).ok())
+    }
+
+    pub fn get_event_heap(&self, pubkey: &Pubkey) -> Option<EventHeapValue> {
+        self.0
+            .get(pubkey)
+            .and_then(|acc| EventHeapValue::from_bytes(&acc.data).ok())
+    }
+
     pub fn get_balance(&self, pubkey: &Pubkey) -> u64 {
         self.get_account::<anchor_spl::token::TokenAccount>(pubkey)
             .unwrap()
@@ -689,6 +778,11 @@ impl AccountsState {
         });
     }
 
+    pub fn add_lamports(&mut self, pubkey: Pubkey, lamports: u64) -> &mut Self {
+        self.0.get_mut(&pubkey).unwrap().lamports += lamports;
+        self
+    }
+
     pub fn add_program(&mut self, pubkey: Pubkey) -> &mut Self {
         self.insert(
             pubkey,
@@ -771,6 +865,11 @@ impl AccountsState {
         self
     }
 
+    pub fn add_openbook_account_with_space(&mut self, pubkey: Pubkey, len: usize) -> &mut Self {
+        self.insert(pubkey, zero_account(len));
+        self
+    }
+
     pub fn add_open_orders_indexer<T>(&mut self, pubkey: Pubkey) -> &mut Self {
         let len = openbook_v2::state::OpenOrdersIndexer::space(1);
         self.insert(pubkey, zero_account(len));
@@ -937,9 +1036,18 @@ impl FuzzContext {
             .add_account_with_lamports(self.payer, INITIAL_BALANCE)
             .add_mint(self.base_mint)
             .add_mint(self.quote_mint)
-            .add_openbook_account::<BookSide>(self.asks)
-            .add_openbook_account::<BookSide>(self.bids)
-            .add_openbook_account::<EventHeap>(self.event_heap)
+            .add_openbook_account_with_space(
+                self.asks,
+                BookSide::space_for_capacity(DEFAULT_ORDERTREE_NODES),
+            )
+            .add_openbook_account_with_space(
+                self.bids,
+                BookSide::space_for_capacity(DEFAULT_ORDERTREE_NODES),
+            )
+            .add_openbook_account_with_space(
+                self.event_heap,
+                EventHeap::space_for_capacity(DEFAULT_NUM_EVENTS as usize),
+            )
             .add_openbook_account::<Market>(self.market)
             .add_empty_system_account(self.market_authority)
             .add_empty_system_account(self.event_authority)
@@ -1027,12 +1135,16 @@ impl FuzzContext {
                 payer: self.payer,
                 market: self.market,
                 system_program: system_program::ID,
+                owner_denylist_entry: None,
             };
             let data = openbook_v2::instruction::CreateOpenOrdersAccount {
                 name: "fuzz test".to_string(),
             };
             process_instruction(&mut self.state, &data, &accounts, &[]).unwrap();
 
+            // Posting bonds are paid out of the lamports above the account's rent
+            self.state.add_lamports(open_orders, INITIAL_BALANCE);
+
             UserAccounts {
                 owner,
                 open_orders,
@@ -1101,6 +1213,9 @@ impl FuzzContext {
             open_orders_admin: None,
             consume_events_admin: None,
             close_market_admin: None,
+            allowlist_admin: None,
+            denylist_admin: None,
+            exposure_admin: None,
             event_authority: self.event_authority,
             program: openbook_v2::ID,
         };
@@ -1116,13 +1231,19 @@ impl FuzzContext {
 
         let accounts = openbook_v2::accounts::Deposit {
             owner: user.owner,
-            user_base_account: user.base_vault,
-            user_quote_account: user.quote_vault,
+            user_base_account: Some(user.base_vault),
+            user_quote_account: Some(user.quote_vault),
             open_orders_account: user.open_orders,
             market: self.market,
             market_base_vault: self.market_base_vault,
             market_quote_vault: self.market_quote_vault,
             token_program: spl_token::ID,
+            system_program: None,
+            native_payer: None,
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::ID,
         };
 
         process_instruction(&mut self.state, data, &accounts, &[])
@@ -1137,13 +1258,19 @@ impl FuzzContext {
 
         let accounts = openbook_v2::accounts::Deposit {
             owner: user.owner,
-            user_base_account: user.base_vault,
-            user_quote_account: user.quote_vault,
+            user_base_account: Some(user.base_vault),
+            user_quote_account: Some(user.quote_vault),
             open_orders_account: user.open_orders,
             market: self.market,
             market_base_vault: self.market_base_vault,
             market_quote_vault: self.market_quote_vault,
             token_program: spl_token::ID,
+            system_program: None,
+            native_payer: None,
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::ID,
         };
 
         process_instruction(&mut self.state, data, &accounts, &[])
@@ -1169,7 +1296,7 @@ impl FuzzContext {
         let accounts = openbook_v2::accounts::PlaceOrder {
             open_orders_account: user.open_orders,
             signer: user.owner,
-            user_token_account,
+            user_token_account: Some(user_token_account),
             open_orders_admin: None,
             market: self.market,
             bids: self.bids,
@@ -1179,6 +1306,17 @@ impl FuzzContext {
             oracle_a: self.oracle_a,
             oracle_b: self.oracle_b,
             token_program: spl_token::ID,
+            open_orders_delegate: None,
+            system_program: Some(system_program::ID),
+            market_allowlist_entry: None,
+            owner_denylist_entry: None,
+            signer_denylist_entry: None,
+            penalty_payer: Some(user.owner),
+            native_payer: None,
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::ID,
         };
 
         let remaining = makers.map_or_else(Vec::new, |makers| {
@@ -1221,7 +1359,7 @@ impl FuzzContext {
         let accounts = openbook_v2::accounts::PlaceOrder {
             open_orders_account: user.open_orders,
             signer: user.owner,
-            user_token_account,
+            user_token_account: Some(user_token_account),
             open_orders_admin: None,
             market: self.market,
             bids: self.bids,
@@ -1231,6 +1369,17 @@ impl FuzzContext {
             oracle_a: self.oracle_a,
             oracle_b: self.oracle_b,
             token_program: spl_token::ID,
+            open_orders_delegate: None,
+            system_program: Some(system_program::ID),
+            market_allowlist_entry: None,
+            owner_denylist_entry: None,
+            signer_denylist_entry: None,
+            penalty_payer: Some(user.owner),
+            native_payer: None,
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::ID,
         };
 
         let remaining = makers.map_or_else(Vec::new, |makers| {
@@ -1274,6 +1423,116 @@ impl FuzzContext {
             token_program: spl_token::ID,
             system_program: system_program::ID,
             open_orders_admin: None,
+            market_allowlist_entry: None,
+            signer_denylist_entry: None,
+            base_owner_denylist_entry: None,
+            quote_owner_denylist_entry: None,
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::ID,
+        };
+
+        let remaining = makers.map_or_else(Vec::new, |makers| {
+            makers
+                .iter()
+                .filter(|id| id != &user_id)
+                .filter_map(|id| self.users.get(id))
+                .map(|user| AccountMeta {
+                    pubkey: user.open_orders,
+                    is_signer: false,
+                    is_writable: true,
+                })
+                .collect::<Vec<_>>()
+        });
+
+        process_instruction(&mut self.state, data, &accounts, &remaining)
+    }
+
+    pub fn swap(
+        &mut self,
+        user_id: &UserId,
+        data: &openbook_v2::instruction::Swap,
+        makers: Option<&HashSet<UserId>>,
+    ) -> ProgramResult {
+        let user = self.get_or_create_new_user(user_id);
+
+        let accounts = openbook_v2::accounts::PlaceTakeOrder {
+            signer: user.owner,
+            penalty_payer: user.owner,
+            user_base_account: user.base_vault,
+            user_quote_account: user.quote_vault,
+            market: self.market,
+            market_authority: self.market_authority,
+            bids: self.bids,
+            asks: self.asks,
+            market_base_vault: self.market_base_vault,
+            market_quote_vault: self.market_quote_vault,
+            event_heap: self.event_heap,
+            oracle_a: self.oracle_a,
+            oracle_b: self.oracle_b,
+            token_program: spl_token::ID,
+            system_program: system_program::ID,
+            open_orders_admin: None,
+            market_allowlist_entry: None,
+            signer_denylist_entry: None,
+            base_owner_denylist_entry: None,
+            quote_owner_denylist_entry: None,
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::ID,
+        };
+
+        let remaining = makers.map_or_else(Vec::new, |makers| {
+            makers
+                .iter()
+                .filter(|id| id != &user_id)
+                .filter_map(|id| self.users.get(id))
+                .map(|user| AccountMeta {
+                    pubkey: user.open_orders,
+                    is_signer: false,
+                    is_writable: true,
+                })
+                .collect::<Vec<_>>()
+        });
+
+        process_instruction(&mut self.state, data, &accounts, &remaining)
+    }
+
+    pub fn swap_exact_out(
+        &mut self,
+        user_id: &UserId,
+        data: &openbook_v2::instruction::SwapExactOut,
+        makers: Option<&HashSet<UserId>>,
+    ) -> ProgramResult {
+        let user = self.get_or_create_new_user(user_id);
+
+        let accounts = openbook_v2::accounts::PlaceTakeOrder {
+            signer: user.owner,
+            penalty_payer: user.owner,
+            user_base_account: user.base_vault,
+            user_quote_account: user.quote_vault,
+            market: self.market,
+            market_authority: self.market_authority,
+            bids: self.bids,
+            asks: self.asks,
+            market_base_vault: self.market_base_vault,
+            market_quote_vault: self.market_quote_vault,
+            event_heap: self.event_heap,
+            oracle_a: self.oracle_a,
+            oracle_b: self.oracle_b,
+            token_program: spl_token::ID,
+            system_program: system_program::ID,
+            open_orders_admin: None,
+            market_allowlist_entry: None,
+            signer_denylist_entry: None,
+            base_owner_denylist_entry: None,
+            quote_owner_denylist_entry: None,
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::ID,
         };
 
         let remaining = makers.map_or_else(Vec::new, |makers| {
@@ -1312,7 +1571,7 @@ impl FuzzContext {
         let accounts = openbook_v2::accounts::PlaceOrder {
             open_orders_account: user.open_orders,
             signer: user.owner,
-            user_token_account,
+            user_token_account: Some(user_token_account),
             open_orders_admin: None,
             market: self.market,
             bids: self.bids,
@@ -1322,6 +1581,17 @@ impl FuzzContext {
             oracle_a: self.oracle_a,
             oracle_b: self.oracle_b,
             token_program: spl_token::ID,
+            open_orders_delegate: None,
+            system_program: Some(system_program::ID),
+            market_allowlist_entry: None,
+            owner_denylist_entry: None,
+            signer_denylist_entry: None,
+            penalty_payer: Some(user.owner),
+            native_payer: None,
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::ID,
         };
 
         let remaining = makers.map_or_else(Vec::new, |makers| {
@@ -1364,7 +1634,7 @@ impl FuzzContext {
         let accounts = openbook_v2::accounts::PlaceOrder {
             open_orders_account: user.open_orders,
             signer: user.owner,
-            user_token_account,
+            user_token_account: Some(user_token_account),
             open_orders_admin: None,
             market: self.market,
             bids: self.bids,
@@ -1374,6 +1644,17 @@ impl FuzzContext {
             oracle_a: self.oracle_a,
             oracle_b: self.oracle_b,
             token_program: spl_token::ID,
+            open_orders_delegate: None,
+            system_program: Some(system_program::ID),
+            market_allowlist_entry: None,
+            owner_denylist_entry: None,
+            signer_denylist_entry: None,
+            penalty_payer: Some(user.owner),
+            native_payer: None,
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::ID,
         };
 
         let remaining = makers.map_or_else(Vec::new, |makers| {
@@ -1415,6 +1696,16 @@ impl FuzzContext {
             oracle_a: self.oracle_a,
             oracle_b: self.oracle_b,
             token_program: spl_token::ID,
+            open_orders_delegate: None,
+            market_allowlist_entry: None,
+            owner_denylist_entry: None,
+            signer_denylist_entry: None,
+            system_program: Some(system_program::ID),
+            penalty_payer: Some(user.owner),
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::ID,
         };
 
         let remaining = makers.map_or_else(Vec::new, |makers| {
@@ -1448,6 +1739,11 @@ impl FuzzContext {
             market: self.market,
             asks: self.asks,
             bids: self.bids,
+            open_orders_delegate: None,
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::ID,
         };
 
         process_instruction(&mut self.state, data, &accounts, &[])
@@ -1468,6 +1764,11 @@ impl FuzzContext {
             market: self.market,
             asks: self.asks,
             bids: self.bids,
+            open_orders_delegate: None,
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::ID,
         };
 
         process_instruction(&mut self.state, data, &accounts, &[])
@@ -1488,6 +1789,11 @@ impl FuzzContext {
             market: self.market,
             asks: self.asks,
             bids: self.bids,
+            open_orders_delegate: None,
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::ID,
         };
 
         process_instruction(&mut self.state, data, &accounts, &[])
@@ -1502,6 +1808,10 @@ impl FuzzContext {
             consume_events_admin: None,
             market: self.market,
             event_heap: self.event_heap,
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::ID,
         };
 
         let remaining = user_ids
@@ -1526,6 +1836,10 @@ impl FuzzContext {
             consume_events_admin: None,
             market: self.market,
             event_heap: self.event_heap,
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::ID,
         };
 
         let remaining = user_ids
@@ -1541,6 +1855,30 @@ impl FuzzContext {
         process_instruction(&mut self.state, data, &accounts, &remaining)
     }
 
+    pub fn purge_expired_orders(
+        &mut self,
+        user_id: &UserId,
+        data: &openbook_v2::instruction::PurgeExpiredOrders,
+    ) -> ProgramResult {
+        let Some(user) = self.users.get(user_id) else {
+            return Ok(());
+        };
+
+        let accounts = openbook_v2::accounts::PurgeExpiredOrders {
+            cranker: user.owner,
+            market: self.market,
+            bids: self.bids,
+            asks: self.asks,
+            event_heap: self.event_heap,
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::ID,
+        };
+
+        process_instruction(&mut self.state, data, &accounts, &[])
+    }
+
     pub fn settle_funds(
         &mut self,
         user_id: &UserId,
@@ -1556,8 +1894,8 @@ impl FuzzContext {
             owner: user.owner,
             penalty_payer: user.owner,
             open_orders_account: user.open_orders,
-            user_base_account: user.base_vault,
-            user_quote_account: user.quote_vault,
+            user_base_account: Some(user.base_vault),
+            user_quote_account: Some(user.quote_vault),
             market: self.market,
             market_authority: self.market_authority,
             market_base_vault: self.market_base_vault,
@@ -1565,6 +1903,18 @@ impl FuzzContext {
             token_program: spl_token::ID,
             system_program: system_program::ID,
             referrer_account,
+            open_orders_delegate: None,
+            native_mint: None,
+            native_settle_account: None,
+            native_destination: None,
+            owner_denylist_entry: None,
+            signer_denylist_entry: None,
+            base_owner_denylist_entry: None,
+            quote_owner_denylist_entry: None,
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::ID,
         };
 
         process_instruction(&mut self.state, data, &accounts, &[])
@@ -1578,6 +1928,10 @@ impl FuzzContext {
             market_authority: self.market_authority,
             market_quote_vault: self.market_quote_vault,
             token_program: spl_token::ID,
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::ID,
         };
 
         process_instruction(&mut self.state, data, &accounts, &[])
@@ -1723,51 +2077,140 @@ pub fn process_instruction(
 }
 
 
-// File: openbook-v2/programs/openbook-v2/src/accounts_ix/cancel_all_and_place_orders.rs
-use crate::error::OpenBookError;
-use crate::pubkey_option::NonZeroKey;
-use crate::state::*;
+// File: openbook-v2/programs/openbook-v2/src/accounts_ix/add_delegate.rs
 use anchor_lang::prelude::*;
-use anchor_spl::token::{Token, TokenAccount};
 
+use crate::state::{OpenOrdersAccount, OpenOrdersDelegate};
+
+#[cfg_attr(feature = "cpi-events", event_cpi)]
 #[derive(Accounts)]
-pub struct CancelAllAndPlaceOrders<'info> {
-    pub signer: Signer<'info>,
-    #[account(
-        mut,
-        has_one = market,
-        constraint = open_orders_account.load()?.is_owner_or_delegate(signer.key()) @ OpenBookError::NoOwnerOrDelegate
-    )]
+pub struct AddDelegate<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub owner: Signer<'info>,
+    #[account(has_one = owner)]
     pub open_orders_account: AccountLoader<'info, OpenOrdersAccount>,
-    pub open_orders_admin: Option<Signer<'info>>,
-
+    /// CHECK:
+    pub delegate_account: UncheckedAccount<'info>,
     #[account(
-        mut,
-        token::mint = market_quote_vault.mint
+        init,
+        seeds = [b"OpenOrdersDelegate".as_ref(), open_orders_account.key().as_ref(), delegate_account.key().as_ref()],
+        bump,
+        payer = payer,
+        space = OpenOrdersDelegate::space(),
     )]
-    pub user_quote_account: Account<'info, TokenAccount>,
+    pub open_orders_delegate: AccountLoader<'info, OpenOrdersDelegate>,
+    pub system_program: Program<'info, System>,
+}
 
-    #[account(
-        mut,
-        token::mint = market_base_vault.mint
-    )]
-    pub user_base_account: Account<'info, TokenAccount>,
 
+// File: openbook-v2/programs/openbook-v2/src/accounts_ix/add_to_allowlist.rs
+use crate::error::OpenBookError;
+use crate::state::{Market, MarketList, MarketListEntry};
+use anchor_lang::prelude::*;
+
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+#[instruction(entry: Pubkey)]
+pub struct AddToAllowlist<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub allowlist_admin: Signer<'info>,
     #[account(
-        mut,
-        has_one = bids,
-        has_one = asks,
-        has_one = event_heap,
-        has_one = market_base_vault,
-        has_one = market_quote_vault,
-        constraint = market.load()?.oracle_a == oracle_a.non_zero_key(),
-        constraint = market.load()?.oracle_b == oracle_b.non_zero_key(),
-        constraint = market.load()?.open_orders_admin == open_orders_admin.non_zero_key() @ OpenBookError::InvalidOpenOrdersAdmin
+        constraint = market.load()?.allowlist_admin.is_some() @ OpenBookError::NoAllowlistAdmin,
+        constraint = market.load()?.allowlist_admin == allowlist_admin.key() @ OpenBookError::InvalidAllowlistAdmin
     )]
     pub market: AccountLoader<'info, Market>,
-    #[account(mut)]
-    pub bids: AccountLoader<'info, BookSide>,
-    #[account(mut)]
+    #[account(
+        init,
+        seeds = [MarketList::Allowlist.seed(), market.key().as_ref(), entry.as_ref()],
+        bump,
+        payer = payer,
+        space = MarketListEntry::space(),
+    )]
+    pub market_allowlist_entry: AccountLoader<'info, MarketListEntry>,
+    pub system_program: Program<'info, System>,
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/accounts_ix/add_to_denylist.rs
+use crate::error::OpenBookError;
+use crate::state::{Market, MarketList, MarketListEntry};
+use anchor_lang::prelude::*;
+
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+#[instruction(entry: Pubkey)]
+pub struct AddToDenylist<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub denylist_admin: Signer<'info>,
+    #[account(
+        constraint = market.load()?.denylist_admin.is_some() @ OpenBookError::NoDenylistAdmin,
+        constraint = market.load()?.denylist_admin == denylist_admin.key() @ OpenBookError::InvalidDenylistAdmin
+    )]
+    pub market: AccountLoader<'info, Market>,
+    #[account(
+        init,
+        seeds = [MarketList::Denylist.seed(), market.key().as_ref(), entry.as_ref()],
+        bump,
+        payer = payer,
+        space = MarketListEntry::space(),
+    )]
+    pub market_denylist_entry: AccountLoader<'info, MarketListEntry>,
+    pub system_program: Program<'info, System>,
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/accounts_ix/cancel_all_and_place_orders.rs
+use crate::error::OpenBookError;
+use crate::pubkey_option::NonZeroKey;
+use crate::state::*;
+use anchor_lang::prelude::*;
+use anchor_spl::token::{Token, TokenAccount};
+
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct CancelAllAndPlaceOrders<'info> {
+    pub signer: Signer<'info>,
+    #[account(
+        mut,
+        has_one = market,
+        constraint = open_orders_signer_allowed(&open_orders_account, &open_orders_delegate, signer.key(), delegate_permissions::PLACE)? @ OpenBookError::NoOwnerOrDelegate
+    )]
+    pub open_orders_account: AccountLoader<'info, OpenOrdersAccount>,
+    pub open_orders_admin: Option<Signer<'info>>,
+
+    #[account(
+        mut,
+        token::mint = market_quote_vault.mint
+    )]
+    pub user_quote_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        token::mint = market_base_vault.mint
+    )]
+    pub user_base_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        has_one = bids,
+        has_one = asks,
+        has_one = event_heap,
+        has_one = market_base_vault,
+        has_one = market_quote_vault,
+        constraint = market.load()?.oracle_a == oracle_a.non_zero_key(),
+        constraint = market.load()?.oracle_b == oracle_b.non_zero_key(),
+        constraint = market.load()?.open_orders_admin == open_orders_admin.non_zero_key() @ OpenBookError::InvalidOpenOrdersAdmin,
+        constraint = market_allowlist_allows(&market, &market_allowlist_entry, &[open_orders_account.load()?.owner, open_orders_account.key()])? @ OpenBookError::NotAllowlisted,
+        constraint = market_denylist_allows(&market, &owner_denylist_entry, open_orders_account.load()?.owner)? @ OpenBookError::Denylisted,
+        constraint = market_denylist_allows(&market, &signer_denylist_entry, signer.key())? @ OpenBookError::Denylisted
+    )]
+    pub market: AccountLoader<'info, Market>,
+    #[account(mut)]
+    pub bids: AccountLoader<'info, BookSide>,
+    #[account(mut)]
     pub asks: AccountLoader<'info, BookSide>,
     #[account(mut)]
     pub event_heap: AccountLoader<'info, EventHeap>,
@@ -1783,21 +2226,35 @@ pub struct CancelAllAndPlaceOrders<'info> {
     pub oracle_b: Option<UncheckedAccount<'info>>,
 
     pub token_program: Program<'info, Token>,
+    pub open_orders_delegate: Option<AccountLoader<'info, OpenOrdersDelegate>>,
+    pub market_allowlist_entry: Option<AccountLoader<'info, MarketListEntry>>,
+    /// CHECK: denylist entry of the open orders account owner, the address is checked above
+    pub owner_denylist_entry: Option<UncheckedAccount<'info>>,
+    /// CHECK: denylist entry of the signer, which may be a delegate, the address is checked above
+    pub signer_denylist_entry: Option<UncheckedAccount<'info>>,
+    pub system_program: Option<Program<'info, System>>,
+    // Pays the event heap penalty right away, on markets that charge it
+    #[account(mut)]
+    pub penalty_payer: Option<Signer<'info>>,
 }
 
 
 // File: openbook-v2/programs/openbook-v2/src/accounts_ix/cancel_order.rs
 use crate::error::OpenBookError;
-use crate::state::{BookSide, Market, OpenOrdersAccount};
+use crate::state::{
+    delegate_permissions, open_orders_signer_allowed, BookSide, Market, OpenOrdersAccount,
+    OpenOrdersDelegate,
+};
 use anchor_lang::prelude::*;
 
+#[cfg_attr(feature = "cpi-events", event_cpi)]
 #[derive(Accounts)]
 pub struct CancelOrder<'info> {
     pub signer: Signer<'info>,
     #[account(
         mut,
         has_one = market,
-        constraint = open_orders_account.load()?.is_owner_or_delegate(signer.key()) @ OpenBookError::NoOwnerOrDelegate
+        constraint = open_orders_signer_allowed(&open_orders_account, &open_orders_delegate, signer.key(), delegate_permissions::CANCEL)? @ OpenBookError::NoOwnerOrDelegate
     )]
     pub open_orders_account: AccountLoader<'info, OpenOrdersAccount>,
     #[account(
@@ -1809,6 +2266,7 @@ pub struct CancelOrder<'info> {
     pub bids: AccountLoader<'info, BookSide>,
     #[account(mut)]
     pub asks: AccountLoader<'info, BookSide>,
+    pub open_orders_delegate: Option<AccountLoader<'info, OpenOrdersDelegate>>,
 }
 
 
@@ -1917,6 +2375,7 @@ use crate::pubkey_option::NonZeroKey;
 use crate::state::*;
 use anchor_lang::prelude::*;
 
+#[cfg_attr(feature = "cpi-events", event_cpi)]
 #[derive(Accounts)]
 pub struct ConsumeEvents<'info> {
     pub consume_events_admin: Option<Signer<'info>>,
@@ -1930,6 +2389,24 @@ pub struct ConsumeEvents<'info> {
     pub event_heap: AccountLoader<'info, EventHeap>,
 }
 
+/// Same as [`ConsumeEvents`], with a `cranker` receiving the crank reward
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct ConsumeEventsWithReward<'info> {
+    pub consume_events_admin: Option<Signer<'info>>,
+    #[account(
+        mut,
+        has_one = event_heap,
+        constraint = market.load()?.consume_events_admin == consume_events_admin.non_zero_key() @ OpenBookError::InvalidConsumeEventsAdmin
+    )]
+    pub market: AccountLoader<'info, Market>,
+    #[account(mut)]
+    pub event_heap: AccountLoader<'info, EventHeap>,
+    /// Receives the crank reward for the consumed events
+    #[account(mut)]
+    pub cranker: Signer<'info>,
+}
+
 
 // File: openbook-v2/programs/openbook-v2/src/accounts_ix/create_market.rs
 use crate::state::*;
@@ -2002,11 +2479,18 @@ pub struct CreateMarket<'info> {
     pub consume_events_admin: Option<UncheckedAccount<'info>>,
     /// CHECK:
     pub close_market_admin: Option<UncheckedAccount<'info>>,
+    /// CHECK:
+    pub allowlist_admin: Option<UncheckedAccount<'info>>,
+    /// CHECK:
+    pub denylist_admin: Option<UncheckedAccount<'info>>,
+    /// CHECK:
+    pub exposure_admin: Option<UncheckedAccount<'info>>,
 }
 
 
 // File: openbook-v2/programs/openbook-v2/src/accounts_ix/create_open_orders_account.rs
-use crate::state::{Market, OpenOrdersAccount, OpenOrdersIndexer};
+use crate::error::OpenBookError;
+use crate::state::{market_denylist_allows, Market, OpenOrdersAccount, OpenOrdersIndexer};
 use anchor_lang::prelude::*;
 
 #[derive(Accounts)]
@@ -2034,8 +2518,13 @@ pub struct CreateOpenOrdersAccount<'info> {
         space = OpenOrdersAccount::space(),
     )]
     pub open_orders_account: AccountLoader<'info, OpenOrdersAccount>,
+    #[account(
+        constraint = market_denylist_allows(&market, &owner_denylist_entry, owner.key())? @ OpenBookError::Denylisted
+    )]
     pub market: AccountLoader<'info, Market>,
     pub system_program: Program<'info, System>,
+    /// CHECK: denylist entry of the owner, the address is checked above
+    pub owner_denylist_entry: Option<UncheckedAccount<'info>>,
 }
 
 
@@ -2065,19 +2554,21 @@ use crate::state::*;
 use anchor_lang::prelude::*;
 use anchor_spl::token::{Token, TokenAccount};
 
+#[cfg_attr(feature = "cpi-events", event_cpi)]
 #[derive(Accounts)]
 pub struct Deposit<'info> {
     pub owner: Signer<'info>,
+    // Leave out the account of a wrapped SOL side to deposit lamports of `native_payer` instead
     #[account(
         mut,
         token::mint = market_base_vault.mint
     )]
-    pub user_base_account: Account<'info, TokenAccount>,
+    pub user_base_account: Option<Account<'info, TokenAccount>>,
     #[account(
         mut,
         token::mint = market_quote_vault.mint
     )]
-    pub user_quote_account: Account<'info, TokenAccount>,
+    pub user_quote_account: Option<Account<'info, TokenAccount>>,
     #[account(
         mut,
         has_one = market,
@@ -2096,10 +2587,31 @@ pub struct Deposit<'info> {
     pub market_quote_vault: Account<'info, TokenAccount>,
 
     pub token_program: Program<'info, Token>,
+    pub system_program: Option<Program<'info, System>>,
+    #[account(mut)]
+    pub native_payer: Option<Signer<'info>>,
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/accounts_ix/migrate_market.rs
+use anchor_lang::prelude::*;
+
+use crate::state::Market;
+
+#[derive(Accounts)]
+pub struct MigrateMarket<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(mut)]
+    pub market: AccountLoader<'info, Market>,
+    pub system_program: Program<'info, System>,
 }
 
 
 // File: openbook-v2/programs/openbook-v2/src/accounts_ix/mod.rs
+pub use add_delegate::*;
+pub use add_to_allowlist::*;
+pub use add_to_denylist::*;
 pub use cancel_all_and_place_orders::*;
 pub use cancel_order::*;
 pub use close_market::*;
@@ -2110,18 +2622,34 @@ pub use create_market::*;
 pub use create_open_orders_account::*;
 pub use create_open_orders_indexer::*;
 pub use deposit::*;
+pub use migrate_market::*;
 pub use place_order::*;
 pub use place_take_order::*;
 pub use prune_orders::*;
+pub use purge_expired_orders::*;
+pub use remove_delegate::*;
+pub use remove_from_allowlist::*;
+pub use remove_from_denylist::*;
+pub use resize_open_orders_account::*;
 pub use set_delegate::*;
+pub use set_delegate_permissions::*;
+pub use set_exposure_limits::*;
 pub use set_market_expired::*;
+pub use set_self_trade_scope::*;
 pub use settle_funds::*;
 pub use settle_funds_expired::*;
 pub use stub_oracle_close::*;
 pub use stub_oracle_create::*;
 pub use stub_oracle_set::*;
 pub use sweep_fees::*;
-
+pub use swap_route::*;
+pub use transfer_free_balance::*;
+pub use view_book::*;
+pub use withdraw::*;
+
+mod add_delegate;
+mod add_to_allowlist;
+mod add_to_denylist;
 mod cancel_all_and_place_orders;
 mod cancel_order;
 mod close_market;
@@ -2132,17 +2660,30 @@ mod create_market;
 mod create_open_orders_account;
 mod create_open_orders_indexer;
 mod deposit;
+mod migrate_market;
 mod place_order;
 mod place_take_order;
 mod prune_orders;
+mod purge_expired_orders;
+mod remove_delegate;
+mod remove_from_allowlist;
+mod remove_from_denylist;
+mod resize_open_orders_account;
 mod set_delegate;
+mod set_delegate_permissions;
+mod set_exposure_limits;
 mod set_market_expired;
+mod set_self_trade_scope;
 mod settle_funds;
 mod settle_funds_expired;
 mod stub_oracle_close;
 mod stub_oracle_create;
 mod stub_oracle_set;
 mod sweep_fees;
+mod swap_route;
+mod transfer_free_balance;
+mod view_book;
+mod withdraw;
 
 
 // File: openbook-v2/programs/openbook-v2/src/accounts_ix/place_order.rs
@@ -2153,22 +2694,24 @@ use crate::state::*;
 use anchor_lang::prelude::*;
 use anchor_spl::token::{Token, TokenAccount};
 
+#[cfg_attr(feature = "cpi-events", event_cpi)]
 #[derive(Accounts)]
 pub struct PlaceOrder<'info> {
     pub signer: Signer<'info>,
     #[account(
         mut,
         has_one = market,
-        constraint = open_orders_account.load()?.is_owner_or_delegate(signer.key()) @ OpenBookError::NoOwnerOrDelegate
+        constraint = open_orders_signer_allowed(&open_orders_account, &open_orders_delegate, signer.key(), delegate_permissions::PLACE)? @ OpenBookError::NoOwnerOrDelegate
     )]
     pub open_orders_account: AccountLoader<'info, OpenOrdersAccount>,
     pub open_orders_admin: Option<Signer<'info>>,
 
+    // Leave out on a wrapped SOL side to pay with lamports of `native_payer` instead
     #[account(
         mut,
         token::mint = market_vault.mint
     )]
-    pub user_token_account: Account<'info, TokenAccount>,
+    pub user_token_account: Option<Account<'info, TokenAccount>>,
 
     #[account(
         mut,
@@ -2177,7 +2720,10 @@ pub struct PlaceOrder<'info> {
         has_one = event_heap,
         constraint = market.load()?.oracle_a == oracle_a.non_zero_key(),
         constraint = market.load()?.oracle_b == oracle_b.non_zero_key(),
-        constraint = market.load()?.open_orders_admin == open_orders_admin.non_zero_key() @ OpenBookError::InvalidOpenOrdersAdmin
+        constraint = market.load()?.open_orders_admin == open_orders_admin.non_zero_key() @ OpenBookError::InvalidOpenOrdersAdmin,
+        constraint = market_allowlist_allows(&market, &market_allowlist_entry, &[open_orders_account.load()?.owner, open_orders_account.key()])? @ OpenBookError::NotAllowlisted,
+        constraint = market_denylist_allows(&market, &owner_denylist_entry, open_orders_account.load()?.owner)? @ OpenBookError::Denylisted,
+        constraint = market_denylist_allows(&market, &signer_denylist_entry, signer.key())? @ OpenBookError::Denylisted
     )]
     pub market: AccountLoader<'info, Market>,
     #[account(mut)]
@@ -2199,6 +2745,18 @@ pub struct PlaceOrder<'info> {
     pub oracle_b: Option<UncheckedAccount<'info>>,
 
     pub token_program: Program<'info, Token>,
+    pub open_orders_delegate: Option<AccountLoader<'info, OpenOrdersDelegate>>,
+    pub system_program: Option<Program<'info, System>>,
+    pub market_allowlist_entry: Option<AccountLoader<'info, MarketListEntry>>,
+    /// CHECK: denylist entry of the open orders account owner, the address is checked above
+    pub owner_denylist_entry: Option<UncheckedAccount<'info>>,
+    /// CHECK: denylist entry of the signer, which may be a delegate, the address is checked above
+    pub signer_denylist_entry: Option<UncheckedAccount<'info>>,
+    // Pays the event heap penalty right away, on markets that charge it
+    #[account(mut)]
+    pub penalty_payer: Option<Signer<'info>>,
+    #[account(mut)]
+    pub native_payer: Option<Signer<'info>>,
 }
 
 impl<'info> PlaceOrder<'info> {
@@ -2209,13 +2767,21 @@ impl<'info> PlaceOrder<'info> {
             asks: self.asks.clone(),
             open_orders_account: self.open_orders_account.clone(),
             market: self.market.clone(),
+            open_orders_delegate: self.open_orders_delegate.clone(),
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority.clone(),
+            #[cfg(feature = "cpi-events")]
+            program: self.program.clone(),
         }
     }
 }
 
 impl PlaceOrderBumps {
     pub fn to_cancel_order(&self) -> CancelOrderBumps {
-        CancelOrderBumps {}
+        CancelOrderBumps {
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+        }
     }
 }
 
@@ -2227,6 +2793,7 @@ use crate::state::*;
 use anchor_lang::prelude::*;
 use anchor_spl::token::{Token, TokenAccount};
 
+#[cfg_attr(feature = "cpi-events", event_cpi)]
 #[derive(Accounts)]
 pub struct PlaceTakeOrder<'info> {
     #[account(mut)]
@@ -2244,7 +2811,11 @@ pub struct PlaceTakeOrder<'info> {
         has_one = market_authority,
         constraint = market.load()?.oracle_a == oracle_a.non_zero_key(),
         constraint = market.load()?.oracle_b == oracle_b.non_zero_key(),
-        constraint = market.load()?.open_orders_admin == open_orders_admin.non_zero_key() @ OpenBookError::InvalidOpenOrdersAdmin
+        constraint = market.load()?.open_orders_admin == open_orders_admin.non_zero_key() @ OpenBookError::InvalidOpenOrdersAdmin,
+        constraint = market_allowlist_allows(&market, &market_allowlist_entry, &[signer.key()])? @ OpenBookError::NotAllowlisted,
+        constraint = market_denylist_allows(&market, &signer_denylist_entry, signer.key())? @ OpenBookError::Denylisted,
+        constraint = market_denylist_allows(&market, &base_owner_denylist_entry, user_base_account.owner)? @ OpenBookError::Denylisted,
+        constraint = market_denylist_allows(&market, &quote_owner_denylist_entry, user_quote_account.owner)? @ OpenBookError::Denylisted
     )]
     pub market: AccountLoader<'info, Market>,
     /// CHECK: checked on has_one in market
@@ -2279,6 +2850,13 @@ pub struct PlaceTakeOrder<'info> {
     pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
     pub open_orders_admin: Option<Signer<'info>>,
+    pub market_allowlist_entry: Option<AccountLoader<'info, MarketListEntry>>,
+    /// CHECK: denylist entry of the signer, the address is checked above
+    pub signer_denylist_entry: Option<UncheckedAccount<'info>>,
+    /// CHECK: denylist entry of the base account owner, the address is checked above
+    pub base_owner_denylist_entry: Option<UncheckedAccount<'info>>,
+    /// CHECK: denylist entry of the quote account owner, the address is checked above
+    pub quote_owner_denylist_entry: Option<UncheckedAccount<'info>>,
 }
 
 
@@ -2287,6 +2865,7 @@ use crate::error::OpenBookError;
 use crate::state::*;
 use anchor_lang::prelude::*;
 
+#[cfg_attr(feature = "cpi-events", event_cpi)]
 #[derive(Accounts)]
 pub struct PruneOrders<'info> {
     pub close_market_admin: Signer<'info>,
@@ -2309,11 +2888,136 @@ pub struct PruneOrders<'info> {
 }
 
 
+// File: openbook-v2/programs/openbook-v2/src/accounts_ix/purge_expired_orders.rs
+use crate::state::*;
+use anchor_lang::prelude::*;
+
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct PurgeExpiredOrders<'info> {
+    /// Receives the bounty for the purged orders
+    #[account(mut)]
+    pub cranker: Signer<'info>,
+    #[account(
+        mut,
+        has_one = bids,
+        has_one = asks,
+        has_one = event_heap,
+    )]
+    pub market: AccountLoader<'info, Market>,
+    #[account(mut)]
+    pub bids: AccountLoader<'info, BookSide>,
+    #[account(mut)]
+    pub asks: AccountLoader<'info, BookSide>,
+    #[account(mut)]
+    pub event_heap: AccountLoader<'info, EventHeap>,
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/accounts_ix/remove_delegate.rs
+use anchor_lang::prelude::*;
+
+use crate::state::{OpenOrdersAccount, OpenOrdersDelegate};
+
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct RemoveDelegate<'info> {
+    pub owner: Signer<'info>,
+    #[account(has_one = owner)]
+    pub open_orders_account: AccountLoader<'info, OpenOrdersAccount>,
+    #[account(
+        mut,
+        has_one = open_orders_account,
+        close = sol_destination,
+    )]
+    pub open_orders_delegate: AccountLoader<'info, OpenOrdersDelegate>,
+    #[account(mut)]
+    /// CHECK: target for account rent needs no checks
+    pub sol_destination: UncheckedAccount<'info>,
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/accounts_ix/remove_from_allowlist.rs
+use crate::error::OpenBookError;
+use crate::state::{Market, MarketList, MarketListEntry};
+use anchor_lang::prelude::*;
+
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+#[instruction(entry: Pubkey)]
+pub struct RemoveFromAllowlist<'info> {
+    pub allowlist_admin: Signer<'info>,
+    #[account(
+        constraint = market.load()?.allowlist_admin.is_some() @ OpenBookError::NoAllowlistAdmin,
+        constraint = market.load()?.allowlist_admin == allowlist_admin.key() @ OpenBookError::InvalidAllowlistAdmin
+    )]
+    pub market: AccountLoader<'info, Market>,
+    #[account(
+        mut,
+        seeds = [MarketList::Allowlist.seed(), market.key().as_ref(), entry.as_ref()],
+        bump = market_allowlist_entry.load()?.bump,
+        close = sol_destination,
+    )]
+    pub market_allowlist_entry: AccountLoader<'info, MarketListEntry>,
+    #[account(mut)]
+    /// CHECK: target for the freed rent needs no checks
+    pub sol_destination: UncheckedAccount<'info>,
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/accounts_ix/remove_from_denylist.rs
+use crate::error::OpenBookError;
+use crate::state::{Market, MarketList, MarketListEntry};
+use anchor_lang::prelude::*;
+
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+#[instruction(entry: Pubkey)]
+pub struct RemoveFromDenylist<'info> {
+    pub denylist_admin: Signer<'info>,
+    #[account(
+        constraint = market.load()?.denylist_admin.is_some() @ OpenBookError::NoDenylistAdmin,
+        constraint = market.load()?.denylist_admin == denylist_admin.key() @ OpenBookError::InvalidDenylistAdmin
+    )]
+    pub market: AccountLoader<'info, Market>,
+    #[account(
+        mut,
+        seeds = [MarketList::Denylist.seed(), market.key().as_ref(), entry.as_ref()],
+        bump = market_denylist_entry.load()?.bump,
+        close = sol_destination,
+    )]
+    pub market_denylist_entry: AccountLoader<'info, MarketListEntry>,
+    #[account(mut)]
+    /// CHECK: target for the freed rent needs no checks
+    pub sol_destination: UncheckedAccount<'info>,
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/accounts_ix/resize_open_orders_account.rs
+use anchor_lang::prelude::*;
+
+use crate::state::OpenOrdersAccount;
+
+#[derive(Accounts)]
+pub struct ResizeOpenOrdersAccount<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub owner: Signer<'info>,
+    #[account(
+        mut,
+        has_one = owner,
+    )]
+    pub open_orders_account: AccountLoader<'info, OpenOrdersAccount>,
+    pub system_program: Program<'info, System>,
+}
+
+
 // File: openbook-v2/programs/openbook-v2/src/accounts_ix/set_delegate.rs
 use anchor_lang::prelude::*;
 
 use crate::state::OpenOrdersAccount;
 
+#[cfg_attr(feature = "cpi-events", event_cpi)]
 #[derive(Accounts)]
 pub struct SetDelegate<'info> {
     #[account(mut)]
@@ -2328,6 +3032,45 @@ pub struct SetDelegate<'info> {
 }
 
 
+// File: openbook-v2/programs/openbook-v2/src/accounts_ix/set_delegate_permissions.rs
+use anchor_lang::prelude::*;
+
+use crate::state::OpenOrdersAccount;
+
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct SetDelegatePermissions<'info> {
+    pub owner: Signer<'info>,
+    #[account(
+        mut,
+        has_one = owner,
+    )]
+    pub open_orders_account: AccountLoader<'info, OpenOrdersAccount>,
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/accounts_ix/set_exposure_limits.rs
+use crate::error::OpenBookError;
+use crate::state::*;
+use anchor_lang::prelude::*;
+
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct SetExposureLimits<'info> {
+    pub exposure_admin: Signer<'info>,
+    #[account(
+        mut,
+        has_one = market
+    )]
+    pub open_orders_account: AccountLoader<'info, OpenOrdersAccount>,
+    #[account(
+        constraint = market.load()?.exposure_admin.is_some() @ OpenBookError::NoExposureAdmin,
+        constraint = market.load()?.exposure_admin == exposure_admin.key() @ OpenBookError::InvalidExposureAdmin
+    )]
+    pub market: AccountLoader<'info, Market>,
+}
+
+
 // File: openbook-v2/programs/openbook-v2/src/accounts_ix/set_market_expired.rs
 use crate::error::OpenBookError;
 use crate::state::*;
@@ -2345,12 +3088,30 @@ pub struct SetMarketExpired<'info> {
 }
 
 
+// File: openbook-v2/programs/openbook-v2/src/accounts_ix/set_self_trade_scope.rs
+use anchor_lang::prelude::*;
+
+use crate::state::OpenOrdersAccount;
+
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct SetSelfTradeScope<'info> {
+    pub owner: Signer<'info>,
+    #[account(
+        mut,
+        has_one = owner,
+    )]
+    pub open_orders_account: AccountLoader<'info, OpenOrdersAccount>,
+}
+
+
 // File: openbook-v2/programs/openbook-v2/src/accounts_ix/settle_funds.rs
 use crate::error::OpenBookError;
 use crate::state::*;
 use anchor_lang::prelude::*;
-use anchor_spl::token::{Token, TokenAccount};
+use anchor_spl::token::{spl_token, Mint, Token, TokenAccount};
 
+#[cfg_attr(feature = "cpi-events", event_cpi)]
 #[derive(Accounts)]
 pub struct SettleFunds<'info> {
     pub owner: Signer<'info>,
@@ -2360,7 +3121,7 @@ pub struct SettleFunds<'info> {
     #[account(
         mut,
         has_one = market,
-        constraint = open_orders_account.load()?.is_owner_or_delegate(owner.key()) @ OpenBookError::NoOwnerOrDelegate
+        constraint = open_orders_signer_allowed(&open_orders_account, &open_orders_delegate, owner.key(), delegate_permissions::SETTLE)? @ OpenBookError::NoOwnerOrDelegate
     )]
     pub open_orders_account: AccountLoader<'info, OpenOrdersAccount>,
     #[account(
@@ -2368,6 +3129,8 @@ pub struct SettleFunds<'info> {
         has_one = market_base_vault,
         has_one = market_quote_vault,
         has_one = market_authority,
+        constraint = market_denylist_allows(&market, &owner_denylist_entry, open_orders_account.load()?.owner)? @ OpenBookError::Denylisted,
+        constraint = market_denylist_allows(&market, &signer_denylist_entry, owner.key())? @ OpenBookError::Denylisted
     )]
     pub market: AccountLoader<'info, Market>,
     /// CHECK: checked on has_one in market
@@ -2376,18 +3139,21 @@ pub struct SettleFunds<'info> {
     pub market_base_vault: Account<'info, TokenAccount>,
     #[account(mut)]
     pub market_quote_vault: Account<'info, TokenAccount>,
+    // Leave out the account of a wrapped SOL side to pay out lamports to `native_destination`
     #[account(
         mut,
         token::mint = market_base_vault.mint,
-        constraint = open_orders_account.load()?.is_settle_destination_allowed(owner.key(), user_base_account.owner)
+        constraint = open_orders_account.load()?.is_settle_destination_allowed(owner.key(), user_base_account.owner),
+        constraint = market_denylist_allows(&market, &base_owner_denylist_entry, user_base_account.owner)? @ OpenBookError::Denylisted
     )]
-    pub user_base_account: Account<'info, TokenAccount>,
+    pub user_base_account: Option<Account<'info, TokenAccount>>,
     #[account(
         mut,
         token::mint = market_quote_vault.mint,
-        constraint = open_orders_account.load()?.is_settle_destination_allowed(owner.key(), user_quote_account.owner)
+        constraint = open_orders_account.load()?.is_settle_destination_allowed(owner.key(), user_quote_account.owner),
+        constraint = market_denylist_allows(&market, &quote_owner_denylist_entry, user_quote_account.owner)? @ OpenBookError::Denylisted
     )]
-    pub user_quote_account: Account<'info, TokenAccount>,
+    pub user_quote_account: Option<Account<'info, TokenAccount>>,
     #[account(
         mut,
         token::mint = market_quote_vault.mint
@@ -2395,6 +3161,26 @@ pub struct SettleFunds<'info> {
     pub referrer_account: Option<Box<Account<'info, TokenAccount>>>,
     pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
+    pub open_orders_delegate: Option<AccountLoader<'info, OpenOrdersDelegate>>,
+    #[account(address = spl_token::native_mint::ID)]
+    pub native_mint: Option<Account<'info, Mint>>,
+    /// CHECK: PDA checked in the ix, temporary token account to unwrap native SOL through
+    #[account(mut)]
+    pub native_settle_account: Option<UncheckedAccount<'info>>,
+    /// CHECK: receives the lamports of a wrapped SOL side, only the owner can
+    #[account(
+        mut,
+        address = open_orders_account.load()?.owner @ OpenBookError::InvalidNativeTransfer
+    )]
+    pub native_destination: Option<UncheckedAccount<'info>>,
+    /// CHECK: denylist entry of the open orders account owner, the address is checked above
+    pub owner_denylist_entry: Option<UncheckedAccount<'info>>,
+    /// CHECK: denylist entry of the signer, which may be a delegate, the address is checked above
+    pub signer_denylist_entry: Option<UncheckedAccount<'info>>,
+    /// CHECK: denylist entry of the base account owner, the address is checked above
+    pub base_owner_denylist_entry: Option<UncheckedAccount<'info>>,
+    /// CHECK: denylist entry of the quote account owner, the address is checked above
+    pub quote_owner_denylist_entry: Option<UncheckedAccount<'info>>,
 }
 
 
@@ -2405,6 +3191,7 @@ use crate::state::*;
 use anchor_lang::prelude::*;
 use anchor_spl::token::{Token, TokenAccount};
 
+#[cfg_attr(feature = "cpi-events", event_cpi)]
 #[derive(Accounts)]
 pub struct SettleFundsExpired<'info> {
     pub close_market_admin: Signer<'info>,
@@ -2463,18 +3250,33 @@ impl<'info> SettleFundsExpired<'info> {
             market_authority: self.market_authority.clone(),
             market_base_vault: self.market_base_vault.clone(),
             market_quote_vault: self.market_quote_vault.clone(),
-            user_base_account: self.user_base_account.clone(),
-            user_quote_account: self.user_quote_account.clone(),
+            user_base_account: Some(self.user_base_account.clone()),
+            user_quote_account: Some(self.user_quote_account.clone()),
             referrer_account: self.referrer_account.clone(),
             token_program: self.token_program.clone(),
             system_program: self.system_program.clone(),
+            open_orders_delegate: None,
+            native_mint: None,
+            native_settle_account: None,
+            native_destination: None,
+            owner_denylist_entry: None,
+            signer_denylist_entry: None,
+            base_owner_denylist_entry: None,
+            quote_owner_denylist_entry: None,
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority.clone(),
+            #[cfg(feature = "cpi-events")]
+            program: self.program.clone(),
         }
     }
 }
 
 impl SettleFundsExpiredBumps {
     pub fn to_settle_funds(&self) -> SettleFundsBumps {
-        SettleFundsBumps {}
+        SettleFundsBumps {
+            #[cfg(feature = "cpi-events")]
+            event_authority: self.event_authority,
+        }
     }
 }
 
@@ -2539,15 +3341,129 @@ pub struct StubOracleSet<'info> {
 }
 
 
-// File: openbook-v2/programs/openbook-v2/src/accounts_ix/sweep_fees.rs
+// File: openbook-v2/programs/openbook-v2/src/accounts_ix/swap_route.rs
+use crate::error::OpenBookError;
+use crate::pubkey_option::NonZeroKey;
 use crate::state::*;
 use anchor_lang::prelude::*;
 use anchor_spl::token::{Token, TokenAccount};
 
+#[cfg_attr(feature = "cpi-events", event_cpi)]
 #[derive(Accounts)]
-pub struct SweepFees<'info> {
-    pub collect_fee_admin: Signer<'info>,
-    #[account(
+pub struct SwapRoute<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(mut)]
+    pub penalty_payer: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = first_market.load()?.bids == first_bids.key(),
+        constraint = first_market.load()?.asks == first_asks.key(),
+        constraint = first_market.load()?.event_heap == first_event_heap.key(),
+        constraint = first_market.load()?.market_base_vault == first_market_base_vault.key(),
+        constraint = first_market.load()?.market_quote_vault == first_market_quote_vault.key(),
+        constraint = first_market.load()?.market_authority == first_market_authority.key(),
+        constraint = first_market.load()?.oracle_a == first_oracle_a.non_zero_key(),
+        constraint = first_market.load()?.oracle_b == first_oracle_b.non_zero_key(),
+        constraint = first_market.load()?.open_orders_admin == first_open_orders_admin.non_zero_key() @ OpenBookError::InvalidOpenOrdersAdmin,
+        constraint = market_allowlist_allows(&first_market, &first_market_allowlist_entry, &[signer.key()])? @ OpenBookError::NotAllowlisted,
+        constraint = market_denylist_allows(&first_market, &first_signer_denylist_entry, signer.key())? @ OpenBookError::Denylisted,
+        constraint = market_denylist_allows(&first_market, &first_intermediate_owner_denylist_entry, user_intermediate_account.owner)? @ OpenBookError::Denylisted,
+        constraint = market_denylist_allows(&first_market, &first_output_owner_denylist_entry, user_output_account.owner)? @ OpenBookError::Denylisted
+    )]
+    pub first_market: AccountLoader<'info, Market>,
+    /// CHECK: checked on first_market
+    pub first_market_authority: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub first_bids: AccountLoader<'info, BookSide>,
+    #[account(mut)]
+    pub first_asks: AccountLoader<'info, BookSide>,
+    #[account(mut)]
+    pub first_market_base_vault: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub first_market_quote_vault: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub first_event_heap: AccountLoader<'info, EventHeap>,
+    /// CHECK: The oracle can be one of several different account types and the pubkey is checked above
+    pub first_oracle_a: Option<UncheckedAccount<'info>>,
+    /// CHECK: The oracle can be one of several different account types and the pubkey is checked above
+    pub first_oracle_b: Option<UncheckedAccount<'info>>,
+    pub first_open_orders_admin: Option<Signer<'info>>,
+
+    #[account(
+        mut,
+        constraint = second_market.load()?.bids == second_bids.key(),
+        constraint = second_market.load()?.asks == second_asks.key(),
+        constraint = second_market.load()?.event_heap == second_event_heap.key(),
+        constraint = second_market.load()?.market_base_vault == second_market_base_vault.key(),
+        constraint = second_market.load()?.market_quote_vault == second_market_quote_vault.key(),
+        constraint = second_market.load()?.market_authority == second_market_authority.key(),
+        constraint = second_market.load()?.oracle_a == second_oracle_a.non_zero_key(),
+        constraint = second_market.load()?.oracle_b == second_oracle_b.non_zero_key(),
+        constraint = second_market.load()?.open_orders_admin == second_open_orders_admin.non_zero_key() @ OpenBookError::InvalidOpenOrdersAdmin,
+        constraint = market_allowlist_allows(&second_market, &second_market_allowlist_entry, &[signer.key()])? @ OpenBookError::NotAllowlisted,
+        constraint = market_denylist_allows(&second_market, &second_signer_denylist_entry, signer.key())? @ OpenBookError::Denylisted,
+        constraint = market_denylist_allows(&second_market, &second_intermediate_owner_denylist_entry, user_intermediate_account.owner)? @ OpenBookError::Denylisted,
+        constraint = market_denylist_allows(&second_market, &second_output_owner_denylist_entry, user_output_account.owner)? @ OpenBookError::Denylisted
+    )]
+    pub second_market: AccountLoader<'info, Market>,
+    /// CHECK: checked on second_market
+    pub second_market_authority: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub second_bids: AccountLoader<'info, BookSide>,
+    #[account(mut)]
+    pub second_asks: AccountLoader<'info, BookSide>,
+    #[account(mut)]
+    pub second_market_base_vault: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub second_market_quote_vault: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub second_event_heap: AccountLoader<'info, EventHeap>,
+    /// CHECK: The oracle can be one of several different account types and the pubkey is checked above
+    pub second_oracle_a: Option<UncheckedAccount<'info>>,
+    /// CHECK: The oracle can be one of several different account types and the pubkey is checked above
+    pub second_oracle_b: Option<UncheckedAccount<'info>>,
+    pub second_open_orders_admin: Option<Signer<'info>>,
+
+    // Mints are checked against the markets inside the ix
+    #[account(mut)]
+    pub user_input_account: Box<Account<'info, TokenAccount>>,
+    /// Receives the intermediate tokens left over after rounding to the second market's lots
+    #[account(mut)]
+    pub user_intermediate_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub user_output_account: Box<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub first_market_allowlist_entry: Option<AccountLoader<'info, MarketListEntry>>,
+    pub second_market_allowlist_entry: Option<AccountLoader<'info, MarketListEntry>>,
+    /// CHECK: denylist entry of the signer, the address is checked above
+    pub first_signer_denylist_entry: Option<UncheckedAccount<'info>>,
+    /// CHECK: denylist entry of the intermediate account owner, the address is checked above
+    pub first_intermediate_owner_denylist_entry: Option<UncheckedAccount<'info>>,
+    /// CHECK: denylist entry of the output account owner, the address is checked above
+    pub first_output_owner_denylist_entry: Option<UncheckedAccount<'info>>,
+    /// CHECK: denylist entry of the signer, the address is checked above
+    pub second_signer_denylist_entry: Option<UncheckedAccount<'info>>,
+    /// CHECK: denylist entry of the intermediate account owner, the address is checked above
+    pub second_intermediate_owner_denylist_entry: Option<UncheckedAccount<'info>>,
+    /// CHECK: denylist entry of the output account owner, the address is checked above
+    pub second_output_owner_denylist_entry: Option<UncheckedAccount<'info>>,
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/accounts_ix/sweep_fees.rs
+use crate::state::*;
+use anchor_lang::prelude::*;
+use anchor_spl::token::{Token, TokenAccount};
+
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct SweepFees<'info> {
+    pub collect_fee_admin: Signer<'info>,
+    #[account(
         mut,
         has_one = market_quote_vault,
         has_one = collect_fee_admin,
@@ -2568,6 +3484,138 @@ pub struct SweepFees<'info> {
 }
 
 
+// File: openbook-v2/programs/openbook-v2/src/accounts_ix/transfer_free_balance.rs
+use crate::error::OpenBookError;
+use crate::state::{market_denylist_allows, Market, OpenOrdersAccount};
+use anchor_lang::prelude::*;
+
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct TransferFreeBalance<'info> {
+    pub owner: Signer<'info>,
+    #[account(
+        mut,
+        has_one = owner,
+        has_one = market,
+    )]
+    pub source_open_orders_account: AccountLoader<'info, OpenOrdersAccount>,
+    #[account(
+        mut,
+        has_one = owner,
+        has_one = market,
+        constraint = destination_open_orders_account.key() != source_open_orders_account.key() @ OpenBookError::SameOpenOrdersAccount
+    )]
+    pub destination_open_orders_account: AccountLoader<'info, OpenOrdersAccount>,
+    #[account(
+        constraint = market_denylist_allows(&market, &owner_denylist_entry, owner.key())? @ OpenBookError::Denylisted
+    )]
+    pub market: AccountLoader<'info, Market>,
+    /// CHECK: denylist entry of the owner, the address is checked above
+    pub owner_denylist_entry: Option<UncheckedAccount<'info>>,
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/accounts_ix/view_book.rs
+use crate::accounts_zerocopy::AccountInfoRef;
+use crate::pubkey_option::NonZeroKey;
+use crate::state::*;
+use anchor_lang::prelude::*;
+
+#[derive(Accounts)]
+pub struct ViewBook<'info> {
+    #[account(
+        has_one = bids,
+        has_one = asks,
+        constraint = market.load()?.oracle_a == oracle_a.non_zero_key(),
+        constraint = market.load()?.oracle_b == oracle_b.non_zero_key(),
+    )]
+    pub market: AccountLoader<'info, Market>,
+    pub bids: AccountLoader<'info, BookSide>,
+    pub asks: AccountLoader<'info, BookSide>,
+
+    /// CHECK: The oracle can be one of several different account types and the pubkey is checked above
+    pub oracle_a: Option<UncheckedAccount<'info>>,
+    /// CHECK: The oracle can be one of several different account types and the pubkey is checked above
+    pub oracle_b: Option<UncheckedAccount<'info>>,
+}
+
+impl<'info> ViewBook<'info> {
+    /// The oracle price used to price pegged orders, falling back to a frozen price if the
+    /// market has one. Nothing is written back to the market.
+    pub fn oracle_price_lots(&self, slot: u64) -> Result<Option<i64>> {
+        let market = self.market.load()?;
+        let oracle_price_lots = market.oracle_price_lots(
+            AccountInfoRef::borrow_some(self.oracle_a.as_ref())?.as_ref(),
+            AccountInfoRef::borrow_some(self.oracle_b.as_ref())?.as_ref(),
+            slot,
+        )?;
+        Ok(oracle_price_lots.or_else(|| market.frozen_oracle_price_lots(slot)))
+    }
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/accounts_ix/withdraw.rs
+use crate::error::OpenBookError;
+use crate::state::*;
+use anchor_lang::prelude::*;
+use anchor_spl::token::{Token, TokenAccount};
+
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct Withdraw<'info> {
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub penalty_payer: Signer<'info>,
+
+    #[account(
+        mut,
+        has_one = market,
+        constraint = open_orders_signer_allowed(&open_orders_account, &open_orders_delegate, owner.key(), delegate_permissions::SETTLE)? @ OpenBookError::NoOwnerOrDelegate
+    )]
+    pub open_orders_account: AccountLoader<'info, OpenOrdersAccount>,
+    #[account(
+        mut,
+        has_one = market_base_vault,
+        has_one = market_quote_vault,
+        has_one = market_authority,
+        constraint = market_denylist_allows(&market, &owner_denylist_entry, open_orders_account.load()?.owner)? @ OpenBookError::Denylisted,
+        constraint = market_denylist_allows(&market, &signer_denylist_entry, owner.key())? @ OpenBookError::Denylisted
+    )]
+    pub market: AccountLoader<'info, Market>,
+    /// CHECK: checked on has_one in market
+    pub market_authority: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub market_base_vault: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub market_quote_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = market_base_vault.mint,
+        constraint = open_orders_account.load()?.is_settle_destination_allowed(owner.key(), user_base_account.owner),
+        constraint = market_denylist_allows(&market, &base_owner_denylist_entry, user_base_account.owner)? @ OpenBookError::Denylisted
+    )]
+    pub user_base_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = market_quote_vault.mint,
+        constraint = open_orders_account.load()?.is_settle_destination_allowed(owner.key(), user_quote_account.owner),
+        constraint = market_denylist_allows(&market, &quote_owner_denylist_entry, user_quote_account.owner)? @ OpenBookError::Denylisted
+    )]
+    pub user_quote_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub open_orders_delegate: Option<AccountLoader<'info, OpenOrdersDelegate>>,
+    /// CHECK: denylist entry of the open orders account owner, the address is checked above
+    pub owner_denylist_entry: Option<UncheckedAccount<'info>>,
+    /// CHECK: denylist entry of the signer, which may be a delegate, the address is checked above
+    pub signer_denylist_entry: Option<UncheckedAccount<'info>>,
+    /// CHECK: denylist entry of the base account owner, the address is checked above
+    pub base_owner_denylist_entry: Option<UncheckedAccount<'info>>,
+    /// CHECK: denylist entry of the quote account owner, the address is checked above
+    pub quote_owner_denylist_entry: Option<UncheckedAccount<'info>>,
+}
+
+
 // File: openbook-v2/programs/openbook-v2/src/accounts_zerocopy.rs
 use anchor_lang::prelude::*;
 use anchor_lang::ZeroCopy;
@@ -2993,6 +4041,72 @@ pub enum OpenBookError {
     NonEmptyOpenOrdersPosition,
     #[msg("Fill-Or-Kill order would generate a partial execution")]
     WouldExecutePartially,
+    #[msg("Swap amounts are beyond the given slippage limit")]
+    SwapSlippageExceeded,
+    #[msg("Open orders account capacity is out of the allowed range")]
+    InvalidOpenOrdersCapacity,
+    #[msg("Cannot shrink an open orders account over slots that are in use")]
+    OpenOrdersSlotsInUse,
+    #[msg("Delegate permissions must be a non-empty combination of the known bits")]
+    InvalidDelegatePermissions,
+    #[msg("Amount to withdraw above the free balance of the open orders account")]
+    InsufficientFreeBalance,
+    #[msg("Source and destination open orders accounts must be different")]
+    SameOpenOrdersAccount,
+    #[msg("Token accounts and markets don't form a route through a shared mint")]
+    InvalidRoute,
+    #[msg("Native SOL can only replace the token account of a wrapped SOL side, and needs the system program")]
+    InvalidNativeTransfer,
+    #[msg("Book side account size is out of the allowed range")]
+    InvalidBookSideCapacity,
+    #[msg("Event heap account size is out of the allowed range")]
+    InvalidEventHeapCapacity,
+    #[msg("Paying the event heap penalty right away needs the system program")]
+    MissingSystemProgramForPenalty,
+    #[msg("This market does not have an `allowlist_admin` and thus no allowlist.")]
+    NoAllowlistAdmin,
+    #[msg("The signer of this transaction is not this market's `allowlist_admin`.")]
+    InvalidAllowlistAdmin,
+    #[msg("Neither the owner nor the open orders account has a `market_allowlist_entry` on this market")]
+    NotAllowlisted,
+    #[msg("The key is already on the market allowlist")]
+    AllowlistEntryAlreadyAdded,
+    #[msg("The key is not on the market allowlist")]
+    AllowlistEntryNotFound,
+    #[msg("This market does not have a `denylist_admin` and thus no denylist.")]
+    NoDenylistAdmin,
+    #[msg("The signer of this transaction is not this market's `denylist_admin`.")]
+    InvalidDenylistAdmin,
+    #[msg("A denylist entry required by this market is missing or is not the entry address of the screened owner")]
+    MissingMarketDenylist,
+    #[msg("The owner or a destination owner is on the market denylist")]
+    Denylisted,
+    #[msg("The key is already on the market denylist")]
+    DenylistEntryAlreadyAdded,
+    #[msg("The key is not on the market denylist")]
+    DenylistEntryNotFound,
+    #[msg("The order would exceed the exposure limits of the open orders account")]
+    ExposureLimitExceeded,
+    #[msg("The order is below the minimum order size of the market")]
+    OrderBelowMinimumSize,
+    #[msg("The open orders account doesn't hold enough lamports above its rent exempt minimum to pay the posting bonds")]
+    InsufficientBondLamports,
+    #[msg("This market does not have an `exposure_admin` and thus no per account exposure limits.")]
+    NoExposureAdmin,
+    #[msg("The signer of this transaction is not this market's `exposure_admin`.")]
+    InvalidExposureAdmin,
+    #[msg("The market already has the current account size")]
+    MarketAlreadyMigrated,
+    #[msg("The open orders account has unpaid event heap penalties, settle its funds first")]
+    UnpaidEventHeapPenalty,
+    #[msg("The event heap penalty is above MAX_EVENT_HEAP_PENALTY")]
+    InvalidEventHeapPenalty,
+    #[msg("Event heap penalties or posting bonds overflow the lamports a market can account for")]
+    PenaltyOverflow,
+    #[msg("Paying the event heap penalty right away needs a `penalty_payer`")]
+    MissingPenaltyPayer,
+    #[msg("The instruction sends more events through self-CPIs than the instruction trace can hold")]
+    CpiEventsBudgetExceeded,
 }
 
 impl From<OpenBookError> for ProgramError {
@@ -3117,6 +4231,90 @@ fn bug_fixed_comparison_u64() {
 }
 
 
+// File: openbook-v2/programs/openbook-v2/src/instructions/add_delegate.rs
+use anchor_lang::prelude::*;
+
+use crate::accounts_ix::*;
+use crate::error::OpenBookError;
+use crate::logs::{event_emitter, AddDelegateLog};
+use crate::state::delegate_permissions;
+use crate::util::fill_from_str;
+
+pub fn add_delegate(
+    ctx: Context<AddDelegate>,
+    label: String,
+    permissions: u8,
+    expiry_timestamp: u64,
+) -> Result<()> {
+    require!(
+        delegate_permissions::is_valid(permissions),
+        OpenBookError::InvalidDelegatePermissions
+    );
+
+    let mut record = ctx.accounts.open_orders_delegate.load_init()?;
+    record.open_orders_account = ctx.accounts.open_orders_account.key();
+    record.delegate = ctx.accounts.delegate_account.key();
+    record.label = fill_from_str(&label)?;
+    record.permissions = permissions;
+    record.bump = ctx.bumps.open_orders_delegate;
+    record.expiry_timestamp = expiry_timestamp;
+
+    event_emitter!(ctx).emit(AddDelegateLog {
+        open_orders_account: record.open_orders_account,
+        delegate: record.delegate,
+        label,
+        permissions,
+        expiry_timestamp,
+    })?;
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/instructions/add_to_allowlist.rs
+use anchor_lang::prelude::*;
+
+use crate::accounts_ix::*;
+use crate::logs::{event_emitter, AddToAllowlistLog};
+use crate::state::MarketList;
+
+pub fn add_to_allowlist(ctx: Context<AddToAllowlist>, entry: Pubkey) -> Result<()> {
+    let market = ctx.accounts.market.key();
+    ctx.accounts.market_allowlist_entry.load_init()?.init(
+        market,
+        entry,
+        MarketList::Allowlist,
+        ctx.bumps.market_allowlist_entry,
+    );
+
+    event_emitter!(ctx).emit(AddToAllowlistLog { market, entry })?;
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/instructions/add_to_denylist.rs
+use anchor_lang::prelude::*;
+
+use crate::accounts_ix::*;
+use crate::logs::{event_emitter, AddToDenylistLog};
+use crate::state::MarketList;
+
+pub fn add_to_denylist(ctx: Context<AddToDenylist>, entry: Pubkey) -> Result<()> {
+    let market = ctx.accounts.market.key();
+    ctx.accounts.market_denylist_entry.load_init()?.init(
+        market,
+        entry,
+        MarketList::Denylist,
+        ctx.bumps.market_denylist_entry,
+    );
+
+    event_emitter!(ctx).emit(AddToDenylistLog { market, entry })?;
+
+    Ok(())
+}
+
+
 // File: openbook-v2/programs/openbook-v2/src/instructions/cancel_all_and_place_orders.rs
 use anchor_lang::prelude::*;
 use std::cmp;
@@ -3124,6 +4322,8 @@ use std::cmp;
 use crate::accounts_ix::*;
 use crate::accounts_zerocopy::AccountInfoRef;
 use crate::error::*;
+use crate::instructions::pay_posting_bonds;
+use crate::logs::event_emitter;
 use crate::state::*;
 use crate::token_utils::*;
 
@@ -3134,7 +4334,20 @@ pub fn cancel_all_and_place_orders<'c: 'info, 'info>(
     mut orders: Vec<Order>,
     limit: u8,
 ) -> Result<Vec<Option<u128>>> {
-    let mut open_orders_account = ctx.accounts.open_orders_account.load_mut()?;
+    // CancelAllAndPlaceOrders only requires the place permission of a delegate
+    if cancel {
+        require!(
+            open_orders_signer_allowed(
+                &ctx.accounts.open_orders_account,
+                &ctx.accounts.open_orders_delegate,
+                ctx.accounts.signer.key(),
+                delegate_permissions::CANCEL,
+            )?,
+            OpenBookError::NoOwnerOrDelegate
+        );
+    }
+
+    let mut open_orders_account = ctx.accounts.open_orders_account.load_full_mut()?;
     let open_orders_account_pk = ctx.accounts.open_orders_account.key();
 
     let clock = Clock::get()?;
@@ -3146,26 +4359,35 @@ pub fn cancel_all_and_place_orders<'c: 'info, 'info>(
     );
 
     let mut book = Orderbook {
-        bids: ctx.accounts.bids.load_mut()?,
-        asks: ctx.accounts.asks.load_mut()?,
+        bids: ctx.accounts.bids.load_full_mut()?,
+        asks: ctx.accounts.asks.load_full_mut()?,
     };
-    let mut event_heap = ctx.accounts.event_heap.load_mut()?;
+    let mut event_heap = ctx.accounts.event_heap.load_full_mut()?;
     let event_heap_size_before = event_heap.len();
 
     let now_ts: u64 = clock.unix_timestamp.try_into().unwrap();
+    let emitter = event_emitter!(ctx);
 
-    let oracle_price_lots = market.oracle_price_lots(
+    let oracle_price_lots = market.update_oracle_price_lots(
         AccountInfoRef::borrow_some(ctx.accounts.oracle_a.as_ref())?.as_ref(),
         AccountInfoRef::borrow_some(ctx.accounts.oracle_b.as_ref())?.as_ref(),
         clock.slot,
     )?;
 
     if cancel {
-        book.cancel_all_orders(&mut open_orders_account, *market, u8::MAX, None, None)?;
+        book.cancel_all_orders(
+            &mut open_orders_account.borrow_mut(),
+            *market,
+            u8::MAX,
+            None,
+            None,
+            &emitter,
+        )?;
     }
 
     let mut base_amount = 0_u64;
     let mut quote_amount = 0_u64;
+    let mut posting_bonds = 0_u64;
     let mut order_ids = Vec::new();
     for order in orders.iter_mut() {
         order.max_base_lots = market.max_base_lots();
@@ -3209,13 +4431,14 @@ pub fn cancel_all_and_place_orders<'c: 'info, 'info>(
             order,
             &mut market,
             &ctx.accounts.market.key(),
-            &mut event_heap,
+            &mut event_heap.borrow_mut(),
             oracle_price_lots,
-            Some(&mut open_orders_account),
+            Some(&mut open_orders_account.borrow_mut()),
             &open_orders_account_pk,
             now_ts,
             limit,
             ctx.remaining_accounts,
+            &emitter,
         )?;
 
         match order.side {
@@ -3233,6 +4456,9 @@ pub fn cancel_all_and_place_orders<'c: 'info, 'info>(
             }
         };
 
+        if order_id.is_some() {
+            posting_bonds += market.posting_bond;
+        }
         order_ids.push(order_id);
     }
 
@@ -3250,8 +4476,17 @@ pub fn cancel_all_and_place_orders<'c: 'info, 'info>(
     market.base_deposit_total += deposit_base_amount;
     market.quote_deposit_total += deposit_quote_amount;
 
-    if event_heap.len() > event_heap_size_before {
-        position.penalty_heap_count += 1;
+    let mut immediate_penalty = 0;
+    let new_events = event_heap.len() - event_heap_size_before;
+    if new_events > 0 {
+        event_heap.set_newest_crank_rewards(new_events, market.crank_reward_per_event(new_events));
+        match market.event_heap_penalty_policy() {
+            EventHeapPenaltyPolicy::Deferred => position.penalty_heap_count += 1,
+            EventHeapPenaltyPolicy::Immediate => {
+                immediate_penalty = market.event_heap_penalty;
+                market.add_to_crank_reward_pool(immediate_penalty)?;
+            }
+        }
     }
 
     token_transfer(
@@ -3269,6 +4504,31 @@ pub fn cancel_all_and_place_orders<'c: 'info, 'info>(
         &ctx.accounts.signer,
     )?;
 
+    drop(market);
+
+    if immediate_penalty > 0 {
+        let system_program = ctx
+            .accounts
+            .system_program
+            .as_ref()
+            .ok_or_else(|| error!(OpenBookError::MissingSystemProgramForPenalty))?;
+        let penalty_payer = ctx
+            .accounts
+            .penalty_payer
+            .as_ref()
+            .ok_or_else(|| error!(OpenBookError::MissingPenaltyPayer))?;
+        system_program_transfer(
+            immediate_penalty,
+            system_program,
+            penalty_payer,
+            &ctx.accounts.market,
+        )?;
+    }
+
+    if posting_bonds > 0 {
+        pay_posting_bonds(posting_bonds, &ctx.accounts.open_orders_account, &ctx.accounts.market)?;
+    }
+
     Ok(order_ids)
 }
 
@@ -3277,6 +4537,7 @@ pub fn cancel_all_and_place_orders<'c: 'info, 'info>(
 use anchor_lang::prelude::*;
 
 use crate::accounts_ix::*;
+use crate::logs::event_emitter;
 use crate::state::*;
 
 pub fn cancel_all_orders(
@@ -3284,15 +4545,22 @@ pub fn cancel_all_orders(
     side_option: Option<Side>,
     limit: u8,
 ) -> Result<()> {
-    let mut account = ctx.accounts.open_orders_account.load_mut()?;
+    let mut account = ctx.accounts.open_orders_account.load_full_mut()?;
 
     let market = ctx.accounts.market.load()?;
     let mut book = Orderbook {
-        bids: ctx.accounts.bids.load_mut()?,
-        asks: ctx.accounts.asks.load_mut()?,
+        bids: ctx.accounts.bids.load_full_mut()?,
+        asks: ctx.accounts.asks.load_full_mut()?,
     };
 
-    book.cancel_all_orders(&mut account, *market, limit, side_option, None)?;
+    book.cancel_all_orders(
+        &mut account.borrow_mut(),
+        *market,
+        limit,
+        side_option,
+        None,
+        &event_emitter!(ctx),
+    )?;
 
     Ok(())
 }
@@ -3303,12 +4571,13 @@ use anchor_lang::prelude::*;
 
 use crate::accounts_ix::*;
 use crate::error::*;
+use crate::logs::event_emitter;
 use crate::state::*;
 
 pub fn cancel_order(ctx: Context<CancelOrder>, order_id: u128) -> Result<()> {
     require_gt!(order_id, 0, OpenBookError::InvalidInputOrderId);
 
-    let mut open_orders_account = ctx.accounts.open_orders_account.load_mut()?;
+    let mut open_orders_account = ctx.accounts.open_orders_account.load_full_mut()?;
     let oo = open_orders_account
         .find_order_with_order_id(order_id)
         .ok_or_else(|| {
@@ -3320,16 +4589,17 @@ pub fn cancel_order(ctx: Context<CancelOrder>, order_id: u128) -> Result<()> {
 
     let market = ctx.accounts.market.load()?;
     let mut book = Orderbook {
-        bids: ctx.accounts.bids.load_mut()?,
-        asks: ctx.accounts.asks.load_mut()?,
+        bids: ctx.accounts.bids.load_full_mut()?,
+        asks: ctx.accounts.asks.load_full_mut()?,
     };
 
     book.cancel_order(
-        &mut open_orders_account,
+        &mut open_orders_account.borrow_mut(),
         order_id,
         order_side_and_tree,
         *market,
         Some(ctx.accounts.open_orders_account.key()),
+        &event_emitter!(ctx),
     )?;
 
     Ok(())
@@ -3340,21 +4610,29 @@ pub fn cancel_order(ctx: Context<CancelOrder>, order_id: u128) -> Result<()> {
 use anchor_lang::prelude::*;
 
 use crate::accounts_ix::*;
+use crate::logs::event_emitter;
 use crate::state::*;
 
 pub fn cancel_order_by_client_order_id(
     ctx: Context<CancelOrder>,
     client_order_id: u64,
 ) -> Result<i64> {
-    let mut account = ctx.accounts.open_orders_account.load_mut()?;
+    let mut account = ctx.accounts.open_orders_account.load_full_mut()?;
 
     let market = ctx.accounts.market.load()?;
     let mut book = Orderbook {
-        bids: ctx.accounts.bids.load_mut()?,
-        asks: ctx.accounts.asks.load_mut()?,
+        bids: ctx.accounts.bids.load_full_mut()?,
+        asks: ctx.accounts.asks.load_full_mut()?,
     };
 
-    book.cancel_all_orders(&mut account, *market, u8::MAX, None, Some(client_order_id))
+    book.cancel_all_orders(
+        &mut account.borrow_mut(),
+        *market,
+        u8::MAX,
+        None,
+        Some(client_order_id),
+        &event_emitter!(ctx),
+    )
 }
 
 
@@ -3373,8 +4651,8 @@ pub fn close_market(ctx: Context<CloseMarket>) -> Result<()> {
     require!(market.is_empty(), OpenBookError::NonEmptyMarket);
 
     let book = Orderbook {
-        bids: ctx.accounts.bids.load_mut()?,
-        asks: ctx.accounts.asks.load_mut()?,
+        bids: ctx.accounts.bids.load_full_mut()?,
+        asks: ctx.accounts.asks.load_full_mut()?,
     };
     require!(book.is_empty(), OpenBookError::BookContainsElements);
 
@@ -3441,6 +4719,7 @@ use crate::error::OpenBookError;
 use crate::state::*;
 
 use crate::accounts_ix::*;
+use crate::logs::{event_emitter, EventEmitter};
 
 // Max events to consume per ix.
 pub const MAX_EVENTS_CONSUME: usize = 8;
@@ -3461,12 +4740,9 @@ macro_rules! load_open_orders_account {
                 continue;
             }
 
-            Some(ai) => {
-                let ooa: AccountLoader<OpenOrdersAccount> = AccountLoader::try_from(ai)?;
-                ooa
-            }
+            Some(ai) => ai,
         };
-        let mut $name = loader.load_mut()?;
+        let mut $name = loader.load_full_mut()?;
     };
 }
 
@@ -3475,42 +4751,97 @@ pub fn consume_events<'c: 'info, 'info>(
     limit: usize,
     slots: Option<Vec<usize>>,
 ) -> Result<()> {
-    let limit = std::cmp::min(limit, MAX_EVENTS_CONSUME);
+    let mut market = ctx.accounts.market.load_mut()?;
+    let mut event_heap = ctx.accounts.event_heap.load_full_mut()?;
+    consume(
+        &mut market,
+        &mut event_heap.borrow_mut(),
+        ctx.remaining_accounts,
+        limit,
+        slots,
+        &event_emitter!(ctx),
+    )?;
+
+    Ok(())
+}
 
+pub fn consume_events_with_reward<'c: 'info, 'info>(
+    ctx: Context<'_, '_, 'c, 'info, ConsumeEventsWithReward>,
+    limit: usize,
+    slots: Option<Vec<usize>>,
+) -> Result<()> {
     let mut market = ctx.accounts.market.load_mut()?;
-    let mut event_heap = ctx.accounts.event_heap.load_mut()?;
-    let remaining_accs = &ctx.remaining_accounts;
+    let mut event_heap = ctx.accounts.event_heap.load_full_mut()?;
+    let crank_reward = consume(
+        &mut market,
+        &mut event_heap.borrow_mut(),
+        ctx.remaining_accounts,
+        limit,
+        slots,
+        &event_emitter!(ctx),
+    )?;
+
+    let reward = market.take_crank_reward(crank_reward);
+    if reward > 0 {
+        **ctx.accounts.market.to_account_info().try_borrow_mut_lamports()? -= reward;
+        **ctx.accounts.cranker.try_borrow_mut_lamports()? += reward;
+    }
+
+    Ok(())
+}
+
+/// Consumes up to `limit` events, those at `slots` first, and returns the crank reward
+/// they earned
+fn consume<'c: 'info, 'info>(
+    market: &mut Market,
+    event_heap: &mut EventHeapRefMut,
+    remaining_accs: &'c [AccountInfo<'info>],
+    limit: usize,
+    slots: Option<Vec<usize>>,
+    emitter: &EventEmitter,
+) -> Result<u64> {
+    let limit = std::cmp::min(limit, MAX_EVENTS_CONSUME);
+    let limit = emitter.cap_limit(limit);
+
+    let slots = slots.unwrap_or_default();
+    require!(
+        slots.iter().all(|slot| *slot < event_heap.capacity()),
+        OpenBookError::InvalidInputHeapSlots
+    );
 
     let slots_to_consume = slots
-        .unwrap_or_default()
         .into_iter()
-        .filter(|slot| !event_heap.nodes[*slot].is_free())
+        .filter(|slot| event_heap.at_slot(*slot).is_some())
         .chain(event_heap.iter().map(|(_event, slot)| slot))
         .unique()
         .take(limit)
         .collect_vec();
 
+    // Events of accounts missing from remaining_accs are skipped and don't earn a reward
+    let mut crank_reward = 0;
     for slot in slots_to_consume {
         let event = event_heap.at_slot(slot).unwrap();
+        let event_crank_reward = event.crank_reward();
 
         match EventType::try_from(event.event_type).map_err(|_| error!(OpenBookError::SomeError))? {
             EventType::Fill => {
                 let fill: &FillEvent = cast_ref(event);
                 load_open_orders_account!(maker, fill.maker, remaining_accs);
-                maker.execute_maker(&mut market, fill);
+                maker.execute_maker(market, fill, emitter)?;
             }
             EventType::Out => {
                 let out: &OutEvent = cast_ref(event);
                 load_open_orders_account!(owner, out.owner, remaining_accs);
-                owner.cancel_order(out.owner_slot as usize, out.quantity, *market);
+                owner.execute_out(out, *market);
             }
         }
 
         // consume this event
         event_heap.delete_slot(slot)?;
+        crank_reward += event_crank_reward;
     }
 
-    Ok(())
+    Ok(crank_reward)
 }
 
 
@@ -3535,6 +4866,15 @@ pub fn create_market(
     maker_fee: i64,
     taker_fee: i64,
     time_expiry: i64,
+    event_heap_penalty: u64,
+    event_heap_penalty_policy: EventHeapPenaltyPolicy,
+    max_bids_quote_lots: i64,
+    max_asks_base_lots: i64,
+    max_open_orders: u16,
+    min_base_lots: i64,
+    min_quote_notional: i64,
+    posting_bond: u64,
+    eviction_policy: EvictionPolicy,
 ) -> Result<()> {
     let registration_time = Clock::get()?.unix_timestamp;
 
@@ -3558,6 +4898,37 @@ pub fn create_market(
 
     require_gt!(quote_lot_size, 0, OpenBookError::InvalidInputLots);
     require_gt!(base_lot_size, 0, OpenBookError::InvalidInputLots);
+    require_gte!(max_bids_quote_lots, 0, OpenBookError::InvalidInputLots);
+    require_gte!(max_asks_base_lots, 0, OpenBookError::InvalidInputLots);
+    require_gte!(min_base_lots, 0, OpenBookError::InvalidInputLots);
+    require_gte!(min_quote_notional, 0, OpenBookError::InvalidInputLots);
+    require_gte!(
+        MAX_EVENT_HEAP_PENALTY,
+        event_heap_penalty,
+        OpenBookError::InvalidEventHeapPenalty
+    );
+
+    // Capacities are chosen by the size the accounts were created with, bytes after the
+    // last whole node are left unused.
+    let max_book_side_capacity = match eviction_policy {
+        EvictionPolicy::WorstPrice => MAX_BOOK_SIDE_CAPACITY,
+        // these scan the whole book side for the order to evict
+        EvictionPolicy::SmallestSize | EvictionPolicy::Oldest => MAX_SCANNED_EVICTION_NODES,
+    };
+    for book_side in [&ctx.accounts.bids, &ctx.accounts.asks] {
+        let capacity = BookSide::capacity_for_space(book_side.as_ref().data_len());
+        require!(
+            (MIN_ORDERTREE_NODES..=max_book_side_capacity).contains(&capacity),
+            OpenBookError::InvalidBookSideCapacity
+        );
+    }
+    let event_heap_capacity =
+        EventHeap::capacity_for_space(ctx.accounts.event_heap.as_ref().data_len());
+    require!(
+        (MIN_NUM_EVENTS as usize..=MAX_EVENT_HEAP_CAPACITY as usize)
+            .contains(&event_heap_capacity),
+        OpenBookError::InvalidEventHeapCapacity
+    );
 
     let oracle_a = ctx.accounts.oracle_a.non_zero_key();
     let oracle_b = ctx.accounts.oracle_b.non_zero_key();
@@ -3615,17 +4986,33 @@ pub fn create_market(
         quote_deposit_total: 0,
         fees_available: 0,
         referrer_rebates_accrued: 0,
-
+        last_oracle_price_lots: 0,
+        last_oracle_slot: 0,
+        crank_reward_pool: 0,
+        event_heap_penalty,
+        event_heap_penalty_policy: event_heap_penalty_policy.into(),
+        padding2: Default::default(),
+        max_open_orders,
+        allowlist_admin: ctx.accounts.allowlist_admin.non_zero_key(),
+        denylist_admin: ctx.accounts.denylist_admin.non_zero_key(),
+        max_bids_quote_lots,
+        max_asks_base_lots,
+        exposure_admin: ctx.accounts.exposure_admin.non_zero_key(),
+        min_base_lots,
+        min_quote_notional,
+        posting_bond,
+        eviction_policy: eviction_policy.into(),
+        padding3: Default::default(),
         reserved: [0; 128],
     };
 
     let mut orderbook = Orderbook {
-        bids: ctx.accounts.bids.load_init()?,
-        asks: ctx.accounts.asks.load_init()?,
+        bids: ctx.accounts.bids.load_full_init()?,
+        asks: ctx.accounts.asks.load_full_init()?,
     };
     orderbook.init();
 
-    let mut event_heap = ctx.accounts.event_heap.load_init()?;
+    let mut event_heap = ctx.accounts.event_heap.load_full_init()?;
     event_heap.init();
 
     emit_cpi!(MarketMetaDataLog {
@@ -3637,6 +5024,8 @@ pub fn create_market(
         quote_decimals: ctx.accounts.quote_mint.decimals,
         base_lot_size,
         quote_lot_size,
+        event_heap_penalty,
+        event_heap_penalty_policy: event_heap_penalty_policy.into(),
     });
 
     Ok(())
@@ -3691,7 +5080,7 @@ pub fn create_open_orders_indexer(ctx: Context<CreateOpenOrdersIndexer>) -> Resu
 // File: openbook-v2/programs/openbook-v2/src/instructions/deposit.rs
 use crate::accounts_ix::Deposit;
 use crate::error::*;
-use crate::logs::{emit_stack, DepositLog};
+use crate::logs::{event_emitter, DepositLog};
 use crate::token_utils::*;
 use anchor_lang::prelude::*;
 
@@ -3703,33 +5092,48 @@ pub fn deposit(ctx: Context<Deposit>, base_amount: u64, quote_amount: u64) -> Re
         OpenBookError::MarketHasExpired
     );
 
-    token_transfer(
-        base_amount,
-        &ctx.accounts.token_program,
-        &ctx.accounts.user_base_account,
-        &ctx.accounts.market_base_vault,
-        &ctx.accounts.owner,
-    )?;
+    for (amount, user_account, market_vault) in [
+        (
+            base_amount,
+            &ctx.accounts.user_base_account,
+            &ctx.accounts.market_base_vault,
+        ),
+        (
+            quote_amount,
+            &ctx.accounts.user_quote_account,
+            &ctx.accounts.market_quote_vault,
+        ),
+    ] {
+        match user_account {
+            Some(user_account) => token_transfer(
+                amount,
+                &ctx.accounts.token_program,
+                user_account,
+                market_vault,
+                &ctx.accounts.owner,
+            )?,
+            None => native_transfer(
+                amount,
+                system_program_for_native(&ctx.accounts.system_program)?,
+                &ctx.accounts.token_program,
+                native_payer(&ctx.accounts.native_payer)?,
+                market_vault,
+            )?,
+        }
+    }
+
     open_orders_account.position.base_free_native += base_amount;
     market.base_deposit_total += base_amount;
-
-    token_transfer(
-        quote_amount,
-        &ctx.accounts.token_program,
-        &ctx.accounts.user_quote_account,
-        &ctx.accounts.market_quote_vault,
-        &ctx.accounts.owner,
-    )?;
     open_orders_account.position.quote_free_native += quote_amount;
     market.quote_deposit_total += quote_amount;
 
     if base_amount > 0 || quote_amount > 0 {
-        emit_stack(DepositLog {
+        event_emitter!(ctx).emit(DepositLog {
             open_orders_account: ctx.accounts.open_orders_account.key(),
             signer: ctx.accounts.owner.key(),
             base_amount,
             quote_amount,
-        });
+        })?;
     }
 
     Ok(())
@@ -3739,7 +5143,7 @@ pub fn deposit(ctx: Context<Deposit>, base_amount: u64, quote_amount: u64) -> Re
 // File: openbook-v2/programs/openbook-v2/src/instructions/edit_order.rs
 use crate::accounts_ix::*;
 use crate::error::*;
-use crate::state::Order;
+use crate::state::{delegate_permissions, open_orders_signer_allowed, Order};
 use anchor_lang::prelude::*;
 
 pub fn edit_order<'c: 'info, 'info>(
@@ -3754,6 +5158,16 @@ pub fn edit_order<'c: 'info, 'info>(
         0,
         OpenBookError::InvalidInputCancelSize
     );
+    // PlaceOrder only requires the place permission of a delegate
+    require!(
+        open_orders_signer_allowed(
+            &ctx.accounts.open_orders_account,
+            &ctx.accounts.open_orders_delegate,
+            ctx.accounts.signer.key(),
+            delegate_permissions::CANCEL,
+        )?,
+        OpenBookError::NoOwnerOrDelegate
+    );
 
     let leaf_node_quantity = crate::instructions::cancel_order_by_client_order_id(
         Context::new(
@@ -3776,7 +5190,136 @@ pub fn edit_order<'c: 'info, 'info>(
 }
 
 
+// File: openbook-v2/programs/openbook-v2/src/instructions/get_best_bid_ask.rs
+use anchor_lang::prelude::*;
+
+use crate::accounts_ix::*;
+use crate::BookPrices;
+use crate::state::BookSideLoader;
+
+pub fn get_best_bid_ask(ctx: Context<ViewBook>) -> Result<BookPrices> {
+    let clock = Clock::get()?;
+    let now_ts: u64 = clock.unix_timestamp.try_into().unwrap();
+    let oracle_price_lots = ctx.accounts.oracle_price_lots(clock.slot)?;
+
+    let bids = ctx.accounts.bids.load_full()?;
+    let asks = ctx.accounts.asks.load_full()?;
+
+    Ok(BookPrices {
+        bid_price_lots: bids.best_price(now_ts, oracle_price_lots),
+        ask_price_lots: asks.best_price(now_ts, oracle_price_lots),
+    })
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/instructions/get_depth.rs
+use anchor_lang::prelude::*;
+
+use crate::accounts_ix::*;
+use crate::state::*;
+use crate::{BookDepth, BookLevel};
+
+/// Each level takes 16 bytes of return data, which is capped at 1024 bytes
+pub const MAX_DEPTH_LEVELS: u8 = 30;
+
+pub fn get_depth(ctx: Context<ViewBook>, levels: u8) -> Result<BookDepth> {
+    let levels = levels.min(MAX_DEPTH_LEVELS) as usize;
+
+    let clock = Clock::get()?;
+    let now_ts: u64 = clock.unix_timestamp.try_into().unwrap();
+    let oracle_price_lots = ctx.accounts.oracle_price_lots(clock.slot)?;
+
+    let bids = ctx.accounts.bids.load_full()?;
+    let asks = ctx.accounts.asks.load_full()?;
+
+    let to_levels = |book_side: &BookSideLoadedRefCell| -> Vec<BookLevel> {
+        book_side
+            .depth(levels, now_ts, oracle_price_lots)
+            .into_iter()
+            .map(|(price_lots, base_lots)| BookLevel {
+                price_lots,
+                base_lots,
+            })
+            .collect()
+    };
+
+    Ok(BookDepth {
+        bids: to_levels(&bids),
+        asks: to_levels(&asks),
+    })
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/instructions/get_impact_price.rs
+use anchor_lang::prelude::*;
+
+use crate::accounts_ix::*;
+use crate::error::*;
+use crate::BookPrices;
+use crate::state::BookSideLoader;
+
+pub fn get_impact_price(ctx: Context<ViewBook>, base_lots: i64) -> Result<BookPrices> {
+    require_gt!(base_lots, 0, OpenBookError::InvalidInputLots);
+
+    let clock = Clock::get()?;
+    let now_ts: u64 = clock.unix_timestamp.try_into().unwrap();
+    let oracle_price_lots = ctx.accounts.oracle_price_lots(clock.slot)?;
+
+    let bids = ctx.accounts.bids.load_full()?;
+    let asks = ctx.accounts.asks.load_full()?;
+
+    Ok(BookPrices {
+        bid_price_lots: bids.impact_price(base_lots, now_ts, oracle_price_lots),
+        ask_price_lots: asks.impact_price(base_lots, now_ts, oracle_price_lots),
+    })
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/instructions/migrate_market.rs
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+
+use crate::accounts_ix::*;
+use crate::error::OpenBookError;
+use crate::state::*;
+
+pub fn migrate_market(ctx: Context<MigrateMarket>) -> Result<()> {
+    let account_info = ctx.accounts.market.to_account_info();
+    let new_space = 8 + std::mem::size_of::<Market>();
+    require_gt!(
+        new_space,
+        account_info.data_len(),
+        OpenBookError::MarketAlreadyMigrated
+    );
+
+    let new_minimum_balance = Rent::get()?.minimum_balance(new_space);
+    let lamports = account_info.lamports();
+    if new_minimum_balance > lamports {
+        system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.payer.to_account_info(),
+                    to: account_info.clone(),
+                },
+            ),
+            new_minimum_balance - lamports,
+        )?;
+    }
+    account_info.realloc(new_space, true)?;
+
+    // The fields added since the market was created are all zero, which keeps its behavior,
+    // except for the event heap penalty that used to be a constant
+    ctx.accounts.market.load_mut()?.event_heap_penalty = PENALTY_EVENT_HEAP;
+
+    Ok(())
+}
+
+
 // File: openbook-v2/programs/openbook-v2/src/instructions/mod.rs
+pub use add_delegate::*;
+pub use add_to_allowlist::*;
+pub use add_to_denylist::*;
 pub use cancel_all_and_place_orders::*;
 pub use cancel_all_orders::*;
 pub use cancel_order::*;
@@ -3790,18 +5333,38 @@ pub use create_open_orders_account::*;
 pub use create_open_orders_indexer::*;
 pub use deposit::*;
 pub use edit_order::*;
+pub use get_best_bid_ask::*;
+pub use get_depth::*;
+pub use get_impact_price::*;
+pub use migrate_market::*;
 pub use place_order::*;
 pub use place_take_order::*;
 pub use prune_orders::*;
+pub use purge_expired_orders::*;
+pub use remove_delegate::*;
+pub use remove_from_allowlist::*;
+pub use remove_from_denylist::*;
+pub use resize_open_orders_account::*;
 pub use set_delegate::*;
+pub use set_delegate_permissions::*;
+pub use set_exposure_limits::*;
 pub use set_market_expired::*;
+pub use set_self_trade_scope::*;
 pub use settle_funds::*;
 pub use settle_funds_expired::*;
 pub use stub_oracle_close::*;
 pub use stub_oracle_create::*;
 pub use stub_oracle_set::*;
 pub use sweep_fees::*;
-
+pub use swap::*;
+pub use swap_exact_out::*;
+pub use swap_route::*;
+pub use transfer_free_balance::*;
+pub use withdraw::*;
+
+mod add_delegate;
+mod add_to_allowlist;
+mod add_to_denylist;
 mod cancel_all_and_place_orders;
 mod cancel_all_orders;
 mod cancel_order;
@@ -3815,17 +5378,34 @@ mod create_open_orders_account;
 mod create_open_orders_indexer;
 mod deposit;
 mod edit_order;
+mod get_best_bid_ask;
+mod get_depth;
+mod get_impact_price;
+mod migrate_market;
 mod place_order;
 mod place_take_order;
 mod prune_orders;
+mod purge_expired_orders;
+mod remove_delegate;
+mod remove_from_allowlist;
+mod remove_from_denylist;
+mod resize_open_orders_account;
 mod set_delegate;
+mod set_delegate_permissions;
+mod set_exposure_limits;
 mod set_market_expired;
+mod set_self_trade_scope;
 mod settle_funds;
 mod settle_funds_expired;
 mod stub_oracle_close;
 mod stub_oracle_create;
 mod stub_oracle_set;
 mod sweep_fees;
+mod swap;
+mod swap_exact_out;
+mod swap_route;
+mod transfer_free_balance;
+mod withdraw;
 
 
 // File: openbook-v2/programs/openbook-v2/src/instructions/place_order.rs
@@ -3835,6 +5415,8 @@ use std::cmp;
 use crate::accounts_ix::*;
 use crate::accounts_zerocopy::AccountInfoRef;
 use crate::error::*;
+use crate::instructions::pay_posting_bonds;
+use crate::logs::event_emitter;
 use crate::state::*;
 use crate::token_utils::*;
 
@@ -3851,7 +5433,7 @@ pub fn place_order<'c: 'info, 'info>(
         OpenBookError::InvalidInputLots
     );
 
-    let mut open_orders_account = ctx.accounts.open_orders_account.load_mut()?;
+    let mut open_orders_account = ctx.accounts.open_orders_account.load_full_mut()?;
     let open_orders_account_pk = ctx.accounts.open_orders_account.key();
 
     let clock = Clock::get()?;
@@ -3868,15 +5450,16 @@ pub fn place_order<'c: 'info, 'info>(
     );
 
     let mut book = Orderbook {
-        bids: ctx.accounts.bids.load_mut()?,
-        asks: ctx.accounts.asks.load_mut()?,
+        bids: ctx.accounts.bids.load_full_mut()?,
+        asks: ctx.accounts.asks.load_full_mut()?,
     };
-    let mut event_heap = ctx.accounts.event_heap.load_mut()?;
+    let mut event_heap = ctx.accounts.event_heap.load_full_mut()?;
     let event_heap_size_before = event_heap.len();
 
     let now_ts: u64 = clock.unix_timestamp.try_into().unwrap();
+    let emitter = event_emitter!(ctx);
 
-    let oracle_price_lots = market.oracle_price_lots(
+    let oracle_price_lots = market.update_oracle_price_lots(
         AccountInfoRef::borrow_some(ctx.accounts.oracle_a.as_ref())?.as_ref(),
         AccountInfoRef::borrow_some(ctx.accounts.oracle_b.as_ref())?.as_ref(),
         clock.slot,
@@ -3895,13 +5478,14 @@ pub fn place_order<'c: 'info, 'info>(
         &order,
         &mut market,
         &ctx.accounts.market.key(),
-        &mut event_heap,
+        &mut event_heap.borrow_mut(),
         oracle_price_lots,
-        Some(&mut open_orders_account),
+        Some(&mut open_orders_account.borrow_mut()),
         &open_orders_account_pk,
         now_ts,
         limit,
         ctx.remaining_accounts,
+        &emitter,
     )?;
 
     let position = &mut open_orders_account.position;
@@ -3936,17 +5520,66 @@ pub fn place_order<'c: 'info, 'info>(
         }
     };
 
-    if event_heap.len() > event_heap_size_before {
-        position.penalty_heap_count += 1;
+    let mut immediate_penalty = 0;
+    let new_events = event_heap.len() - event_heap_size_before;
+    if new_events > 0 {
+        event_heap.set_newest_crank_rewards(new_events, market.crank_reward_per_event(new_events));
+        match market.event_heap_penalty_policy() {
+            EventHeapPenaltyPolicy::Deferred => position.penalty_heap_count += 1,
+            EventHeapPenaltyPolicy::Immediate => {
+                immediate_penalty = market.event_heap_penalty;
+                market.add_to_crank_reward_pool(immediate_penalty)?;
+            }
+        }
     }
 
-    token_transfer(
-        deposit_amount,
-        &ctx.accounts.token_program,
-        &ctx.accounts.user_token_account,
-        &ctx.accounts.market_vault,
-        &ctx.accounts.signer,
-    )?;
+    let posting_bond = if order_id.is_some() {
+        market.posting_bond
+    } else {
+        0
+    };
+
+    match &ctx.accounts.user_token_account {
+        Some(user_token_account) => token_transfer(
+            deposit_amount,
+            &ctx.accounts.token_program,
+            user_token_account,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.signer,
+        )?,
+        None => native_transfer(
+            deposit_amount,
+            system_program_for_native(&ctx.accounts.system_program)?,
+            &ctx.accounts.token_program,
+            native_payer(&ctx.accounts.native_payer)?,
+            &ctx.accounts.market_vault,
+        )?,
+    }
+
+    drop(market);
+
+    if immediate_penalty > 0 {
+        let system_program = ctx
+            .accounts
+            .system_program
+            .as_ref()
+            .ok_or_else(|| error!(OpenBookError::MissingSystemProgramForPenalty))?;
+        let penalty_payer = ctx
+            .accounts
+            .penalty_payer
+            .as_ref()
+            .ok_or_else(|| error!(OpenBookError::MissingPenaltyPayer))?;
+        system_program_transfer(
+            immediate_penalty,
+            system_program,
+            penalty_payer,
+            &ctx.accounts.market,
+        )?;
+    }
+
+    if posting_bond > 0 {
+        pay_posting_bonds(posting_bond, &ctx.accounts.open_orders_account, &ctx.accounts.market)?;
+    }
 
     Ok(order_id)
 }
@@ -3958,15 +5591,17 @@ use anchor_lang::prelude::*;
 use crate::accounts_ix::*;
 use crate::accounts_zerocopy::AccountInfoRef;
 use crate::error::*;
+use crate::logs::{event_emitter, EventEmitter};
 use crate::state::*;
 use crate::token_utils::*;
 
+/// Returns the native amounts moved from and to the user's token accounts
 #[allow(clippy::too_many_arguments)]
 pub fn place_take_order<'c: 'info, 'info>(
     ctx: Context<'_, '_, 'c, 'info, PlaceTakeOrder<'info>>,
     order: Order,
     limit: u8,
-) -> Result<()> {
+) -> Result<(u64, u64)> {
     require_gte!(order.max_base_lots, 0, OpenBookError::InvalidInputLots);
     require_gte!(
         order.max_quote_lots_including_fees,
@@ -3974,32 +5609,121 @@ pub fn place_take_order<'c: 'info, 'info>(
         OpenBookError::InvalidInputLots
     );
 
+    let TakeAmounts {
+        deposit_amount,
+        withdraw_amount,
+        event_heap_penalty,
+    } = take_order(
+        &ctx.accounts.market,
+        &ctx.accounts.bids,
+        &ctx.accounts.asks,
+        &ctx.accounts.event_heap,
+        ctx.accounts.oracle_a.as_ref(),
+        ctx.accounts.oracle_b.as_ref(),
+        &order,
+        &ctx.accounts.signer.key(),
+        limit,
+        ctx.remaining_accounts,
+        &event_emitter!(ctx),
+    )?;
+
+    let market = ctx.accounts.market.load()?;
+    let seeds = market_seeds!(market, ctx.accounts.market.key());
+
+    drop(market);
+
+    system_program_transfer(
+        event_heap_penalty,
+        &ctx.accounts.system_program,
+        &ctx.accounts.penalty_payer,
+        &ctx.accounts.market,
+    )?;
+
+    let (user_deposit_acc, user_withdraw_acc, market_deposit_acc, market_withdraw_acc) =
+        match order.side {
+            Side::Bid => (
+                &ctx.accounts.user_quote_account,
+                &ctx.accounts.user_base_account,
+                &ctx.accounts.market_quote_vault,
+                &ctx.accounts.market_base_vault,
+            ),
+            Side::Ask => (
+                &ctx.accounts.user_base_account,
+                &ctx.accounts.user_quote_account,
+                &ctx.accounts.market_base_vault,
+                &ctx.accounts.market_quote_vault,
+            ),
+        };
+
+    token_transfer(
+        deposit_amount,
+        &ctx.accounts.token_program,
+        user_deposit_acc.as_ref(),
+        market_deposit_acc,
+        &ctx.accounts.signer,
+    )?;
+
+    token_transfer_signed(
+        withdraw_amount,
+        &ctx.accounts.token_program,
+        market_withdraw_acc,
+        user_withdraw_acc.as_ref(),
+        &ctx.accounts.market_authority,
+        seeds,
+    )?;
+
+    Ok((deposit_amount, withdraw_amount))
+}
+
+pub struct TakeAmounts {
+    /// Native amount the taker pays into the market vault
+    pub deposit_amount: u64,
+    /// Native amount the taker receives from the market vault
+    pub withdraw_amount: u64,
+    /// Lamports the taker owes the market because its order added events to the heap
+    pub event_heap_penalty: u64,
+}
+
+/// Matches a take `order` against the book and updates the market deposit totals,
+/// leaving the token transfers to the caller.
+#[allow(clippy::too_many_arguments)]
+pub fn take_order<'c: 'info, 'info>(
+    market_loader: &AccountLoader<'info, Market>,
+    bids: &AccountLoader<'info, BookSide>,
+    asks: &AccountLoader<'info, BookSide>,
+    event_heap: &AccountLoader<'info, EventHeap>,
+    oracle_a: Option<&UncheckedAccount<'info>>,
+    oracle_b: Option<&UncheckedAccount<'info>>,
+    order: &Order,
+    taker: &Pubkey,
+    limit: u8,
+    remaining_accounts: &'c [AccountInfo<'info>],
+    emitter: &EventEmitter,
+) -> Result<TakeAmounts> {
     let clock = Clock::get()?;
 
-    let mut market = ctx.accounts.market.load_mut()?;
+    let mut market = market_loader.load_mut()?;
     require!(
         !market.is_expired(clock.unix_timestamp),
         OpenBookError::MarketHasExpired
     );
 
     let mut book = Orderbook {
-        bids: ctx.accounts.bids.load_mut()?,
-        asks: ctx.accounts.asks.load_mut()?,
+        bids: bids.load_full_mut()?,
+        asks: asks.load_full_mut()?,
     };
 
-    let mut event_heap = ctx.accounts.event_heap.load_mut()?;
+    let mut event_heap = event_heap.load_full_mut()?;
     let event_heap_size_before = event_heap.len();
 
     let now_ts: u64 = clock.unix_timestamp.try_into().unwrap();
 
-    let oracle_price_lots = market.oracle_price_lots(
-        AccountInfoRef::borrow_some(ctx.accounts.oracle_a.as_ref())?.as_ref(),
-        AccountInfoRef::borrow_some(ctx.accounts.oracle_b.as_ref())?.as_ref(),
+    let oracle_price_lots = market.update_oracle_price_lots(
+        AccountInfoRef::borrow_some(oracle_a)?.as_ref(),
+        AccountInfoRef::borrow_some(oracle_b)?.as_ref(),
         clock.slot,
     )?;
 
-    let side = order.side;
-
     let OrderWithAmounts {
         total_base_taken_native,
         total_quote_taken_native,
@@ -4007,22 +5731,23 @@ pub fn place_take_order<'c: 'info, 'info>(
         taker_fees,
         ..
     } = book.new_order(
-        &order,
+        order,
         &mut market,
-        &ctx.accounts.market.key(),
-        &mut event_heap,
+        &market_loader.key(),
+        &mut event_heap.borrow_mut(),
         oracle_price_lots,
         None,
-        &ctx.accounts.signer.key(),
+        taker,
         now_ts,
         limit,
-        ctx.remaining_accounts,
+        remaining_accounts,
+        emitter,
     )?;
 
     // place_take_orders doesnt pay to referrers
     let makers_rebates = taker_fees - referrer_amount;
 
-    let (deposit_amount, withdraw_amount) = match side {
+    let (deposit_amount, withdraw_amount) = match order.side {
         Side::Bid => {
             let total_quote_including_fees = total_quote_taken_native + makers_rebates;
             market.base_deposit_total -= total_base_taken_native;
@@ -4037,53 +5762,20 @@ pub fn place_take_order<'c: 'info, 'info>(
         }
     };
 
-    let seeds = market_seeds!(market, ctx.accounts.market.key());
-
-    drop(market);
-
-    if event_heap.len() > event_heap_size_before {
-        system_program_transfer(
-            PENALTY_EVENT_HEAP,
-            &ctx.accounts.system_program,
-            &ctx.accounts.penalty_payer,
-            &ctx.accounts.market,
-        )?;
-    }
-
-    let (user_deposit_acc, user_withdraw_acc, market_deposit_acc, market_withdraw_acc) = match side
-    {
-        Side::Bid => (
-            &ctx.accounts.user_quote_account,
-            &ctx.accounts.user_base_account,
-            &ctx.accounts.market_quote_vault,
-            &ctx.accounts.market_base_vault,
-        ),
-        Side::Ask => (
-            &ctx.accounts.user_base_account,
-            &ctx.accounts.user_quote_account,
-            &ctx.accounts.market_base_vault,
-            &ctx.accounts.market_quote_vault,
-        ),
+    let new_events = event_heap.len() - event_heap_size_before;
+    let event_heap_penalty = if new_events > 0 {
+        event_heap.set_newest_crank_rewards(new_events, market.crank_reward_per_event(new_events));
+        market.event_heap_penalty
+    } else {
+        0
     };
+    market.add_to_crank_reward_pool(event_heap_penalty)?;
 
-    token_transfer(
+    Ok(TakeAmounts {
         deposit_amount,
-        &ctx.accounts.token_program,
-        user_deposit_acc.as_ref(),
-        market_deposit_acc,
-        &ctx.accounts.signer,
-    )?;
-
-    token_transfer_signed(
         withdraw_amount,
-        &ctx.accounts.token_program,
-        market_withdraw_acc,
-        user_withdraw_acc.as_ref(),
-        &ctx.accounts.market_authority,
-        seeds,
-    )?;
-
-    Ok(())
+        event_heap_penalty,
+    })
 }
 
 
@@ -4092,10 +5784,11 @@ use anchor_lang::prelude::*;
 
 use crate::accounts_ix::*;
 use crate::error::*;
+use crate::logs::event_emitter;
 use crate::state::*;
 
 pub fn prune_orders(ctx: Context<PruneOrders>, limit: u8) -> Result<()> {
-    let mut account = ctx.accounts.open_orders_account.load_mut()?;
+    let mut account = ctx.accounts.open_orders_account.load_full_mut()?;
     let market = ctx.accounts.market.load()?;
 
     require!(
@@ -4104,55 +5797,287 @@ pub fn prune_orders(ctx: Context<PruneOrders>, limit: u8) -> Result<()> {
     );
 
     let mut book = Orderbook {
-        bids: ctx.accounts.bids.load_mut()?,
-        asks: ctx.accounts.asks.load_mut()?,
+        bids: ctx.accounts.bids.load_full_mut()?,
+        asks: ctx.accounts.asks.load_full_mut()?,
     };
 
-    book.cancel_all_orders(&mut account, *market, limit, None, None)?;
+    book.cancel_all_orders(
+        &mut account.borrow_mut(),
+        *market,
+        limit,
+        None,
+        None,
+        &event_emitter!(ctx),
+    )?;
 
     Ok(())
 }
 
 
-// File: openbook-v2/programs/openbook-v2/src/instructions/set_delegate.rs
+// File: openbook-v2/programs/openbook-v2/src/instructions/purge_expired_orders.rs
 use anchor_lang::prelude::*;
 
 use crate::accounts_ix::*;
-use crate::logs::{emit_stack, SetDelegateLog};
-use crate::pubkey_option::NonZeroPubkeyOption;
-
-pub fn set_delegate(ctx: Context<SetDelegate>) -> Result<()> {
-    let mut account = ctx.accounts.open_orders_account.load_mut()?;
+use crate::logs::event_emitter;
+use crate::state::*;
 
-    let delegate_account: NonZeroPubkeyOption = ctx
-        .accounts
-        .delegate_account
-        .as_ref()
-        .map(|account| account.key())
-        .into();
+pub fn purge_expired_orders(ctx: Context<PurgeExpiredOrders>, limit: u8) -> Result<()> {
+    let market = ctx.accounts.market.load()?;
+    let mut book = Orderbook {
+        bids: ctx.accounts.bids.load_full_mut()?,
+        asks: ctx.accounts.asks.load_full_mut()?,
+    };
+    let mut event_heap = ctx.accounts.event_heap.load_full_mut()?;
+    let now_ts: u64 = Clock::get()?.unix_timestamp.try_into().unwrap();
 
-    account.delegate = delegate_account;
+    // The bounty is a capped slice of the purged orders' own bonds, which the market holds,
+    // so the crank reward pool stays with the events takers paid for
+    let bounty_per_order = market.posting_bond.min(CRANK_REWARD_PER_EVENT);
+    let purged = book.purge_expired_orders(
+        &ctx.accounts.market.key(),
+        &mut event_heap.borrow_mut(),
+        now_ts,
+        limit,
+        bounty_per_order,
+        &event_emitter!(ctx),
+    )?;
 
-    emit_stack(SetDelegateLog {
-        open_orders_account: ctx.accounts.open_orders_account.key(),
-        delegate: delegate_account.into(),
-    });
+    let bounty = purged as u64 * bounty_per_order;
+    if bounty > 0 {
+        **ctx.accounts.market.to_account_info().try_borrow_mut_lamports()? -= bounty;
+        **ctx.accounts.cranker.try_borrow_mut_lamports()? += bounty;
+    }
 
     Ok(())
 }
 
 
-// File: openbook-v2/programs/openbook-v2/src/instructions/set_market_expired.rs
-use crate::accounts_ix::*;
-use crate::error::OpenBookError;
+// File: openbook-v2/programs/openbook-v2/src/instructions/remove_delegate.rs
 use anchor_lang::prelude::*;
 
-pub fn set_market_expired(ctx: Context<SetMarketExpired>) -> Result<()> {
-    let mut market = ctx.accounts.market.load_mut()?;
-    require!(
-        !market.is_expired(Clock::get()?.unix_timestamp),
-        OpenBookError::MarketHasExpired
-    );
+use crate::accounts_ix::*;
+use crate::logs::{event_emitter, RemoveDelegateLog};
+
+pub fn remove_delegate(ctx: Context<RemoveDelegate>) -> Result<()> {
+    let record = ctx.accounts.open_orders_delegate.load()?;
+
+    event_emitter!(ctx).emit(RemoveDelegateLog {
+        open_orders_account: record.open_orders_account,
+        delegate: record.delegate,
+    })?;
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/instructions/remove_from_allowlist.rs
+use anchor_lang::prelude::*;
+
+use crate::accounts_ix::*;
+use crate::logs::{event_emitter, RemoveFromAllowlistLog};
+
+pub fn remove_from_allowlist(ctx: Context<RemoveFromAllowlist>, entry: Pubkey) -> Result<()> {
+    event_emitter!(ctx).emit(RemoveFromAllowlistLog {
+        market: ctx.accounts.market.key(),
+        entry,
+    })?;
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/instructions/remove_from_denylist.rs
+use anchor_lang::prelude::*;
+
+use crate::accounts_ix::*;
+use crate::logs::{event_emitter, RemoveFromDenylistLog};
+
+pub fn remove_from_denylist(ctx: Context<RemoveFromDenylist>, entry: Pubkey) -> Result<()> {
+    event_emitter!(ctx).emit(RemoveFromDenylistLog {
+        market: ctx.accounts.market.key(),
+        entry,
+    })?;
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/instructions/resize_open_orders_account.rs
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+
+use crate::accounts_ix::*;
+use crate::error::OpenBookError;
+use crate::state::*;
+
+pub fn resize_open_orders_account(
+    ctx: Context<ResizeOpenOrdersAccount>,
+    capacity: u16,
+) -> Result<()> {
+    let capacity = capacity as usize;
+    require!(
+        (MAX_OPEN_ORDERS..=MAX_OPEN_ORDERS_CAPACITY).contains(&capacity),
+        OpenBookError::InvalidOpenOrdersCapacity
+    );
+
+    let old_capacity = {
+        let open_orders_account = ctx.accounts.open_orders_account.load_full()?;
+        require!(
+            open_orders_account
+                .all_orders()
+                .skip(capacity)
+                .all(|oo| oo.is_free()),
+            OpenBookError::OpenOrdersSlotsInUse
+        );
+        open_orders_account.capacity()
+    };
+
+    // Move the rent exempt minimum along with the size: top up from the payer when growing,
+    // refund the payer when shrinking. Lamports above it pay posting bonds and stay put.
+    let account_info = ctx.accounts.open_orders_account.to_account_info();
+    let new_space = OpenOrdersAccount::space_for_capacity(capacity);
+    let rent = Rent::get()?;
+    let new_minimum_balance = rent.minimum_balance(new_space);
+    let old_minimum_balance = rent.minimum_balance(account_info.data_len());
+    if new_minimum_balance > old_minimum_balance {
+        system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.payer.to_account_info(),
+                    to: account_info.clone(),
+                },
+            ),
+            new_minimum_balance - old_minimum_balance,
+        )?;
+    } else if old_minimum_balance > new_minimum_balance {
+        let excess = old_minimum_balance - new_minimum_balance;
+        **account_info.try_borrow_mut_lamports()? -= excess;
+        **ctx.accounts.payer.try_borrow_mut_lamports()? += excess;
+    }
+    account_info.realloc(new_space, false)?;
+
+    // The header has to match the new size before the slots can be loaded
+    ctx.accounts.open_orders_account.load_mut()?.extra_order_slots =
+        (capacity - MAX_OPEN_ORDERS) as u16;
+
+    let mut open_orders_account = ctx.accounts.open_orders_account.load_full_mut()?;
+    for slot in old_capacity..capacity {
+        *open_orders_account.open_order_mut_by_raw_index(slot) = OpenOrder::default();
+    }
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/instructions/set_delegate.rs
+use anchor_lang::prelude::*;
+
+use crate::accounts_ix::*;
+use crate::logs::{event_emitter, SetDelegateLog};
+use crate::pubkey_option::NonZeroPubkeyOption;
+
+pub fn set_delegate(ctx: Context<SetDelegate>) -> Result<()> {
+    let mut account = ctx.accounts.open_orders_account.load_mut()?;
+
+    let delegate_account: NonZeroPubkeyOption = ctx
+        .accounts
+        .delegate_account
+        .as_ref()
+        .map(|account| account.key())
+        .into();
+
+    account.delegate = delegate_account;
+    // a new delegate starts with full permissions, like before they could be scoped
+    account.position.delegate_permissions = 0;
+    account.position.delegate_expiry_timestamp = 0;
+
+    event_emitter!(ctx).emit(SetDelegateLog {
+        open_orders_account: ctx.accounts.open_orders_account.key(),
+        delegate: delegate_account.into(),
+    })?;
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/instructions/set_delegate_permissions.rs
+use anchor_lang::prelude::*;
+
+use crate::accounts_ix::*;
+use crate::error::OpenBookError;
+use crate::logs::{event_emitter, SetDelegatePermissionsLog};
+use crate::state::delegate_permissions;
+
+pub fn set_delegate_permissions(
+    ctx: Context<SetDelegatePermissions>,
+    permissions: u8,
+    expiry_timestamp: u64,
+) -> Result<()> {
+    require!(
+        delegate_permissions::is_valid(permissions),
+        OpenBookError::InvalidDelegatePermissions
+    );
+
+    let mut account = ctx.accounts.open_orders_account.load_mut()?;
+    account.position.delegate_permissions = permissions;
+    account.position.delegate_expiry_timestamp = expiry_timestamp;
+
+    event_emitter!(ctx).emit(SetDelegatePermissionsLog {
+        open_orders_account: ctx.accounts.open_orders_account.key(),
+        delegate: account.delegate.into(),
+        permissions,
+        expiry_timestamp,
+    })?;
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/instructions/set_exposure_limits.rs
+use anchor_lang::prelude::*;
+
+use crate::accounts_ix::*;
+use crate::error::OpenBookError;
+use crate::logs::{event_emitter, SetExposureLimitsLog};
+
+pub fn set_exposure_limits(
+    ctx: Context<SetExposureLimits>,
+    max_bids_quote_lots: i64,
+    max_asks_base_lots: i64,
+    max_open_orders: u16,
+) -> Result<()> {
+    require_gte!(max_bids_quote_lots, 0, OpenBookError::InvalidInputLots);
+    require_gte!(max_asks_base_lots, 0, OpenBookError::InvalidInputLots);
+
+    let mut account = ctx.accounts.open_orders_account.load_mut()?;
+    account.position.max_bids_quote_lots = max_bids_quote_lots;
+    account.position.max_asks_base_lots = max_asks_base_lots;
+    account.position.max_open_orders = max_open_orders;
+
+    event_emitter!(ctx).emit(SetExposureLimitsLog {
+        market: account.market,
+        open_orders_account: ctx.accounts.open_orders_account.key(),
+        max_bids_quote_lots,
+        max_asks_base_lots,
+        max_open_orders,
+    })?;
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/instructions/set_market_expired.rs
+use crate::accounts_ix::*;
+use crate::error::OpenBookError;
+use anchor_lang::prelude::*;
+
+pub fn set_market_expired(ctx: Context<SetMarketExpired>) -> Result<()> {
+    let mut market = ctx.accounts.market.load_mut()?;
+    require!(
+        !market.is_expired(Clock::get()?.unix_timestamp),
+        OpenBookError::MarketHasExpired
+    );
 
     market.time_expiry = -1;
 
@@ -4160,11 +6085,35 @@ pub fn set_market_expired(ctx: Context<SetMarketExpired>) -> Result<()> {
 }
 
 
+// File: openbook-v2/programs/openbook-v2/src/instructions/set_self_trade_scope.rs
+use anchor_lang::prelude::*;
+
+use crate::accounts_ix::*;
+use crate::logs::{event_emitter, SetSelfTradeScopeLog};
+use crate::state::SelfTradeScope;
+
+pub fn set_self_trade_scope(
+    ctx: Context<SetSelfTradeScope>,
+    scope: SelfTradeScope,
+) -> Result<()> {
+    let mut account = ctx.accounts.open_orders_account.load_mut()?;
+    account.position.self_trade_scope = scope.into();
+
+    event_emitter!(ctx).emit(SetSelfTradeScopeLog {
+        open_orders_account: ctx.accounts.open_orders_account.key(),
+        self_trade_scope: scope.into(),
+    })?;
+
+    Ok(())
+}
+
+
 // File: openbook-v2/programs/openbook-v2/src/instructions/settle_funds.rs
 use anchor_lang::prelude::*;
 
 use crate::accounts_ix::*;
-use crate::logs::emit_stack;
+use crate::error::OpenBookError;
+use crate::logs::event_emitter;
 use crate::logs::SettleFundsLog;
 use crate::state::*;
 use crate::token_utils::*;
@@ -4172,6 +6121,7 @@ use crate::token_utils::*;
 pub fn settle_funds<'info>(ctx: Context<'_, '_, '_, 'info, SettleFunds<'info>>) -> Result<()> {
     let mut open_orders_account = ctx.accounts.open_orders_account.load_mut()?;
     let mut market = ctx.accounts.market.load_mut()?;
+    let market_key = ctx.accounts.market.key();
 
     let mut roundoff_maker_fees = 0;
 
@@ -4198,15 +6148,13 @@ pub fn settle_funds<'info>(ctx: Context<'_, '_, '_, 'info, SettleFunds<'info>>)
 
     drop(market);
 
-    if pa.penalty_heap_count > 0 {
-        system_program_transfer(
-            pa.penalty_heap_count * PENALTY_EVENT_HEAP,
-            &ctx.accounts.system_program,
-            &ctx.accounts.penalty_payer,
-            &ctx.accounts.market,
-        )?;
-        pa.penalty_heap_count = 0;
-    }
+    charge_penalty_and_refund_bonds(
+        pa,
+        &ctx.accounts.market,
+        &ctx.accounts.open_orders_account,
+        &ctx.accounts.penalty_payer,
+        &ctx.accounts.system_program,
+    )?;
 
     if let Some(referrer_account) = &ctx.accounts.referrer_account {
         token_transfer_signed(
@@ -4219,31 +6167,74 @@ pub fn settle_funds<'info>(ctx: Context<'_, '_, '_, 'info, SettleFunds<'info>>)
         )?;
     }
 
-    token_transfer_signed(
-        pa.base_free_native,
-        &ctx.accounts.token_program,
-        &ctx.accounts.market_base_vault,
-        &ctx.accounts.user_base_account,
-        &ctx.accounts.market_authority,
-        seeds,
-    )?;
+    for (amount, user_account, market_vault) in [
+        (
+            pa.base_free_native,
+            &ctx.accounts.user_base_account,
+            &ctx.accounts.market_base_vault,
+        ),
+        (
+            pa.quote_free_native,
+            &ctx.accounts.user_quote_account,
+            &ctx.accounts.market_quote_vault,
+        ),
+    ] {
+        match user_account {
+            Some(user_account) => token_transfer_signed(
+                amount,
+                &ctx.accounts.token_program,
+                market_vault,
+                user_account,
+                &ctx.accounts.market_authority,
+                seeds,
+            )?,
+            None => {
+                let (native_mint, native_settle_account, native_destination) = match (
+                    &ctx.accounts.native_mint,
+                    &ctx.accounts.native_settle_account,
+                    &ctx.accounts.native_destination,
+                ) {
+                    (Some(mint), Some(account), Some(destination)) => (mint, account, destination),
+                    _ => return Err(OpenBookError::InvalidNativeTransfer.into()),
+                };
+                let (native_settle_address, native_settle_bump) = Pubkey::find_program_address(
+                    &[b"NativeSettle".as_ref(), market_key.as_ref()],
+                    ctx.program_id,
+                );
+                require_keys_eq!(
+                    native_settle_account.key(),
+                    native_settle_address,
+                    OpenBookError::InvalidNativeTransfer
+                );
 
-    token_transfer_signed(
-        pa.quote_free_native,
-        &ctx.accounts.token_program,
-        &ctx.accounts.market_quote_vault,
-        &ctx.accounts.user_quote_account,
-        &ctx.accounts.market_authority,
-        seeds,
-    )?;
+                native_transfer_signed(
+                    amount,
+                    &ctx.accounts.token_program,
+                    &ctx.accounts.system_program,
+                    market_vault,
+                    &native_mint.to_account_info(),
+                    native_settle_account,
+                    &[
+                        b"NativeSettle".as_ref(),
+                        market_key.as_ref(),
+                        &[native_settle_bump],
+                    ],
+                    &ctx.accounts.penalty_payer,
+                    native_destination,
+                    &ctx.accounts.market_authority,
+                    seeds,
+                )?;
+            }
+        }
+    }
 
-    emit_stack(SettleFundsLog {
+    event_emitter!(ctx).emit(SettleFundsLog {
         open_orders_account: ctx.accounts.open_orders_account.key(),
         base_native: pa.base_free_native,
         quote_native: pa.quote_free_native,
         referrer_rebate,
         referrer: ctx.accounts.referrer_account.as_ref().map(|acc| acc.key()),
-    });
+    })?;
 
     pa.base_free_native = 0;
     pa.quote_free_native = 0;
@@ -4252,6 +6243,55 @@ pub fn settle_funds<'info>(ctx: Context<'_, '_, '_, 'info, SettleFunds<'info>>)
     Ok(())
 }
 
+/// Pays posting bonds out of the lamports an open orders account holds above its rent exempt
+/// minimum. Refunds go back to the same account, so whoever funded it doesn't matter.
+pub(crate) fn pay_posting_bonds<'info>(
+    bonds: u64,
+    open_orders_account: &AccountLoader<'info, OpenOrdersAccount>,
+    market: &AccountLoader<'info, Market>,
+) -> Result<()> {
+    let open_orders_account = open_orders_account.to_account_info();
+    let rent_exempt = Rent::get()?.minimum_balance(open_orders_account.data_len());
+    let spare_lamports = open_orders_account.lamports().saturating_sub(rent_exempt);
+    require_gte!(spare_lamports, bonds, OpenBookError::InsufficientBondLamports);
+    **open_orders_account.try_borrow_mut_lamports()? -= bonds;
+    **market.to_account_info().try_borrow_mut_lamports()? += bonds;
+    Ok(())
+}
+
+/// Charges the deferred event heap penalties of an open orders account to `penalty_payer` and
+/// refunds its posting bonds. Needs to run whenever free funds leave the open orders account.
+pub(crate) fn charge_penalty_and_refund_bonds<'info>(
+    pa: &mut Position,
+    market: &AccountLoader<'info, Market>,
+    open_orders_account: &AccountLoader<'info, OpenOrdersAccount>,
+    penalty_payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    if pa.penalty_heap_count > 0 {
+        let penalty = {
+            let mut market = market.load_mut()?;
+            let penalty = market.event_heap_penalty_for(pa.penalty_heap_count)?;
+            market.add_to_crank_reward_pool(penalty)?;
+            penalty
+        };
+        system_program_transfer(penalty, system_program, penalty_payer, market)?;
+        pa.penalty_heap_count = 0;
+    }
+
+    // Bonds are returned to the open orders account, the owner gets them back on close
+    if pa.posting_bonds_refundable > 0 {
+        let bonds = pa.posting_bonds_refundable;
+        **market.to_account_info().try_borrow_mut_lamports()? -= bonds;
+        **open_orders_account
+            .to_account_info()
+            .try_borrow_mut_lamports()? += bonds;
+        pa.posting_bonds_refundable = 0;
+    }
+
+    Ok(())
+}
+
 
 // File: openbook-v2/programs/openbook-v2/src/instructions/settle_funds_expired.rs
 use crate::accounts_ix::*;
@@ -4322,58 +6362,467 @@ pub fn stub_oracle_set(ctx: Context<StubOracleSet>, price: f64) -> Result<()> {
 }
 
 
-// File: openbook-v2/programs/openbook-v2/src/instructions/sweep_fees.rs
-use crate::state::market_seeds;
+// File: openbook-v2/programs/openbook-v2/src/instructions/swap.rs
 use anchor_lang::prelude::*;
 
 use crate::accounts_ix::*;
-use crate::logs::{emit_stack, SweepFeesLog};
-use crate::token_utils::*;
-
-pub fn sweep_fees(ctx: Context<SweepFees>) -> Result<()> {
-    let mut market = ctx.accounts.market.load_mut()?;
-
-    let amount = market.fees_available;
-    market.fees_available = 0;
-    market.quote_deposit_total -= amount;
-
-    let seeds = market_seeds!(market, ctx.accounts.market.key());
-    drop(market);
+use crate::error::*;
+use crate::instructions::place_take_order;
+use crate::state::*;
 
-    token_transfer_signed(
-        amount,
-        &ctx.accounts.token_program,
-        &ctx.accounts.market_quote_vault,
-        &ctx.accounts.token_receiver_account,
-        &ctx.accounts.market_authority,
-        seeds,
-    )?;
+pub fn swap<'c: 'info, 'info>(
+    ctx: Context<'_, '_, 'c, 'info, PlaceTakeOrder<'info>>,
+    side: Side,
+    amount_in: u64,
+    min_amount_out: u64,
+    limit: u8,
+) -> Result<u64> {
+    let order = swap_order(&ctx.accounts.market.load()?, side, amount_in);
 
-    emit_stack(SweepFeesLog {
-        market: ctx.accounts.market.key(),
-        amount,
-        receiver: ctx.accounts.token_receiver_account.key(),
-    });
+    let (_, amount_out) = place_take_order(ctx, order, limit)?;
+    require_gte!(
+        amount_out,
+        min_amount_out,
+        OpenBookError::SwapSlippageExceeded
+    );
 
-    Ok(())
+    Ok(amount_out)
 }
 
+/// Market order spending at most `amount_in` native tokens of the input side
+pub fn swap_order(market: &Market, side: Side, amount_in: u64) -> Order {
+    // Only the input side is bounded, fees are taken out of the quote lots by new_order
+    let (max_base_lots, max_quote_lots_including_fees) = match side {
+        Side::Bid => (
+            market.max_base_lots(),
+            market.max_quote_lots_from_lamports(amount_in),
+        ),
+        Side::Ask => (
+            market.max_base_lots_from_lamports(amount_in),
+            market.max_quote_lots(),
+        ),
+    };
+    Order {
+        side,
+        max_base_lots,
+        max_quote_lots_including_fees,
+        client_order_id: 0,
+        time_in_force: 0,
+        self_trade_behavior: SelfTradeBehavior::default(),
+        params: OrderParams::Market,
+    }
+}
 
-// File: openbook-v2/programs/openbook-v2/src/lib.rs
-//! A central-limit order book (CLOB) program that targets the Sealevel runtime.
 
-use anchor_lang::prelude::{
-    borsh::{BorshDeserialize, BorshSerialize},
-    *,
-};
+// File: openbook-v2/programs/openbook-v2/src/instructions/swap_exact_out.rs
+use anchor_lang::prelude::*;
 
-declare_id!("opnb2LAfJYbRMAHHvqjCwQxanZn7ReEHp1k81EohpZb");
+use crate::accounts_ix::*;
+use crate::accounts_zerocopy::AccountInfoRef;
+use crate::error::*;
+use crate::instructions::place_take_order;
+use crate::state::*;
 
-#[macro_use]
-pub mod util;
+pub fn swap_exact_out<'c: 'info, 'info>(
+    ctx: Context<'_, '_, 'c, 'info, PlaceTakeOrder<'info>>,
+    side: Side,
+    amount_out: u64,
+    max_amount_in: u64,
+    limit: u8,
+) -> Result<u64> {
+    let order = {
+        let mut market = ctx.accounts.market.load_mut()?;
 
-pub mod accounts_ix;
-pub mod accounts_zerocopy;
+        // The number of base lots to take is fixed upfront, so matching stops exactly there
+        let (max_base_lots, price_lots) = match side {
+            Side::Bid => {
+                require_eq!(
+                    amount_out % market.base_lot_size as u64,
+                    0,
+                    OpenBookError::InvalidInputLotsSize
+                );
+                (market.max_base_lots_from_lamports(amount_out), i64::MAX)
+            }
+            Side::Ask => {
+                let clock = Clock::get()?;
+                let now_ts: u64 = clock.unix_timestamp.try_into().unwrap();
+                let oracle_price_lots = market.update_oracle_price_lots(
+                    AccountInfoRef::borrow_some(ctx.accounts.oracle_a.as_ref())?.as_ref(),
+                    AccountInfoRef::borrow_some(ctx.accounts.oracle_b.as_ref())?.as_ref(),
+                    clock.slot,
+                )?;
+                let book = Orderbook {
+                    bids: ctx.accounts.bids.load_full_mut()?,
+                    asks: ctx.accounts.asks.load_full_mut()?,
+                };
+                let base_lots = book
+                    .base_lots_for_quote_out(&market, amount_out, now_ts, oracle_price_lots)?
+                    .ok_or(OpenBookError::WouldExecutePartially)?;
+                (base_lots, 1)
+            }
+        };
+
+        Order {
+            side,
+            max_base_lots,
+            max_quote_lots_including_fees: market.max_quote_lots(),
+            client_order_id: 0,
+            time_in_force: 0,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            params: OrderParams::FillOrKill { price_lots },
+        }
+    };
+
+    let (amount_in, received) = place_take_order(ctx, order, limit)?;
+    require_eq!(received, amount_out, OpenBookError::WouldExecutePartially);
+    require_gte!(
+        max_amount_in,
+        amount_in,
+        OpenBookError::SwapSlippageExceeded
+    );
+
+    Ok(amount_in)
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/instructions/swap_route.rs
+use anchor_lang::prelude::*;
+
+use crate::accounts_ix::*;
+use crate::error::*;
+use crate::instructions::{swap_order, take_order};
+use crate::logs::event_emitter;
+use crate::state::*;
+use crate::token_utils::*;
+
+/// Side to take on a market to spend `mint_in`, given its base and quote mints
+fn side_for_input(mint_in: Pubkey, base_mint: Pubkey, quote_mint: Pubkey) -> Result<Side> {
+    if mint_in == base_mint {
+        Ok(Side::Ask)
+    } else if mint_in == quote_mint {
+        Ok(Side::Bid)
+    } else {
+        Err(OpenBookError::InvalidRoute.into())
+    }
+}
+
+pub fn swap_route<'c: 'info, 'info>(
+    ctx: Context<'_, '_, 'c, 'info, SwapRoute<'info>>,
+    amount_in: u64,
+    min_amount_out: u64,
+    limit: u8,
+) -> Result<u64> {
+    let accounts = &ctx.accounts;
+    let emitter = event_emitter!(ctx);
+
+    let first_side = side_for_input(
+        accounts.user_input_account.mint,
+        accounts.first_market_base_vault.mint,
+        accounts.first_market_quote_vault.mint,
+    )?;
+    let (first_vault_in, first_vault_out) = match first_side {
+        Side::Bid => (
+            &accounts.first_market_quote_vault,
+            &accounts.first_market_base_vault,
+        ),
+        Side::Ask => (
+            &accounts.first_market_base_vault,
+            &accounts.first_market_quote_vault,
+        ),
+    };
+
+    let second_side = side_for_input(
+        first_vault_out.mint,
+        accounts.second_market_base_vault.mint,
+        accounts.second_market_quote_vault.mint,
+    )?;
+    let (second_vault_in, second_vault_out) = match second_side {
+        Side::Bid => (
+            &accounts.second_market_quote_vault,
+            &accounts.second_market_base_vault,
+        ),
+        Side::Ask => (
+            &accounts.second_market_base_vault,
+            &accounts.second_market_quote_vault,
+        ),
+    };
+
+    require_keys_eq!(
+        accounts.user_intermediate_account.mint,
+        first_vault_out.mint,
+        OpenBookError::InvalidRoute
+    );
+    require_keys_eq!(
+        accounts.user_output_account.mint,
+        second_vault_out.mint,
+        OpenBookError::InvalidRoute
+    );
+
+    let first_order = swap_order(&accounts.first_market.load()?, first_side, amount_in);
+    let first = take_order(
+        &accounts.first_market,
+        &accounts.first_bids,
+        &accounts.first_asks,
+        &accounts.first_event_heap,
+        accounts.first_oracle_a.as_ref(),
+        accounts.first_oracle_b.as_ref(),
+        &first_order,
+        &accounts.signer.key(),
+        limit,
+        ctx.remaining_accounts,
+        &emitter,
+    )?;
+
+    // The intermediate tokens go straight from one market vault to the other
+    let second_order = swap_order(
+        &accounts.second_market.load()?,
+        second_side,
+        first.withdraw_amount,
+    );
+    let second = take_order(
+        &accounts.second_market,
+        &accounts.second_bids,
+        &accounts.second_asks,
+        &accounts.second_event_heap,
+        accounts.second_oracle_a.as_ref(),
+        accounts.second_oracle_b.as_ref(),
+        &second_order,
+        &accounts.signer.key(),
+        limit,
+        ctx.remaining_accounts,
+        &emitter,
+    )?;
+
+    require_gte!(
+        second.withdraw_amount,
+        min_amount_out,
+        OpenBookError::SwapSlippageExceeded
+    );
+
+    for (market, amounts) in [
+        (&accounts.first_market, &first),
+        (&accounts.second_market, &second),
+    ] {
+        system_program_transfer(
+            amounts.event_heap_penalty,
+            &accounts.system_program,
+            &accounts.penalty_payer,
+            market,
+        )?;
+    }
+
+    let first_market = accounts.first_market.load()?;
+    let first_seeds = market_seeds!(first_market, accounts.first_market.key());
+    drop(first_market);
+
+    let second_market = accounts.second_market.load()?;
+    let second_seeds = market_seeds!(second_market, accounts.second_market.key());
+    drop(second_market);
+
+    token_transfer(
+        first.deposit_amount,
+        &accounts.token_program,
+        accounts.user_input_account.as_ref(),
+        first_vault_in.as_ref(),
+        &accounts.signer,
+    )?;
+
+    token_transfer_signed(
+        second.deposit_amount,
+        &accounts.token_program,
+        first_vault_out.as_ref(),
+        second_vault_in.as_ref(),
+        &accounts.first_market_authority,
+        first_seeds,
+    )?;
+
+    token_transfer_signed(
+        first.withdraw_amount - second.deposit_amount,
+        &accounts.token_program,
+        first_vault_out.as_ref(),
+        accounts.user_intermediate_account.as_ref(),
+        &accounts.first_market_authority,
+        first_seeds,
+    )?;
+
+    token_transfer_signed(
+        second.withdraw_amount,
+        &accounts.token_program,
+        second_vault_out.as_ref(),
+        accounts.user_output_account.as_ref(),
+        &accounts.second_market_authority,
+        second_seeds,
+    )?;
+
+    Ok(second.withdraw_amount)
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/instructions/sweep_fees.rs
+use crate::state::market_seeds;
+use anchor_lang::prelude::*;
+
+use crate::accounts_ix::*;
+use crate::logs::{event_emitter, SweepFeesLog};
+use crate::token_utils::*;
+
+pub fn sweep_fees(ctx: Context<SweepFees>) -> Result<()> {
+    let mut market = ctx.accounts.market.load_mut()?;
+
+    let amount = market.fees_available;
+    market.fees_available = 0;
+    market.quote_deposit_total -= amount;
+
+    let seeds = market_seeds!(market, ctx.accounts.market.key());
+    drop(market);
+
+    token_transfer_signed(
+        amount,
+        &ctx.accounts.token_program,
+        &ctx.accounts.market_quote_vault,
+        &ctx.accounts.token_receiver_account,
+        &ctx.accounts.market_authority,
+        seeds,
+    )?;
+
+    event_emitter!(ctx).emit(SweepFeesLog {
+        market: ctx.accounts.market.key(),
+        amount,
+        receiver: ctx.accounts.token_receiver_account.key(),
+    })?;
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/instructions/transfer_free_balance.rs
+use anchor_lang::prelude::*;
+
+use crate::accounts_ix::*;
+use crate::error::OpenBookError;
+use crate::logs::{event_emitter, TransferFreeBalanceLog};
+
+pub fn transfer_free_balance(
+    ctx: Context<TransferFreeBalance>,
+    base_amount: u64,
+    quote_amount: u64,
+) -> Result<()> {
+    let mut source = ctx.accounts.source_open_orders_account.load_mut()?;
+    let mut destination = ctx.accounts.destination_open_orders_account.load_mut()?;
+
+    // Otherwise the penalty could be dodged by settling a fresh account of the owner instead
+    require_eq!(
+        source.position.penalty_heap_count,
+        0,
+        OpenBookError::UnpaidEventHeapPenalty
+    );
+    require!(
+        base_amount <= source.position.base_free_native
+            && quote_amount <= source.position.quote_free_native,
+        OpenBookError::InsufficientFreeBalance
+    );
+
+    // Funds stay in the market vaults, so the market deposit totals are unchanged
+    source.position.base_free_native -= base_amount;
+    source.position.quote_free_native -= quote_amount;
+    destination.position.base_free_native += base_amount;
+    destination.position.quote_free_native += quote_amount;
+
+    if base_amount > 0 || quote_amount > 0 {
+        event_emitter!(ctx).emit(TransferFreeBalanceLog {
+            source_open_orders_account: ctx.accounts.source_open_orders_account.key(),
+            destination_open_orders_account: ctx.accounts.destination_open_orders_account.key(),
+            base_native: base_amount,
+            quote_native: quote_amount,
+        })?;
+    }
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/instructions/withdraw.rs
+use anchor_lang::prelude::*;
+
+use crate::accounts_ix::*;
+use crate::error::OpenBookError;
+use crate::instructions::charge_penalty_and_refund_bonds;
+use crate::logs::{event_emitter, WithdrawLog};
+use crate::state::*;
+use crate::token_utils::*;
+
+pub fn withdraw(ctx: Context<Withdraw>, base_amount: u64, quote_amount: u64) -> Result<()> {
+    let mut open_orders_account = ctx.accounts.open_orders_account.load_mut()?;
+    let mut market = ctx.accounts.market.load_mut()?;
+
+    let pa = &mut open_orders_account.position;
+    require!(
+        base_amount <= pa.base_free_native && quote_amount <= pa.quote_free_native,
+        OpenBookError::InsufficientFreeBalance
+    );
+
+    market.base_deposit_total -= base_amount;
+    market.quote_deposit_total -= quote_amount;
+
+    let seeds = market_seeds!(market, ctx.accounts.market.key());
+
+    drop(market);
+
+    charge_penalty_and_refund_bonds(
+        pa,
+        &ctx.accounts.market,
+        &ctx.accounts.open_orders_account,
+        &ctx.accounts.penalty_payer,
+        &ctx.accounts.system_program,
+    )?;
+
+    token_transfer_signed(
+        base_amount,
+        &ctx.accounts.token_program,
+        &ctx.accounts.market_base_vault,
+        &ctx.accounts.user_base_account,
+        &ctx.accounts.market_authority,
+        seeds,
+    )?;
+    pa.base_free_native -= base_amount;
+
+    token_transfer_signed(
+        quote_amount,
+        &ctx.accounts.token_program,
+        &ctx.accounts.market_quote_vault,
+        &ctx.accounts.user_quote_account,
+        &ctx.accounts.market_authority,
+        seeds,
+    )?;
+    pa.quote_free_native -= quote_amount;
+
+    if base_amount > 0 || quote_amount > 0 {
+        event_emitter!(ctx).emit(WithdrawLog {
+            open_orders_account: ctx.accounts.open_orders_account.key(),
+            base_native: base_amount,
+            quote_native: quote_amount,
+        })?;
+    }
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/lib.rs
+//! A central-limit order book (CLOB) program that targets the Sealevel runtime.
+
+use anchor_lang::prelude::{
+    borsh::{BorshDeserialize, BorshSerialize},
+    *,
+};
+
+declare_id!("opnb2LAfJYbRMAHHvqjCwQxanZn7ReEHp1k81EohpZb");
+
+#[macro_use]
+pub mod util;
+
+pub mod accounts_ix;
+pub mod accounts_zerocopy;
 pub mod error;
 pub mod logs;
 pub mod pubkey_option;
@@ -4389,7 +6838,10 @@ pub mod instructions;
 use accounts_ix::*;
 use accounts_ix::{StubOracleCreate, StubOracleSet};
 use error::*;
-use state::{OracleConfigParams, Order, OrderParams, PlaceOrderType, SelfTradeBehavior, Side};
+use state::{
+    EventHeapPenaltyPolicy, EvictionPolicy, OracleConfigParams, Order, OrderParams,
+    PlaceOrderType, SelfTradeBehavior, SelfTradeScope, Side,
+};
 use std::cmp;
 
 #[cfg(all(not(feature = "no-entrypoint"), not(feature = "enable-gpl")))]
@@ -4400,6 +6852,36 @@ pub mod openbook_v2 {
     use super::*;
 
     /// Create a [`Market`](crate::state::Market) for a given token pair.
+    ///
+    /// The capacities of the book sides and the event heap follow from the sizes their
+    /// accounts were created with, see
+    /// [`BookSide::space_for_capacity`](crate::state::BookSide::space_for_capacity) and
+    /// [`EventHeap::space_for_capacity`](crate::state::EventHeap::space_for_capacity).
+    ///
+    /// `event_heap_penalty` is the amount of lamports a taker pays for every transaction that
+    /// adds to the event heap, at most
+    /// [`MAX_EVENT_HEAP_PENALTY`](crate::state::MAX_EVENT_HEAP_PENALTY).
+    /// `event_heap_penalty_policy` decides whether orders placed through an open orders
+    /// account pay it right away or when settling funds.
+    ///
+    /// Passing an `allowlist_admin` restricts order placement to the owners and open orders
+    /// accounts added with `add_to_allowlist`, as an alternative to an `open_orders_admin`
+    /// co-signing every order. Passing a `denylist_admin` screens open orders account creation,
+    /// order placement and settlement against the owners added with `add_to_denylist`.
+    ///
+    /// `max_bids_quote_lots`, `max_asks_base_lots` and `max_open_orders` cap the resting
+    /// exposure of every open orders account, 0 meaning no limit. The `exposure_admin` can
+    /// override them per account with `set_exposure_limits`.
+    ///
+    /// Order remainders below `min_base_lots` or `min_quote_notional` (in quote lots) aren't
+    /// posted, and resting orders left below them by a partial fill are cancelled.
+    ///
+    /// Every posted order pays a `posting_bond` in lamports, refunded in `settle_funds` once the
+    /// order leaves the book. When a book side is full, `eviction_policy` picks the order that
+    /// makes room for a new one priced at least as well, and the bond of the evicted order is
+    /// forfeited if it had the worst price on the book side. Policies other
+    /// than `WorstPrice` scan the whole book side, which caps its capacity at
+    /// [`MAX_SCANNED_EVICTION_NODES`](crate::state::MAX_SCANNED_EVICTION_NODES).
     #[allow(clippy::too_many_arguments)]
     pub fn create_market(
         ctx: Context<CreateMarket>,
@@ -4410,6 +6892,15 @@ pub mod openbook_v2 {
         maker_fee: i64,
         taker_fee: i64,
         time_expiry: i64,
+        event_heap_penalty: u64,
+        event_heap_penalty_policy: EventHeapPenaltyPolicy,
+        max_bids_quote_lots: i64,
+        max_asks_base_lots: i64,
+        max_open_orders: u16,
+        min_base_lots: i64,
+        min_quote_notional: i64,
+        posting_bond: u64,
+        eviction_policy: EvictionPolicy,
     ) -> Result<()> {
         #[cfg(feature = "enable-gpl")]
         instructions::create_market(
@@ -4421,6 +6912,15 @@ pub mod openbook_v2 {
             maker_fee,
             taker_fee,
             time_expiry,
+            event_heap_penalty,
+            event_heap_penalty_policy,
+            max_bids_quote_lots,
+            max_asks_base_lots,
+            max_open_orders,
+            min_base_lots,
+            min_quote_notional,
+            posting_bond,
+            eviction_policy,
         )?;
         Ok(())
     }
@@ -4433,6 +6933,15 @@ pub mod openbook_v2 {
         Ok(())
     }
 
+    /// Grow a [`Market`](crate::state::Market) created by an earlier version of the program
+    /// to the current account size, paid for by `payer`. Until then such markets can't be
+    /// used: every other instruction fails to load them.
+    pub fn migrate_market(ctx: Context<MigrateMarket>) -> Result<()> {
+        #[cfg(feature = "enable-gpl")]
+        instructions::migrate_market(ctx)?;
+        Ok(())
+    }
+
     /// Create an [`OpenOrdersIndexer`](crate::state::OpenOrdersIndexer) account.
     pub fn create_open_orders_indexer(ctx: Context<CreateOpenOrdersIndexer>) -> Result<()> {
         #[cfg(feature = "enable-gpl")]
@@ -4464,6 +6973,20 @@ pub mod openbook_v2 {
         Ok(())
     }
 
+    /// Change the number of order slots of an
+    /// [`OpenOrdersAccount`](crate::state::OpenOrdersAccount) to `capacity`.
+    ///
+    /// Growing is paid for by `payer`, shrinking refunds the freed rent to it. Only
+    /// free slots can be removed.
+    pub fn resize_open_orders_account(
+        ctx: Context<ResizeOpenOrdersAccount>,
+        capacity: u16,
+    ) -> Result<()> {
+        #[cfg(feature = "enable-gpl")]
+        instructions::resize_open_orders_account(ctx, capacity)?;
+        Ok(())
+    }
+
     /// Place an order.
     ///
     /// Different types of orders have different effects on the order book,
@@ -4479,7 +7002,24 @@ pub mod openbook_v2 {
     ///
     /// `limit` determines the maximum number of orders from the book to fill,
     /// and can be used to limit CU spent. When the limit is reached, processing
-    /// stops and the instruction succeeds.
+    /// stops and the instruction succeeds. Programs built with the `cpi-events` feature
+    /// cap it at what is left of [`CPI_EVENTS_LIMIT`](crate::logs::CPI_EVENTS_LIMIT) for the
+    /// whole instruction, since every event takes an entry of the transaction's instruction
+    /// trace.
+    ///
+    /// On a wrapped SOL side, leaving out `user_token_account` pays with lamports
+    /// of `native_payer` instead.
+    ///
+    /// On markets with [`EventHeapPenaltyPolicy::Immediate`] the `penalty_payer` pays the event
+    /// heap penalty right away, which needs `system_program`. On markets with a posting bond
+    /// the open orders account pays the bond of a posted order out of its lamports above the
+    /// rent exempt minimum, and gets it back on `settle_funds` once the order leaves the book.
+    ///
+    /// `self_trade_behavior` applies to the resting orders of the account's whole self-trade
+    /// group, see [`SelfTradeScope`](crate::state::SelfTradeScope). Accounts default to
+    /// `SelfTradeScope::Owner`, so orders that matched between two accounts of the same owner
+    /// before are now self-trades. Such accounts need `set_self_trade_scope` with
+    /// `SelfTradeScope::Account` to keep trading with each other.
     pub fn place_order<'c: 'info, 'info>(
         ctx: Context<'_, '_, 'c, 'info, PlaceOrder<'info>>,
         args: PlaceOrderArgs,
@@ -4628,6 +7168,11 @@ pub mod openbook_v2 {
     }
 
     /// Place multiple orders
+    ///
+    /// On markets with [`EventHeapPenaltyPolicy::Immediate`] the `penalty_payer` pays the event
+    /// heap penalty right away, which needs `system_program`. On markets with a posting bond
+    /// the open orders account pays the bonds of posted orders out of its lamports above the
+    /// rent exempt minimum, and gets them back on `settle_funds` once the orders leave the book.
     pub fn place_orders<'c: 'info, 'info>(
         ctx: Context<'_, '_, 'c, 'info, CancelAllAndPlaceOrders<'info>>,
         orders_type: PlaceOrderType,
@@ -4679,6 +7224,11 @@ pub mod openbook_v2 {
     }
 
     /// Cancel orders and place multiple orders.
+    ///
+    /// On markets with [`EventHeapPenaltyPolicy::Immediate`] the `penalty_payer` pays the event
+    /// heap penalty right away, which needs `system_program`. On markets with a posting bond
+    /// the open orders account pays the bonds of posted orders out of its lamports above the
+    /// rent exempt minimum, and gets them back on `settle_funds` once the orders leave the book.
     pub fn cancel_all_and_place_orders<'c: 'info, 'info>(
         ctx: Context<'_, '_, 'c, 'info, CancelAllAndPlaceOrders<'info>>,
         orders_type: PlaceOrderType,
@@ -4803,6 +7353,71 @@ pub mod openbook_v2 {
         Ok(())
     }
 
+    /// Swap `amount_in` native tokens against the book, like a market order with
+    /// instant settlement. Uses the same accounts as `place_take_order`.
+    ///
+    /// A [`Side::Bid`](crate::state::Side::Bid) spends quote to buy base, a
+    /// [`Side::Ask`](crate::state::Side::Ask) sells base for quote. Fails if less than
+    /// `min_amount_out` native tokens would be received, otherwise returns the amount out.
+    pub fn swap<'c: 'info, 'info>(
+        ctx: Context<'_, '_, 'c, 'info, PlaceTakeOrder<'info>>,
+        args: SwapArgs,
+    ) -> Result<u64> {
+        #[cfg(feature = "enable-gpl")]
+        return instructions::swap(
+            ctx,
+            args.side,
+            args.amount_in,
+            args.min_amount_out,
+            args.limit,
+        );
+
+        #[cfg(not(feature = "enable-gpl"))]
+        Ok(0)
+    }
+
+    /// Swap against the book so that exactly `amount_out` native tokens are received.
+    /// Uses the same accounts as `place_take_order`.
+    ///
+    /// Buying base, `amount_out` has to be a multiple of the base lot size. Selling base,
+    /// the quote received after fees must be reachable with whole base lots at the
+    /// current bids. Fails if the book can't fill it exactly or if more than
+    /// `max_amount_in` would be paid, otherwise returns the amount paid.
+    pub fn swap_exact_out<'c: 'info, 'info>(
+        ctx: Context<'_, '_, 'c, 'info, PlaceTakeOrder<'info>>,
+        args: SwapExactOutArgs,
+    ) -> Result<u64> {
+        #[cfg(feature = "enable-gpl")]
+        return instructions::swap_exact_out(
+            ctx,
+            args.side,
+            args.amount_out,
+            args.max_amount_in,
+            args.limit,
+        );
+
+        #[cfg(not(feature = "enable-gpl"))]
+        Ok(0)
+    }
+
+    /// Swap `amount_in` native tokens through two markets sharing a mint in one go, e.g.
+    /// SOL/USDC then USDC/BONK. The sides are given by the mints of the token accounts.
+    ///
+    /// The intermediate tokens move directly between the market vaults, except for what is
+    /// left over after rounding to the second market's lots, which goes to
+    /// `user_intermediate_account`. Fails if less than `min_amount_out` native tokens would
+    /// be received, otherwise returns the amount out.
+    pub fn swap_route<'c: 'info, 'info>(
+        ctx: Context<'_, '_, 'c, 'info, SwapRoute<'info>>,
+        args: SwapRouteArgs,
+    ) -> Result<u64> {
+        #[cfg(feature = "enable-gpl")]
+        return instructions::swap_route(ctx, args.amount_in, args.min_amount_out, args.limit);
+
+        #[cfg(not(feature = "enable-gpl"))]
+        Ok(0)
+    }
+
     /// Process up to `limit` [events](crate::state::AnyEvent).
     ///
     /// When a user places a 'take' order, they do not know beforehand which
@@ -4826,7 +7441,12 @@ pub mod openbook_v2 {
     /// the book during a `place_order` invocation, and it is handled by
     /// crediting whatever the maker would have sold (quote token in a bid,
     /// base token in an ask) back to the maker.
-    pub fn consume_events<'c: 'info, 'info>(
+    ///
+    /// Crankers get paid with `consume_events_with_reward`.
+    ///
+    /// Programs built with the `cpi-events` feature consume at most
+    /// [`CPI_EVENTS_LIMIT`](crate::logs::CPI_EVENTS_LIMIT) events per instruction.
+    pub fn consume_events<'c: 'info, 'info>(
         ctx: Context<'_, '_, 'c, 'info, ConsumeEvents>,
         limit: usize,
     ) -> Result<()> {
@@ -4840,17 +7460,34 @@ pub mod openbook_v2 {
         ctx: Context<'_, '_, 'c, 'info, ConsumeEvents>,
         slots: Vec<usize>,
     ) -> Result<()> {
-        require!(
-            slots
-                .iter()
-                .all(|slot| *slot < crate::state::MAX_NUM_EVENTS as usize),
-            OpenBookError::InvalidInputHeapSlots
-        );
         #[cfg(feature = "enable-gpl")]
         instructions::consume_events(ctx, slots.len(), Some(slots))?;
         Ok(())
     }
 
+    /// Same as `consume_events`, and the `cranker` receives the crank reward of the consumed
+    /// events, paid out of the event heap penalties collected by the market. The penalty a
+    /// transaction pays is shared among the events it added, at most
+    /// [`CRANK_REWARD_PER_EVENT`](crate::state::CRANK_REWARD_PER_EVENT) lamports each.
+    pub fn consume_events_with_reward<'c: 'info, 'info>(
+        ctx: Context<'_, '_, 'c, 'info, ConsumeEventsWithReward>,
+        limit: usize,
+    ) -> Result<()> {
+        #[cfg(feature = "enable-gpl")]
+        instructions::consume_events_with_reward(ctx, limit, None)?;
+        Ok(())
+    }
+
+    /// Same as `consume_given_events`, with the crank reward of `consume_events_with_reward`.
+    pub fn consume_given_events_with_reward<'c: 'info, 'info>(
+        ctx: Context<'_, '_, 'c, 'info, ConsumeEventsWithReward>,
+        slots: Vec<usize>,
+    ) -> Result<()> {
+        #[cfg(feature = "enable-gpl")]
+        instructions::consume_events_with_reward(ctx, slots.len(), Some(slots))?;
+        Ok(())
+    }
+
     /// Cancel an order by its `order_id`.
     ///
     /// Note that this doesn't emit an [`OutEvent`](crate::state::OutEvent) because a
@@ -4892,6 +7529,9 @@ pub mod openbook_v2 {
     ///
     /// Makers might wish to `deposit`, rather than have actual tokens moved for
     /// each trade, in order to reduce CUs.
+    ///
+    /// Leaving out the token account of a wrapped SOL side deposits lamports of
+    /// `native_payer` instead.
     pub fn deposit(ctx: Context<Deposit>, base_amount: u64, quote_amount: u64) -> Result<()> {
         #[cfg(feature = "enable-gpl")]
         instructions::deposit(ctx, base_amount, quote_amount)?;
@@ -4918,12 +7558,38 @@ pub mod openbook_v2 {
     }
 
     /// Withdraw any available tokens.
+    ///
+    /// Leaving out the token account of a wrapped SOL side pays out lamports to
+    /// `native_destination`, the owner of the open orders account, through the temporary
+    /// `native_settle_account`.
     pub fn settle_funds<'info>(ctx: Context<'_, '_, '_, 'info, SettleFunds<'info>>) -> Result<()> {
         #[cfg(feature = "enable-gpl")]
         instructions::settle_funds(ctx)?;
         Ok(())
     }
 
+    /// Withdraw only `base_amount` and `quote_amount` of the available tokens, leaving
+    /// the rest free for future orders. Like `settle_funds`, it charges the deferred event
+    /// heap penalties to `penalty_payer` and refunds posting bonds.
+    pub fn withdraw(ctx: Context<Withdraw>, base_amount: u64, quote_amount: u64) -> Result<()> {
+        #[cfg(feature = "enable-gpl")]
+        instructions::withdraw(ctx, base_amount, quote_amount)?;
+        Ok(())
+    }
+
+    /// Move `base_amount` and `quote_amount` of free native tokens between two open orders
+    /// accounts of the same owner on the same market, without any token transfer.
+    /// The source account must not owe event heap penalties.
+    pub fn transfer_free_balance(
+        ctx: Context<TransferFreeBalance>,
+        base_amount: u64,
+        quote_amount: u64,
+    ) -> Result<()> {
+        #[cfg(feature = "enable-gpl")]
+        instructions::transfer_free_balance(ctx, base_amount, quote_amount)?;
+        Ok(())
+    }
+
     /// Withdraw any available tokens when the market is expired (only
     /// [`close_market_admin`](crate::state::Market::close_market_admin)).
     pub fn settle_funds_expired<'info>(
@@ -4948,6 +7614,107 @@ pub mod openbook_v2 {
         Ok(())
     }
 
+    /// Restrict the delegate of an open orders account to `permissions`, a bitmask of
+    /// [`delegate_permissions`](crate::state::delegate_permissions), until
+    /// `expiry_timestamp` (0 for no expiry).
+    ///
+    /// Setting a new delegate with `set_delegate` grants it all permissions again. Deposits
+    /// need no permission, `deposit` is permissionless.
+    pub fn set_delegate_permissions(
+        ctx: Context<SetDelegatePermissions>,
+        permissions: u8,
+        expiry_timestamp: u64,
+    ) -> Result<()> {
+        #[cfg(feature = "enable-gpl")]
+        instructions::set_delegate_permissions(ctx, permissions, expiry_timestamp)?;
+        Ok(())
+    }
+
+    /// Set the [`SelfTradeScope`](crate::state::SelfTradeScope) of the orders an open orders
+    /// account places from now on.
+    ///
+    /// With the default `SelfTradeScope::Owner`, the account's taker orders apply their
+    /// `self_trade_behavior` to resting orders of all of the owner's accounts with that scope.
+    /// `SelfTradeScope::Account` lets the account trade with the owner's other accounts.
+    ///
+    /// The scope is stored in every order the account places. Orders already on the book keep
+    /// the scope they were placed with, orders placed before self-trade groups existed only
+    /// prevent self-trades with their own account.
+    pub fn set_self_trade_scope(
+        ctx: Context<SetSelfTradeScope>,
+        scope: SelfTradeScope,
+    ) -> Result<()> {
+        #[cfg(feature = "enable-gpl")]
+        instructions::set_self_trade_scope(ctx, scope)?;
+        Ok(())
+    }
+
+    /// Add another delegate to an open orders account, with its own `label`, `permissions`
+    /// and `expiry_timestamp` (0 for no expiry), e.g. a session key for one bot instance.
+    ///
+    /// The delegate passes the created
+    /// [`OpenOrdersDelegate`](crate::state::OpenOrdersDelegate) as `open_orders_delegate`
+    /// to the instructions it signs.
+    pub fn add_delegate(
+        ctx: Context<AddDelegate>,
+        label: String,
+        permissions: u8,
+        expiry_timestamp: u64,
+    ) -> Result<()> {
+        #[cfg(feature = "enable-gpl")]
+        instructions::add_delegate(ctx, label, permissions, expiry_timestamp)?;
+        Ok(())
+    }
+
+    /// Remove a delegate added with `add_delegate`, refunding the rent to `sol_destination`.
+    pub fn remove_delegate(ctx: Context<RemoveDelegate>) -> Result<()> {
+        #[cfg(feature = "enable-gpl")]
+        instructions::remove_delegate(ctx)?;
+        Ok(())
+    }
+
+    /// Allow an owner or an open orders account to place orders on the market (only
+    /// [`allowlist_admin`](crate::state::Market::allowlist_admin)).
+    ///
+    /// Creates the [`MarketListEntry`](crate::state::MarketListEntry) of `entry`, which order
+    /// placing instructions take as `market_allowlist_entry`.
+    pub fn add_to_allowlist(ctx: Context<AddToAllowlist>, entry: Pubkey) -> Result<()> {
+        #[cfg(feature = "enable-gpl")]
+        instructions::add_to_allowlist(ctx, entry)?;
+        Ok(())
+    }
+
+    /// Remove an entry added with `add_to_allowlist`, closing its account and refunding the
+    /// rent to `sol_destination` (only
+    /// [`allowlist_admin`](crate::state::Market::allowlist_admin)).
+    ///
+    /// Orders already on the book stay there.
+    pub fn remove_from_allowlist(ctx: Context<RemoveFromAllowlist>, entry: Pubkey) -> Result<()> {
+        #[cfg(feature = "enable-gpl")]
+        instructions::remove_from_allowlist(ctx, entry)?;
+        Ok(())
+    }
+
+    /// Stop an owner from creating open orders accounts, placing orders and receiving settled
+    /// funds on the market (only [`denylist_admin`](crate::state::Market::denylist_admin)).
+    ///
+    /// Creates the [`MarketListEntry`](crate::state::MarketListEntry) of `entry`. Screened
+    /// instructions take the entry address of every screened owner, e.g. as
+    /// `owner_denylist_entry`, and fail while an account exists there.
+    pub fn add_to_denylist(ctx: Context<AddToDenylist>, entry: Pubkey) -> Result<()> {
+        #[cfg(feature = "enable-gpl")]
+        instructions::add_to_denylist(ctx, entry)?;
+        Ok(())
+    }
+
+    /// Remove an entry added with `add_to_denylist`, closing its account and refunding the
+    /// rent to `sol_destination` (only [`denylist_admin`](crate::state::Market::denylist_admin)).
+    pub fn remove_from_denylist(ctx: Context<RemoveFromDenylist>, entry: Pubkey) -> Result<()> {
+        #[cfg(feature = "enable-gpl")]
+        instructions::remove_from_denylist(ctx, entry)?;
+        Ok(())
+    }
+
     /// Set market to expired before pruning orders and closing the market (only
     /// [`close_market_admin`](crate::state::Market::close_market_admin)).
     pub fn set_market_expired(ctx: Context<SetMarketExpired>) -> Result<()> {
@@ -4964,6 +7731,69 @@ pub mod openbook_v2 {
         Ok(())
     }
 
+    /// Remove up to `limit` expired orders from the book, queueing an `OutEvent` for each
+    /// so the owners get their funds and posting bonds back when the events are consumed.
+    /// Permissionless: the `cranker` earns up to
+    /// [`CRANK_REWARD_PER_EVENT`](crate::state::CRANK_REWARD_PER_EVENT) lamports per purged
+    /// order out of its [`posting_bond`](crate::state::Market::posting_bond), which the owner
+    /// doesn't get back. There is no bounty on markets without posting bonds.
+    pub fn purge_expired_orders(ctx: Context<PurgeExpiredOrders>, limit: u8) -> Result<()> {
+        #[cfg(feature = "enable-gpl")]
+        instructions::purge_expired_orders(ctx, limit)?;
+        Ok(())
+    }
+
+    /// Override the market's exposure limits for one open orders account (only
+    /// [`exposure_admin`](crate::state::Market::exposure_admin)). 0 falls back to
+    /// the market's limit, use the maximum value to exempt the account.
+    ///
+    /// Orders already on the book stay there, the limits apply to orders placed afterwards.
+    pub fn set_exposure_limits(
+        ctx: Context<SetExposureLimits>,
+        max_bids_quote_lots: i64,
+        max_asks_base_lots: i64,
+        max_open_orders: u16,
+    ) -> Result<()> {
+        #[cfg(feature = "enable-gpl")]
+        instructions::set_exposure_limits(
+            ctx,
+            max_bids_quote_lots,
+            max_asks_base_lots,
+            max_open_orders,
+        )?;
+        Ok(())
+    }
+
+    /// Return the best bid and ask prices, in lots. Read-only, meant to be simulated or
+    /// called via CPI.
+    pub fn get_best_bid_ask(ctx: Context<ViewBook>) -> Result<BookPrices> {
+        #[cfg(feature = "enable-gpl")]
+        return instructions::get_best_bid_ask(ctx);
+
+        #[cfg(not(feature = "enable-gpl"))]
+        Ok(Default::default())
+    }
+
+    /// Return the aggregated quantity on up to `levels` price levels of each side of the
+    /// book, capped at [`MAX_DEPTH_LEVELS`](crate::instructions::MAX_DEPTH_LEVELS).
+    pub fn get_depth(ctx: Context<ViewBook>, levels: u8) -> Result<BookDepth> {
+        #[cfg(feature = "enable-gpl")]
+        return instructions::get_depth(ctx, levels);
+
+        #[cfg(not(feature = "enable-gpl"))]
+        Ok(Default::default())
+    }
+
+    /// Return the price at which a market order of `base_lots` would be completely filled
+    /// on each side of the book, or `None` if the side isn't deep enough.
+    pub fn get_impact_price(ctx: Context<ViewBook>, base_lots: i64) -> Result<BookPrices> {
+        #[cfg(feature = "enable-gpl")]
+        return instructions::get_impact_price(ctx, base_lots);
+
+        #[cfg(not(feature = "enable-gpl"))]
+        Ok(Default::default())
+    }
+
     pub fn stub_oracle_create(ctx: Context<StubOracleCreate>, price: f64) -> Result<()> {
         #[cfg(feature = "enable-gpl")]
         instructions::stub_oracle_create(ctx, price)?;
@@ -5058,6 +7888,61 @@ pub struct PlaceTakeOrderArgs {
     pub limit: u8,
 }
 
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Copy, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SwapArgs {
+    // Bid to swap quote for base, Ask to swap base for quote.
+    pub side: Side,
+    // Native amount of the input token. Rounded down to whole lots.
+    pub amount_in: u64,
+    // Native amount of the output token below which the swap fails.
+    pub min_amount_out: u64,
+    // Maximum number of orders from the book to fill.
+    pub limit: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Copy, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SwapExactOutArgs {
+    // Bid to receive base, Ask to receive quote.
+    pub side: Side,
+    // Exact native amount of the output token.
+    pub amount_out: u64,
+    // Native amount of the input token above which the swap fails.
+    pub max_amount_in: u64,
+    // Maximum number of orders from the book to fill.
+    pub limit: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Copy, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SwapRouteArgs {
+    // Native amount of the input token. Rounded down to whole lots of the first market.
+    pub amount_in: u64,
+    // Native amount of the output token below which the swap fails.
+    pub min_amount_out: u64,
+    // Maximum number of orders to fill on each book.
+    pub limit: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct BookPrices {
+    pub bid_price_lots: Option<i64>,
+    pub ask_price_lots: Option<i64>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct BookLevel {
+    pub price_lots: i64,
+    pub base_lots: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct BookDepth {
+    pub bids: Vec<BookLevel>,
+    pub asks: Vec<BookLevel>,
+}
+
 // Add security details to explorer.solana.com
 #[cfg(not(feature = "no-entrypoint"))]
 use {default_env::default_env, solana_security_txt::security_txt};
@@ -5079,6 +7964,9 @@ security_txt! {
 // File: openbook-v2/programs/openbook-v2/src/logs.rs
 use anchor_lang::prelude::*;
 use borsh::BorshSerialize;
+use std::cell::Cell;
+
+use crate::error::OpenBookError;
 
 #[inline(never)] // ensure fresh stack frame
 pub fn emit_stack<T: anchor_lang::Event>(e: T) {
@@ -5096,6 +7984,132 @@ pub fn emit_stack<T: anchor_lang::Event>(e: T) {
     anchor_lang::solana_program::log::sol_log_data(&[&buffer[..pos]]);
 }
 
+/// Most events one instruction sends through self-CPIs. Every self-CPI takes an entry of the
+/// transaction's instruction trace, which is limited to 64 entries, the rest is left for the
+/// instruction itself and its token and system program transfers.
+pub const CPI_EVENTS_BUDGET: u8 = 56;
+
+/// Most self-CPIs sent for one processed order or event: a fill sends the taker signature,
+/// the fill and the maker position, and a cancellation if the maker is left with dust.
+pub const CPI_EVENTS_PER_LIMIT: u8 = 4;
+
+/// Most orders or events one instruction processes when events are sent through self-CPIs,
+/// shared by all book operations of the instruction. The rest of [`CPI_EVENTS_BUDGET`] is
+/// left for the events each order sends once and for expired orders dropped while matching.
+pub const CPI_EVENTS_LIMIT: u8 = 8;
+
+/// Same as anchor's `emit_cpi!`, but serializes on a fresh stack frame and doesn't need the
+/// instruction context: the event is sent as the data of a self-CPI signed by the event
+/// authority. Unlike log messages, instruction data is never truncated by the validator.
+#[inline(never)] // ensure fresh stack frame
+pub fn emit_cpi_stack<T: anchor_lang::Event>(
+    e: T,
+    event_authority: &AccountInfo,
+    bump: u8,
+) -> Result<()> {
+    use anchor_lang::solana_program::{instruction::Instruction, program::invoke_signed};
+    use std::io::{Cursor, Write};
+
+    // stack buffer, stack frames are 4kb
+    let mut buffer = [0u8; 3000];
+
+    let mut cursor = Cursor::new(&mut buffer[..]);
+    cursor
+        .write_all(&anchor_lang::event::EVENT_IX_TAG_LE[..])
+        .unwrap();
+    cursor.write_all(&T::DISCRIMINATOR).unwrap();
+    e.serialize(&mut cursor)
+        .expect("event must fit into stack buffer");
+
+    let pos = cursor.position() as usize;
+    let ix = Instruction::new_with_bytes(
+        crate::ID,
+        &buffer[..pos],
+        vec![AccountMeta::new_readonly(*event_authority.key, true)],
+    );
+    invoke_signed(
+        &ix,
+        &[event_authority.clone()],
+        &[&[anchor_lang::event::EVENT_AUTHORITY_SEED, &[bump]]],
+    )?;
+    Ok(())
+}
+
+/// Sends events either to the program log through [`emit_stack`] or, when the program is
+/// built with the `cpi-events` feature, through a self-CPI with [`emit_cpi_stack`].
+///
+/// Instruction handlers get one with [`event_emitter!`] and pass it down to the state code.
+#[derive(Clone, Default)]
+pub struct EventEmitter<'a, 'info> {
+    event_authority: Option<(&'a AccountInfo<'info>, u8)>,
+    /// Number of self-CPIs sent so far by the instruction
+    cpi_events: Cell<u8>,
+}
+
+impl<'a, 'info> EventEmitter<'a, 'info> {
+    pub fn cpi(event_authority: &'a AccountInfo<'info>, bump: u8) -> Self {
+        Self {
+            event_authority: Some((event_authority, bump)),
+            cpi_events: Cell::new(0),
+        }
+    }
+
+    /// Fails once the instruction sent [`CPI_EVENTS_BUDGET`] self-CPIs, or if the self-CPI fails
+    pub fn emit<T: anchor_lang::Event>(&self, e: T) -> Result<()> {
+        match self.event_authority {
+            Some((event_authority, bump)) => {
+                let cpi_events = self.cpi_events.get();
+                require_gt!(
+                    CPI_EVENTS_BUDGET,
+                    cpi_events,
+                    OpenBookError::CpiEventsBudgetExceeded
+                );
+                self.cpi_events.set(cpi_events + 1);
+                emit_cpi_stack(e, event_authority, bump)
+            }
+            None => {
+                emit_stack(e);
+                Ok(())
+            }
+        }
+    }
+
+    /// Caps the number of orders or events to process at what is left of [`CPI_EVENTS_LIMIT`]
+    /// when events are sent through self-CPIs. Every self-CPI the instruction sent so far,
+    /// in this or an earlier book operation, counts against it.
+    pub fn cap_limit<T: From<u8> + Ord>(&self, limit: T) -> T {
+        match self.event_authority {
+            Some(_) => {
+                let left = (CPI_EVENTS_LIMIT * CPI_EVENTS_PER_LIMIT)
+                    .saturating_sub(self.cpi_events.get())
+                    / CPI_EVENTS_PER_LIMIT;
+                limit.min(left.into())
+            }
+            None => limit,
+        }
+    }
+}
+
+/// Creates the [`EventEmitter`] for an instruction context. With the `cpi-events` feature the
+/// accounts struct must be annotated with `#[event_cpi]`.
+///
+/// Example: let emitter = event_emitter!(ctx);
+#[macro_export]
+macro_rules! event_emitter {
+    ($ctx:expr) => {{
+        #[cfg(feature = "cpi-events")]
+        let emitter = $crate::logs::EventEmitter::cpi(
+            &$ctx.accounts.event_authority,
+            $ctx.bumps.event_authority,
+        );
+        #[cfg(not(feature = "cpi-events"))]
+        let emitter = $crate::logs::EventEmitter::default();
+        emitter
+    }};
+}
+
+pub use event_emitter;
+
 #[event]
 pub struct DepositLog {
     pub open_orders_account: Pubkey,
@@ -5108,7 +8122,7 @@ pub struct DepositLog {
 pub struct FillLog {
     pub market: Pubkey,
     pub taker_side: u8, // side from the taker's POV
-    pub maker_slot: u8,
+    pub maker_slot: u16,
     pub maker_out: bool, // true if maker order quantity == 0
     pub timestamp: u64,
     pub seq_num: u64, // note: usize same as u64
@@ -5144,6 +8158,8 @@ pub struct MarketMetaDataLog {
     pub quote_decimals: u8,
     pub base_lot_size: i64,
     pub quote_lot_size: i64,
+    pub event_heap_penalty: u64,
+    pub event_heap_penalty_policy: u8,
 }
 
 #[event]
@@ -5161,6 +8177,68 @@ pub struct SetDelegateLog {
     pub delegate: Option<Pubkey>,
 }
 
+#[event]
+pub struct AddDelegateLog {
+    pub open_orders_account: Pubkey,
+    pub delegate: Pubkey,
+    pub label: String,
+    pub permissions: u8,
+    pub expiry_timestamp: u64,
+}
+
+#[event]
+pub struct RemoveDelegateLog {
+    pub open_orders_account: Pubkey,
+    pub delegate: Pubkey,
+}
+
+#[event]
+pub struct AddToAllowlistLog {
+    pub market: Pubkey,
+    pub entry: Pubkey,
+}
+
+#[event]
+pub struct RemoveFromAllowlistLog {
+    pub market: Pubkey,
+    pub entry: Pubkey,
+}
+
+#[event]
+pub struct AddToDenylistLog {
+    pub market: Pubkey,
+    pub entry: Pubkey,
+}
+
+#[event]
+pub struct RemoveFromDenylistLog {
+    pub market: Pubkey,
+    pub entry: Pubkey,
+}
+
+#[event]
+pub struct SetDelegatePermissionsLog {
+    pub open_orders_account: Pubkey,
+    pub delegate: Option<Pubkey>,
+    pub permissions: u8,
+    pub expiry_timestamp: u64,
+}
+
+#[event]
+pub struct SetSelfTradeScopeLog {
+    pub open_orders_account: Pubkey,
+    pub self_trade_scope: u8,
+}
+
+#[event]
+pub struct SetExposureLimitsLog {
+    pub market: Pubkey,
+    pub open_orders_account: Pubkey,
+    pub max_bids_quote_lots: i64,
+    pub max_asks_base_lots: i64,
+    pub max_open_orders: u16,
+}
+
 #[event]
 pub struct SettleFundsLog {
     pub open_orders_account: Pubkey,
@@ -5170,6 +8248,21 @@ pub struct SettleFundsLog {
     pub referrer: Option<Pubkey>,
 }
 
+#[event]
+pub struct WithdrawLog {
+    pub open_orders_account: Pubkey,
+    pub base_native: u64,
+    pub quote_native: u64,
+}
+
+#[event]
+pub struct TransferFreeBalanceLog {
+    pub source_open_orders_account: Pubkey,
+    pub destination_open_orders_account: Pubkey,
+    pub base_native: u64,
+    pub quote_native: u64,
+}
+
 #[event]
 pub struct SweepFeesLog {
     pub market: Pubkey,
@@ -5177,6 +8270,57 @@ pub struct SweepFeesLog {
     pub receiver: Pubkey,
 }
 
+#[event]
+pub struct OrderPlacedLog {
+    pub market: Pubkey,
+    pub owner: Pubkey, // open orders account
+    pub order_id: u128,
+    pub client_order_id: u64,
+    pub side_and_tree: u8, // SideAndOrderTree
+    /// Price in lots, or the offset to the oracle price for oracle pegged orders
+    pub price_lots: i64,
+    pub quantity: i64, // number of base lots
+}
+
+#[event]
+pub struct OrderCancelledLog {
+    pub market: Pubkey,
+    pub owner: Pubkey, // open orders account
+    pub order_id: u128,
+    pub client_order_id: u64,
+    pub side_and_tree: u8, // SideAndOrderTree
+    /// Price in lots, or the offset to the oracle price for oracle pegged orders
+    pub price_lots: i64,
+    pub quantity: i64, // remaining base lots
+}
+
+/// An order was dropped from the book because it expired or its peg limit was hit
+#[event]
+pub struct OrderExpiredLog {
+    pub market: Pubkey,
+    pub owner: Pubkey, // open orders account
+    pub order_id: u128,
+    pub client_order_id: u64,
+    pub side_and_tree: u8, // SideAndOrderTree
+    /// Price in lots, or the offset to the oracle price for oracle pegged orders
+    pub price_lots: i64,
+    pub quantity: i64, // remaining base lots
+}
+
+/// An order was forced off the book, to make room for a better one or because the
+/// oracle became unusable
+#[event]
+pub struct OrderEvictedLog {
+    pub market: Pubkey,
+    pub owner: Pubkey, // open orders account
+    pub order_id: u128,
+    pub client_order_id: u64,
+    pub side_and_tree: u8, // SideAndOrderTree
+    /// Price in lots, or the offset to the oracle price for oracle pegged orders
+    pub price_lots: i64,
+    pub quantity: i64, // remaining base lots
+}
+
 #[event]
 pub struct OpenOrdersPositionLog {
     pub owner: Pubkey,
@@ -5305,6 +8449,7 @@ mod tests {
 // File: openbook-v2/programs/openbook-v2/src/state/market.rs
 use anchor_lang::prelude::*;
 use fixed::types::I80F48;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
 use static_assertions::const_assert_eq;
 use std::convert::{TryFrom, TryInto};
 use std::mem::size_of;
@@ -5314,12 +8459,18 @@ use crate::pubkey_option::NonZeroPubkeyOption;
 use crate::state::oracle;
 use crate::{accounts_zerocopy::KeyedAccountReader, state::orderbook::Side};
 
-use super::{orderbook, OracleConfig};
+use super::{orderbook, OracleConfig, OracleStalenessPolicy};
 
 // For a 1bps taker fee, set taker_fee to 100, so taker_fee/FEES_SCALE_FACTOR = 10e-4
 pub const FEES_SCALE_FACTOR: i128 = 1_000_000;
-// taker pays 500 lamports penalty for every transaction that adds to the event heap
+// default for the lamports a taker pays for every transaction that adds to the event heap
 pub const PENALTY_EVENT_HEAP: u64 = 500;
+// upper bound for the event heap penalty a market can be created with, 0.01 SOL
+pub const MAX_EVENT_HEAP_PENALTY: u64 = 10_000_000;
+// crankers earn up to 100 lamports out of the collected penalties for every consumed event
+// whose penalty was paid, see Market::crank_reward_per_event(), and up to as much out of the
+// posting bond of every expired order they purge
+pub const CRANK_REWARD_PER_EVENT: u64 = 100;
 
 #[account(zero_copy)]
 #[derive(Debug)]
@@ -5421,6 +8572,49 @@ pub struct Market {
     pub market_quote_vault: Pubkey,
     pub quote_deposit_total: u64,
 
+    /// Last oracle price in lots that passed the staleness and confidence checks
+    pub last_oracle_price_lots: i64,
+    /// Slot at which `last_oracle_price_lots` was read, 0 if there's none yet
+    pub last_oracle_slot: u64,
+
+    /// Event heap penalties held by the market account, paid out to whoever consumes events
+    pub crank_reward_pool: u64,
+
+    /// Lamports a taker pays for every transaction that adds to the event heap
+    pub event_heap_penalty: u64,
+    /// See [`EventHeapPenaltyPolicy`]
+    pub event_heap_penalty_policy: u8,
+    pub padding2: [u8; 5],
+    /// Max number of orders an open orders account can have on the book, 0 for no limit
+    pub max_open_orders: u16,
+
+    /// Admin who adds allowlist [`MarketListEntry`](super::MarketListEntry) accounts. When set,
+    /// only allowlisted owners and open orders accounts can place orders.
+    pub allowlist_admin: NonZeroPubkeyOption,
+    /// Admin who adds denylist [`MarketListEntry`](super::MarketListEntry) accounts. When set,
+    /// denylisted owners can't create open orders accounts, place orders, settle or withdraw funds.
+    pub denylist_admin: NonZeroPubkeyOption,
+
+    /// Max quote lots an open orders account can have in open bids, 0 for no limit
+    pub max_bids_quote_lots: i64,
+    /// Max base lots an open orders account can have in open asks, 0 for no limit
+    pub max_asks_base_lots: i64,
+    /// Admin who can override the exposure limits per open orders account
+    pub exposure_admin: NonZeroPubkeyOption,
+
+    /// Minimum base lots of an order resting on the book
+    pub min_base_lots: i64,
+    /// Minimum quote lots (base lots times price lots) of an order resting on the book
+    pub min_quote_notional: i64,
+
+    /// Lamports paid for every order posted on the book. Refunded when the order leaves the
+    /// book, unless it's evicted from a full book side with the worst price on it: then it
+    /// goes to the crank reward pool.
+    pub posting_bond: u64,
+    /// See [`EvictionPolicy`]
+    pub eviction_policy: u8,
+    pub padding3: [u8; 7],
+
     pub reserved: [u8; 128],
 }
 
@@ -5456,9 +8650,26 @@ const_assert_eq!(
     8 +                         // quote_deposit_total
     8 +                         // base_fees_accrued
     8 +                         // referrer_rebates_accrued
+    8 +                         // last_oracle_price_lots
+    8 +                         // last_oracle_slot
+    8 +                         // crank_reward_pool
+    8 +                         // event_heap_penalty
+    1 +                         // event_heap_penalty_policy
+    5 +                         // padding2
+    2 +                         // max_open_orders
+    32 +                        // allowlist_admin
+    32 +                        // denylist_admin
+    8 +                         // max_bids_quote_lots
+    8 +                         // max_asks_base_lots
+    32 +                        // exposure_admin
+    8 +                         // min_base_lots
+    8 +                         // min_quote_notional
+    8 +                         // posting_bond
+    1 +                         // eviction_policy
+    7 +                         // padding3
     128 // reserved
 );
-const_assert_eq!(size_of::<Market>(), 840);
+const_assert_eq!(size_of::<Market>(), 896 + 128);
 const_assert_eq!(size_of::<Market>() % 8, 0);
 
 impl Market {
@@ -5468,7 +8679,21 @@ impl Market {
             .trim_matches(char::from(0))
     }
 
-    pub fn is_expired(&self, timestamp: i64) -> bool {
+    /// Whether an order of `base_lots` at `price_lots` is large enough to rest on the book
+    pub fn meets_min_order_size(&self, base_lots: i64, price_lots: i64) -> bool {
+        base_lots >= self.min_base_lots
+            && base_lots.saturating_mul(price_lots) >= self.min_quote_notional
+    }
+
+    pub fn event_heap_penalty_policy(&self) -> EventHeapPenaltyPolicy {
+        EventHeapPenaltyPolicy::try_from(self.event_heap_penalty_policy).unwrap()
+    }
+
+    pub fn eviction_policy(&self) -> EvictionPolicy {
+        EvictionPolicy::try_from(self.eviction_policy).unwrap()
+    }
+
+    pub fn is_expired(&self, timestamp: i64) -> bool {
         self.time_expiry != 0 && self.time_expiry < timestamp
     }
 
@@ -5479,6 +8704,35 @@ impl Market {
             && self.referrer_rebates_accrued == 0
     }
 
+    /// Crank reward of each of the `events` events added to the event heap by one transaction,
+    /// which pays the event heap penalty once. Consuming them never pays out more than that.
+    pub fn crank_reward_per_event(&self, events: usize) -> u64 {
+        (self.event_heap_penalty / events as u64).min(CRANK_REWARD_PER_EVENT)
+    }
+
+    /// Event heap penalty owed for `count` transactions that added to the event heap
+    pub fn event_heap_penalty_for(&self, count: u64) -> Result<u64> {
+        count
+            .checked_mul(self.event_heap_penalty)
+            .ok_or_else(|| error!(OpenBookError::PenaltyOverflow))
+    }
+
+    /// Adds collected penalties or forfeited posting bonds to the crank reward pool
+    pub fn add_to_crank_reward_pool(&mut self, lamports: u64) -> Result<()> {
+        self.crank_reward_pool = self
+            .crank_reward_pool
+            .checked_add(lamports)
+            .ok_or_else(|| error!(OpenBookError::PenaltyOverflow))?;
+        Ok(())
+    }
+
+    /// Takes up to `reward` out of the crank reward pool
+    pub fn take_crank_reward(&mut self, reward: u64) -> u64 {
+        let reward = reward.min(self.crank_reward_pool);
+        self.crank_reward_pool -= reward;
+        reward
+    }
+
     pub fn is_market_vault(&self, pubkey: Pubkey) -> bool {
         pubkey == self.market_quote_vault || pubkey == self.market_base_vault
     }
@@ -5544,6 +8798,57 @@ impl Market {
         }
     }
 
+    /// Like [`Self::oracle_price_lots`], but applies the market's [`OracleStalenessPolicy`].
+    ///
+    /// A usable oracle price is recorded as the last good one. With `Freeze`, that price keeps
+    /// being returned for `freeze_slots` slots while the oracle is unusable.
+    pub fn update_oracle_price_lots(
+        &mut self,
+        oracle_a_acc: Option<&impl KeyedAccountReader>,
+        oracle_b_acc: Option<&impl KeyedAccountReader>,
+        slot: u64,
+    ) -> Result<Option<i64>> {
+        let oracle_price_lots = self.oracle_price_lots(oracle_a_acc, oracle_b_acc, slot)?;
+        Ok(self.apply_oracle_staleness_policy(oracle_price_lots, slot))
+    }
+
+    pub fn apply_oracle_staleness_policy(
+        &mut self,
+        oracle_price_lots: Option<i64>,
+        now_slot: u64,
+    ) -> Option<i64> {
+        if let Some(price_lots) = oracle_price_lots {
+            self.last_oracle_price_lots = price_lots;
+            self.last_oracle_slot = now_slot;
+            return Some(price_lots);
+        }
+
+        let frozen_price_lots = self.frozen_oracle_price_lots(now_slot);
+        if let Some(price_lots) = frozen_price_lots {
+            msg!(
+                "Oracle unusable, using last price {} from slot {}",
+                price_lots,
+                self.last_oracle_slot
+            );
+        }
+        frozen_price_lots
+    }
+
+    /// The last good oracle price, if the market freezes it and the freeze window is still open
+    pub fn frozen_oracle_price_lots(&self, now_slot: u64) -> Option<i64> {
+        let frozen_until = self
+            .last_oracle_slot
+            .saturating_add(self.oracle_config.freeze_slots.into());
+        if self.oracle_config.staleness_policy() == OracleStalenessPolicy::Freeze
+            && self.last_oracle_slot > 0
+            && now_slot <= frozen_until
+        {
+            Some(self.last_oracle_price_lots)
+        } else {
+            None
+        }
+    }
+
     pub fn oracle_price(
         &self,
         oracle_a_acc: Option<&impl KeyedAccountReader>,
@@ -5572,9 +8877,7 @@ impl Market {
         {
             Ok(None)
         } else {
-            let decimals = (self.quote_decimals as i8) - (self.base_decimals as i8);
-            let decimal_adj = oracle::power_of_ten_float(decimals);
-            Ok(I80F48::checked_from_num(oracle.price * decimal_adj))
+            Ok(oracle::scale_by_power_of_ten(oracle.price, self.decimals_diff()))
         }
     }
 
@@ -5596,13 +8899,18 @@ impl Market {
         {
             Ok(None)
         } else {
-            let price = oracle_a.price / oracle_b.price;
-            let decimals = (self.quote_decimals as i8) - (self.base_decimals as i8);
-            let decimal_adj = oracle::power_of_ten_float(decimals);
-            Ok(I80F48::checked_from_num(price * decimal_adj))
+            Ok(oracle_a
+                .price
+                .checked_div(oracle_b.price)
+                .and_then(|price| oracle::scale_by_power_of_ten(price, self.decimals_diff())))
         }
     }
 
+    /// Exponent converting a ui price into a native/native price
+    fn decimals_diff(&self) -> i32 {
+        (self.quote_decimals as i32) - (self.base_decimals as i32)
+    }
+
     pub fn subtract_taker_fees(&self, quote: i64) -> i64 {
         ((quote as i128) * FEES_SCALE_FACTOR / (FEES_SCALE_FACTOR + (self.taker_fee as i128)))
             .try_into()
@@ -5668,16 +8976,187 @@ macro_rules! market_seeds {
 }
 pub(crate) use market_seeds;
 
+/// When the event heap penalty is charged for orders placed through an open orders account
+///
+/// Orders that don't go through an open orders account, like `place_take_order`, always pay it
+/// immediately.
+#[derive(
+    Eq,
+    PartialEq,
+    Copy,
+    Clone,
+    Default,
+    TryFromPrimitive,
+    IntoPrimitive,
+    Debug,
+    AnchorSerialize,
+    AnchorDeserialize,
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[repr(u8)]
+pub enum EventHeapPenaltyPolicy {
+    /// Counted on the position and paid in `settle_funds`
+    #[default]
+    Deferred = 0,
+    /// Paid by the signer of `place_order`
+    Immediate = 1,
+}
+
+/// Which order is evicted when a new order is posted on a full book side
+#[derive(
+    Eq,
+    PartialEq,
+    Copy,
+    Clone,
+    Default,
+    TryFromPrimitive,
+    IntoPrimitive,
+    Debug,
+    AnchorSerialize,
+    AnchorDeserialize,
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[repr(u8)]
+pub enum EvictionPolicy {
+    /// The worst priced order, if the new order has a better price
+    #[default]
+    WorstPrice = 0,
+    /// The order with the smallest quantity, if the new order is larger and priced at least
+    /// as well
+    SmallestSize = 1,
+    /// The order placed first, if the new order is priced at least as well
+    Oldest = 2,
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/state/market_list.rs
+use anchor_lang::prelude::*;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use static_assertions::const_assert_eq;
+use std::mem::size_of;
+
+use super::Market;
+use crate::error::OpenBookError;
+
+/// The per-market key lists kept in [`MarketListEntry`] accounts
+#[derive(Eq, PartialEq, Copy, Clone, TryFromPrimitive, IntoPrimitive, Debug)]
+#[repr(u8)]
+pub enum MarketList {
+    /// Owners and open orders accounts allowed to place orders on a market with an
+    /// [`allowlist_admin`](Market::allowlist_admin)
+    Allowlist = 0,
+    /// Owners that can't trade on a market with a [`denylist_admin`](Market::denylist_admin),
+    /// e.g. sanctioned addresses
+    Denylist = 1,
+}
+
+impl MarketList {
+    /// First seed of the entries on this list
+    pub fn seed(self) -> &'static [u8] {
+        match self {
+            MarketList::Allowlist => b"MarketAllowlistEntry",
+            MarketList::Denylist => b"MarketDenylistEntry",
+        }
+    }
+
+    /// Address of the entry of `key` on this list of `market`, whether it exists or not
+    pub fn entry_address(self, market: &Pubkey, key: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[self.seed(), market.as_ref(), key.as_ref()], &crate::ID).0
+    }
+}
+
+/// One key on a [`MarketList`] of a market.
+///
+/// Lives at the PDA `[list.seed(), market, key]`, so checking a key only needs its own
+/// entry instead of the whole list.
+#[account(zero_copy)]
+#[derive(Debug)]
+pub struct MarketListEntry {
+    pub market: Pubkey,
+    pub key: Pubkey,
+
+    /// The [`MarketList`] the entry is on
+    pub list: u8,
+    pub bump: u8,
+    pub padding: [u8; 6],
+
+    pub reserved: [u8; 32],
+}
+
+const_assert_eq!(size_of::<MarketListEntry>(), 32 + 32 + 1 + 1 + 6 + 32);
+const_assert_eq!(size_of::<MarketListEntry>(), 104);
+const_assert_eq!(size_of::<MarketListEntry>() % 8, 0);
+
+impl MarketListEntry {
+    /// Number of bytes needed for the MarketListEntry, including the discriminator
+    pub fn space() -> usize {
+        8 + size_of::<MarketListEntry>()
+    }
+
+    pub fn init(&mut self, market: Pubkey, key: Pubkey, list: MarketList, bump: u8) {
+        self.market = market;
+        self.key = key;
+        self.list = list.into();
+        self.bump = bump;
+    }
+
+    /// Whether this is the entry of one of `keys` on `list` of `market`
+    pub fn lists_any(&self, market: Pubkey, list: MarketList, keys: &[Pubkey]) -> bool {
+        self.market == market && self.list == u8::from(list) && keys.contains(&self.key)
+    }
+}
+
+/// Whether any of `keys` is allowlisted on the market, always true on markets without an
+/// [`allowlist_admin`](Market::allowlist_admin). Needs the allowlist entry of one of the keys.
+pub fn market_allowlist_allows(
+    market: &AccountLoader<Market>,
+    market_allowlist_entry: &Option<AccountLoader<MarketListEntry>>,
+    keys: &[Pubkey],
+) -> Result<bool> {
+    if market.load()?.allowlist_admin.is_none() {
+        return Ok(true);
+    }
+    match market_allowlist_entry {
+        Some(entry) => Ok(entry
+            .load()?
+            .lists_any(market.key(), MarketList::Allowlist, keys)),
+        None => Ok(false),
+    }
+}
+
+/// Whether `key` is not denylisted on the market, always true on markets without a
+/// [`denylist_admin`](Market::denylist_admin). Needs the denylist entry address of `key`, which
+/// only holds an account while the key is denylisted.
+pub fn market_denylist_allows(
+    market: &AccountLoader<Market>,
+    market_denylist_entry: &Option<UncheckedAccount>,
+    key: Pubkey,
+) -> Result<bool> {
+    if market.load()?.denylist_admin.is_none() {
+        return Ok(true);
+    }
+    match market_denylist_entry {
+        Some(entry) if entry.key() == MarketList::Denylist.entry_address(&market.key(), &key) => {
+            Ok(entry.data_is_empty())
+        }
+        _ => Err(OpenBookError::MissingMarketDenylist.into()),
+    }
+}
+
 
 // File: openbook-v2/programs/openbook-v2/src/state/mod.rs
 pub use market::*;
+pub use market_list::*;
 pub use open_orders_account::*;
+pub use open_orders_delegate::*;
 pub use open_orders_indexer::*;
 pub use oracle::*;
 pub use orderbook::*;
 
 mod market;
+mod market_list;
 mod open_orders_account;
+mod open_orders_delegate;
 mod open_orders_indexer;
 mod orderbook;
 
@@ -5689,16 +9168,39 @@ mod raydium_internal;
 use anchor_lang::prelude::*;
 use derivative::Derivative;
 use static_assertions::const_assert_eq;
+use std::cell::{Ref, RefMut};
 use std::mem::size_of;
+use std::ops::{Deref, DerefMut};
 
-use crate::logs::{emit_stack, FillLog};
+use crate::accounts_zerocopy::LoadZeroCopyRef;
+use crate::logs::{EventEmitter, FillLog};
 use crate::pubkey_option::NonZeroPubkeyOption;
 use crate::{error::*, logs::OpenOrdersPositionLog};
 
-use super::{BookSideOrderTree, FillEvent, LeafNode, Market, Side, SideAndOrderTree};
+use super::{
+    BookSideOrderTree, FillEvent, LeafNode, Market, SelfTradeScope, Side, SideAndOrderTree,
+};
 
+/// Number of order slots stored inline in every OpenOrdersAccount
 pub const MAX_OPEN_ORDERS: usize = 24;
 
+/// Upper limit for the total number of order slots, see resize_open_orders_account
+pub const MAX_OPEN_ORDERS_CAPACITY: usize = 256;
+
+/// Bits of [`Position::delegate_permissions`], the actions the delegate of an
+/// OpenOrdersAccount may take. There is no deposit bit: `deposit` is permissionless.
+pub mod delegate_permissions {
+    pub const PLACE: u8 = 1 << 0;
+    pub const CANCEL: u8 = 1 << 1;
+    pub const SETTLE: u8 = 1 << 2;
+    pub const ALL: u8 = PLACE | CANCEL | SETTLE;
+
+    /// A non-empty combination of the bits above
+    pub fn is_valid(permissions: u8) -> bool {
+        permissions != 0 && permissions & !ALL == 0
+    }
+}
+
 #[account(zero_copy)]
 #[derive(Debug)]
 pub struct OpenOrdersAccount {
@@ -5717,7 +9219,9 @@ pub struct OpenOrdersAccount {
     // Introducing a version as we are adding a new field bids_quote_lots
     pub version: u8,
 
-    pub padding: [u8; 2],
+    /// Number of order slots stored after this struct in the account data, in addition
+    /// to the inline `open_orders`. Used to be padding, so it's zero for older accounts.
+    pub extra_order_slots: u16,
 
     pub position: Position,
 
@@ -5744,52 +9248,199 @@ impl OpenOrdersAccount {
         8 + size_of::<OpenOrdersAccount>()
     }
 
+    /// Number of bytes needed for an OpenOrdersAccount with `capacity` order slots in total
+    pub fn space_for_capacity(capacity: usize) -> usize {
+        Self::space() + capacity.saturating_sub(MAX_OPEN_ORDERS) * size_of::<OpenOrder>()
+    }
+
+    /// Total number of order slots, inline and dynamic
+    pub fn capacity(&self) -> usize {
+        MAX_OPEN_ORDERS + self.extra_order_slots as usize
+    }
+
     pub fn name(&self) -> &str {
         std::str::from_utf8(&self.name)
             .unwrap()
             .trim_matches(char::from(0))
     }
 
-    pub fn default_for_tests() -> Box<OpenOrdersAccount> {
-        Box::new(OpenOrdersAccount {
-            owner: Pubkey::default(),
-            market: Pubkey::default(),
-            name: [0; 32],
-            delegate: NonZeroPubkeyOption::default(),
-            account_num: 0,
-            bump: 0,
-            version: 1,
-            padding: [0; 2],
-            position: Position::default(),
-            open_orders: [OpenOrder::default(); MAX_OPEN_ORDERS],
-        })
+    pub fn self_trade_scope(&self) -> SelfTradeScope {
+        SelfTradeScope::try_from(self.position.self_trade_scope).unwrap()
     }
 
-    pub fn is_owner_or_delegate(&self, ix_signer: Pubkey) -> bool {
-        let delegate_option: Option<Pubkey> = Option::from(self.delegate);
-        if let Some(delegate) = delegate_option {
-            return self.owner == ix_signer || delegate == ix_signer;
+    /// `stp_account_num` and `stp_bump` of the orders placed by this account, see LeafNode.
+    /// Accounts numbered beyond u16::MAX only prevent self-trades with themselves.
+    pub fn stp_group(&self) -> (u16, u8) {
+        match (self.self_trade_scope(), u16::try_from(self.account_num)) {
+            (SelfTradeScope::Owner, Ok(account_num)) => (account_num, self.bump),
+            _ => (0, 0),
         }
-        self.owner == ix_signer
     }
 
-    pub fn is_settle_destination_allowed(&self, ix_signer: Pubkey, account_owner: Pubkey) -> bool {
-        // delegate can withdraw to owner accounts
-        let delegate_option: Option<Pubkey> = Option::from(self.delegate);
-        if Some(ix_signer) == delegate_option {
-            return self.owner == account_owner;
+    pub fn default_for_tests() -> OpenOrdersAccountValue {
+        Self::with_capacity_for_tests(MAX_OPEN_ORDERS)
+    }
+
+    pub fn with_capacity_for_tests(capacity: usize) -> OpenOrdersAccountValue {
+        OpenOrdersAccountDynamic {
+            fixed: Box::new(OpenOrdersAccount {
+                owner: Pubkey::default(),
+                market: Pubkey::default(),
+                name: [0; 32],
+                delegate: NonZeroPubkeyOption::default(),
+                account_num: 0,
+                bump: 0,
+                version: 1,
+                extra_order_slots: (capacity - MAX_OPEN_ORDERS) as u16,
+                position: Position::default(),
+                open_orders: [OpenOrder::default(); MAX_OPEN_ORDERS],
+            }),
+            dynamic: vec![OpenOrder::default(); capacity - MAX_OPEN_ORDERS],
+        }
+    }
+
+    /// Permissions of the delegate, see [`delegate_permissions`]. Delegates set before
+    /// permissions existed have all of them.
+    pub fn delegate_permissions(&self) -> u8 {
+        match self.position.delegate_permissions {
+            0 => delegate_permissions::ALL,
+            permissions => permissions,
+        }
+    }
+
+    pub fn is_delegate_expired(&self, now_ts: i64) -> bool {
+        let expiry = self.position.delegate_expiry_timestamp;
+        expiry != 0 && now_ts as u64 >= expiry
+    }
+
+    /// The owner may do anything, the delegate only what all of `required_permissions`
+    /// allow and only until its expiry
+    pub fn is_owner_or_delegate(
+        &self,
+        ix_signer: Pubkey,
+        required_permissions: u8,
+        now_ts: i64,
+    ) -> bool {
+        if self.owner == ix_signer {
+            return true;
         }
+        let delegate_option: Option<Pubkey> = Option::from(self.delegate);
+        delegate_option == Some(ix_signer)
+            && self.delegate_permissions() & required_permissions == required_permissions
+            && !self.is_delegate_expired(now_ts)
+    }
 
+    /// Expects `ix_signer` to already be checked to be the owner or a delegate
+    pub fn is_settle_destination_allowed(&self, ix_signer: Pubkey, account_owner: Pubkey) -> bool {
         // owner can withdraw to anywhere
-        ix_signer == self.owner
+        if ix_signer == self.owner {
+            return true;
+        }
+
+        // delegates can withdraw to owner accounts
+        self.owner == account_owner
+    }
+
+    /// Release funds and apply taker fees to the taker account. Account fees for referrer
+    pub fn execute_taker(
+        &mut self,
+        market: &mut Market,
+        taker_side: Side,
+        base_native: u64,
+        quote_native: u64,
+        taker_fees: u64,
+        referrer_amount: u64,
+        emitter: &EventEmitter,
+    ) -> Result<()> {
+        let pa = &mut self.position;
+        match taker_side {
+            Side::Bid => pa.base_free_native += base_native,
+            Side::Ask => pa.quote_free_native += quote_native - taker_fees,
+        };
+
+        pa.taker_volume += quote_native as u128;
+        pa.referrer_rebates_available += referrer_amount;
+        market.referrer_rebates_accrued += referrer_amount;
+
+        emitter.emit(OpenOrdersPositionLog {
+            owner: self.owner,
+            open_orders_account_num: self.account_num,
+            market: self.market,
+            bids_base_lots: pa.bids_base_lots,
+            bids_quote_lots: pa.bids_quote_lots,
+            asks_base_lots: pa.asks_base_lots,
+            base_free_native: pa.base_free_native,
+            quote_free_native: pa.quote_free_native,
+            locked_maker_fees: pa.locked_maker_fees,
+            referrer_rebates_available: pa.referrer_rebates_available,
+            maker_volume: pa.maker_volume,
+            taker_volume: pa.taker_volume,
+        })
+    }
+}
+
+/// An OpenOrdersAccount together with the order slots that follow it in the account data.
+///
+/// The fixed part is reachable through Deref, order slots must be accessed through the
+/// methods here so the dynamic ones aren't missed.
+pub struct OpenOrdersAccountDynamic<Fixed, Dynamic> {
+    pub fixed: Fixed,
+    /// Order slots beyond the `MAX_OPEN_ORDERS` stored inline in the fixed part
+    pub dynamic: Dynamic,
+}
+
+pub type OpenOrdersAccountRef<'a> =
+    OpenOrdersAccountDynamic<&'a OpenOrdersAccount, &'a [OpenOrder]>;
+pub type OpenOrdersAccountRefMut<'a> =
+    OpenOrdersAccountDynamic<&'a mut OpenOrdersAccount, &'a mut [OpenOrder]>;
+pub type OpenOrdersAccountLoadedRefCell<'a> =
+    OpenOrdersAccountDynamic<Ref<'a, OpenOrdersAccount>, Ref<'a, [OpenOrder]>>;
+pub type OpenOrdersAccountLoadedRefCellMut<'a> =
+    OpenOrdersAccountDynamic<RefMut<'a, OpenOrdersAccount>, RefMut<'a, [OpenOrder]>>;
+pub type OpenOrdersAccountValue =
+    OpenOrdersAccountDynamic<Box<OpenOrdersAccount>, Vec<OpenOrder>>;
+
+impl<F: Deref<Target = OpenOrdersAccount>, D> Deref for OpenOrdersAccountDynamic<F, D> {
+    type Target = OpenOrdersAccount;
+
+    fn deref(&self) -> &OpenOrdersAccount {
+        &self.fixed
+    }
+}
+
+impl<F: DerefMut<Target = OpenOrdersAccount>, D> DerefMut for OpenOrdersAccountDynamic<F, D> {
+    fn deref_mut(&mut self) -> &mut OpenOrdersAccount {
+        &mut self.fixed
+    }
+}
+
+impl OpenOrdersAccountValue {
+    /// Decodes an account from its data, including the discriminator
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let (fixed, dynamic) = split_open_orders_account_data(data)?;
+        Ok(Self {
+            fixed: Box::new(*bytemuck::from_bytes(fixed)),
+            dynamic: bytemuck::cast_slice(dynamic).to_vec(),
+        })
+    }
+}
+
+impl<F: Deref<Target = OpenOrdersAccount>, D: Deref<Target = [OpenOrder]>>
+    OpenOrdersAccountDynamic<F, D>
+{
+    pub fn borrow(&self) -> OpenOrdersAccountRef {
+        OpenOrdersAccountDynamic {
+            fixed: &self.fixed,
+            dynamic: &self.dynamic,
+        }
     }
 
     pub fn all_orders(&self) -> impl Iterator<Item = &OpenOrder> {
-        self.open_orders.iter()
+        self.fixed.open_orders.iter().chain(self.dynamic.iter())
     }
 
     pub fn has_no_orders(&self) -> bool {
-        self.open_orders.iter().count() == 0
+        self.all_orders().count() == 0
     }
 
     pub fn all_orders_in_use(&self) -> impl Iterator<Item = &OpenOrder> {
@@ -5812,15 +9463,39 @@ impl OpenOrdersAccount {
     }
 
     pub fn open_order_by_raw_index(&self, raw_index: usize) -> &OpenOrder {
-        &self.open_orders[raw_index]
+        if raw_index < MAX_OPEN_ORDERS {
+            &self.fixed.open_orders[raw_index]
+        } else {
+            &self.dynamic[raw_index - MAX_OPEN_ORDERS]
+        }
+    }
+}
+
+impl<F: DerefMut<Target = OpenOrdersAccount>, D: DerefMut<Target = [OpenOrder]>>
+    OpenOrdersAccountDynamic<F, D>
+{
+    pub fn borrow_mut(&mut self) -> OpenOrdersAccountRefMut {
+        OpenOrdersAccountDynamic {
+            fixed: &mut self.fixed,
+            dynamic: &mut self.dynamic,
+        }
     }
 
     pub fn open_order_mut_by_raw_index(&mut self, raw_index: usize) -> &mut OpenOrder {
-        &mut self.open_orders[raw_index]
+        if raw_index < MAX_OPEN_ORDERS {
+            &mut self.fixed.open_orders[raw_index]
+        } else {
+            &mut self.dynamic[raw_index - MAX_OPEN_ORDERS]
+        }
     }
 
-    pub fn execute_maker(&mut self, market: &mut Market, fill: &FillEvent) {
-        let is_self_trade = fill.maker == fill.taker;
+    pub fn execute_maker(
+        &mut self,
+        market: &mut Market,
+        fill: &FillEvent,
+        emitter: &EventEmitter,
+    ) -> Result<()> {
+        let is_self_trade = fill.maker == fill.taker || fill.self_trade();
 
         let side = fill.taker_side().invert_side();
         let quote_native = (fill.quantity * fill.price * market.quote_lot_size) as u64;
@@ -5874,7 +9549,8 @@ impl OpenOrdersAccount {
             market.fees_accrued += maker_fees as u128;
 
             if fill.maker_out() {
-                self.remove_order(fill.maker_slot as usize, fill.quantity, locked_price);
+                pa.posting_bonds_refundable += market.posting_bond;
+                self.remove_order(fill.maker_slot() as usize, fill.quantity, locked_price);
             } else {
                 match side {
                     Side::Bid => {
@@ -5887,16 +9563,16 @@ impl OpenOrdersAccount {
         }
 
         // Calculate taker fee, ignoring self trades
-        let taker_fee_ceil = if quote_native > 0 && fill.maker != fill.taker {
+        let taker_fee_ceil = if quote_native > 0 && !is_self_trade {
             market.taker_fees_ceil(quote_native)
         } else {
             0
         };
 
-        emit_stack(FillLog {
+        emitter.emit(FillLog {
             market: self.market,
             taker_side: fill.taker_side,
-            maker_slot: fill.maker_slot,
+            maker_slot: fill.maker_slot(),
             maker_out: fill.maker_out(),
             timestamp: fill.timestamp,
             seq_num: fill.market_seq_num,
@@ -5909,10 +9585,10 @@ impl OpenOrdersAccount {
             taker_fee_ceil,
             price: fill.price,
             quantity: fill.quantity,
-        });
+        })?;
 
         let pa = &self.position;
-        emit_stack(OpenOrdersPositionLog {
+        emitter.emit(OpenOrdersPositionLog {
             owner: self.owner,
             open_orders_account_num: self.account_num,
             market: self.market,
@@ -5928,59 +9604,41 @@ impl OpenOrdersAccount {
         })
     }
 
-    /// Release funds and apply taker fees to the taker account. Account fees for referrer
-    pub fn execute_taker(
+    pub fn add_order(
         &mut self,
-        market: &mut Market,
-        taker_side: Side,
-        base_native: u64,
-        quote_native: u64,
-        taker_fees: u64,
-        referrer_amount: u64,
-    ) {
-        let pa = &mut self.position;
-        match taker_side {
-            Side::Bid => pa.base_free_native += base_native,
-            Side::Ask => pa.quote_free_native += quote_native - taker_fees,
-        };
-
-        pa.taker_volume += quote_native as u128;
-        pa.referrer_rebates_available += referrer_amount;
-        market.referrer_rebates_accrued += referrer_amount;
+        side: Side,
+        order_tree: BookSideOrderTree,
+        order: &LeafNode,
+        client_order_id: u64,
+        locked_price: i64,
+        market: &Market,
+    ) -> Result<()> {
+        let (max_bids_quote_lots, max_asks_base_lots, max_open_orders) =
+            self.position.exposure_limits(market);
+        require!(
+            max_open_orders == 0 || self.all_orders_in_use().count() < max_open_orders as usize,
+            OpenBookError::ExposureLimitExceeded
+        );
 
-        emit_stack(OpenOrdersPositionLog {
-            owner: self.owner,
-            open_orders_account_num: self.account_num,
-            market: self.market,
-            bids_base_lots: pa.bids_base_lots,
-            bids_quote_lots: pa.bids_quote_lots,
-            asks_base_lots: pa.asks_base_lots,
-            base_free_native: pa.base_free_native,
-            quote_free_native: pa.quote_free_native,
-            locked_maker_fees: pa.locked_maker_fees,
-            referrer_rebates_available: pa.referrer_rebates_available,
-            maker_volume: pa.maker_volume,
-            taker_volume: pa.taker_volume,
-        })
-    }
-
-    pub fn add_order(
-        &mut self,
-        side: Side,
-        order_tree: BookSideOrderTree,
-        order: &LeafNode,
-        client_order_id: u64,
-        locked_price: i64,
-    ) {
         let position = &mut self.position;
         match side {
             Side::Bid => {
                 position.bids_base_lots += order.quantity;
                 position.bids_quote_lots += order.quantity * locked_price;
+                require!(
+                    max_bids_quote_lots == 0 || position.bids_quote_lots <= max_bids_quote_lots,
+                    OpenBookError::ExposureLimitExceeded
+                );
+            }
+            Side::Ask => {
+                position.asks_base_lots += order.quantity;
+                require!(
+                    max_asks_base_lots == 0 || position.asks_base_lots <= max_asks_base_lots,
+                    OpenBookError::ExposureLimitExceeded
+                );
             }
-            Side::Ask => position.asks_base_lots += order.quantity,
         };
-        let slot = order.owner_slot as usize;
+        let slot = order.owner_slot() as usize;
 
         let oo = self.open_order_mut_by_raw_index(slot);
         oo.is_free = false.into();
@@ -5988,6 +9646,8 @@ impl OpenOrdersAccount {
         oo.id = order.key;
         oo.client_id = client_order_id;
         oo.locked_price = locked_price;
+
+        Ok(())
     }
 
     pub fn remove_order(&mut self, slot: usize, base_quantity: i64, locked_price: i64) {
@@ -6011,6 +9671,31 @@ impl OpenOrdersAccount {
     }
 
     pub fn cancel_order(&mut self, slot: usize, base_quantity: i64, market: Market) {
+        self.position.posting_bonds_refundable += market.posting_bond;
+        self.release_order(slot, base_quantity, market);
+    }
+
+    /// Like [`Self::cancel_order`], but the order's posting bond is forfeited to the market
+    pub fn evict_order(&mut self, slot: usize, base_quantity: i64, market: Market) {
+        self.release_order(slot, base_quantity, market);
+    }
+
+    /// Applies an [`OutEvent`], cancelling or evicting the order it refers to
+    pub fn execute_out(&mut self, out: &OutEvent, market: Market) {
+        if out.is_evicted() {
+            self.evict_order(out.owner_slot() as usize, out.quantity, market);
+        } else {
+            self.cancel_order(out.owner_slot() as usize, out.quantity, market);
+            // the purge bounty was paid out of the bond already
+            self.position.posting_bonds_refundable = self
+                .position
+                .posting_bonds_refundable
+                .saturating_sub(out.purge_bounty);
+        }
+    }
+
+    /// Frees the locked funds of an order and removes it from the account
+    fn release_order(&mut self, slot: usize, base_quantity: i64, market: Market) {
         let oo = self.open_order_by_raw_index(slot);
         let price = oo.locked_price;
         let order_side = oo.side_and_tree().side();
@@ -6032,6 +9717,80 @@ impl OpenOrdersAccount {
     }
 }
 
+/// Splits OpenOrdersAccount data into the fixed part and the dynamic order slots, checking
+/// that the account is large enough for the number of slots in its header.
+fn split_open_orders_account_data(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    let fixed_end = OpenOrdersAccount::space();
+    require_gte!(data.len(), fixed_end, ErrorCode::AccountDidNotDeserialize);
+    let fixed: &OpenOrdersAccount = bytemuck::from_bytes(&data[8..fixed_end]);
+    let dynamic_end = OpenOrdersAccount::space_for_capacity(fixed.capacity());
+    require_gte!(data.len(), dynamic_end, ErrorCode::AccountDidNotDeserialize);
+    Ok((&data[8..fixed_end], &data[fixed_end..dynamic_end]))
+}
+
+/// Loads an OpenOrdersAccount with all its order slots, see [`OpenOrdersAccountDynamic`]
+pub trait OpenOrdersLoader<'a> {
+    fn load_full(self) -> Result<OpenOrdersAccountLoadedRefCell<'a>>;
+    fn load_full_mut(self) -> Result<OpenOrdersAccountLoadedRefCellMut<'a>>;
+}
+
+/// Owner, discriminator and size checks shared by the loaders
+fn check_open_orders_account_info(info: &AccountInfo) -> Result<()> {
+    require_gte!(
+        info.data_len(),
+        OpenOrdersAccount::space(),
+        ErrorCode::AccountDidNotDeserialize
+    );
+    // checks owner and discriminator
+    let fixed = LoadZeroCopyRef::load::<OpenOrdersAccount>(info)?;
+    require_gte!(
+        info.data_len(),
+        OpenOrdersAccount::space_for_capacity(fixed.capacity()),
+        ErrorCode::AccountDidNotDeserialize
+    );
+    Ok(())
+}
+
+impl<'a, 'info: 'a> OpenOrdersLoader<'a> for &'a AccountInfo<'info> {
+    fn load_full(self) -> Result<OpenOrdersAccountLoadedRefCell<'a>> {
+        check_open_orders_account_info(self)?;
+
+        let data = self.try_borrow_data()?;
+        let (fixed, dynamic) = Ref::map_split(data, |data| {
+            let (fixed, dynamic) = split_open_orders_account_data(data).unwrap();
+            (
+                bytemuck::from_bytes::<OpenOrdersAccount>(fixed),
+                bytemuck::cast_slice::<u8, OpenOrder>(dynamic),
+            )
+        });
+        Ok(OpenOrdersAccountDynamic { fixed, dynamic })
+    }
+
+    fn load_full_mut(self) -> Result<OpenOrdersAccountLoadedRefCellMut<'a>> {
+        require!(self.is_writable, ErrorCode::AccountNotMutable);
+        check_open_orders_account_info(self)?;
+
+        let data = self.try_borrow_mut_data()?;
+        let (fixed, dynamic) = RefMut::map_split(data, |data| {
+            let (fixed, dynamic) = data[8..].split_at_mut(size_of::<OpenOrdersAccount>());
+            let fixed: &mut OpenOrdersAccount = bytemuck::from_bytes_mut(fixed);
+            let dynamic_len = fixed.extra_order_slots as usize * size_of::<OpenOrder>();
+            (fixed, bytemuck::cast_slice_mut(&mut dynamic[..dynamic_len]))
+        });
+        Ok(OpenOrdersAccountDynamic { fixed, dynamic })
+    }
+}
+
+impl<'a, 'info: 'a> OpenOrdersLoader<'a> for &'a AccountLoader<'info, OpenOrdersAccount> {
+    fn load_full(self) -> Result<OpenOrdersAccountLoadedRefCell<'a>> {
+        self.as_ref().load_full()
+    }
+
+    fn load_full_mut(self) -> Result<OpenOrdersAccountLoadedRefCellMut<'a>> {
+        self.as_ref().load_full_mut()
+    }
+}
+
 #[zero_copy]
 #[derive(Derivative)]
 #[derivative(Debug)]
@@ -6058,13 +9817,35 @@ pub struct Position {
     /// Quote lots in open bids
     pub bids_quote_lots: i64,
 
+    /// Bitmask of [`delegate_permissions`] granted to the account's delegate,
+    /// 0 grants all of them. Deposits need no permission, `deposit` is permissionless.
+    pub delegate_permissions: u8,
+    /// [`SelfTradeScope`] of the orders placed by this account
+    pub self_trade_scope: u8,
     #[derivative(Debug = "ignore")]
-    pub reserved: [u8; 64],
+    pub padding: [u8; 6],
+    /// Unix timestamp at which the delegate loses its permissions, 0 for never
+    pub delegate_expiry_timestamp: u64,
+
+    /// Overrides of the market's exposure limits, set by the market's `exposure_admin`.
+    /// 0 falls back to the market's limit.
+    pub max_bids_quote_lots: i64,
+    pub max_asks_base_lots: i64,
+    pub max_open_orders: u16,
+    #[derivative(Debug = "ignore")]
+    pub padding2: [u8; 6],
+
+    /// Posting bonds in lamports of orders that left the book without being evicted,
+    /// paid back to the open orders account on `settle_funds`
+    pub posting_bonds_refundable: u64,
+
+    #[derivative(Debug = "ignore")]
+    pub reserved: [u8; 16],
 }
 
 const_assert_eq!(
     size_of::<Position>(),
-    8 + 8 + 8 + 8 + 8 + 8 + 8 + 16 + 16 + 8 + 64
+    8 + 8 + 8 + 8 + 8 + 8 + 8 + 16 + 16 + 8 + 1 + 1 + 6 + 8 + 8 + 8 + 2 + 6 + 8 + 16
 );
 const_assert_eq!(size_of::<Position>(), 160);
 const_assert_eq!(size_of::<Position>() % 8, 0);
@@ -6082,7 +9863,16 @@ impl Default for Position {
             maker_volume: 0,
             taker_volume: 0,
             bids_quote_lots: 0,
-            reserved: [0; 64],
+            delegate_permissions: 0,
+            self_trade_scope: 0,
+            padding: [0; 6],
+            delegate_expiry_timestamp: 0,
+            max_bids_quote_lots: 0,
+            max_asks_base_lots: 0,
+            max_open_orders: 0,
+            padding2: [0; 6],
+            posting_bonds_refundable: 0,
+            reserved: [0; 16],
         }
     }
 }
@@ -6104,9 +9894,32 @@ impl Position {
             && self.locked_maker_fees == 0
             && self.referrer_rebates_available == 0
             && self.penalty_heap_count == 0
+            && self.posting_bonds_refundable == 0
             // For version 0, bids_quote_lots was not properly tracked
             && (version == 0 || self.bids_quote_lots == 0)
     }
+
+    /// Exposure limits in effect for the account as `(max_bids_quote_lots,
+    /// max_asks_base_lots, max_open_orders)`, 0 meaning no limit
+    pub fn exposure_limits(&self, market: &Market) -> (i64, i64, u16) {
+        (
+            if self.max_bids_quote_lots != 0 {
+                self.max_bids_quote_lots
+            } else {
+                market.max_bids_quote_lots
+            },
+            if self.max_asks_base_lots != 0 {
+                self.max_asks_base_lots
+            } else {
+                market.max_asks_base_lots
+            },
+            if self.max_open_orders != 0 {
+                self.max_open_orders
+            } else {
+                market.max_open_orders
+            },
+        )
+    }
 }
 
 #[zero_copy]
@@ -6149,6 +9962,99 @@ impl OpenOrder {
 }
 
 
+// File: openbook-v2/programs/openbook-v2/src/state/open_orders_delegate.rs
+use anchor_lang::prelude::*;
+use static_assertions::const_assert_eq;
+use std::mem::size_of;
+
+use super::{delegate_permissions, OpenOrdersAccount};
+
+/// An additional delegate of an OpenOrdersAccount, e.g. the session key of one bot instance.
+///
+/// Lives at the PDA `[b"OpenOrdersDelegate", open_orders_account, delegate]` and is passed
+/// as the optional `open_orders_delegate` account of the instructions delegates may sign.
+#[account(zero_copy)]
+#[derive(Debug)]
+pub struct OpenOrdersDelegate {
+    pub open_orders_account: Pubkey,
+    pub delegate: Pubkey,
+
+    pub label: [u8; 32],
+
+    /// Bitmask of [`delegate_permissions`]
+    pub permissions: u8,
+    pub bump: u8,
+    pub padding: [u8; 6],
+    /// Unix timestamp at which the delegate loses its permissions, 0 for never
+    pub expiry_timestamp: u64,
+
+    pub reserved: [u8; 64],
+}
+
+const_assert_eq!(
+    size_of::<OpenOrdersDelegate>(),
+    32 + 32 + 32 + 1 + 1 + 6 + 8 + 64
+);
+const_assert_eq!(size_of::<OpenOrdersDelegate>(), 176);
+const_assert_eq!(size_of::<OpenOrdersDelegate>() % 8, 0);
+
+impl OpenOrdersDelegate {
+    /// Number of bytes needed for the OpenOrdersDelegate, including the discriminator
+    pub fn space() -> usize {
+        8 + size_of::<OpenOrdersDelegate>()
+    }
+
+    pub fn label(&self) -> &str {
+        std::str::from_utf8(&self.label)
+            .unwrap()
+            .trim_matches(char::from(0))
+    }
+
+    pub fn is_expired(&self, now_ts: i64) -> bool {
+        self.expiry_timestamp != 0 && now_ts as u64 >= self.expiry_timestamp
+    }
+
+    pub fn allows(
+        &self,
+        open_orders_account: Pubkey,
+        ix_signer: Pubkey,
+        required_permissions: u8,
+        now_ts: i64,
+    ) -> bool {
+        self.open_orders_account == open_orders_account
+            && self.delegate == ix_signer
+            && self.permissions & required_permissions == required_permissions
+            && !self.is_expired(now_ts)
+    }
+}
+
+/// Whether `ix_signer` may act on the open orders account with `required_permissions`, as
+/// its owner, its delegate or through an [`OpenOrdersDelegate`]
+pub fn open_orders_signer_allowed(
+    open_orders_account: &AccountLoader<OpenOrdersAccount>,
+    open_orders_delegate: &Option<AccountLoader<OpenOrdersDelegate>>,
+    ix_signer: Pubkey,
+    required_permissions: u8,
+) -> Result<bool> {
+    let now_ts = Clock::get()?.unix_timestamp;
+    if open_orders_account
+        .load()?
+        .is_owner_or_delegate(ix_signer, required_permissions, now_ts)
+    {
+        return Ok(true);
+    }
+    match open_orders_delegate {
+        Some(record) => Ok(record.load()?.allows(
+            open_orders_account.key(),
+            ix_signer,
+            required_permissions,
+            now_ts,
+        )),
+        None => Ok(false),
+    }
+}
+
+
 // File: openbook-v2/programs/openbook-v2/src/state/open_orders_indexer.rs
 use anchor_lang::prelude::*;
 
@@ -6174,7 +10080,8 @@ impl OpenOrdersIndexer {
 // File: openbook-v2/programs/openbook-v2/src/state/oracle.rs
 use anchor_lang::prelude::*;
 use anchor_lang::Discriminator;
-use fixed::types::U64F64;
+use fixed::types::{I80F48, U64F64};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
 use static_assertions::const_assert_eq;
 use std::mem::size_of;
 use switchboard_program::FastRoundResultAccountData;
@@ -6185,14 +10092,43 @@ use crate::error::*;
 use crate::state::raydium_internal;
 use crate::state::raydium_internal::PoolState;
 
-const DECIMAL_CONSTANT_ZERO_INDEX: i8 = 12;
-const DECIMAL_CONSTANTS_F64: [f64; 25] = [
-    1e-12, 1e-11, 1e-10, 1e-9, 1e-8, 1e-7, 1e-6, 1e-5, 1e-4, 1e-3, 1e-2, 1e-1, 1e0, 1e1, 1e2, 1e3,
-    1e4, 1e5, 1e6, 1e7, 1e8, 1e9, 1e10, 1e11, 1e12,
-];
+/// Largest power of ten that fits in the integer part of an I80F48
+const MAX_DECIMAL_EXPONENT: u32 = 23;
+
+/// Returns 10^exponent, exponent must not exceed [`MAX_DECIMAL_EXPONENT`]
+pub fn power_of_ten(exponent: u32) -> I80F48 {
+    I80F48::from_num(10_i128.pow(exponent))
+}
+
+/// Scales `value` by 10^decimals.
+///
+/// Negative decimals divide by an exact integer power of ten instead of multiplying by a
+/// rounded fraction, so a price of 4_000_000_000 with decimals -8 is exactly 40.
+pub fn scale_by_power_of_ten(value: I80F48, decimals: i32) -> Option<I80F48> {
+    let mut result = value;
+    let mut remaining = decimals.unsigned_abs();
+    while remaining > 0 {
+        let step = remaining.min(MAX_DECIMAL_EXPONENT);
+        result = if decimals > 0 {
+            result.checked_mul(power_of_ten(step))?
+        } else {
+            result.checked_div(power_of_ten(step))?
+        };
+        remaining -= step;
+    }
+    Some(result)
+}
 
-pub const fn power_of_ten_float(decimals: i8) -> f64 {
-    DECIMAL_CONSTANTS_F64[(decimals + DECIMAL_CONSTANT_ZERO_INDEX) as usize]
+/// Converts `mantissa * 10^exponent` into an I80F48.
+///
+/// Mantissas that don't fit in an I80F48 have their lowest digits dropped first; with a negative
+/// exponent those digits are far below the 2^-48 resolution of the result anyway.
+pub fn decimal_to_fixed(mut mantissa: i128, mut exponent: i32) -> Option<I80F48> {
+    while I80F48::checked_from_num(mantissa).is_none() && exponent < 0 {
+        mantissa /= 10;
+        exponent += 1;
+    }
+    scale_by_power_of_ten(I80F48::checked_from_num(mantissa)?, exponent)
 }
 
 pub mod switchboard_v1_devnet_oracle {
@@ -6204,17 +10140,59 @@ pub mod switchboard_v2_mainnet_oracle {
     declare_id!("DtmE9D2CSB4L5D6A15mraeEjrGMm6auWVzgaD8hK2tZM");
 }
 
+/// What happens to oracle pegged orders while the oracle is stale or not confident enough
+#[derive(
+    Eq,
+    PartialEq,
+    Copy,
+    Clone,
+    Default,
+    TryFromPrimitive,
+    IntoPrimitive,
+    Debug,
+    AnchorSerialize,
+    AnchorDeserialize,
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[repr(u8)]
+pub enum OracleStalenessPolicy {
+    /// Pegged orders can't be matched nor placed until the oracle recovers
+    #[default]
+    Skip = 0,
+    /// Keep using the last good oracle price for up to `freeze_slots` slots
+    Freeze = 1,
+    /// Remove pegged orders from the book
+    CancelPegged = 2,
+}
+
 #[zero_copy]
 #[derive(AnchorDeserialize, AnchorSerialize, Debug)]
 pub struct OracleConfig {
     pub conf_filter: f64,
     pub max_staleness_slots: i64,
-    pub reserved: [u8; 72],
+    /// See [`OracleStalenessPolicy`]
+    pub staleness_policy: u8,
+    pub padding: [u8; 3],
+    /// Number of slots the last good price is used for with [`OracleStalenessPolicy::Freeze`]
+    pub freeze_slots: u32,
+    pub reserved: [u8; 64],
 }
-const_assert_eq!(size_of::<OracleConfig>(), 8 + 8 + 72);
+const_assert_eq!(size_of::<OracleConfig>(), 8 + 8 + 1 + 3 + 4 + 64);
 const_assert_eq!(size_of::<OracleConfig>(), 88);
 const_assert_eq!(size_of::<OracleConfig>() % 8, 0);
 
+impl OracleConfig {
+    /// Maximum accepted deviation / price ratio. A filter that isn't representable
+    /// (NaN or infinite) never rejects a price.
+    pub fn conf_filter(&self) -> I80F48 {
+        I80F48::checked_from_num(self.conf_filter).unwrap_or(I80F48::MAX)
+    }
+
+    pub fn staleness_policy(&self) -> OracleStalenessPolicy {
+        OracleStalenessPolicy::try_from(self.staleness_policy).unwrap()
+    }
+}
+
 #[derive(AnchorDeserialize, AnchorSerialize, Debug, Clone)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct OracleConfigParams {
@@ -6222,6 +10200,8 @@ pub struct OracleConfigParams {
     pub conf_filter: f32,
     #[cfg_attr(feature = "arbitrary", arbitrary(default))]
     pub max_staleness_slots: Option<u32>,
+    pub staleness_policy: OracleStalenessPolicy,
+    pub freeze_slots: u32,
 }
 
 impl OracleConfigParams {
@@ -6229,7 +10209,10 @@ impl OracleConfigParams {
         OracleConfig {
             conf_filter: self.conf_filter as f64,
             max_staleness_slots: self.max_staleness_slots.map(|v| v as i64).unwrap_or(-1),
-            reserved: [0; 72],
+            staleness_policy: self.staleness_policy.into(),
+            padding: Default::default(),
+            freeze_slots: self.freeze_slots,
+            reserved: [0; 64],
         }
     }
 }
@@ -6244,8 +10227,8 @@ pub enum OracleType {
 }
 
 pub struct OracleState {
-    pub price: f64,
-    pub deviation: f64,
+    pub price: I80F48,
+    pub deviation: I80F48,
     pub last_update_slot: u64,
     pub oracle_type: OracleType,
 }
@@ -6272,7 +10255,7 @@ impl OracleState {
     }
 
     pub fn has_valid_confidence(&self, oracle_pk: &Pubkey, config: &OracleConfig) -> bool {
-        if self.deviation > config.conf_filter * self.price {
+        if self.deviation > config.conf_filter().saturating_mul(self.price) {
             msg!(
                 "Oracle confidence not good enough: pubkey {}, price: {}, deviation: {}, conf_filter: {}",
                 oracle_pk,
@@ -6291,10 +10274,19 @@ impl OracleState {
         //   $ \sigma \approx \frac{A}{B} * \sqrt{(\sigma_A/A)^2 + (\sigma_B/B)^2} $
         // but alternatively, to avoid costly operations, we compute the square
         // Also note that the relative scaled var, i.e. without the \frac{A}{B} factor, is computed
-        let relative_var =
-            (self.deviation / self.price).powi(2) + (other.deviation / other.price).powi(2);
+        let (Some(relative_dev), Some(other_relative_dev)) = (
+            self.deviation.checked_div(self.price),
+            other.deviation.checked_div(other.price),
+        ) else {
+            msg!("Combined confidence can't be computed for a zero price");
+            return false;
+        };
+        let relative_var = relative_dev
+            .saturating_mul(relative_dev)
+            .saturating_add(other_relative_dev.saturating_mul(other_relative_dev));
 
-        let relative_target_var = config.conf_filter.powi(2);
+        let conf_filter = config.conf_filter();
+        let relative_target_var = conf_filter.saturating_mul(conf_filter);
 
         if relative_var > relative_target_var {
             msg!(
@@ -6399,10 +10391,13 @@ pub fn oracle_state_unchecked(acc_info: &impl KeyedAccountReader) -> Result<Orac
             } else {
                 stub.last_update_slot
             };
+            // Stored as f64 to keep the account layout, all computations are fixed point
             OracleState {
-                price: stub.price,
+                price: I80F48::checked_from_num(stub.price)
+                    .ok_or(OpenBookError::InvalidOraclePrice)?,
                 last_update_slot,
-                deviation: stub.deviation,
+                deviation: I80F48::checked_from_num(stub.deviation)
+                    .ok_or(OpenBookError::InvalidOraclePrice)?,
                 oracle_type: OracleType::Stub,
             }
         }
@@ -6410,11 +10405,11 @@ pub fn oracle_state_unchecked(acc_info: &impl KeyedAccountReader) -> Result<Orac
             let price_account = pyth_sdk_solana::state::load_price_account(data).unwrap();
             let (price_data, last_update_slot) = pyth_get_price(price_account);
 
-            let decimals = price_account.expo as i8;
-            let decimal_adj = power_of_ten_float(decimals);
-            let price = price_data.price as f64 * decimal_adj;
-            let deviation = price_data.conf as f64 * decimal_adj;
-            require_gte!(price, 0f64);
+            let price = decimal_to_fixed(price_data.price.into(), price_account.expo)
+                .ok_or(OpenBookError::InvalidOraclePrice)?;
+            let deviation = decimal_to_fixed(price_data.conf.into(), price_account.expo)
+                .ok_or(OpenBookError::InvalidOraclePrice)?;
+            require_gte!(price, I80F48::ZERO);
             OracleState {
                 price,
                 last_update_slot,
@@ -6427,20 +10422,27 @@ pub fn oracle_state_unchecked(acc_info: &impl KeyedAccountReader) -> Result<Orac
                 error_msg!("{}", e)
             }
 
+            fn from_switchboard_decimal(
+                decimal: switchboard_solana::SwitchboardDecimal,
+            ) -> Result<I80F48> {
+                let scale: i32 = decimal
+                    .scale
+                    .try_into()
+                    .map_err(|_| OpenBookError::InvalidOraclePrice)?;
+                decimal_to_fixed(decimal.mantissa, -scale)
+                    .ok_or_else(|| OpenBookError::InvalidOraclePrice.into())
+            }
+
             let feed = bytemuck::from_bytes::<AggregatorAccountData>(&data[8..]);
             let feed_result = feed.get_result().map_err(from_foreign_error)?;
-            let price: f64 = feed_result.try_into().map_err(from_foreign_error)?;
-            let deviation: f64 = feed
-                .latest_confirmed_round
-                .std_deviation
-                .try_into()
-                .map_err(from_foreign_error)?;
+            let price = from_switchboard_decimal(feed_result)?;
+            let deviation = from_switchboard_decimal(feed.latest_confirmed_round.std_deviation)?;
 
             // The round_open_slot is an underestimate of the last update slot: Reporters will see
             // the round opening and only then start executing the price tasks.
             let last_update_slot = feed.latest_confirmed_round.round_open_slot;
 
-            require_gte!(price, 0f64);
+            require_gte!(price, I80F48::ZERO);
             OracleState {
                 price,
                 last_update_slot,
@@ -6449,12 +10451,16 @@ pub fn oracle_state_unchecked(acc_info: &impl KeyedAccountReader) -> Result<Orac
             }
         }
         OracleType::SwitchboardV1 => {
+            // v1 only publishes floats, they are converted once and never used in arithmetic
             let result = FastRoundResultAccountData::deserialize(data).unwrap();
-            let price = result.result.result;
+            let price = I80F48::checked_from_num(result.result.result)
+                .ok_or(OpenBookError::InvalidOraclePrice)?;
 
-            let deviation = result.result.max_response - result.result.min_response;
+            let deviation =
+                I80F48::checked_from_num(result.result.max_response - result.result.min_response)
+                    .ok_or(OpenBookError::InvalidOraclePrice)?;
             let last_update_slot = result.result.round_open_slot;
-            require_gte!(price, 0f64);
+            require_gte!(price, I80F48::ZERO);
             OracleState {
                 price,
                 last_update_slot,
@@ -6467,15 +10473,16 @@ pub fn oracle_state_unchecked(acc_info: &impl KeyedAccountReader) -> Result<Orac
 
             let sqrt_price = U64F64::from_bits(pool.sqrt_price_x64);
 
-            let decimals: i8 = (pool.mint_decimals_0 as i8) - (pool.mint_decimals_1 as i8);
-            let price: f64 =
-                (sqrt_price * sqrt_price).to_num::<f64>() * power_of_ten_float(decimals);
+            let decimals = (pool.mint_decimals_0 as i32) - (pool.mint_decimals_1 as i32);
+            let price = I80F48::checked_from_num(sqrt_price * sqrt_price)
+                .and_then(|p| scale_by_power_of_ten(p, decimals))
+                .ok_or(OpenBookError::InvalidOraclePrice)?;
 
-            require_gte!(price, 0f64);
+            require_gte!(price, I80F48::ZERO);
             OracleState {
                 price,
                 last_update_slot: u64::MAX, // ensure staleness slot will never fail
-                deviation: 0f64,
+                deviation: I80F48::ZERO,
                 oracle_type: OracleType::RaydiumCLMM,
             }
         }
@@ -6551,23 +10558,121 @@ mod tests {
 
         let price_from_raydium_sdk = 24.470_087_964_273_85f64;
         println!("{:?}", oracle.price);
-        assert!((oracle.price - price_from_raydium_sdk).abs() < 1e-10);
+        assert!((oracle.price.to_num::<f64>() - price_from_raydium_sdk).abs() < 1e-10);
 
         Ok(())
     }
 
     #[test]
     pub fn lookup_test() {
-        for idx in -12..0_i8 {
-            let s = format!("0.{}1", str::repeat("0", (idx.unsigned_abs() as usize) - 1));
-            assert_eq!(power_of_ten_float(idx), f64::from_str(&s).unwrap());
+        for idx in 0..=MAX_DECIMAL_EXPONENT {
+            let s = format!("1{}", str::repeat("0", idx as usize));
+            assert_eq!(power_of_ten(idx), I80F48::from_str(&s).unwrap());
+        }
+
+        for idx in 1..=12_i32 {
+            let s = format!("0.{}1", str::repeat("0", (idx as usize) - 1));
+            let expected = I80F48::from_str(&s).unwrap();
+            let scaled = scale_by_power_of_ten(I80F48::ONE, -idx).unwrap();
+            assert!((scaled - expected).abs() <= I80F48::DELTA);
+        }
+    }
+
+    #[test]
+    pub fn test_decimal_to_fixed() {
+        // pyth style: mantissa and negative exponent
+        assert_eq!(decimal_to_fixed(4_000_000_000, -8), Some(I80F48::from(40)));
+        assert_eq!(decimal_to_fixed(-25, 1), Some(I80F48::from(-250)));
+        assert_eq!(decimal_to_fixed(1, 23), Some(power_of_ten(23)));
+        assert_eq!(decimal_to_fixed(1, 24), None);
+
+        // switchboard style: mantissa larger than the I80F48 range, digits are dropped
+        let mantissa = 24_470_087_964_273_850_000_000_000_000_i128;
+        let value = decimal_to_fixed(mantissa, -27).unwrap();
+        assert!((value.to_num::<f64>() - 24.470_087_964_273_85).abs() < 1e-12);
+        assert_eq!(decimal_to_fixed(i128::MAX, 0), None);
+    }
+
+    // The previous f64 pipeline, kept to check the fixed-point one against it
+    fn power_of_ten_float(decimals: i8) -> f64 {
+        const DECIMAL_CONSTANTS_F64: [f64; 25] = [
+            1e-12, 1e-11, 1e-10, 1e-9, 1e-8, 1e-7, 1e-6, 1e-5, 1e-4, 1e-3, 1e-2, 1e-1, 1e0, 1e1,
+            1e2, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8, 1e9, 1e10, 1e11, 1e12,
+        ];
+        DECIMAL_CONSTANTS_F64[(decimals + 12) as usize]
+    }
+
+    // f64 carries ~16 significant digits while I80F48 has an absolute resolution of 2^-48,
+    // which grows by 10^decimals when a rounded value is scaled up
+    fn tolerance(float: f64, decimals: i8) -> f64 {
+        float.abs() * 1e-12 + 1e-14 * power_of_ten_float(decimals.max(0))
+    }
+
+    fn assert_close(fixed: I80F48, float: f64, decimals: i8) {
+        let tolerance = tolerance(float, decimals);
+        assert!(
+            (fixed.to_num::<f64>() - float).abs() <= tolerance,
+            "fixed {} float {}",
+            fixed,
+            float
+        );
+    }
+
+    #[test]
+    pub fn test_pyth_price_matches_float() {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..10_000 {
+            let expo: i8 = rng.gen_range(-12..=0);
+            let mantissa: i64 = rng.gen_range(0..1_000_000_000_000);
+            let market_decimals: i8 = rng.gen_range(-12..=12);
+            if (expo + market_decimals).abs() > 12 {
+                continue;
+            }
+
+            let float = mantissa as f64 * power_of_ten_float(expo);
+            let float_native = float * power_of_ten_float(market_decimals);
+            if float_native > 1e12 {
+                continue;
+            }
+
+            let fixed = decimal_to_fixed(mantissa.into(), expo.into()).unwrap();
+            assert_close(fixed, float, 0);
+            let fixed_native = scale_by_power_of_ten(fixed, market_decimals.into()).unwrap();
+            assert_close(fixed_native, float_native, market_decimals);
         }
+    }
+
+    #[test]
+    pub fn test_price_ratio_matches_float() {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..10_000 {
+            let expo_a: i8 = rng.gen_range(-8..=0);
+            let expo_b: i8 = rng.gen_range(-8..=0);
+            let mantissa_a: i64 = rng.gen_range(1_000_000..1_000_000_000_000);
+            let mantissa_b: i64 = rng.gen_range(1_000_000..1_000_000_000_000);
+            let market_decimals: i8 = rng.gen_range(-6..=6);
+
+            let float_a = mantissa_a as f64 * power_of_ten_float(expo_a);
+            let float_b = mantissa_b as f64 * power_of_ten_float(expo_b);
+            let float = float_a / float_b * power_of_ten_float(market_decimals);
+            if !(1e-6..=1e12).contains(&float) {
+                continue;
+            }
 
-        assert_eq!(power_of_ten_float(0), 1.);
+            let fixed_a = decimal_to_fixed(mantissa_a.into(), expo_a.into()).unwrap();
+            let fixed_b = decimal_to_fixed(mantissa_b.into(), expo_b.into()).unwrap();
+            let fixed = fixed_a
+                .checked_div(fixed_b)
+                .and_then(|p| scale_by_power_of_ten(p, market_decimals.into()))
+                .unwrap();
 
-        for idx in 1..=12_i8 {
-            let s = format!("1{}", str::repeat("0", idx.unsigned_abs() as usize));
-            assert_eq!(power_of_ten_float(idx), f64::from_str(&s).unwrap());
+            // the ratio of two rounded inputs loses a few more bits than a single conversion
+            let tolerance = float * 1e-9 + tolerance(float, market_decimals);
+            assert!((fixed.to_num::<f64>() - float).abs() <= tolerance);
         }
     }
 }
@@ -6575,14 +10680,15 @@ mod tests {
 
 // File: openbook-v2/programs/openbook-v2/src/state/orderbook/book.rs
 use crate::logs::*;
-use crate::state::MAX_OPEN_ORDERS;
 use crate::{
     error::*,
-    state::{orderbook::bookside::*, EventHeap, Market, OpenOrdersAccount},
+    state::{
+        orderbook::bookside::*, EventHeapRefMut, EvictionPolicy, Market,
+        OpenOrdersAccountRefMut, OpenOrdersLoader, OracleStalenessPolicy,
+    },
 };
 use anchor_lang::prelude::*;
 use bytemuck::cast;
-use std::cell::RefMut;
 
 use super::*;
 
@@ -6593,9 +10699,33 @@ pub const DROP_EXPIRED_ORDER_LIMIT: usize = 5;
 /// Process up to this remaining accounts in the fill event
 pub const FILL_EVENT_REMAINING_LIMIT: usize = 15;
 
+/// Cancel at most this many pegged orders per BookSide when the oracle is unusable on a
+/// market with [`OracleStalenessPolicy::CancelPegged`].
+pub const CANCEL_PEGGED_ORDER_LIMIT: usize = 5;
+
+/// Emit one of the order lifecycle logs for a leaf on the given side and order tree
+macro_rules! emit_order_log {
+    ($emitter:expr, $log:ident, $market_pk:expr, $side:expr, $order_tree:expr, $leaf:expr) => {{
+        let leaf: &LeafNode = $leaf;
+        let order_tree: BookSideOrderTree = $order_tree;
+        $emitter.emit($log {
+            market: $market_pk,
+            owner: leaf.owner,
+            order_id: leaf.key,
+            client_order_id: leaf.client_order_id,
+            side_and_tree: SideAndOrderTree::new($side, order_tree).into(),
+            price_lots: match order_tree {
+                BookSideOrderTree::Fixed => fixed_price_lots(leaf.price_data()),
+                BookSideOrderTree::OraclePegged => oracle_pegged_price_offset(leaf.price_data()),
+            },
+            quantity: leaf.quantity,
+        })
+    }};
+}
+
 pub struct Orderbook<'a> {
-    pub bids: RefMut<'a, BookSide>,
-    pub asks: RefMut<'a, BookSide>,
+    pub bids: BookSideLoadedRefCellMut<'a>,
+    pub asks: BookSideLoadedRefCellMut<'a>,
 }
 
 pub struct OrderWithAmounts {
@@ -6619,14 +10749,14 @@ impl<'a> Orderbook<'a> {
         self.bids.is_empty() && self.asks.is_empty()
     }
 
-    pub fn bookside_mut(&mut self, side: Side) -> &mut BookSide {
+    pub fn bookside_mut(&mut self, side: Side) -> &mut BookSideLoadedRefCellMut<'a> {
         match side {
             Side::Bid => &mut self.bids,
             Side::Ask => &mut self.asks,
         }
     }
 
-    pub fn bookside(&self, side: Side) -> &BookSide {
+    pub fn bookside(&self, side: Side) -> &BookSideLoadedRefCellMut<'a> {
         match side {
             Side::Bid => &self.bids,
             Side::Ask => &self.asks,
@@ -6639,15 +10769,17 @@ impl<'a> Orderbook<'a> {
         order: &Order,
         open_book_market: &mut Market,
         market_pk: &Pubkey,
-        event_heap: &mut EventHeap,
+        event_heap: &mut EventHeapRefMut,
         oracle_price_lots: Option<i64>,
-        mut open_orders_account: Option<&mut OpenOrdersAccount>,
+        mut open_orders_account: Option<&mut OpenOrdersAccountRefMut>,
         owner: &Pubkey,
         now_ts: u64,
         mut limit: u8,
         remaining_accs: &'c [AccountInfo<'info>],
+        emitter: &EventEmitter,
     ) -> std::result::Result<OrderWithAmounts, Error> {
         let market = open_book_market;
+        limit = emitter.cap_limit(limit);
 
         let side = order.side;
 
@@ -6655,6 +10787,22 @@ impl<'a> Orderbook<'a> {
         let post_only = order.is_post_only();
         let fill_or_kill = order.is_fill_or_kill();
         let mut post_target = order.post_target();
+
+        if oracle_price_lots.is_none()
+            && market.oracle_config.staleness_policy() == OracleStalenessPolicy::CancelPegged
+        {
+            self.cancel_pegged_orders(
+                market,
+                market_pk,
+                event_heap,
+                open_orders_account.as_mut().map(|acc| &mut **acc),
+                owner,
+                now_ts,
+                remaining_accs,
+                emitter,
+            )?;
+        }
+
         let (price_lots, price_data) = order.price(now_ts, oracle_price_lots, self)?;
 
         // generate new order id
@@ -6696,6 +10844,15 @@ impl<'a> Orderbook<'a> {
         let mut number_of_dropped_expired_orders = 0;
         let mut number_of_processed_fill_events = 0;
 
+        // Owner wallet of the taker if it prevents self-trades with all of its accounts
+        let stp_owner = open_orders_account
+            .as_ref()
+            .filter(|oo| oo.self_trade_scope() == SelfTradeScope::Owner)
+            .map(|oo| oo.owner);
+        // Makers already checked against stp_owner. Deriving their address costs ~1500 CU, so
+        // it only happens once per maker account.
+        let mut stp_group_makers: Vec<(Pubkey, bool)> = vec![];
+
         let opposing_bookside = self.bookside_mut(other_side);
         for best_opposing in opposing_bookside.iter_all_including_invalid(now_ts, oracle_price_lots)
         {
@@ -6707,9 +10864,17 @@ impl<'a> Orderbook<'a> {
                 // Remove the order from the book unless we've done that enough
                 if number_of_dropped_expired_orders < DROP_EXPIRED_ORDER_LIMIT {
                     number_of_dropped_expired_orders += 1;
+                    emit_order_log!(
+                        emitter,
+                        OrderExpiredLog,
+                        *market_pk,
+                        other_side,
+                        best_opposing.handle.order_tree,
+                        best_opposing.node
+                    )?;
                     let event = OutEvent::new(
                         other_side,
-                        best_opposing.node.owner_slot,
+                        best_opposing.node.owner_slot(),
                         now_ts,
                         event_heap.header.seq_num,
                         best_opposing.node.owner,
@@ -6720,7 +10885,7 @@ impl<'a> Orderbook<'a> {
                         event,
                         market,
                         event_heap,
-                        open_orders_account.as_deref_mut(),
+                        open_orders_account.as_mut().map(|acc| &mut **acc),
                         owner,
                         remaining_accs,
                     )?;
@@ -6758,29 +10923,75 @@ impl<'a> Orderbook<'a> {
                 .min(max_match_by_quote);
             let match_quote_lots = match_base_lots * best_opposing_price;
 
-            // Self-trade behaviour
-            if open_orders_account.is_some() && owner == &best_opposing.node.owner {
+            // Self-trade behaviour, also for orders placed before a change of SelfTradeScope
+            let is_self_trade = open_orders_account.is_some()
+                && (owner == &best_opposing.node.owner
+                    || stp_owner.map_or(false, |stp_owner| {
+                        // orders without a group never match and need no derivation
+                        if best_opposing.node.stp_account_num == 0 {
+                            return false;
+                        }
+                        let maker = best_opposing.node.owner;
+                        match stp_group_makers.iter().find(|(pk, _)| pk == &maker) {
+                            Some(&(_, in_group)) => in_group,
+                            None => {
+                                let in_group = best_opposing.node.is_in_stp_group_of(&stp_owner);
+                                stp_group_makers.push((maker, in_group));
+                                in_group
+                            }
+                        }
+                    }));
+            if is_self_trade {
                 match order.self_trade_behavior {
                     SelfTradeBehavior::DecrementTake => {
                         // remember all decremented quote lots to only charge fees on not-self-trades
                         decremented_quote_lots += match_quote_lots;
                     }
-                    SelfTradeBehavior::CancelProvide => {
-                        // The open orders acc is always present in this case, no need event_heap
-                        open_orders_account.as_mut().unwrap().cancel_order(
-                            best_opposing.node.owner_slot as usize,
+                    SelfTradeBehavior::CancelProvide | SelfTradeBehavior::CancelBoth => {
+                        emit_order_log!(
+                            emitter,
+                            OrderCancelledLog,
+                            *market_pk,
+                            other_side,
+                            best_opposing.handle.order_tree,
+                            best_opposing.node
+                        )?;
+                        // The maker may be another account of the group, which only gets
+                        // cancelled directly if it's the taker or passed in remaining_accs
+                        let event = OutEvent::new(
+                            other_side,
+                            best_opposing.node.owner_slot(),
+                            now_ts,
+                            event_heap.header.seq_num,
+                            best_opposing.node.owner,
                             best_opposing.node.quantity,
-                            *market,
                         );
+                        process_out_event(
+                            event,
+                            market,
+                            event_heap,
+                            open_orders_account.as_mut().map(|acc| &mut **acc),
+                            owner,
+                            remaining_accs,
+                        )?;
                         matched_order_deletes
                             .push((best_opposing.handle.order_tree, best_opposing.node.key));
 
+                        if order.self_trade_behavior == SelfTradeBehavior::CancelBoth {
+                            post_target = None;
+                            break;
+                        }
+
                         // skip actual matching
                         continue;
                     }
                     SelfTradeBehavior::AbortTransaction => {
                         return err!(OpenBookError::WouldSelfTrade)
                     }
+                    SelfTradeBehavior::CancelTake => {
+                        post_target = None;
+                        break;
+                    }
                 }
                 assert!(order.self_trade_behavior == SelfTradeBehavior::DecrementTake);
             } else {
@@ -6794,7 +11005,10 @@ impl<'a> Orderbook<'a> {
 
             let new_best_opposing_quantity = best_opposing.node.quantity - match_base_lots;
             let maker_out = new_best_opposing_quantity == 0;
-            if maker_out {
+            // A remainder below the minimum order size would only take up book space
+            let maker_dust = !maker_out
+                && !market.meets_min_order_size(new_best_opposing_quantity, best_opposing_price);
+            if maker_out || maker_dust {
                 matched_order_deletes
                     .push((best_opposing.handle.order_tree, best_opposing.node.key));
             } else {
@@ -6804,7 +11018,7 @@ impl<'a> Orderbook<'a> {
             let fill = FillEvent::new(
                 side,
                 maker_out,
-                best_opposing.node.owner_slot,
+                best_opposing.node.owner_slot(),
                 now_ts,
                 market.seq_num,
                 best_opposing.node.owner,
@@ -6815,12 +11029,13 @@ impl<'a> Orderbook<'a> {
                 best_opposing_price,
                 best_opposing.node.peg_limit,
                 match_base_lots,
+                is_self_trade,
             );
 
-            emit_stack(TakerSignatureLog {
+            emitter.emit(TakerSignatureLog {
                 market: *market_pk,
                 seq_num: market.seq_num,
-            });
+            })?;
 
             process_fill_event(
                 fill,
@@ -6828,8 +11043,40 @@ impl<'a> Orderbook<'a> {
                 event_heap,
                 remaining_accs,
                 &mut number_of_processed_fill_events,
+                emitter,
             )?;
 
+            if maker_dust {
+                let dust = LeafNode {
+                    quantity: new_best_opposing_quantity,
+                    ..*best_opposing.node
+                };
+                emit_order_log!(
+                    emitter,
+                    OrderCancelledLog,
+                    *market_pk,
+                    other_side,
+                    best_opposing.handle.order_tree,
+                    &dust
+                )?;
+                let event = OutEvent::new(
+                    other_side,
+                    dust.owner_slot(),
+                    now_ts,
+                    event_heap.header.seq_num,
+                    dust.owner,
+                    dust.quantity,
+                );
+                process_out_event(
+                    event,
+                    market,
+                    event_heap,
+                    open_orders_account.as_mut().map(|acc| &mut **acc),
+                    owner,
+                    remaining_accs,
+                )?;
+            }
+
             limit -= 1;
         }
 
@@ -6864,7 +11111,8 @@ impl<'a> Orderbook<'a> {
                     total_quote_taken_native,
                     taker_fees_native,
                     referrer_amount,
-                );
+                    emitter,
+                )?;
             } else {
                 market.taker_volume_wo_oo += total_quote_taken_native as u128;
             }
@@ -6880,13 +11128,13 @@ impl<'a> Orderbook<'a> {
                 ),
             };
 
-            emit_stack(TotalOrderFillEvent {
+            emitter.emit(TotalOrderFillEvent {
                 side: side.into(),
                 taker: *owner,
                 total_quantity_paid,
                 total_quantity_received,
                 fees: taker_fees_native,
-            });
+            })?;
         }
 
         // The native taker fees in lots, rounded up.
@@ -6937,6 +11185,14 @@ impl<'a> Orderbook<'a> {
             post_target = None;
         }
 
+        if post_target.is_some() && !market.meets_min_order_size(book_base_quantity_lots, price_lots)
+        {
+            // Orders that matched keep their fills, only the remainder is dropped
+            require!(total_base_lots_taken > 0, OpenBookError::OrderBelowMinimumSize);
+            msg!("Remainder below the minimum order size is not posted");
+            post_target = None;
+        }
+
         if is_oracle_peg && side.is_price_better(price_lots, order.peg_limit()) {
             msg!(
                 "Posting on book disallowed due to peg_limit, order price {:?}, limit {:?}",
@@ -6980,10 +11236,20 @@ impl<'a> Orderbook<'a> {
 
             let bookside = self.bookside_mut(side);
             // Drop an expired order if possible
-            if let Some(expired_order) = bookside.remove_one_expired(order_tree_target, now_ts) {
+            if let Some((expired_order, expired_order_tree)) =
+                bookside.remove_one_expired(order_tree_target, now_ts)
+            {
+                emit_order_log!(
+                    emitter,
+                    OrderExpiredLog,
+                    *market_pk,
+                    side,
+                    expired_order_tree,
+                    &expired_order
+                )?;
                 let event = OutEvent::new(
                     side,
-                    expired_order.owner_slot,
+                    expired_order.owner_slot(),
                     now_ts,
                     event_heap.header.seq_num,
                     expired_order.owner,
@@ -7000,22 +11266,47 @@ impl<'a> Orderbook<'a> {
             }
 
             if bookside.is_full() {
-                // If this bid is higher than lowest bid, boot that bid and insert this one
-                let (worst_order, worst_price) =
-                    bookside.remove_worst(now_ts, oracle_price_lots).unwrap();
+                // Boot the order picked by the market's eviction policy if the new one
+                // is preferable to it and at least as well priced
+                let eviction_policy = market.eviction_policy();
+                let (evicted_order, evicted_order_tree, evicted_price, evicted_worst_price) =
+                    bookside
+                        .remove_for_eviction(eviction_policy, now_ts, oracle_price_lots)
+                        .unwrap();
+                let priced_at_least_as_well = !side.is_price_better(evicted_price, price_lots);
+                let can_evict = match eviction_policy {
+                    EvictionPolicy::WorstPrice => side.is_price_better(price_lots, evicted_price),
+                    EvictionPolicy::SmallestSize => {
+                        priced_at_least_as_well && book_base_quantity_lots > evicted_order.quantity
+                    }
+                    EvictionPolicy::Oldest => priced_at_least_as_well,
+                };
                 // OpenBookErrorCode::OutOfSpace
-                require!(
-                    side.is_price_better(price_lots, worst_price),
-                    OpenBookError::SomeError
-                );
-                let event = OutEvent::new(
+                require!(can_evict, OpenBookError::SomeError);
+                emit_order_log!(
+                    emitter,
+                    OrderEvictedLog,
+                    *market_pk,
                     side,
-                    worst_order.owner_slot,
-                    now_ts,
-                    event_heap.header.seq_num,
-                    worst_order.owner,
-                    worst_order.quantity,
-                );
+                    evicted_order_tree,
+                    &evicted_order
+                )?;
+                // Only the worst priced order forfeits its posting bond, a better priced one
+                // picked by the policy gets it back
+                let new_out_event = if evicted_worst_price {
+                    market.add_to_crank_reward_pool(market.posting_bond)?;
+                    OutEvent::new_evicted
+                } else {
+                    OutEvent::new
+                };
+                let event = new_out_event(
+                    side,
+                    evicted_order.owner_slot(),
+                    now_ts,
+                    event_heap.header.seq_num,
+                    evicted_order.owner,
+                    evicted_order.quantity,
+                );
                 process_out_event(
                     event,
                     market,
@@ -7027,17 +11318,30 @@ impl<'a> Orderbook<'a> {
             }
 
             let owner_slot = open_orders.next_order_slot()?;
-            let new_order = LeafNode::new(
-                owner_slot as u8,
-                order_id,
-                *owner,
-                book_base_quantity_lots,
-                now_ts,
-                order.time_in_force,
-                order.peg_limit(),
-                order.client_order_id,
-            );
+            let (stp_account_num, stp_bump) = open_orders.stp_group();
+            let new_order = LeafNode {
+                stp_bump,
+                stp_account_num,
+                ..LeafNode::new(
+                    owner_slot as u16,
+                    order_id,
+                    *owner,
+                    book_base_quantity_lots,
+                    now_ts,
+                    order.time_in_force,
+                    order.peg_limit(),
+                    order.client_order_id,
+                )
+            };
             let _result = bookside.insert_leaf(order_tree_target, &new_order)?;
+            emit_order_log!(
+                emitter,
+                OrderPlacedLog,
+                *market_pk,
+                side,
+                order_tree_target,
+                &new_order
+            )?;
 
             open_orders.add_order(
                 side,
@@ -7045,7 +11349,8 @@ impl<'a> Orderbook<'a> {
                 &new_order,
                 order.client_order_id,
                 price,
-            );
+                market,
+            )?;
         }
 
         let placed_order_id = if post_target.is_some() {
@@ -7066,20 +11371,134 @@ impl<'a> Orderbook<'a> {
         })
     }
 
+    /// Removes up to [`CANCEL_PEGGED_ORDER_LIMIT`] oracle pegged orders from each side of the book,
+    /// worst priced first. Funds are released the same way as for expired orders.
+    pub fn cancel_pegged_orders<'c: 'info, 'info>(
+        &mut self,
+        market: &Market,
+        market_pk: &Pubkey,
+        event_heap: &mut EventHeapRefMut,
+        mut open_orders_account: Option<&mut OpenOrdersAccountRefMut>,
+        owner: &Pubkey,
+        now_ts: u64,
+        remaining_accs: &'c [AccountInfo<'info>],
+        emitter: &EventEmitter,
+    ) -> Result<()> {
+        for side in [Side::Bid, Side::Ask] {
+            let bookside = self.bookside_mut(side);
+            for _ in 0..CANCEL_PEGGED_ORDER_LIMIT {
+                if event_heap.is_full() {
+                    return Ok(());
+                }
+
+                let root = bookside.root(BookSideOrderTree::OraclePegged);
+                let Some((_, worst_order)) = bookside.nodes().find_worst(root) else {
+                    break;
+                };
+                let order = bookside
+                    .remove_by_key(BookSideOrderTree::OraclePegged, worst_order.key)
+                    .unwrap();
+                emit_order_log!(
+                    emitter,
+                    OrderEvictedLog,
+                    *market_pk,
+                    side,
+                    BookSideOrderTree::OraclePegged,
+                    &order
+                )?;
+
+                let event = OutEvent::new(
+                    side,
+                    order.owner_slot(),
+                    now_ts,
+                    event_heap.header.seq_num,
+                    order.owner,
+                    order.quantity,
+                );
+                process_out_event(
+                    event,
+                    market,
+                    event_heap,
+                    open_orders_account.as_mut().map(|acc| &mut **acc),
+                    owner,
+                    remaining_accs,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of base lots a taker has to sell so that the quote it receives is exactly
+    /// `quote_out_native`, or None if the current bids can't produce that amount.
+    ///
+    /// Follows the fee accounting of [`Self::new_order`] for take orders without an
+    /// open orders account, where the taker pays the maker rebate of every fill.
+    pub fn base_lots_for_quote_out(
+        &self,
+        market: &Market,
+        quote_out_native: u64,
+        now_ts: u64,
+        oracle_price_lots: Option<i64>,
+    ) -> Result<Option<i64>> {
+        let quote_out_for_fill = |base_lots: i64, price_lots: i64| -> Result<u64> {
+            let quote_native: u64 = base_lots
+                .checked_mul(price_lots)
+                .and_then(|quote_lots| quote_lots.checked_mul(market.quote_lot_size))
+                .and_then(|quote_native| quote_native.try_into().ok())
+                .ok_or(OpenBookError::InvalidInputOrdersAmounts)?;
+            Ok(quote_native - market.maker_rebate_floor(quote_native))
+        };
+
+        let mut remaining_quote_native = quote_out_native;
+        let mut base_lots = 0_i64;
+        for order in self.bids.iter_valid(now_ts, oracle_price_lots) {
+            if remaining_quote_native == 0 {
+                break;
+            }
+
+            let quantity = order.node.quantity;
+            let full_fill = quote_out_for_fill(quantity, order.price_lots)?;
+            if full_fill < remaining_quote_native {
+                remaining_quote_native -= full_fill;
+                base_lots += quantity;
+                continue;
+            }
+
+            // smallest partial fill reaching the remaining amount, it has to match exactly
+            let (mut low, mut high) = (1, quantity);
+            while low < high {
+                let mid = low + (high - low) / 2;
+                if quote_out_for_fill(mid, order.price_lots)? < remaining_quote_native {
+                    low = mid + 1;
+                } else {
+                    high = mid;
+                }
+            }
+            return Ok(
+                (quote_out_for_fill(low, order.price_lots)? == remaining_quote_native)
+                    .then_some(base_lots + low),
+            );
+        }
+
+        Ok((remaining_quote_native == 0).then_some(base_lots))
+    }
+
     /// Cancels up to `limit` orders that are listed on the openorders account for the given market.
     /// Optionally filters by `side_to_cancel_option`.
     /// The orders are removed from the book and from the openorders account open order list.
     pub fn cancel_all_orders(
         &mut self,
-        open_orders_account: &mut OpenOrdersAccount,
+        open_orders_account: &mut OpenOrdersAccountRefMut,
         market: Market,
         mut limit: u8,
         side_to_cancel_option: Option<Side>,
         client_id_option: Option<u64>,
+        emitter: &EventEmitter,
     ) -> Result<i64> {
+        limit = emitter.cap_limit(limit);
         let mut total_quantity = 0_i64;
-        for i in 0..MAX_OPEN_ORDERS {
-            let oo = open_orders_account.open_orders[i];
+        for i in 0..open_orders_account.capacity() {
+            let oo = *open_orders_account.open_order_by_raw_index(i);
             if oo.is_free() {
                 continue;
             }
@@ -7110,6 +11529,7 @@ impl<'a> Orderbook<'a> {
                 order_side_and_tree,
                 market,
                 None,
+                emitter,
             );
             if cancel_result.is_anchor_error_with_code(OpenBookError::OrderIdNotFound.into()) {
                 // It's possible for the order to be filled or expired already.
@@ -7131,11 +11551,12 @@ impl<'a> Orderbook<'a> {
     /// Cancels an order on a side, removing it from the book and the openorders account orders list
     pub fn cancel_order(
         &mut self,
-        open_orders_account: &mut OpenOrdersAccount,
+        open_orders_account: &mut OpenOrdersAccountRefMut,
         order_id: u128,
         side_and_tree: SideAndOrderTree,
         market: Market,
         expected_owner: Option<Pubkey>,
+        emitter: &EventEmitter,
     ) -> Result<LeafNode> {
         let side = side_and_tree.side();
         let book_component = side_and_tree.order_tree();
@@ -7147,31 +11568,84 @@ impl<'a> Orderbook<'a> {
         if let Some(owner) = expected_owner {
             require_keys_eq!(leaf_node.owner, owner);
         }
-        open_orders_account.cancel_order(leaf_node.owner_slot as usize, leaf_node.quantity, market);
+        emit_order_log!(
+            emitter,
+            OrderCancelledLog,
+            open_orders_account.market,
+            side,
+            book_component,
+            &leaf_node
+        )?;
+        open_orders_account.cancel_order(leaf_node.owner_slot() as usize, leaf_node.quantity, market);
 
         Ok(leaf_node)
     }
+
+    /// Removes up to `limit` expired orders from both book sides, queueing an `OutEvent` that
+    /// refunds the posting bond minus `purge_bounty` for each one. Stops early when the event
+    /// heap is full and returns the number of removed orders.
+    pub fn purge_expired_orders(
+        &mut self,
+        market_pk: &Pubkey,
+        event_heap: &mut EventHeapRefMut,
+        now_ts: u64,
+        limit: u8,
+        purge_bounty: u64,
+        emitter: &EventEmitter,
+    ) -> Result<usize> {
+        let limit = emitter.cap_limit(limit);
+        let mut purged = 0;
+        for side in [Side::Bid, Side::Ask] {
+            let bookside = self.bookside_mut(side);
+            while purged < limit as usize && !event_heap.is_full() {
+                let Some((expired_order, expired_order_tree)) =
+                    bookside.remove_one_expired(BookSideOrderTree::Fixed, now_ts)
+                else {
+                    break;
+                };
+                emit_order_log!(
+                    emitter,
+                    OrderExpiredLog,
+                    *market_pk,
+                    side,
+                    expired_order_tree,
+                    &expired_order
+                )?;
+                let event = OutEvent::new_purged(
+                    side,
+                    expired_order.owner_slot(),
+                    now_ts,
+                    event_heap.header.seq_num,
+                    expired_order.owner,
+                    expired_order.quantity,
+                    purge_bounty,
+                );
+                event_heap.push_back(cast(event));
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
 }
 
 pub fn process_out_event<'c: 'info, 'info>(
     event: OutEvent,
     market: &Market,
-    event_heap: &mut EventHeap,
-    open_orders_account: Option<&mut OpenOrdersAccount>,
+    event_heap: &mut EventHeapRefMut,
+    open_orders_account: Option<&mut OpenOrdersAccountRefMut>,
     owner: &Pubkey,
     remaining_accs: &'c [AccountInfo<'info>],
 ) -> Result<()> {
     if let Some(acc) = open_orders_account {
         if owner == &event.owner {
-            acc.cancel_order(event.owner_slot as usize, event.quantity, *market);
+            acc.execute_out(&event, *market);
             return Ok(());
         }
     }
 
     if let Some(acc) = remaining_accs.iter().find(|ai| ai.key == &event.owner) {
-        let ooa: AccountLoader<OpenOrdersAccount> = AccountLoader::try_from(acc)?;
-        let mut acc = ooa.load_mut()?;
-        acc.cancel_order(event.owner_slot as usize, event.quantity, *market);
+        let mut acc = acc.load_full_mut()?;
+        acc.execute_out(&event, *market);
     } else {
         event_heap.push_back(cast(event));
     }
@@ -7182,16 +11656,16 @@ pub fn process_out_event<'c: 'info, 'info>(
 pub fn process_fill_event<'c: 'info, 'info>(
     event: FillEvent,
     market: &mut Market,
-    event_heap: &mut EventHeap,
+    event_heap: &mut EventHeapRefMut,
     remaining_accs: &'c [AccountInfo<'info>],
     number_of_processed_fill_events: &mut usize,
+    emitter: &EventEmitter,
 ) -> Result<()> {
     let mut is_processed = false;
     if *number_of_processed_fill_events < FILL_EVENT_REMAINING_LIMIT {
         if let Some(acc) = remaining_accs.iter().find(|ai| ai.key == &event.maker) {
-            let ooa: AccountLoader<OpenOrdersAccount> = AccountLoader::try_from(acc)?;
-            let mut maker = ooa.load_mut()?;
-            maker.execute_maker(market, &event);
+            let mut maker = acc.load_full_mut()?;
+            maker.execute_maker(market, &event, emitter)?;
             is_processed = true;
             *number_of_processed_fill_events += 1;
         }
@@ -7207,10 +11681,15 @@ pub fn process_fill_event<'c: 'info, 'info>(
 
 // File: openbook-v2/programs/openbook-v2/src/state/orderbook/bookside.rs
 use anchor_lang::prelude::*;
+use bytemuck::Zeroable;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use static_assertions::const_assert_eq;
+use std::cell::{Ref, RefMut};
+use std::mem::size_of;
+use std::ops::{Deref, DerefMut};
 
 use super::*;
+use crate::state::EvictionPolicy;
 
 #[derive(
     Eq,
@@ -7235,6 +11714,10 @@ pub struct BookSideOrderHandle {
     pub order_tree: BookSideOrderTree,
 }
 
+/// Fixed part of a book side account, the order tree nodes follow it in the account data.
+///
+/// The number of nodes is chosen when the account is created and derived from its size,
+/// see [`BookSide::capacity_for_space`].
 #[account(zero_copy)]
 pub struct BookSide {
     pub roots: [OrderTreeRoot; 2],
@@ -7246,10 +11729,108 @@ const_assert_eq!(
     std::mem::size_of::<BookSide>(),
     std::mem::size_of::<OrderTreeNodes>() + 6 * std::mem::size_of::<OrderTreeRoot>() + 256
 );
-const_assert_eq!(std::mem::size_of::<BookSide>(), 90944);
+const_assert_eq!(std::mem::size_of::<BookSide>(), 832);
 const_assert_eq!(std::mem::size_of::<BookSide>() % 8, 0);
 
 impl BookSide {
+    /// Number of bytes needed for a BookSide with `capacity` nodes, including the discriminator
+    pub fn space_for_capacity(capacity: usize) -> usize {
+        8 + size_of::<BookSide>() + capacity * size_of::<AnyNode>()
+    }
+
+    /// Number of nodes that fit in an account of `space` bytes
+    pub fn capacity_for_space(space: usize) -> usize {
+        space.saturating_sub(Self::space_for_capacity(0)) / size_of::<AnyNode>()
+    }
+
+    pub fn with_capacity_for_tests(
+        order_tree_type: OrderTreeType,
+        capacity: usize,
+    ) -> BookSideValue {
+        let mut fixed = Box::new(BookSide::zeroed());
+        fixed.nodes.order_tree_type = order_tree_type.into();
+        BookSideDynamic {
+            fixed,
+            dynamic: vec![AnyNode::zeroed(); capacity],
+        }
+    }
+
+    pub fn root(&self, component: BookSideOrderTree) -> &OrderTreeRoot {
+        &self.roots[component as usize]
+    }
+
+    pub fn root_mut(&mut self, component: BookSideOrderTree) -> &mut OrderTreeRoot {
+        &mut self.roots[component as usize]
+    }
+
+    pub fn side(&self) -> Side {
+        self.nodes.order_tree_type().side()
+    }
+}
+
+/// A BookSide together with the order tree nodes that follow it in the account data.
+///
+/// The fixed part is reachable through Deref, nodes must be accessed through the
+/// methods here.
+pub struct BookSideDynamic<Fixed, Dynamic> {
+    pub fixed: Fixed,
+    /// Order tree nodes, indexed by NodeHandle
+    pub dynamic: Dynamic,
+}
+
+pub type BookSideRef<'a> = BookSideDynamic<&'a BookSide, &'a [AnyNode]>;
+pub type BookSideRefMut<'a> = BookSideDynamic<&'a mut BookSide, &'a mut [AnyNode]>;
+pub type BookSideLoadedRefCell<'a> = BookSideDynamic<Ref<'a, BookSide>, Ref<'a, [AnyNode]>>;
+pub type BookSideLoadedRefCellMut<'a> =
+    BookSideDynamic<RefMut<'a, BookSide>, RefMut<'a, [AnyNode]>>;
+pub type BookSideValue = BookSideDynamic<Box<BookSide>, Vec<AnyNode>>;
+
+impl<F: Deref<Target = BookSide>, D> Deref for BookSideDynamic<F, D> {
+    type Target = BookSide;
+
+    fn deref(&self) -> &BookSide {
+        &self.fixed
+    }
+}
+
+impl<F: DerefMut<Target = BookSide>, D> DerefMut for BookSideDynamic<F, D> {
+    fn deref_mut(&mut self) -> &mut BookSide {
+        &mut self.fixed
+    }
+}
+
+impl BookSideValue {
+    /// Decodes an account from its data, including the discriminator
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let (fixed, dynamic) = split_book_side_data(data)?;
+        Ok(Self {
+            fixed: Box::new(*bytemuck::from_bytes(fixed)),
+            dynamic: bytemuck::cast_slice(dynamic).to_vec(),
+        })
+    }
+}
+
+impl<F: Deref<Target = BookSide>, D: Deref<Target = [AnyNode]>> BookSideDynamic<F, D> {
+    pub fn borrow(&self) -> BookSideRef {
+        BookSideDynamic {
+            fixed: &self.fixed,
+            dynamic: &self.dynamic,
+        }
+    }
+
+    /// The order trees' nodes, shared by both components
+    pub fn nodes(&self) -> OrderTreeNodesRef {
+        OrderTreeNodesRef {
+            fixed: &self.fixed.nodes,
+            dynamic: &self.dynamic,
+        }
+    }
+
+    /// Maximum number of nodes in this book side
+    pub fn capacity(&self) -> usize {
+        self.dynamic.len()
+    }
+
     /// Iterate over all entries in the book filtering out invalid orders
     ///
     /// smallest to highest for asks
@@ -7259,7 +11840,7 @@ impl BookSide {
         now_ts: u64,
         oracle_price_lots: Option<i64>,
     ) -> impl Iterator<Item = BookSideIterItem> {
-        BookSideIter::new(self, now_ts, oracle_price_lots).filter(|it| it.is_valid())
+        BookSideIter::new(self.borrow(), now_ts, oracle_price_lots).filter(|it| it.is_valid())
     }
 
     /// Iterate over all entries, including invalid orders
@@ -7268,33 +11849,115 @@ impl BookSide {
         now_ts: u64,
         oracle_price_lots: Option<i64>,
     ) -> BookSideIter {
-        BookSideIter::new(self, now_ts, oracle_price_lots)
+        BookSideIter::new(self.borrow(), now_ts, oracle_price_lots)
     }
 
     pub fn node(&self, handle: NodeHandle) -> Option<&AnyNode> {
-        self.nodes.node(handle)
+        self.nodes().node(handle)
     }
 
-    pub fn node_mut(&mut self, handle: NodeHandle) -> Option<&mut AnyNode> {
-        self.nodes.node_mut(handle)
+    pub fn is_full(&self) -> bool {
+        self.nodes().is_full()
     }
 
-    pub fn root(&self, component: BookSideOrderTree) -> &OrderTreeRoot {
-        &self.roots[component as usize]
+    pub fn is_empty(&self) -> bool {
+        [BookSideOrderTree::Fixed, BookSideOrderTree::OraclePegged]
+            .into_iter()
+            .all(|component| self.nodes().iter(self.root(component)).count() == 0)
     }
 
-    pub fn root_mut(&mut self, component: BookSideOrderTree) -> &mut OrderTreeRoot {
-        &mut self.roots[component as usize]
+    /// Return the quantity of orders that can be matched by an order at `limit_price_lots`
+    pub fn quantity_at_price(
+        &self,
+        limit_price_lots: i64,
+        now_ts: u64,
+        oracle_price_lots: Option<i64>,
+    ) -> i64 {
+        let side = self.side();
+        let mut sum = 0;
+        for item in self.iter_valid(now_ts, oracle_price_lots) {
+            if side.is_price_better(limit_price_lots, item.price_lots) {
+                break;
+            }
+            sum += item.node.quantity;
+        }
+        sum
     }
 
-    pub fn is_full(&self) -> bool {
-        self.nodes.is_full()
+    /// Return the price of the order closest to the spread
+    pub fn best_price(&self, now_ts: u64, oracle_price_lots: Option<i64>) -> Option<i64> {
+        Some(
+            self.iter_valid(now_ts, oracle_price_lots)
+                .next()?
+                .price_lots,
+        )
     }
 
-    pub fn is_empty(&self) -> bool {
-        [BookSideOrderTree::Fixed, BookSideOrderTree::OraclePegged]
-            .into_iter()
-            .all(|component| self.nodes.iter(self.root(component)).count() == 0)
+    /// Walk up the book `quantity` units and return the price at that level. If `quantity` units
+    /// not on book, return None
+    pub fn impact_price(
+        &self,
+        quantity: i64,
+        now_ts: u64,
+        oracle_price_lots: Option<i64>,
+    ) -> Option<i64> {
+        let mut sum: i64 = 0;
+        for order in self.iter_valid(now_ts, oracle_price_lots) {
+            sum += order.node.quantity;
+            if sum >= quantity {
+                return Some(order.price_lots);
+            }
+        }
+        None
+    }
+
+    /// Aggregate the quantity on the first `levels` price levels, best price first
+    pub fn depth(
+        &self,
+        levels: usize,
+        now_ts: u64,
+        oracle_price_lots: Option<i64>,
+    ) -> Vec<(i64, i64)> {
+        let mut depth: Vec<(i64, i64)> = Vec::with_capacity(levels);
+        for order in self.iter_valid(now_ts, oracle_price_lots) {
+            match depth.last_mut() {
+                Some((price_lots, quantity)) if *price_lots == order.price_lots => {
+                    *quantity += order.node.quantity;
+                }
+                _ if depth.len() == levels => break,
+                _ => depth.push((order.price_lots, order.node.quantity)),
+            }
+        }
+        depth
+    }
+}
+
+impl<F: DerefMut<Target = BookSide>, D: DerefMut<Target = [AnyNode]>> BookSideDynamic<F, D> {
+    pub fn borrow_mut(&mut self) -> BookSideRefMut {
+        BookSideDynamic {
+            fixed: &mut self.fixed,
+            dynamic: &mut self.dynamic,
+        }
+    }
+
+    /// Borrows the order tree roots and nodes separately, so both can be modified
+    fn roots_and_nodes_mut(&mut self) -> (&mut [OrderTreeRoot; 2], OrderTreeNodesRefMut) {
+        let fixed = &mut *self.fixed;
+        (
+            &mut fixed.roots,
+            OrderTreeNodesRefMut {
+                fixed: &mut fixed.nodes,
+                dynamic: &mut self.dynamic,
+            },
+        )
+    }
+
+    pub fn node_mut(&mut self, handle: NodeHandle) -> Option<&mut AnyNode> {
+        let node = &mut self.dynamic[handle as usize];
+        match NodeTag::try_from(node.tag) {
+            Ok(NodeTag::InnerNode) | Ok(NodeTag::LeafNode) => Some(node),
+            _ => None,
+        }
     }
 
     pub fn insert_leaf(
@@ -7302,8 +11965,8 @@ impl BookSide {
         component: BookSideOrderTree,
         new_leaf: &LeafNode,
     ) -> Result<(NodeHandle, Option<LeafNode>)> {
-        let root = &mut self.roots[component as usize];
-        self.nodes.insert_leaf(root, new_leaf)
+        let (roots, mut nodes) = self.roots_and_nodes_mut();
+        nodes.insert_leaf(&mut roots[component as usize], new_leaf)
     }
 
     /// Remove the overall worst-price order.
@@ -7311,10 +11974,11 @@ impl BookSide {
         &mut self,
         now_ts: u64,
         oracle_price_lots: Option<i64>,
-    ) -> Option<(LeafNode, i64)> {
-        let worst_fixed = self.nodes.find_worst(&self.roots[0]);
-        let worst_pegged = self.nodes.find_worst(&self.roots[1]);
-        let side = self.nodes.order_tree_type().side();
+    ) -> Option<(LeafNode, BookSideOrderTree, i64)> {
+        let nodes = self.nodes();
+        let worst_fixed = nodes.find_worst(&self.roots[0]);
+        let worst_pegged = nodes.find_worst(&self.roots[1]);
+        let side = self.side();
         let worse = rank_orders(
             side,
             worst_fixed,
@@ -7327,7 +11991,44 @@ impl BookSide {
         let key = worse.node.key;
         let order_tree = worse.handle.order_tree;
         let n = self.remove_by_key(order_tree, key)?;
-        Some((n, price))
+        Some((n, order_tree, price))
+    }
+
+    /// Remove the order chosen by the eviction policy to make room on a full book.
+    /// Ties are broken in favor of evicting the worse priced order.
+    ///
+    /// Also returns whether the removed order had the worst price on the book side.
+    pub fn remove_for_eviction(
+        &mut self,
+        policy: EvictionPolicy,
+        now_ts: u64,
+        oracle_price_lots: Option<i64>,
+    ) -> Option<(LeafNode, BookSideOrderTree, i64, bool)> {
+        let eviction_key: fn(&BookSideIterItem) -> u64 = match policy {
+            EvictionPolicy::WorstPrice => {
+                let (n, order_tree, price) = self.remove_worst(now_ts, oracle_price_lots)?;
+                return Some((n, order_tree, price, true));
+            }
+            EvictionPolicy::SmallestSize => |item: &BookSideIterItem| item.node.quantity as u64,
+            EvictionPolicy::Oldest => |item: &BookSideIterItem| item.node.timestamp,
+        };
+        // orders are iterated from best to worst price, so later ones win ties
+        let mut worst_price = None;
+        let candidate = self
+            .iter_all_including_invalid(now_ts, oracle_price_lots)
+            .inspect(|item| worst_price = Some(item.price_lots))
+            .reduce(|best, item| {
+                if eviction_key(&item) <= eviction_key(&best) {
+                    item
+                } else {
+                    best
+                }
+            })?;
+        let price = candidate.price_lots;
+        let key = candidate.node.key;
+        let order_tree = candidate.handle.order_tree;
+        let n = self.remove_by_key(order_tree, key)?;
+        Some((n, order_tree, price, worst_price == Some(price)))
     }
 
     /// Remove the order with the lowest expiry timestamp in the component, if that's < now_ts.
@@ -7336,18 +12037,20 @@ impl BookSide {
         &mut self,
         component: BookSideOrderTree,
         now_ts: u64,
-    ) -> Option<LeafNode> {
-        let root = &mut self.roots[component as usize];
-        if let Some(n) = self.nodes.remove_one_expired(root, now_ts) {
-            return Some(n);
+    ) -> Option<(LeafNode, BookSideOrderTree)> {
+        let (roots, mut nodes) = self.roots_and_nodes_mut();
+        let root = &mut roots[component as usize];
+        if let Some(n) = nodes.remove_one_expired(root, now_ts) {
+            return Some((n, component));
         }
 
         let other_component = match component {
             BookSideOrderTree::Fixed => BookSideOrderTree::OraclePegged,
             BookSideOrderTree::OraclePegged => BookSideOrderTree::Fixed,
         };
-        let other_root = &mut self.roots[other_component as usize];
-        self.nodes.remove_one_expired(other_root, now_ts)
+        let other_root = &mut roots[other_component as usize];
+        let n = nodes.remove_one_expired(other_root, now_ts)?;
+        Some((n, other_component))
     }
 
     pub fn remove_by_key(
@@ -7355,65 +12058,74 @@ impl BookSide {
         component: BookSideOrderTree,
         search_key: u128,
     ) -> Option<LeafNode> {
-        let root = &mut self.roots[component as usize];
-        self.nodes.remove_by_key(root, search_key)
+        let (roots, mut nodes) = self.roots_and_nodes_mut();
+        nodes.remove_by_key(&mut roots[component as usize], search_key)
     }
+}
 
-    pub fn side(&self) -> Side {
-        self.nodes.order_tree_type().side()
+/// Splits BookSide account data into the fixed part and the nodes that fit after it
+fn split_book_side_data(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    let fixed_end = BookSide::space_for_capacity(0);
+    require_gte!(data.len(), fixed_end, ErrorCode::AccountDidNotDeserialize);
+    let dynamic_end = BookSide::space_for_capacity(BookSide::capacity_for_space(data.len()));
+    Ok((&data[8..fixed_end], &data[fixed_end..dynamic_end]))
+}
+
+/// Loads a BookSide with all its nodes, see [`BookSideDynamic`]
+pub trait BookSideLoader<'a> {
+    fn load_full(self) -> Result<BookSideLoadedRefCell<'a>>;
+    fn load_full_mut(self) -> Result<BookSideLoadedRefCellMut<'a>>;
+    /// Like load_full_mut(), for accounts that don't have their discriminator yet
+    fn load_full_init(self) -> Result<BookSideLoadedRefCellMut<'a>>;
+}
+
+impl<'a, 'info: 'a> BookSideLoader<'a> for &'a AccountLoader<'info, BookSide> {
+    fn load_full(self) -> Result<BookSideLoadedRefCell<'a>> {
+        // checks the discriminator
+        drop(self.load()?);
+
+        let data = self.as_ref().try_borrow_data()?;
+        let (fixed, dynamic) = Ref::map_split(data, |data| {
+            let (fixed, dynamic) = split_book_side_data(data).unwrap();
+            (
+                bytemuck::from_bytes::<BookSide>(fixed),
+                bytemuck::cast_slice::<u8, AnyNode>(dynamic),
+            )
+        });
+        Ok(BookSideDynamic { fixed, dynamic })
     }
 
-    /// Return the quantity of orders that can be matched by an order at `limit_price_lots`
-    pub fn quantity_at_price(
-        &self,
-        limit_price_lots: i64,
-        now_ts: u64,
-        oracle_price_lots: i64,
-    ) -> i64 {
-        let side = self.side();
-        let mut sum = 0;
-        for item in self.iter_valid(now_ts, Some(oracle_price_lots)) {
-            if side.is_price_better(limit_price_lots, item.price_lots) {
-                break;
-            }
-            sum += item.node.quantity;
-        }
-        sum
+    fn load_full_mut(self) -> Result<BookSideLoadedRefCellMut<'a>> {
+        // checks the discriminator and that the account is writable
+        drop(self.load_mut()?);
+        load_book_side_data_mut(self.as_ref())
     }
 
-    /// Return the price of the order closest to the spread
-    pub fn best_price(&self, now_ts: u64, oracle_price_lots: Option<i64>) -> Option<i64> {
-        Some(
-            self.iter_valid(now_ts, oracle_price_lots)
-                .next()?
-                .price_lots,
-        )
+    fn load_full_init(self) -> Result<BookSideLoadedRefCellMut<'a>> {
+        // checks that the discriminator is unset and that the account is writable
+        drop(self.load_init()?);
+        load_book_side_data_mut(self.as_ref())
     }
+}
 
-    /// Walk up the book `quantity` units and return the price at that level. If `quantity` units
-    /// not on book, return None
-    pub fn impact_price(&self, quantity: i64, now_ts: u64, oracle_price_lots: i64) -> Option<i64> {
-        let mut sum: i64 = 0;
-        for order in self.iter_valid(now_ts, Some(oracle_price_lots)) {
-            sum += order.node.quantity;
-            if sum >= quantity {
-                return Some(order.price_lots);
-            }
-        }
-        None
-    }
+fn load_book_side_data_mut<'a>(info: &'a AccountInfo) -> Result<BookSideLoadedRefCellMut<'a>> {
+    let capacity = BookSide::capacity_for_space(info.data_len());
+    let data = info.try_borrow_mut_data()?;
+    let (fixed, dynamic) = RefMut::map_split(data, |data| {
+        let (fixed, dynamic) = data[8..].split_at_mut(size_of::<BookSide>());
+        (
+            bytemuck::from_bytes_mut::<BookSide>(fixed),
+            bytemuck::cast_slice_mut::<u8, AnyNode>(
+                &mut dynamic[..capacity * size_of::<AnyNode>()],
+            ),
+        )
+    });
+    Ok(BookSideDynamic { fixed, dynamic })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use bytemuck::Zeroable;
-
-    fn new_order_tree(order_tree_type: OrderTreeType) -> OrderTreeNodes {
-        let mut ot = OrderTreeNodes::zeroed();
-        ot.order_tree_type = order_tree_type.into();
-        ot
-    }
 
     fn bookside_iteration_random_helper(side: Side) {
         use rand::Rng;
@@ -7424,9 +12136,8 @@ mod tests {
             Side::Ask => OrderTreeType::Asks,
         };
 
-        let mut order_tree = new_order_tree(order_tree_type);
-        let mut root_fixed = OrderTreeRoot::zeroed();
-        let mut root_pegged = OrderTreeRoot::zeroed();
+        let mut bookside =
+            BookSide::with_capacity_for_tests(order_tree_type, DEFAULT_ORDERTREE_NODES);
         let new_leaf = |key: u128| LeafNode::new(0, key, Pubkey::default(), 0, 1, 0, -1, 0);
 
         // add 100 leaves to each BookSide, mostly random
@@ -7435,11 +12146,11 @@ mod tests {
         // ensure at least one oracle pegged order visible even at oracle price 1
         let key = new_node_key(side, oracle_pegged_price_data(20), 0);
         keys.push(key);
-        order_tree
-            .insert_leaf(&mut root_pegged, &new_leaf(key))
+        bookside
+            .insert_leaf(BookSideOrderTree::OraclePegged, &new_leaf(key))
             .unwrap();
 
-        while root_pegged.leaf_count < 100 {
+        while bookside.root(BookSideOrderTree::OraclePegged).leaf_count < 100 {
             let price_data: u64 = oracle_pegged_price_data(rng.gen_range(-20..20));
             let seq_num: u64 = rng.gen_range(0..1000);
             let key = new_node_key(side, price_data, seq_num);
@@ -7447,12 +12158,12 @@ mod tests {
                 continue;
             }
             keys.push(key);
-            order_tree
-                .insert_leaf(&mut root_pegged, &new_leaf(key))
+            bookside
+                .insert_leaf(BookSideOrderTree::OraclePegged, &new_leaf(key))
                 .unwrap();
         }
 
-        while root_fixed.leaf_count < 100 {
+        while bookside.root(BookSideOrderTree::Fixed).leaf_count < 100 {
             let price_data: u64 = rng.gen_range(1..50);
             let seq_num: u64 = rng.gen_range(0..1000);
             let key = new_node_key(side, price_data, seq_num);
@@ -7460,18 +12171,11 @@ mod tests {
                 continue;
             }
             keys.push(key);
-            order_tree
-                .insert_leaf(&mut root_fixed, &new_leaf(key))
+            bookside
+                .insert_leaf(BookSideOrderTree::Fixed, &new_leaf(key))
                 .unwrap();
         }
 
-        let bookside = BookSide {
-            roots: [root_fixed, root_pegged],
-            reserved_roots: [OrderTreeRoot::zeroed(); 4],
-            reserved: [0; 256],
-            nodes: order_tree,
-        };
-
         // verify iteration order for different oracle prices
         for oracle_price_lots in 1..40 {
             let mut total = 0;
@@ -7501,30 +12205,31 @@ mod tests {
         bookside_iteration_random_helper(Side::Ask);
     }
 
-    fn bookside_setup() -> BookSide {
+    fn bookside_setup() -> BookSideValue {
         use std::cell::RefCell;
 
         let side = Side::Bid;
         let order_tree_type = OrderTreeType::Bids;
 
-        let order_tree = RefCell::new(new_order_tree(order_tree_type));
-        let mut root_fixed = OrderTreeRoot::zeroed();
-        let mut root_pegged = OrderTreeRoot::zeroed();
+        let bookside = RefCell::new(BookSide::with_capacity_for_tests(
+            order_tree_type,
+            DEFAULT_ORDERTREE_NODES,
+        ));
         let new_node = |key: u128, tif: u16, peg_limit: i64| {
             LeafNode::new(0, key, Pubkey::default(), 0, 1000, tif, peg_limit, 0)
         };
-        let mut add_fixed = |price: i64, tif: u16| {
+        let add_fixed = |price: i64, tif: u16| {
             let key = new_node_key(side, fixed_price_data(price).unwrap(), 0);
-            order_tree
+            bookside
                 .borrow_mut()
-                .insert_leaf(&mut root_fixed, &new_node(key, tif, -1))
+                .insert_leaf(BookSideOrderTree::Fixed, &new_node(key, tif, -1))
                 .unwrap();
         };
-        let mut add_pegged = |price_offset: i64, tif: u16, peg_limit: i64| {
+        let add_pegged = |price_offset: i64, tif: u16, peg_limit: i64| {
             let key = new_node_key(side, oracle_pegged_price_data(price_offset), 0);
-            order_tree
+            bookside
                 .borrow_mut()
-                .insert_leaf(&mut root_pegged, &new_node(key, tif, peg_limit))
+                .insert_leaf(BookSideOrderTree::OraclePegged, &new_node(key, tif, peg_limit))
                 .unwrap();
         };
 
@@ -7534,12 +12239,7 @@ mod tests {
         add_pegged(-15, 0, -1);
         add_pegged(-20, 7, 95);
 
-        BookSide {
-            roots: [root_fixed, root_pegged],
-            reserved_roots: [OrderTreeRoot::zeroed(); 4],
-            reserved: [0; 256],
-            nodes: order_tree.into_inner(),
-        }
+        bookside.into_inner()
     }
 
     #[test]
@@ -7582,30 +12282,81 @@ mod tests {
 
         // remove pegged order
         assert_eq!(order_prices(0, 100), vec![120, 100, 90, 85, 80]);
-        let (_, p) = bookside.borrow_mut().remove_worst(0, Some(100)).unwrap();
+        let (_, _, p) = bookside.borrow_mut().remove_worst(0, Some(100)).unwrap();
         assert_eq!(p, 80);
         assert_eq!(order_prices(0, 100), vec![120, 100, 90, 85]);
 
         // remove fixed order (order at 190=200-10 hits the peg limit)
         assert_eq!(order_prices(0, 200), vec![185, 120, 100]);
-        let (_, p) = bookside.borrow_mut().remove_worst(0, Some(200)).unwrap();
+        let (_, _, p) = bookside.borrow_mut().remove_worst(0, Some(200)).unwrap();
         assert_eq!(p, 100);
         assert_eq!(order_prices(0, 200), vec![185, 120]);
 
         // remove until end
 
         assert_eq!(order_prices(0, 100), vec![120, 90, 85]);
-        let (_, p) = bookside.borrow_mut().remove_worst(0, Some(100)).unwrap();
+        let (_, _, p) = bookside.borrow_mut().remove_worst(0, Some(100)).unwrap();
         assert_eq!(p, 85);
         assert_eq!(order_prices(0, 100), vec![120, 90]);
-        let (_, p) = bookside.borrow_mut().remove_worst(0, Some(100)).unwrap();
+        let (_, _, p) = bookside.borrow_mut().remove_worst(0, Some(100)).unwrap();
         assert_eq!(p, 90);
         assert_eq!(order_prices(0, 100), vec![120]);
-        let (_, p) = bookside.borrow_mut().remove_worst(0, Some(100)).unwrap();
+        let (_, _, p) = bookside.borrow_mut().remove_worst(0, Some(100)).unwrap();
         assert_eq!(p, 120);
         assert_eq!(order_prices(0, 100), Vec::<i64>::new());
     }
 
+    #[test]
+    fn bookside_remove_for_eviction() {
+        let side = Side::Bid;
+        let mut bookside =
+            BookSide::with_capacity_for_tests(OrderTreeType::Bids, DEFAULT_ORDERTREE_NODES);
+        let mut add_fixed = |price: i64, quantity: i64, timestamp: u64| {
+            let key = new_node_key(side, fixed_price_data(price).unwrap(), 0);
+            let node = LeafNode::new(0, key, Pubkey::default(), quantity, timestamp, 0, -1, 0);
+            bookside.insert_leaf(BookSideOrderTree::Fixed, &node).unwrap();
+        };
+
+        add_fixed(100, 5, 10);
+        add_fixed(110, 2, 30);
+        add_fixed(90, 2, 20);
+        add_fixed(95, 7, 20);
+
+        let mut evict = |policy: EvictionPolicy| {
+            bookside
+                .remove_for_eviction(policy, 0, None)
+                .map(|(_, _, price, worst_price)| (price, worst_price))
+        };
+
+        // equal sizes, the worse priced order goes first
+        assert_eq!(evict(EvictionPolicy::SmallestSize), Some((90, true)));
+        assert_eq!(evict(EvictionPolicy::Oldest), Some((100, false)));
+        assert_eq!(evict(EvictionPolicy::Oldest), Some((95, true)));
+        assert_eq!(evict(EvictionPolicy::WorstPrice), Some((110, true)));
+        assert_eq!(evict(EvictionPolicy::SmallestSize), None);
+    }
+
+    #[test]
+    fn bookside_depth() {
+        let bookside = bookside_setup();
+
+        assert_eq!(
+            bookside.depth(10, 0, Some(110)),
+            vec![(120, 1000), (100, 2000), (95, 1000), (90, 1000)]
+        );
+        assert_eq!(
+            bookside.depth(2, 0, Some(110)),
+            vec![(120, 1000), (100, 2000)]
+        );
+        assert_eq!(bookside.depth(0, 0, Some(110)), vec![]);
+
+        // pegged orders are skipped without an oracle price
+        assert_eq!(
+            bookside.depth(10, 0, None),
+            vec![(120, 1000), (100, 1000)]
+        );
+    }
+
     // add test for oracle expired
 }
 
@@ -7642,14 +12393,14 @@ pub struct BookSideIter<'a> {
 }
 
 impl<'a> BookSideIter<'a> {
-    pub fn new(book_side: &'a BookSide, now_ts: u64, oracle_price_lots: Option<i64>) -> Self {
+    pub fn new(book_side: BookSideRef<'a>, now_ts: u64, oracle_price_lots: Option<i64>) -> Self {
+        let nodes = OrderTreeNodesRef {
+            fixed: &book_side.fixed.nodes,
+            dynamic: book_side.dynamic,
+        };
         Self {
-            fixed_iter: book_side
-                .nodes
-                .iter(book_side.root(BookSideOrderTree::Fixed)),
-            oracle_pegged_iter: book_side
-                .nodes
-                .iter(book_side.root(BookSideOrderTree::OraclePegged)),
+            fixed_iter: nodes.iter(book_side.root(BookSideOrderTree::Fixed)),
+            oracle_pegged_iter: nodes.iter(book_side.root(BookSideOrderTree::OraclePegged)),
             now_ts,
             oracle_price_lots,
         }
@@ -7822,49 +12573,63 @@ impl<'a> Iterator for BookSideIter<'a> {
 // File: openbook-v2/programs/openbook-v2/src/state/orderbook/heap.rs
 use crate::error::OpenBookError;
 use anchor_lang::prelude::*;
+use bytemuck::Zeroable;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use static_assertions::const_assert_eq;
+use std::cell::{Ref, RefMut};
 use std::mem::size_of;
+use std::ops::{Deref, DerefMut};
 
 use super::Side;
 
+/// Number of events of an event heap account created with the default size
+pub const DEFAULT_NUM_EVENTS: u16 = 600;
+/// Smallest number of events an event heap can be created with
+pub const MIN_NUM_EVENTS: u16 = 64;
+/// Largest number of events an event heap can be created with, slots must stay below NO_NODE
+pub const MAX_EVENT_HEAP_CAPACITY: u16 = NO_NODE;
+/// Number of events of every event heap before their capacity became configurable
+#[deprecated(note = "use DEFAULT_NUM_EVENTS or MAX_EVENT_HEAP_CAPACITY")]
 pub const MAX_NUM_EVENTS: u16 = 600;
 pub const NO_NODE: u16 = u16::MAX;
 
 /// Container for the different EventTypes.
 ///
-/// Events are stored in a fixed-array of nodes. Free nodes are connected by a single-linked list
-/// starting at free_head while used nodes form a circular doubly-linked list starting at
-/// used_head.
+/// Events are stored in an array of nodes following this header in the account data, its
+/// length is chosen when the account is created, see [`EventHeap::capacity_for_space`].
+/// Free nodes are connected by a single-linked list starting at free_head while used nodes
+/// form a circular doubly-linked list starting at used_head.
 #[account(zero_copy)]
 pub struct EventHeap {
     pub header: EventHeapHeader,
-    pub nodes: [EventNode; MAX_NUM_EVENTS as usize],
-    pub reserved: [u8; 64],
 }
-const_assert_eq!(
-    std::mem::size_of::<EventHeap>(),
-    16 + MAX_NUM_EVENTS as usize * (EVENT_SIZE + 8) + 64
-);
-// Costs 0.636 SOL to create this account
-const_assert_eq!(std::mem::size_of::<EventHeap>(), 91280);
+const_assert_eq!(std::mem::size_of::<EventHeap>(), 16);
 const_assert_eq!(std::mem::size_of::<EventHeap>() % 8, 0);
 
 impl EventHeap {
-    pub fn init(&mut self) {
-        self.header = EventHeapHeader {
-            free_head: 0,
-            used_head: NO_NODE,
-            count: 0,
-            seq_num: 0,
-            _padd: Default::default(),
-        };
+    /// Number of bytes needed for an EventHeap with `capacity` events, including the
+    /// discriminator
+    pub fn space_for_capacity(capacity: usize) -> usize {
+        8 + size_of::<EventHeap>() + capacity * size_of::<EventNode>()
+    }
 
-        for i in 0..MAX_NUM_EVENTS {
-            self.nodes[i as usize].next = i + 1;
-            self.nodes[i as usize].prev = NO_NODE;
-        }
-        self.nodes[MAX_NUM_EVENTS as usize - 1].next = NO_NODE;
+    /// Number of events that fit in an account of `space` bytes
+    ///
+    /// Accounts created before the capacity was configurable have 64 reserved bytes at the
+    /// end, which don't make up a whole node.
+    pub fn capacity_for_space(space: usize) -> usize {
+        space.saturating_sub(Self::space_for_capacity(0)) / size_of::<EventNode>()
+    }
+
+    pub fn with_capacity_for_tests(capacity: u16) -> EventHeapValue {
+        let mut heap = EventHeapDynamic {
+            fixed: Box::new(EventHeap {
+                header: EventHeapHeader::zeroed(),
+            }),
+            dynamic: vec![EventNode::zeroed(); capacity as usize],
+        };
+        heap.init();
+        heap
     }
 
     pub fn len(&self) -> usize {
@@ -7874,54 +12639,157 @@ impl EventHeap {
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
+}
+
+/// An EventHeap together with the event nodes that follow it in the account data.
+///
+/// The fixed part is reachable through Deref, nodes must be accessed through the
+/// methods here.
+pub struct EventHeapDynamic<Fixed, Dynamic> {
+    pub fixed: Fixed,
+    /// Event nodes, indexed by slot
+    pub dynamic: Dynamic,
+}
+
+pub type EventHeapRef<'a> = EventHeapDynamic<&'a EventHeap, &'a [EventNode]>;
+pub type EventHeapRefMut<'a> = EventHeapDynamic<&'a mut EventHeap, &'a mut [EventNode]>;
+pub type EventHeapLoadedRefCell<'a> = EventHeapDynamic<Ref<'a, EventHeap>, Ref<'a, [EventNode]>>;
+pub type EventHeapLoadedRefCellMut<'a> =
+    EventHeapDynamic<RefMut<'a, EventHeap>, RefMut<'a, [EventNode]>>;
+pub type EventHeapValue = EventHeapDynamic<Box<EventHeap>, Vec<EventNode>>;
+
+impl<F: Deref<Target = EventHeap>, D> Deref for EventHeapDynamic<F, D> {
+    type Target = EventHeap;
+
+    fn deref(&self) -> &EventHeap {
+        &self.fixed
+    }
+}
+
+impl<F: DerefMut<Target = EventHeap>, D> DerefMut for EventHeapDynamic<F, D> {
+    fn deref_mut(&mut self) -> &mut EventHeap {
+        &mut self.fixed
+    }
+}
+
+impl EventHeapValue {
+    /// Decodes an account from its data, including the discriminator
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let (fixed, dynamic) = split_event_heap_data(data)?;
+        Ok(Self {
+            fixed: Box::new(*bytemuck::from_bytes(fixed)),
+            dynamic: bytemuck::cast_slice(dynamic).to_vec(),
+        })
+    }
+}
+
+impl<F: Deref<Target = EventHeap>, D: Deref<Target = [EventNode]>> EventHeapDynamic<F, D> {
+    pub fn borrow(&self) -> EventHeapRef {
+        EventHeapDynamic {
+            fixed: &self.fixed,
+            dynamic: &self.dynamic,
+        }
+    }
+
+    /// Maximum number of events in the heap
+    pub fn capacity(&self) -> usize {
+        self.dynamic.len()
+    }
 
     pub fn is_full(&self) -> bool {
-        self.len() == self.nodes.len()
+        self.len() == self.capacity()
     }
 
     pub fn front(&self) -> Option<&AnyEvent> {
         if self.is_empty() {
             None
         } else {
-            Some(&self.nodes[self.header.used_head()].event)
+            Some(&self.dynamic[self.header.used_head()].event)
         }
     }
 
     pub fn at_slot(&self, slot: usize) -> Option<&AnyEvent> {
-        if slot >= self.nodes.len() || self.nodes[slot].is_free() {
+        if slot >= self.capacity() || self.dynamic[slot].is_free() {
             None
         } else {
-            Some(&self.nodes[slot].event)
+            Some(&self.dynamic[slot].event)
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&AnyEvent, usize)> {
+        EventHeapIterator {
+            nodes: &self.dynamic,
+            len: self.len(),
+            index: 0,
+            slot: self.header.used_head(),
+        }
+    }
+}
+
+impl<F: DerefMut<Target = EventHeap>, D: DerefMut<Target = [EventNode]>> EventHeapDynamic<F, D> {
+    pub fn borrow_mut(&mut self) -> EventHeapRefMut {
+        EventHeapDynamic {
+            fixed: &mut self.fixed,
+            dynamic: &mut self.dynamic,
+        }
+    }
+
+    pub fn init(&mut self) {
+        self.fixed.header = EventHeapHeader {
+            free_head: 0,
+            used_head: NO_NODE,
+            count: 0,
+            seq_num: 0,
+            _padd: Default::default(),
+        };
+
+        let capacity = self.capacity() as u16;
+        for (i, node) in (0..capacity).zip(self.dynamic.iter_mut()) {
+            node.next = i + 1;
+            node.prev = NO_NODE;
         }
+        self.dynamic[capacity as usize - 1].next = NO_NODE;
     }
 
     pub fn push_back(&mut self, value: AnyEvent) {
         assert!(!self.is_full());
 
-        let slot = self.header.free_head;
-        self.header.free_head = self.nodes[slot as usize].next;
+        let header = &mut self.fixed.header;
+        let nodes = &mut *self.dynamic;
+
+        let slot = header.free_head;
+        header.free_head = nodes[slot as usize].next;
 
         let new_next: u16;
         let new_prev: u16;
 
-        if self.is_empty() {
+        if header.count() == 0 {
             new_next = slot;
             new_prev = slot;
 
-            self.header.used_head = slot;
+            header.used_head = slot;
         } else {
-            new_next = self.header.used_head;
-            new_prev = self.nodes[new_next as usize].prev;
+            new_next = header.used_head;
+            new_prev = nodes[new_next as usize].prev;
 
-            self.nodes[new_prev as usize].next = slot;
-            self.nodes[new_next as usize].prev = slot;
+            nodes[new_prev as usize].next = slot;
+            nodes[new_next as usize].prev = slot;
         }
 
-        self.header.incr_count();
-        self.header.incr_event_id();
-        self.nodes[slot as usize].event = value;
-        self.nodes[slot as usize].next = new_next;
-        self.nodes[slot as usize].prev = new_prev;
+        header.incr_count();
+        header.incr_event_id();
+        nodes[slot as usize].event = value;
+        nodes[slot as usize].next = new_next;
+        nodes[slot as usize].prev = new_prev;
+    }
+
+    /// Sets the crank reward of the `count` most recently pushed events
+    pub fn set_newest_crank_rewards(&mut self, count: usize, crank_reward: u64) {
+        let mut slot = self.header.used_head();
+        for _ in 0..count {
+            slot = self.dynamic[slot].prev as usize;
+            self.dynamic[slot].event.set_crank_reward(crank_reward);
+        }
     }
 
     pub fn pop_front(&mut self) -> Result<AnyEvent> {
@@ -7929,42 +12797,38 @@ impl EventHeap {
     }
 
     pub fn delete_slot(&mut self, slot: usize) -> Result<AnyEvent> {
-        if slot >= self.nodes.len() || self.is_empty() || self.nodes[slot].is_free() {
+        if slot >= self.capacity() || self.is_empty() || self.dynamic[slot].is_free() {
             return Err(OpenBookError::SomeError.into());
         }
 
-        let prev_slot = self.nodes[slot].prev;
-        let next_slot = self.nodes[slot].next;
-        let next_free = self.header.free_head;
+        let header = &mut self.fixed.header;
+        let nodes = &mut *self.dynamic;
 
-        self.nodes[prev_slot as usize].next = next_slot;
-        self.nodes[next_slot as usize].prev = prev_slot;
+        let prev_slot = nodes[slot].prev;
+        let next_slot = nodes[slot].next;
+        let next_free = header.free_head;
 
-        if self.header.count() == 1 {
-            self.header.used_head = NO_NODE;
-        } else if self.header.used_head() == slot {
-            self.header.used_head = next_slot;
-        };
+        nodes[prev_slot as usize].next = next_slot;
+        nodes[next_slot as usize].prev = prev_slot;
 
-        self.header.decr_count();
-        self.header.free_head = slot.try_into().unwrap();
-        self.nodes[slot].next = next_free;
-        self.nodes[slot].prev = NO_NODE;
+        if header.count() == 1 {
+            header.used_head = NO_NODE;
+        } else if header.used_head() == slot {
+            header.used_head = next_slot;
+        };
 
-        Ok(self.nodes[slot].event)
-    }
+        header.decr_count();
+        header.free_head = slot.try_into().unwrap();
+        nodes[slot].next = next_free;
+        nodes[slot].prev = NO_NODE;
 
-    pub fn iter(&self) -> impl Iterator<Item = (&AnyEvent, usize)> {
-        EventHeapIterator {
-            heap: self,
-            index: 0,
-            slot: self.header.used_head(),
-        }
+        Ok(nodes[slot].event)
     }
 }
 
 struct EventHeapIterator<'a> {
-    heap: &'a EventHeap,
+    nodes: &'a [EventNode],
+    len: usize,
     index: usize,
     slot: usize,
 }
@@ -7972,17 +12836,77 @@ struct EventHeapIterator<'a> {
 impl<'a> Iterator for EventHeapIterator<'a> {
     type Item = (&'a AnyEvent, usize);
     fn next(&mut self) -> Option<Self::Item> {
-        if self.index == self.heap.len() {
+        if self.index == self.len {
             None
         } else {
             let current_slot = self.slot;
-            self.slot = self.heap.nodes[current_slot].next as usize;
+            self.slot = self.nodes[current_slot].next as usize;
             self.index += 1;
-            Some((&self.heap.nodes[current_slot].event, current_slot))
+            Some((&self.nodes[current_slot].event, current_slot))
         }
     }
 }
 
+/// Splits EventHeap account data into the fixed part and the nodes that fit after it
+fn split_event_heap_data(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    let fixed_end = EventHeap::space_for_capacity(0);
+    require_gte!(data.len(), fixed_end, ErrorCode::AccountDidNotDeserialize);
+    let dynamic_end = EventHeap::space_for_capacity(EventHeap::capacity_for_space(data.len()));
+    Ok((&data[8..fixed_end], &data[fixed_end..dynamic_end]))
+}
+
+/// Loads an EventHeap with all its nodes, see [`EventHeapDynamic`]
+pub trait EventHeapLoader<'a> {
+    fn load_full(self) -> Result<EventHeapLoadedRefCell<'a>>;
+    fn load_full_mut(self) -> Result<EventHeapLoadedRefCellMut<'a>>;
+    /// Like load_full_mut(), for accounts that don't have their discriminator yet
+    fn load_full_init(self) -> Result<EventHeapLoadedRefCellMut<'a>>;
+}
+
+impl<'a, 'info: 'a> EventHeapLoader<'a> for &'a AccountLoader<'info, EventHeap> {
+    fn load_full(self) -> Result<EventHeapLoadedRefCell<'a>> {
+        // checks the discriminator
+        drop(self.load()?);
+
+        let data = self.as_ref().try_borrow_data()?;
+        let (fixed, dynamic) = Ref::map_split(data, |data| {
+            let (fixed, dynamic) = split_event_heap_data(data).unwrap();
+            (
+                bytemuck::from_bytes::<EventHeap>(fixed),
+                bytemuck::cast_slice::<u8, EventNode>(dynamic),
+            )
+        });
+        Ok(EventHeapDynamic { fixed, dynamic })
+    }
+
+    fn load_full_mut(self) -> Result<EventHeapLoadedRefCellMut<'a>> {
+        // checks the discriminator and that the account is writable
+        drop(self.load_mut()?);
+        load_event_heap_data_mut(self.as_ref())
+    }
+
+    fn load_full_init(self) -> Result<EventHeapLoadedRefCellMut<'a>> {
+        // checks that the discriminator is unset and that the account is writable
+        drop(self.load_init()?);
+        load_event_heap_data_mut(self.as_ref())
+    }
+}
+
+fn load_event_heap_data_mut<'a>(info: &'a AccountInfo) -> Result<EventHeapLoadedRefCellMut<'a>> {
+    let capacity = EventHeap::capacity_for_space(info.data_len());
+    let data = info.try_borrow_mut_data()?;
+    let (fixed, dynamic) = RefMut::map_split(data, |data| {
+        let (fixed, dynamic) = data[8..].split_at_mut(size_of::<EventHeap>());
+        (
+            bytemuck::from_bytes_mut::<EventHeap>(fixed),
+            bytemuck::cast_slice_mut::<u8, EventNode>(
+                &mut dynamic[..capacity * size_of::<EventNode>()],
+            ),
+        )
+    });
+    Ok(EventHeapDynamic { fixed, dynamic })
+}
+
 #[zero_copy]
 pub struct EventHeapHeader {
     free_head: u16,
@@ -8047,6 +12971,28 @@ pub struct AnyEvent {
 
 const_assert_eq!(size_of::<AnyEvent>(), EVENT_SIZE);
 
+impl AnyEvent {
+    pub fn crank_reward(&self) -> u64 {
+        match EventType::try_from(self.event_type).unwrap() {
+            EventType::Fill => bytemuck::cast::<_, FillEvent>(*self).crank_reward,
+            EventType::Out => bytemuck::cast::<_, OutEvent>(*self).crank_reward,
+        }
+    }
+
+    pub fn set_crank_reward(&mut self, crank_reward: u64) {
+        *self = match EventType::try_from(self.event_type).unwrap() {
+            EventType::Fill => bytemuck::cast(FillEvent {
+                crank_reward,
+                ..bytemuck::cast(*self)
+            }),
+            EventType::Out => bytemuck::cast(OutEvent {
+                crank_reward,
+                ..bytemuck::cast(*self)
+            }),
+        };
+    }
+}
+
 #[derive(Copy, Clone, IntoPrimitive, TryFromPrimitive, Eq, PartialEq)]
 #[repr(u8)]
 pub enum EventType {
@@ -8062,8 +13008,11 @@ pub struct FillEvent {
     pub event_type: u8,
     pub taker_side: u8, // Side, from the taker's POV
     pub maker_out: u8,  // 1 if maker order quantity == 0
-    pub maker_slot: u8,
-    pub padding: [u8; 4],
+    pub maker_slot_lo: u8,
+    pub maker_slot_hi: u8,
+    /// 1 if maker and taker are in the same self-trade prevention group
+    pub self_trade: u8,
+    pub padding: [u8; 2],
     pub timestamp: u64,
     pub market_seq_num: u64,
 
@@ -8079,7 +13028,8 @@ pub struct FillEvent {
     pub peg_limit: i64,
     pub quantity: i64, // number of base lots
     pub maker_client_order_id: u64,
-    pub reserved: [u8; 8],
+    /// Lamports paid to the cranker consuming this event, see Market::crank_reward_per_event()
+    pub crank_reward: u64,
 }
 const_assert_eq!(size_of::<FillEvent>() % 8, 0);
 const_assert_eq!(size_of::<FillEvent>(), EVENT_SIZE);
@@ -8089,7 +13039,7 @@ impl FillEvent {
     pub fn new(
         taker_side: Side,
         maker_out: bool,
-        maker_slot: u8,
+        maker_slot: u16,
         timestamp: u64,
         market_seq_num: u64,
         maker: Pubkey,
@@ -8100,12 +13050,15 @@ impl FillEvent {
         price: i64,
         peg_limit: i64,
         quantity: i64,
+        self_trade: bool,
     ) -> FillEvent {
         Self {
             event_type: EventType::Fill as u8,
             taker_side: taker_side.into(),
             maker_out: maker_out.into(),
-            maker_slot,
+            maker_slot_lo: maker_slot as u8,
+            maker_slot_hi: (maker_slot >> 8) as u8,
+            self_trade: self_trade.into(),
             timestamp,
             market_seq_num,
             maker,
@@ -8117,7 +13070,7 @@ impl FillEvent {
             peg_limit,
             quantity,
             padding: Default::default(),
-            reserved: [0; 8],
+            crank_reward: 0,
         }
     }
 
@@ -8127,6 +13080,12 @@ impl FillEvent {
     pub fn maker_out(&self) -> bool {
         self.maker_out == 1
     }
+    pub fn maker_slot(&self) -> u16 {
+        u16::from_le_bytes([self.maker_slot_lo, self.maker_slot_hi])
+    }
+    pub fn self_trade(&self) -> bool {
+        self.self_trade == 1
+    }
 }
 
 #[derive(
@@ -8136,13 +13095,22 @@ impl FillEvent {
 pub struct OutEvent {
     pub event_type: u8,
     pub side: u8, // Side
-    pub owner_slot: u8,
-    padding0: [u8; 5],
+    pub owner_slot_lo: u8,
+    pub owner_slot_hi: u8,
+    /// 1 if the order was evicted from a full book with the worst price on it, its posting
+    /// bond is forfeited
+    pub evicted: u8,
+    padding0: [u8; 3],
     pub timestamp: u64,
     pub seq_num: u64,
     pub owner: Pubkey,
     pub quantity: i64,
-    padding1: [u8; 80],
+    /// Lamports paid to the cranker consuming this event, see Market::crank_reward_per_event()
+    pub crank_reward: u64,
+    /// Lamports of the posting bond paid to whoever purged the expired order, they aren't
+    /// refunded
+    pub purge_bounty: u64,
+    padding1: [u8; 64],
 }
 const_assert_eq!(size_of::<OutEvent>() % 8, 0);
 const_assert_eq!(size_of::<OutEvent>(), EVENT_SIZE);
@@ -8150,7 +13118,7 @@ const_assert_eq!(size_of::<OutEvent>(), EVENT_SIZE);
 impl OutEvent {
     pub fn new(
         side: Side,
-        owner_slot: u8,
+        owner_slot: u16,
         timestamp: u64,
         seq_num: u64,
         owner: Pubkey,
@@ -8159,30 +13127,68 @@ impl OutEvent {
         Self {
             event_type: EventType::Out.into(),
             side: side.into(),
-            owner_slot,
-            padding0: [0; 5],
+            owner_slot_lo: owner_slot as u8,
+            owner_slot_hi: (owner_slot >> 8) as u8,
+            evicted: 0,
+            padding0: [0; 3],
             timestamp,
             seq_num,
             owner,
             quantity,
-            padding1: [0; EVENT_SIZE - 64],
+            crank_reward: 0,
+            purge_bounty: 0,
+            padding1: [0; EVENT_SIZE - 80],
+        }
+    }
+
+    pub fn new_evicted(
+        side: Side,
+        owner_slot: u16,
+        timestamp: u64,
+        seq_num: u64,
+        owner: Pubkey,
+        quantity: i64,
+    ) -> Self {
+        Self {
+            evicted: 1,
+            ..Self::new(side, owner_slot, timestamp, seq_num, owner, quantity)
+        }
+    }
+
+    pub fn new_purged(
+        side: Side,
+        owner_slot: u16,
+        timestamp: u64,
+        seq_num: u64,
+        owner: Pubkey,
+        quantity: i64,
+        purge_bounty: u64,
+    ) -> Self {
+        Self {
+            purge_bounty,
+            ..Self::new(side, owner_slot, timestamp, seq_num, owner, quantity)
         }
     }
 
     pub fn side(&self) -> Side {
         self.side.try_into().unwrap()
     }
+    pub fn owner_slot(&self) -> u16 {
+        u16::from_le_bytes([self.owner_slot_lo, self.owner_slot_hi])
+    }
+    pub fn is_evicted(&self) -> bool {
+        self.evicted == 1
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use bytemuck::Zeroable;
 
-    const LAST_SLOT: u16 = MAX_NUM_EVENTS - 1;
+    const LAST_SLOT: u16 = DEFAULT_NUM_EVENTS - 1;
 
-    fn count_free_nodes(event_heap: &EventHeap) -> usize {
-        event_heap.nodes.iter().filter(|n| n.is_free()).count()
+    fn count_free_nodes(event_heap: &EventHeapValue) -> usize {
+        event_heap.dynamic.iter().filter(|n| n.is_free()).count()
     }
 
     fn dummy_event_with_number(number: u8) -> AnyEvent {
@@ -8193,21 +13199,19 @@ mod tests {
 
     #[test]
     fn init() {
-        let mut eq = EventHeap::zeroed();
-        eq.init();
+        let mut eq = EventHeap::with_capacity_for_tests(DEFAULT_NUM_EVENTS);
 
         assert_eq!(eq.header.count(), 0);
         assert_eq!(eq.header.free_head(), 0);
         assert_eq!(eq.header.used_head(), NO_NODE as usize);
-        assert_eq!(count_free_nodes(&eq), MAX_NUM_EVENTS as usize);
+        assert_eq!(count_free_nodes(&eq), DEFAULT_NUM_EVENTS as usize);
     }
 
     #[test]
     #[should_panic]
     fn cannot_insert_if_full() {
-        let mut eq = EventHeap::zeroed();
-        eq.init();
-        for _ in 0..MAX_NUM_EVENTS + 1 {
+        let mut eq = EventHeap::with_capacity_for_tests(DEFAULT_NUM_EVENTS);
+        for _ in 0..DEFAULT_NUM_EVENTS + 1 {
             eq.push_back(AnyEvent::zeroed());
         }
     }
@@ -8215,93 +13219,89 @@ mod tests {
     #[test]
     #[should_panic]
     fn cannot_delete_if_empty() {
-        let mut eq = EventHeap::zeroed();
-        eq.init();
+        let mut eq = EventHeap::with_capacity_for_tests(DEFAULT_NUM_EVENTS);
         eq.pop_front().unwrap();
     }
 
     #[test]
     fn insert_until_full() {
-        let mut eq = EventHeap::zeroed();
-        eq.init();
+        let mut eq = EventHeap::with_capacity_for_tests(DEFAULT_NUM_EVENTS);
 
         // insert one event in the first slot; the single used node should point to himself
         eq.push_back(AnyEvent::zeroed());
         assert_eq!(eq.header.used_head, 0);
         assert_eq!(eq.header.free_head, 1);
-        assert_eq!(eq.nodes[0].prev, 0);
-        assert_eq!(eq.nodes[0].next, 0);
-        assert_eq!(eq.nodes[1].next, 2);
+        assert_eq!(eq.dynamic[0].prev, 0);
+        assert_eq!(eq.dynamic[0].next, 0);
+        assert_eq!(eq.dynamic[1].next, 2);
 
-        for i in 1..MAX_NUM_EVENTS - 2 {
+        for i in 1..DEFAULT_NUM_EVENTS - 2 {
             eq.push_back(AnyEvent::zeroed());
             assert_eq!(eq.header.used_head, 0);
             assert_eq!(eq.header.free_head, i + 1);
-            assert_eq!(eq.nodes[0].prev, i);
-            assert_eq!(eq.nodes[0].next, 1);
-            assert_eq!(eq.nodes[i as usize + 1].next, i + 2);
+            assert_eq!(eq.dynamic[0].prev, i);
+            assert_eq!(eq.dynamic[0].next, 1);
+            assert_eq!(eq.dynamic[i as usize + 1].next, i + 2);
         }
 
         // insert another one, afterwards only one free node pointing to null should be left
         eq.push_back(AnyEvent::zeroed());
         assert_eq!(eq.header.used_head, 0);
         assert_eq!(eq.header.free_head, LAST_SLOT);
-        assert_eq!(eq.nodes[0].prev, LAST_SLOT - 1);
-        assert_eq!(eq.nodes[0].next, 1);
-        assert_eq!(eq.nodes[LAST_SLOT as usize].next, NO_NODE);
+        assert_eq!(eq.dynamic[0].prev, LAST_SLOT - 1);
+        assert_eq!(eq.dynamic[0].next, 1);
+        assert_eq!(eq.dynamic[LAST_SLOT as usize].next, NO_NODE);
 
         // insert last available event
         eq.push_back(AnyEvent::zeroed());
         assert_eq!(eq.header.used_head, 0);
         assert_eq!(eq.header.free_head, NO_NODE);
-        assert_eq!(eq.nodes[0].prev, LAST_SLOT);
-        assert_eq!(eq.nodes[0].next, 1);
+        assert_eq!(eq.dynamic[0].prev, LAST_SLOT);
+        assert_eq!(eq.dynamic[0].next, 1);
     }
 
     #[test]
     fn delete_full() {
-        let mut eq = EventHeap::zeroed();
-        eq.init();
-        for _ in 0..MAX_NUM_EVENTS {
+        let mut eq = EventHeap::with_capacity_for_tests(DEFAULT_NUM_EVENTS);
+        for _ in 0..DEFAULT_NUM_EVENTS {
             eq.push_back(AnyEvent::zeroed());
         }
 
         eq.pop_front().unwrap();
         assert_eq!(eq.header.free_head, 0);
         assert_eq!(eq.header.used_head, 1);
-        assert_eq!(eq.nodes[0].next, NO_NODE);
-        assert_eq!(eq.nodes[1].prev, LAST_SLOT);
-        assert_eq!(eq.nodes[1].next, 2);
+        assert_eq!(eq.dynamic[0].next, NO_NODE);
+        assert_eq!(eq.dynamic[1].prev, LAST_SLOT);
+        assert_eq!(eq.dynamic[1].next, 2);
 
-        for i in 1..MAX_NUM_EVENTS - 2 {
+        for i in 1..DEFAULT_NUM_EVENTS - 2 {
             eq.pop_front().unwrap();
             assert_eq!(eq.header.free_head, i);
             assert_eq!(eq.header.used_head, i + 1);
-            assert_eq!(eq.nodes[i as usize].next, i - 1);
-            assert_eq!(eq.nodes[i as usize + 1].prev, LAST_SLOT);
-            assert_eq!(eq.nodes[i as usize + 1].next, i + 2);
+            assert_eq!(eq.dynamic[i as usize].next, i - 1);
+            assert_eq!(eq.dynamic[i as usize + 1].prev, LAST_SLOT);
+            assert_eq!(eq.dynamic[i as usize + 1].next, i + 2);
         }
 
         eq.pop_front().unwrap();
         assert_eq!(eq.header.free_head, LAST_SLOT - 1);
         assert_eq!(eq.header.used_head, LAST_SLOT);
-        assert_eq!(eq.nodes[LAST_SLOT as usize - 1].next, LAST_SLOT - 2);
-        assert_eq!(eq.nodes[LAST_SLOT as usize].prev, LAST_SLOT);
-        assert_eq!(eq.nodes[LAST_SLOT as usize].next, LAST_SLOT);
+        assert_eq!(eq.dynamic[LAST_SLOT as usize - 1].next, LAST_SLOT - 2);
+        assert_eq!(eq.dynamic[LAST_SLOT as usize].prev, LAST_SLOT);
+        assert_eq!(eq.dynamic[LAST_SLOT as usize].next, LAST_SLOT);
 
         eq.pop_front().unwrap();
         assert_eq!(eq.header.used_head, NO_NODE);
         assert_eq!(eq.header.free_head, LAST_SLOT);
-        assert_eq!(eq.nodes[LAST_SLOT as usize].next, LAST_SLOT - 1);
+        assert_eq!(eq.dynamic[LAST_SLOT as usize].next, LAST_SLOT - 1);
 
         assert_eq!(eq.header.count(), 0);
-        assert_eq!(count_free_nodes(&eq), MAX_NUM_EVENTS as usize);
+        assert_eq!(count_free_nodes(&eq), DEFAULT_NUM_EVENTS as usize);
     }
 
     #[test]
     fn delete_at_given_position() {
-        let mut eq = EventHeap::zeroed();
-        eq.init();
+        let mut eq = EventHeap::with_capacity_for_tests(DEFAULT_NUM_EVENTS);
         for _ in 0..5 {
             eq.push_back(AnyEvent::zeroed());
         }
@@ -8313,8 +13313,7 @@ mod tests {
     #[test]
     #[should_panic]
     fn cannot_delete_twice_same() {
-        let mut eq = EventHeap::zeroed();
-        eq.init();
+        let mut eq = EventHeap::with_capacity_for_tests(DEFAULT_NUM_EVENTS);
         for _ in 0..5 {
             eq.push_back(AnyEvent::zeroed());
         }
@@ -8324,8 +13323,7 @@ mod tests {
 
     #[test]
     fn read_front() {
-        let mut eq = EventHeap::zeroed();
-        eq.init();
+        let mut eq = EventHeap::with_capacity_for_tests(DEFAULT_NUM_EVENTS);
         eq.push_back(dummy_event_with_number(1));
         eq.push_back(AnyEvent::zeroed());
         assert_eq!(eq.front().unwrap().event_type, 1);
@@ -8333,8 +13331,7 @@ mod tests {
 
     #[test]
     fn read_at_slot() {
-        let mut eq = EventHeap::zeroed();
-        eq.init();
+        let mut eq = EventHeap::with_capacity_for_tests(DEFAULT_NUM_EVENTS);
         eq.push_back(AnyEvent::zeroed());
         eq.push_back(AnyEvent::zeroed());
         eq.push_back(dummy_event_with_number(1));
@@ -8350,36 +13347,35 @@ mod tests {
         // [3|2| | | ] push_back
         // [3| | | | ] pop_front
 
-        let mut eq = EventHeap::zeroed();
-        eq.init();
-        assert!(eq.nodes[0].is_free());
-        assert!(eq.nodes[1].is_free());
-        assert!(eq.nodes[2].is_free());
+        let mut eq = EventHeap::with_capacity_for_tests(DEFAULT_NUM_EVENTS);
+        assert!(eq.dynamic[0].is_free());
+        assert!(eq.dynamic[1].is_free());
+        assert!(eq.dynamic[2].is_free());
 
         eq.push_back(dummy_event_with_number(1));
-        assert_eq!(eq.nodes[0].event.event_type, 1);
-        assert!(eq.nodes[1].is_free());
-        assert!(eq.nodes[2].is_free());
+        assert_eq!(eq.dynamic[0].event.event_type, 1);
+        assert!(eq.dynamic[1].is_free());
+        assert!(eq.dynamic[2].is_free());
 
         eq.push_back(dummy_event_with_number(2));
-        assert_eq!(eq.nodes[0].event.event_type, 1);
-        assert_eq!(eq.nodes[1].event.event_type, 2);
-        assert!(eq.nodes[2].is_free());
+        assert_eq!(eq.dynamic[0].event.event_type, 1);
+        assert_eq!(eq.dynamic[1].event.event_type, 2);
+        assert!(eq.dynamic[2].is_free());
 
         eq.pop_front().unwrap();
-        assert!(eq.nodes[0].is_free());
-        assert_eq!(eq.nodes[1].event.event_type, 2);
-        assert!(eq.nodes[2].is_free());
+        assert!(eq.dynamic[0].is_free());
+        assert_eq!(eq.dynamic[1].event.event_type, 2);
+        assert!(eq.dynamic[2].is_free());
 
         eq.push_back(dummy_event_with_number(3));
-        assert_eq!(eq.nodes[0].event.event_type, 3);
-        assert_eq!(eq.nodes[1].event.event_type, 2);
-        assert!(eq.nodes[2].is_free());
+        assert_eq!(eq.dynamic[0].event.event_type, 3);
+        assert_eq!(eq.dynamic[1].event.event_type, 2);
+        assert!(eq.dynamic[2].is_free());
 
         eq.pop_front().unwrap();
-        assert_eq!(eq.nodes[0].event.event_type, 3);
-        assert!(eq.nodes[1].is_free());
-        assert!(eq.nodes[2].is_free());
+        assert_eq!(eq.dynamic[0].event.event_type, 3);
+        assert!(eq.dynamic[1].is_free());
+        assert!(eq.dynamic[2].is_free());
     }
 
     #[test]
@@ -8392,34 +13388,33 @@ mod tests {
         // [0| |1|2|3] push_back
         // [ | |0|1|2] push_back
 
-        let mut eq = EventHeap::zeroed();
-        eq.init();
+        let mut eq = EventHeap::with_capacity_for_tests(DEFAULT_NUM_EVENTS);
         assert_eq!(eq.header.free_head(), 0);
-        assert_eq!(eq.nodes[0].next, 1);
+        assert_eq!(eq.dynamic[0].next, 1);
 
         eq.push_back(AnyEvent::zeroed());
         assert_eq!(eq.header.free_head(), 1);
-        assert_eq!(eq.nodes[1].next, 2);
+        assert_eq!(eq.dynamic[1].next, 2);
 
         eq.push_back(AnyEvent::zeroed());
         assert_eq!(eq.header.free_head(), 2);
-        assert_eq!(eq.nodes[2].next, 3);
+        assert_eq!(eq.dynamic[2].next, 3);
 
         eq.pop_front().unwrap();
         assert_eq!(eq.header.free_head(), 0);
-        assert_eq!(eq.nodes[0].next, 2);
+        assert_eq!(eq.dynamic[0].next, 2);
 
         eq.pop_front().unwrap();
         assert_eq!(eq.header.free_head(), 1);
-        assert_eq!(eq.nodes[1].next, 0);
+        assert_eq!(eq.dynamic[1].next, 0);
 
         eq.push_back(AnyEvent::zeroed());
         assert_eq!(eq.header.free_head(), 0);
-        assert_eq!(eq.nodes[0].next, 2);
+        assert_eq!(eq.dynamic[0].next, 2);
 
         eq.push_back(AnyEvent::zeroed());
         assert_eq!(eq.header.free_head(), 2);
-        assert_eq!(eq.nodes[2].next, 3);
+        assert_eq!(eq.dynamic[2].next, 3);
     }
 }
 
@@ -8448,15 +13443,19 @@ mod ordertree_iterator;
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::logs::EventEmitter;
     use crate::state::{Market, OpenOrdersAccount, FEES_SCALE_FACTOR};
     use bytemuck::Zeroable;
     use fixed::types::I80F48;
     use solana_program::pubkey::Pubkey;
-    use std::cell::RefCell;
+    use std::cell::{RefCell, RefMut};
 
-    fn order_tree_leaf_by_key(bookside: &BookSide, key: u128) -> Option<&LeafNode> {
+    fn order_tree_leaf_by_key<'a>(
+        bookside: &'a BookSideLoadedRefCellMut,
+        key: u128,
+    ) -> Option<&'a LeafNode> {
         for component in [BookSideOrderTree::Fixed, BookSideOrderTree::OraclePegged] {
-            for (_, leaf) in bookside.nodes.iter(bookside.root(component)) {
+            for (_, leaf) in bookside.nodes().iter(bookside.root(component)) {
                 if leaf.key == key {
                     return Some(leaf);
                 }
@@ -8465,13 +13464,13 @@ mod tests {
         None
     }
 
-    fn order_tree_contains_key(bookside: &BookSide, key: u128) -> bool {
+    fn order_tree_contains_key(bookside: &BookSideLoadedRefCellMut, key: u128) -> bool {
         order_tree_leaf_by_key(bookside, key).is_some()
     }
 
-    fn order_tree_contains_price(bookside: &BookSide, price_data: u64) -> bool {
+    fn order_tree_contains_price(bookside: &BookSideLoadedRefCellMut, price_data: u64) -> bool {
         for component in [BookSideOrderTree::Fixed, BookSideOrderTree::OraclePegged] {
-            for (_, leaf) in bookside.nodes.iter(bookside.root(component)) {
+            for (_, leaf) in bookside.nodes().iter(bookside.root(component)) {
                 if leaf.price_data() == price_data {
                     return true;
                 }
@@ -8481,33 +13480,43 @@ mod tests {
     }
 
     struct OrderbookAccounts {
-        bids: Box<RefCell<BookSide>>,
-        asks: Box<RefCell<BookSide>>,
+        bids: Box<RefCell<BookSideValue>>,
+        asks: Box<RefCell<BookSideValue>>,
     }
 
     impl OrderbookAccounts {
         fn new() -> Self {
-            let s = Self {
-                bids: Box::new(RefCell::new(BookSide::zeroed())),
-                asks: Box::new(RefCell::new(BookSide::zeroed())),
-            };
-            s.bids.borrow_mut().nodes.order_tree_type = OrderTreeType::Bids.into();
-            s.asks.borrow_mut().nodes.order_tree_type = OrderTreeType::Asks.into();
-            s
+            Self {
+                bids: Box::new(RefCell::new(BookSide::with_capacity_for_tests(
+                    OrderTreeType::Bids,
+                    DEFAULT_ORDERTREE_NODES,
+                ))),
+                asks: Box::new(RefCell::new(BookSide::with_capacity_for_tests(
+                    OrderTreeType::Asks,
+                    DEFAULT_ORDERTREE_NODES,
+                ))),
+            }
         }
 
         fn orderbook(&self) -> Orderbook {
             Orderbook {
-                bids: self.bids.borrow_mut(),
-                asks: self.asks.borrow_mut(),
+                bids: borrow_book_side(&self.bids),
+                asks: borrow_book_side(&self.asks),
             }
         }
     }
 
-    fn test_setup(price: f64) -> (Market, Option<i64>, EventHeap, OrderbookAccounts) {
+    fn borrow_book_side(cell: &RefCell<BookSideValue>) -> BookSideLoadedRefCellMut {
+        let (fixed, dynamic) = RefMut::map_split(cell.borrow_mut(), |value| {
+            (&mut *value.fixed, &mut value.dynamic[..])
+        });
+        BookSideDynamic { fixed, dynamic }
+    }
+
+    fn test_setup(price: f64) -> (Market, Option<i64>, EventHeapValue, OrderbookAccounts) {
         let book = OrderbookAccounts::new();
 
-        let event_heap = EventHeap::zeroed();
+        let event_heap = EventHeap::with_capacity_for_tests(DEFAULT_NUM_EVENTS);
 
         let mut openbook_market = Market::zeroed();
         openbook_market.quote_lot_size = 1;
@@ -8528,39 +13537,44 @@ mod tests {
         let mut book = book_accs.orderbook();
         let market_pk = Pubkey::new_unique();
 
-        let mut new_order =
-            |book: &mut Orderbook, event_heap: &mut EventHeap, side, price_lots, now_ts| -> u128 {
-                let mut account = OpenOrdersAccount::default_for_tests();
-
-                let max_base_lots = 1;
-                let time_in_force = 100;
-
-                book.new_order(
-                    &Order {
-                        side,
-                        max_base_lots,
-                        max_quote_lots_including_fees: i64::MAX / openbook_market.quote_lot_size,
-                        client_order_id: 0,
-                        time_in_force,
-                        params: OrderParams::Fixed {
-                            price_lots,
-                            order_type: PostOrderType::Limit,
-                        },
-                        self_trade_behavior: SelfTradeBehavior::DecrementTake,
+        let mut new_order = |book: &mut Orderbook,
+                             event_heap: &mut EventHeapValue,
+                             side,
+                             price_lots,
+                             now_ts|
+         -> u128 {
+            let mut account = OpenOrdersAccount::default_for_tests();
+
+            let max_base_lots = 1;
+            let time_in_force = 100;
+
+            book.new_order(
+                &Order {
+                    side,
+                    max_base_lots,
+                    max_quote_lots_including_fees: i64::MAX / openbook_market.quote_lot_size,
+                    client_order_id: 0,
+                    time_in_force,
+                    params: OrderParams::Fixed {
+                        price_lots,
+                        order_type: PostOrderType::Limit,
                     },
-                    &mut openbook_market,
-                    &market_pk,
-                    event_heap,
-                    oracle_price_lots,
-                    Some(&mut account),
-                    &Pubkey::new_unique(),
-                    now_ts,
-                    u8::MAX,
-                    &[],
-                )
-                .unwrap();
-                account.open_order_by_raw_index(0).id
-            };
+                    self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                },
+                &mut openbook_market,
+                &market_pk,
+                &mut event_heap.borrow_mut(),
+                oracle_price_lots,
+                Some(&mut account.borrow_mut()),
+                &Pubkey::new_unique(),
+                now_ts,
+                u8::MAX,
+                &[],
+                &EventEmitter::default(),
+            )
+            .unwrap();
+            account.open_order_by_raw_index(0).id
+        };
 
         // insert bids until book side is full
         for i in 1..10 {
@@ -8587,7 +13601,7 @@ mod tests {
         assert!(book.bids.is_full());
         assert_eq!(
             book.bids
-                .nodes
+                .nodes()
                 .min_leaf(&book.bids.roots[0])
                 .unwrap()
                 .1
@@ -8597,7 +13611,7 @@ mod tests {
         assert_eq!(
             fixed_price_lots(
                 book.bids
-                    .nodes
+                    .nodes()
                     .max_leaf(&book.bids.roots[0])
                     .unwrap()
                     .1
@@ -8610,7 +13624,7 @@ mod tests {
         new_order(&mut book, &mut event_heap, Side::Bid, 1005, 1000000 - 1);
         assert_eq!(
             book.bids
-                .nodes
+                .nodes()
                 .min_leaf(&book.bids.roots[0])
                 .unwrap()
                 .1
@@ -8623,7 +13637,7 @@ mod tests {
         new_order(&mut book, &mut event_heap, Side::Bid, 999, 2000000);
         assert_eq!(
             book.bids
-                .nodes
+                .nodes()
                 .min_leaf(&book.bids.roots[0])
                 .unwrap()
                 .1
@@ -8636,7 +13650,7 @@ mod tests {
         // adding an ask will wipe up to three expired bids at the top of the book
         let bids_max = book
             .bids
-            .nodes
+            .nodes()
             .max_leaf(&book.bids.roots[0])
             .unwrap()
             .1
@@ -8693,13 +13707,14 @@ mod tests {
             },
             &mut market,
             &market_pk,
-            &mut event_heap,
+            &mut event_heap.borrow_mut(),
             oracle_price_lots,
-            Some(&mut maker),
+            Some(&mut maker.borrow_mut()),
             &maker_pk,
             now_ts,
             u8::MAX,
             &[],
+            &EventEmitter::default(),
         )
         .unwrap();
         let order =
@@ -8740,13 +13755,14 @@ mod tests {
             },
             &mut market,
             &market_pk,
-            &mut event_heap,
+            &mut event_heap.borrow_mut(),
             oracle_price_lots,
-            Some(&mut taker),
+            Some(&mut taker.borrow_mut()),
             &taker_pk,
             now_ts,
             u8::MAX,
             &[],
+            &EventEmitter::default(),
         )
         .unwrap();
         // the remainder of the maker order is still on the book
@@ -8780,8 +13796,19 @@ mod tests {
         assert_eq!(fill.taker, taker_pk);
 
         // simulate event heap processing
-        maker.execute_maker(&mut market, fill);
-        taker.execute_taker(&mut market, Side::Ask, 0, 0, 0, 0);
+        maker
+            .execute_maker(&mut market, fill, &EventEmitter::default())
+            .unwrap();
+        taker.execute_taker(
+            &mut market,
+            Side::Ask,
+            0,
+            0,
+            0,
+            0,
+            &EventEmitter::default(),
+        )
+        .unwrap();
 
         assert_eq!(maker.position.bids_base_lots, bid_quantity - match_quantity);
         assert_eq!(maker.position.asks_base_lots, 0);
@@ -8806,7 +13833,7 @@ mod tests {
         let market_pk = Pubkey::new_unique();
 
         let mut new_order = |book: &mut Orderbook,
-                             event_heap: &mut EventHeap,
+                             event_heap: &mut EventHeapValue,
                              side,
                              price_lots,
                              max_base_lots: i64,
@@ -8829,13 +13856,14 @@ mod tests {
                 },
                 &mut market,
                 &market_pk,
-                event_heap,
+                &mut event_heap.borrow_mut(),
                 oracle_price_lots,
-                Some(&mut account),
+                Some(&mut account.borrow_mut()),
                 &Pubkey::default(),
                 0, // now_ts
                 u8::MAX,
                 &[],
+                &EventEmitter::default(),
             )
             .unwrap();
             account.open_order_by_raw_index(0).id
@@ -8877,27 +13905,79 @@ mod tests {
         new_order(&mut book, &mut event_heap, Side::Bid, 5005, 30, 1);
         assert_eq!(event_heap.len(), 1);
     }
-}
-
-
-// File: openbook-v2/programs/openbook-v2/src/state/orderbook/nodes.rs
-use std::mem::{align_of, size_of};
-
-use anchor_lang::prelude::*;
-use bytemuck::{cast_mut, cast_ref};
-use num_enum::{IntoPrimitive, TryFromPrimitive};
-use static_assertions::const_assert_eq;
-
-use super::order_type::Side;
 
-pub type NodeHandle = u32;
-const NODE_SIZE: usize = 88;
+    #[test]
+    fn book_base_lots_for_quote_out() {
+        let (mut openbook_market, oracle_price_lots, mut event_heap, book_accs) =
+            test_setup(5000.0);
+        openbook_market.maker_fee = -200;
+        let mut book = book_accs.orderbook();
+        let market_pk = Pubkey::new_unique();
 
-#[derive(IntoPrimitive, TryFromPrimitive)]
-#[repr(u8)]
-pub enum NodeTag {
-    Uninitialized = 0,
-    InnerNode = 1,
+        for (price_lots, max_base_lots) in [(10_000, 2), (9_000, 10)] {
+            let mut account = OpenOrdersAccount::default_for_tests();
+            book.new_order(
+                &Order {
+                    side: Side::Bid,
+                    max_base_lots,
+                    max_quote_lots_including_fees: i64::MAX / openbook_market.quote_lot_size,
+                    client_order_id: 0,
+                    time_in_force: 0,
+                    params: OrderParams::Fixed {
+                        price_lots,
+                        order_type: PostOrderType::Limit,
+                    },
+                    self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                },
+                &mut openbook_market,
+                &market_pk,
+                &mut event_heap.borrow_mut(),
+                oracle_price_lots,
+                Some(&mut account.borrow_mut()),
+                &Pubkey::new_unique(),
+                0,
+                u8::MAX,
+                &[],
+                &EventEmitter::default(),
+            )
+            .unwrap();
+        }
+
+        let base_lots_for = |quote_out_native: u64| {
+            book.base_lots_for_quote_out(&openbook_market, quote_out_native, 0, oracle_price_lots)
+                .unwrap()
+        };
+
+        // every fill pays the maker a 0.02% rebate, rounded down
+        assert_eq!(base_lots_for(9_998), Some(1));
+        assert_eq!(base_lots_for(9_999), None);
+        assert_eq!(base_lots_for(19_996), Some(2));
+        assert_eq!(base_lots_for(19_996 + 8_999), Some(3));
+        assert_eq!(base_lots_for(19_996 + 17_997), Some(4));
+        assert_eq!(base_lots_for(19_996 + 89_982), Some(12));
+        assert_eq!(base_lots_for(19_996 + 89_983), None);
+    }
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/state/orderbook/nodes.rs
+use std::mem::{align_of, size_of};
+
+use anchor_lang::prelude::*;
+use bytemuck::{cast_mut, cast_ref};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use static_assertions::const_assert_eq;
+
+use super::order_type::Side;
+
+pub type NodeHandle = u32;
+const NODE_SIZE: usize = 88;
+
+#[derive(IntoPrimitive, TryFromPrimitive)]
+#[repr(u8)]
+pub enum NodeTag {
+    Uninitialized = 0,
+    InnerNode = 1,
     LeafNode = 2,
     FreeNode = 3,
     LastFreeNode = 4,
@@ -9030,14 +14110,24 @@ pub struct LeafNode {
     /// NodeTag
     pub tag: u8,
 
-    /// Index into the owning OpenOrdersAccount's OpenOrders
-    pub owner_slot: u8,
+    /// Index into the owning OpenOrdersAccount's OpenOrders, low byte. See owner_slot().
+    pub owner_slot_lo: u8,
 
     /// Time in seconds after `timestamp` at which the order expires.
     /// A value of 0 means no expiry.
     pub time_in_force: u16,
 
-    pub padding: [u8; 4],
+    /// High byte of the owner slot, for accounts with more than 256 order slots
+    pub owner_slot_hi: u8,
+
+    /// Bump of the owning OpenOrdersAccount, see `stp_account_num`
+    pub stp_bump: u8,
+
+    /// `account_num` of the owning OpenOrdersAccount if it prevents self-trades with the other
+    /// accounts of its owner (SelfTradeScope::Owner), 0 otherwise. Together with `stp_bump`
+    /// it derives the account's address from the owner wallet of a taker, see
+    /// is_in_stp_group_of().
+    pub stp_account_num: u16,
 
     /// The binary tree key, see new_node_key()
     pub key: u128,
@@ -9062,7 +14152,7 @@ pub struct LeafNode {
 }
 const_assert_eq!(
     size_of::<LeafNode>(),
-    4 + 1 + 1 + 1 + 1 + 16 + 32 + 8 + 8 + 8 + 8
+    1 + 1 + 2 + 1 + 1 + 2 + 16 + 32 + 8 + 8 + 8 + 8
 );
 const_assert_eq!(size_of::<LeafNode>(), NODE_SIZE);
 const_assert_eq!(size_of::<LeafNode>() % 8, 0);
@@ -9070,7 +14160,7 @@ const_assert_eq!(size_of::<LeafNode>() % 8, 0);
 impl LeafNode {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
-        owner_slot: u8,
+        owner_slot: u16,
         key: u128,
         owner: Pubkey,
         quantity: i64,
@@ -9081,9 +14171,11 @@ impl LeafNode {
     ) -> Self {
         Self {
             tag: NodeTag::LeafNode.into(),
-            owner_slot,
+            owner_slot_lo: owner_slot as u8,
             time_in_force,
-            padding: Default::default(),
+            owner_slot_hi: (owner_slot >> 8) as u8,
+            stp_bump: 0,
+            stp_account_num: 0,
             key,
             owner,
             quantity,
@@ -9093,6 +14185,28 @@ impl LeafNode {
         }
     }
 
+    /// Index into the owning OpenOrdersAccount's order slots
+    #[inline(always)]
+    pub fn owner_slot(&self) -> u16 {
+        u16::from_le_bytes([self.owner_slot_lo, self.owner_slot_hi])
+    }
+
+    /// Whether the order belongs to the self-trade prevention group of the wallet `owner`,
+    /// i.e. was placed by one of its open orders accounts with SelfTradeScope::Owner
+    pub fn is_in_stp_group_of(&self, owner: &Pubkey) -> bool {
+        self.stp_account_num != 0
+            && Pubkey::create_program_address(
+                &[
+                    b"OpenOrders".as_ref(),
+                    owner.as_ref(),
+                    &u32::from(self.stp_account_num).to_le_bytes(),
+                    &[self.stp_bump],
+                ],
+                &crate::id(),
+            )
+            .map_or(false, |address| address == self.owner)
+    }
+
     /// The order's price_data as stored in the key
     ///
     /// Needs to be unpacked differently for fixed and oracle pegged orders.
@@ -9610,7 +14724,8 @@ pub enum PostOrderType {
 )]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[repr(u8)]
-/// Self trade behavior controls how taker orders interact with resting limit orders of the same account.
+/// Self trade behavior controls how taker orders interact with resting limit orders of the same
+/// self-trade group, see [`SelfTradeScope`].
 /// This setting has no influence on placing a resting or oracle pegged limit order that does not match
 /// immediately, instead it's the responsibility of the user to correctly configure his taker orders.
 pub enum SelfTradeBehavior {
@@ -9624,6 +14739,40 @@ pub enum SelfTradeBehavior {
 
     /// Cancels the whole transaction as soon as a self-matching scenario is encountered.
     AbortTransaction = 2,
+
+    /// Cancels the maker side of the trade and stops matching, the rest of the taker order
+    /// is cancelled as well.
+    CancelBoth = 3,
+
+    /// Stops matching and cancels the rest of the taker order, the maker side is left on the book.
+    CancelTake = 4,
+}
+
+#[derive(
+    Eq,
+    PartialEq,
+    Copy,
+    Clone,
+    Default,
+    TryFromPrimitive,
+    IntoPrimitive,
+    Debug,
+    AnchorSerialize,
+    AnchorDeserialize,
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[repr(u8)]
+/// Which orders an open orders account's taker orders consider self-trades, i.e. the
+/// self-trade prevention group its orders are placed with.
+///
+/// Two accounts only prevent self-trades with each other if both use `Owner`.
+pub enum SelfTradeScope {
+    /// Orders of all open orders accounts of the same owner wallet that use this scope.
+    #[default]
+    Owner = 0,
+
+    /// Only orders of the same open orders account.
+    Account = 1,
 }
 
 #[derive(
@@ -9733,7 +14882,18 @@ use static_assertions::const_assert_eq;
 use super::*;
 use crate::error::OpenBookError;
 
+/// Number of nodes of a book side account created with the default size
+pub const DEFAULT_ORDERTREE_NODES: usize = 1024;
+/// Smallest number of nodes a book side can be created with
+pub const MIN_ORDERTREE_NODES: usize = 64;
+/// Largest number of nodes a book side can be created with, keeps the account below 10MiB
+pub const MAX_BOOK_SIDE_CAPACITY: usize = 100_000;
+/// Number of nodes of every book side before their capacity became configurable
+#[deprecated(note = "use DEFAULT_ORDERTREE_NODES or MAX_BOOK_SIDE_CAPACITY")]
 pub const MAX_ORDERTREE_NODES: usize = 1024;
+/// Largest number of nodes of a book side whose eviction policy scans every order, see
+/// [`EvictionPolicy`](crate::state::EvictionPolicy)
+pub const MAX_SCANNED_EVICTION_NODES: usize = DEFAULT_ORDERTREE_NODES;
 
 #[derive(
     Eq,
@@ -9779,9 +14939,10 @@ impl OrderTreeRoot {
     }
 }
 
-/// A binary tree on AnyNode::key()
+/// Header of a binary tree on AnyNode::key()
 ///
-/// The key encodes the price in the top 64 bits.
+/// The key encodes the price in the top 64 bits. The nodes themselves follow the
+/// BookSide in the account data, see [`OrderTreeNodesRef`].
 #[zero_copy]
 pub struct OrderTreeNodes {
     pub order_tree_type: u8, // OrderTreeType, but that's not POD
@@ -9790,38 +14951,51 @@ pub struct OrderTreeNodes {
     pub free_list_len: u32,
     pub free_list_head: NodeHandle,
     pub reserved: [u8; 512],
-    pub nodes: [AnyNode; MAX_ORDERTREE_NODES],
 }
-const_assert_eq!(
-    std::mem::size_of::<OrderTreeNodes>(),
-    1 + 3 + 4 * 2 + 4 + 512 + 88 * 1024
-);
-const_assert_eq!(std::mem::size_of::<OrderTreeNodes>(), 90640);
+const_assert_eq!(std::mem::size_of::<OrderTreeNodes>(), 1 + 3 + 4 * 2 + 4 + 512);
+const_assert_eq!(std::mem::size_of::<OrderTreeNodes>(), 528);
 const_assert_eq!(std::mem::size_of::<OrderTreeNodes>() % 8, 0);
 
 impl OrderTreeNodes {
     pub fn order_tree_type(&self) -> OrderTreeType {
         OrderTreeType::try_from(self.order_tree_type).unwrap()
     }
+}
+
+/// An order tree header together with its nodes
+#[derive(Clone, Copy)]
+pub struct OrderTreeNodesRef<'a> {
+    pub fixed: &'a OrderTreeNodes,
+    /// Indexed by NodeHandle, the length is the capacity of the tree
+    pub dynamic: &'a [AnyNode],
+}
+
+/// Mutable version of [`OrderTreeNodesRef`]
+pub struct OrderTreeNodesRefMut<'a> {
+    pub fixed: &'a mut OrderTreeNodes,
+    pub dynamic: &'a mut [AnyNode],
+}
+
+impl<'a> OrderTreeNodesRef<'a> {
+    pub fn order_tree_type(&self) -> OrderTreeType {
+        self.fixed.order_tree_type()
+    }
+
+    /// Maximum number of nodes in the tree
+    pub fn capacity(&self) -> usize {
+        self.dynamic.len()
+    }
 
     /// Iterate over all entries, including invalid orders
     ///
     /// smallest to highest for asks
     /// highest to smallest for bids
-    pub fn iter(&self, root: &OrderTreeRoot) -> OrderTreeIter {
-        OrderTreeIter::new(self, root)
+    pub fn iter(&self, root: &OrderTreeRoot) -> OrderTreeIter<'a> {
+        OrderTreeIter::new(*self, root)
     }
 
-    pub fn node_mut(&mut self, handle: NodeHandle) -> Option<&mut AnyNode> {
-        let node = &mut self.nodes[handle as usize];
-        let tag = NodeTag::try_from(node.tag);
-        match tag {
-            Ok(NodeTag::InnerNode) | Ok(NodeTag::LeafNode) => Some(node),
-            _ => None,
-        }
-    }
-    pub fn node(&self, handle: NodeHandle) -> Option<&AnyNode> {
-        let node = &self.nodes[handle as usize];
+    pub fn node(&self, handle: NodeHandle) -> Option<&'a AnyNode> {
+        let node = &self.dynamic[handle as usize];
         let tag = NodeTag::try_from(node.tag);
         match tag {
             Ok(NodeTag::InnerNode) | Ok(NodeTag::LeafNode) => Some(node),
@@ -9829,31 +15003,13 @@ impl OrderTreeNodes {
         }
     }
 
-    pub fn remove_worst(&mut self, root: &mut OrderTreeRoot) -> Option<LeafNode> {
-        self.remove_by_key(root, self.find_worst(root)?.1.key)
-    }
-
-    pub fn find_worst(&self, root: &OrderTreeRoot) -> Option<(NodeHandle, &LeafNode)> {
+    pub fn find_worst(&self, root: &OrderTreeRoot) -> Option<(NodeHandle, &'a LeafNode)> {
         match self.order_tree_type() {
             OrderTreeType::Bids => self.min_leaf(root),
             OrderTreeType::Asks => self.max_leaf(root),
         }
     }
 
-    /// Remove the order with the lowest expiry timestamp, if that's < now_ts.
-    pub fn remove_one_expired(
-        &mut self,
-        root: &mut OrderTreeRoot,
-        now_ts: u64,
-    ) -> Option<LeafNode> {
-        let (handle, expires_at) = self.find_earliest_expiry(root)?;
-        if expires_at < now_ts {
-            self.remove_by_key(root, self.node(handle)?.key()?)
-        } else {
-            None
-        }
-    }
-
     // only for fixed-price ordertrees
     #[cfg(test)]
     #[allow(dead_code)]
@@ -9889,18 +15045,18 @@ impl OrderTreeNodes {
         }
     }
 
-    pub fn min_leaf(&self, root: &OrderTreeRoot) -> Option<(NodeHandle, &LeafNode)> {
+    pub fn min_leaf(&self, root: &OrderTreeRoot) -> Option<(NodeHandle, &'a LeafNode)> {
         self.leaf_min_max(false, root)
     }
 
-    pub fn max_leaf(&self, root: &OrderTreeRoot) -> Option<(NodeHandle, &LeafNode)> {
+    pub fn max_leaf(&self, root: &OrderTreeRoot) -> Option<(NodeHandle, &'a LeafNode)> {
         self.leaf_min_max(true, root)
     }
     fn leaf_min_max(
         &self,
         find_max: bool,
         root: &OrderTreeRoot,
-    ) -> Option<(NodeHandle, &LeafNode)> {
+    ) -> Option<(NodeHandle, &'a LeafNode)> {
         let mut node_handle: NodeHandle = root.node()?;
 
         let i = usize::from(find_max);
@@ -9917,6 +15073,71 @@ impl OrderTreeNodes {
         }
     }
 
+    pub fn is_full(&self) -> bool {
+        self.fixed.free_list_len <= 1 && (self.fixed.bump_index as usize) >= self.capacity() - 1
+    }
+
+    /// Returns the handle of the node with the lowest expiry timestamp, and this timestamp
+    pub fn find_earliest_expiry(&self, root: &OrderTreeRoot) -> Option<(NodeHandle, u64)> {
+        let mut current: NodeHandle = match root.node() {
+            Some(h) => h,
+            None => return None,
+        };
+
+        loop {
+            let contents = *self.node(current).unwrap();
+            match contents.case() {
+                None => unreachable!(),
+                Some(NodeRef::Inner(inner)) => {
+                    current = inner.children[(inner.child_earliest_expiry[0]
+                        > inner.child_earliest_expiry[1])
+                        as usize];
+                }
+                _ => {
+                    return Some((current, contents.earliest_expiry()));
+                }
+            };
+        }
+    }
+}
+
+impl<'a> OrderTreeNodesRefMut<'a> {
+    pub fn borrow(&self) -> OrderTreeNodesRef {
+        OrderTreeNodesRef {
+            fixed: &*self.fixed,
+            dynamic: &*self.dynamic,
+        }
+    }
+
+    pub fn node_mut(&mut self, handle: NodeHandle) -> Option<&mut AnyNode> {
+        let node = &mut self.dynamic[handle as usize];
+        let tag = NodeTag::try_from(node.tag);
+        match tag {
+            Ok(NodeTag::InnerNode) | Ok(NodeTag::LeafNode) => Some(node),
+            _ => None,
+        }
+    }
+
+    pub fn remove_worst(&mut self, root: &mut OrderTreeRoot) -> Option<LeafNode> {
+        let worst_key = self.borrow().find_worst(root)?.1.key;
+        self.remove_by_key(root, worst_key)
+    }
+
+    /// Remove the order with the lowest expiry timestamp, if that's < now_ts.
+    pub fn remove_one_expired(
+        &mut self,
+        root: &mut OrderTreeRoot,
+        now_ts: u64,
+    ) -> Option<LeafNode> {
+        let (handle, expires_at) = self.borrow().find_earliest_expiry(root)?;
+        if expires_at < now_ts {
+            let key = self.borrow().node(handle)?.key()?;
+            self.remove_by_key(root, key)
+        } else {
+            None
+        }
+    }
+
     pub fn remove_by_key(
         &mut self,
         root: &mut OrderTreeRoot,
@@ -9927,7 +15148,8 @@ impl OrderTreeNodes {
 
         // special case potentially removing the root
         let mut parent_h = root.node()?;
-        let (mut child_h, mut crit_bit) = match self.node(parent_h).unwrap().case().unwrap() {
+        let parent = *self.borrow().node(parent_h).unwrap();
+        let (mut child_h, mut crit_bit) = match parent.case().unwrap() {
             NodeRef::Leaf(&leaf) if leaf.key == search_key => {
                 assert_eq!(root.leaf_count, 1);
                 root.maybe_node = 0;
@@ -9942,7 +15164,7 @@ impl OrderTreeNodes {
 
         // walk down the tree until finding the key
         loop {
-            match self.node(child_h).unwrap().case().unwrap() {
+            match self.borrow().node(child_h).unwrap().case().unwrap() {
                 NodeRef::Inner(inner) => {
                     parent_h = child_h;
                     let (new_child_h, new_crit_bit) = inner.walk_down(search_key);
@@ -9961,7 +15183,8 @@ impl OrderTreeNodes {
 
         // replace parent with its remaining child node
         // free child_h, replace *parent_h with *other_child_h, free other_child_h
-        let other_child_h = self.node(parent_h).unwrap().children().unwrap()[!crit_bit as usize];
+        let other_child_h =
+            self.borrow().node(parent_h).unwrap().children().unwrap()[!crit_bit as usize];
         let other_child_node_contents = self.remove(other_child_h).unwrap();
         let new_expiry = other_child_node_contents.earliest_expiry();
         *self.node_mut(parent_h).unwrap() = other_child_node_contents;
@@ -9978,22 +15201,22 @@ impl OrderTreeNodes {
 
     /// Internal: Removes only the node, does not remove any links etc, use remove_key()
     fn remove(&mut self, key: NodeHandle) -> Option<AnyNode> {
-        let val = *self.node(key)?;
+        let val = *self.borrow().node(key)?;
 
-        self.nodes[key as usize] = cast(FreeNode {
-            tag: if self.free_list_len == 0 {
+        self.dynamic[key as usize] = cast(FreeNode {
+            tag: if self.fixed.free_list_len == 0 {
                 NodeTag::LastFreeNode.into()
             } else {
                 NodeTag::FreeNode.into()
             },
             padding: Default::default(),
-            next: self.free_list_head,
+            next: self.fixed.free_list_head,
             reserved: [0; 72],
             force_align: 0,
         });
 
-        self.free_list_len += 1;
-        self.free_list_head = key;
+        self.fixed.free_list_len += 1;
+        self.fixed.free_list_head = key;
         Some(val)
     }
 
@@ -10004,29 +15227,30 @@ impl OrderTreeNodes {
             _ => unreachable!(),
         };
 
-        if self.free_list_len == 0 {
+        let fixed = &mut *self.fixed;
+        if fixed.free_list_len == 0 {
             require!(
-                (self.bump_index as usize) < self.nodes.len() && self.bump_index < u32::MAX,
+                (fixed.bump_index as usize) < self.dynamic.len() && fixed.bump_index < u32::MAX,
                 OpenBookError::SomeError
             );
 
-            self.nodes[self.bump_index as usize] = *val;
-            let key = self.bump_index;
-            self.bump_index += 1;
+            self.dynamic[fixed.bump_index as usize] = *val;
+            let key = fixed.bump_index;
+            fixed.bump_index += 1;
             return Ok(key);
         }
 
-        let key = self.free_list_head;
-        let node = &mut self.nodes[key as usize];
+        let key = fixed.free_list_head;
+        let node = &mut self.dynamic[key as usize];
 
         match NodeTag::try_from(node.tag) {
-            Ok(NodeTag::FreeNode) => assert!(self.free_list_len > 1),
-            Ok(NodeTag::LastFreeNode) => assert_eq!(self.free_list_len, 1),
+            Ok(NodeTag::FreeNode) => assert!(fixed.free_list_len > 1),
+            Ok(NodeTag::LastFreeNode) => assert_eq!(fixed.free_list_len, 1),
             _ => unreachable!(),
         };
 
-        self.free_list_head = cast_ref::<AnyNode, FreeNode>(node).next;
-        self.free_list_len -= 1;
+        fixed.free_list_head = cast_ref::<AnyNode, FreeNode>(node).next;
+        fixed.free_list_len -= 1;
         *node = *val;
         Ok(key)
     }
@@ -10054,7 +15278,7 @@ impl OrderTreeNodes {
         // walk down the tree until we find the insert location
         loop {
             // require if the new node will be a child of the root
-            let parent_contents = *self.node(parent_handle).unwrap();
+            let parent_contents = *self.borrow().node(parent_handle).unwrap();
             let parent_key = parent_contents.key().unwrap();
             if parent_key == new_leaf.key {
                 // This should never happen because key should never match
@@ -10122,10 +15346,6 @@ impl OrderTreeNodes {
         }
     }
 
-    pub fn is_full(&self) -> bool {
-        self.free_list_len <= 1 && (self.bump_index as usize) >= self.nodes.len() - 1
-    }
-
     /// When a node changes, the parents' child_earliest_expiry may need to be updated.
     ///
     /// This function walks up the `stack` of parents and applies the change where the
@@ -10148,29 +15368,6 @@ impl OrderTreeNodes {
             new_expiry = parent.earliest_expiry();
         }
     }
-
-    /// Returns the handle of the node with the lowest expiry timestamp, and this timestamp
-    pub fn find_earliest_expiry(&self, root: &OrderTreeRoot) -> Option<(NodeHandle, u64)> {
-        let mut current: NodeHandle = match root.node() {
-            Some(h) => h,
-            None => return None,
-        };
-
-        loop {
-            let contents = *self.node(current).unwrap();
-            match contents.case() {
-                None => unreachable!(),
-                Some(NodeRef::Inner(inner)) => {
-                    current = inner.children[(inner.child_earliest_expiry[0]
-                        > inner.child_earliest_expiry[1])
-                        as usize];
-                }
-                _ => {
-                    return Some((current, contents.earliest_expiry()));
-                }
-            };
-        }
-    }
 }
 
 #[cfg(test)]
@@ -10179,21 +15376,21 @@ mod tests {
     use super::*;
     use bytemuck::Zeroable;
 
-    fn new_order_tree(order_tree_type: OrderTreeType) -> OrderTreeNodes {
+    fn new_order_tree(order_tree_type: OrderTreeType) -> (OrderTreeNodes, Vec<AnyNode>) {
         let mut ot = OrderTreeNodes::zeroed();
         ot.order_tree_type = order_tree_type.into();
-        ot
+        (ot, vec![AnyNode::zeroed(); DEFAULT_ORDERTREE_NODES])
     }
 
-    fn verify_order_tree(order_tree: &OrderTreeNodes, root: &OrderTreeRoot) {
+    fn verify_order_tree(order_tree: OrderTreeNodesRef, root: &OrderTreeRoot) {
         verify_order_tree_invariant(order_tree, root);
         verify_order_tree_iteration(order_tree, root);
         verify_order_tree_expiry(order_tree, root);
     }
 
     // check that BookSide binary tree key invariant holds
-    fn verify_order_tree_invariant(order_tree: &OrderTreeNodes, root: &OrderTreeRoot) {
-        fn recursive_check(order_tree: &OrderTreeNodes, h: NodeHandle) {
+    fn verify_order_tree_invariant(order_tree: OrderTreeNodesRef, root: &OrderTreeRoot) {
+        fn recursive_check(order_tree: OrderTreeNodesRef, h: NodeHandle) {
             if let NodeRef::Inner(&inner) = order_tree.node(h).unwrap().case().unwrap() {
                 let left = order_tree.node(inner.children[0]).unwrap().key().unwrap();
                 let right = order_tree.node(inner.children[1]).unwrap().key().unwrap();
@@ -10218,7 +15415,7 @@ mod tests {
     }
 
     // check that iteration of order tree has the right order and misses no leaves
-    fn verify_order_tree_iteration(order_tree: &OrderTreeNodes, root: &OrderTreeRoot) {
+    fn verify_order_tree_iteration(order_tree: OrderTreeNodesRef, root: &OrderTreeRoot) {
         let mut total = 0;
         let ascending = order_tree.order_tree_type() == OrderTreeType::Asks;
         let mut last_key = if ascending { 0 } else { u128::MAX };
@@ -10236,8 +15433,8 @@ mod tests {
     }
 
     // check that BookSide::child_expiry invariant holds
-    fn verify_order_tree_expiry(order_tree: &OrderTreeNodes, root: &OrderTreeRoot) {
-        fn recursive_check(order_tree: &OrderTreeNodes, h: NodeHandle) {
+    fn verify_order_tree_expiry(order_tree: OrderTreeNodesRef, root: &OrderTreeRoot) {
+        fn recursive_check(order_tree: OrderTreeNodesRef, h: NodeHandle) {
             if let NodeRef::Inner(&inner) = order_tree.node(h).unwrap().case().unwrap() {
                 let left = order_tree
                     .node(inner.children[0])
@@ -10263,40 +15460,44 @@ mod tests {
 
     #[test]
     fn order_tree_expiry_manual() {
-        let mut bids = new_order_tree(OrderTreeType::Bids);
+        let (mut header, mut nodes) = new_order_tree(OrderTreeType::Bids);
+        let mut bids = OrderTreeNodesRefMut {
+            fixed: &mut header,
+            dynamic: &mut nodes,
+        };
         let new_expiring_leaf = |key: u128, expiry: u64| {
             LeafNode::new(0, key, Pubkey::default(), 0, expiry - 1, 1, -1, 0)
         };
 
         let mut root = OrderTreeRoot::zeroed();
 
-        assert!(bids.find_earliest_expiry(&root).is_none());
+        assert!(bids.borrow().find_earliest_expiry(&root).is_none());
 
         bids.insert_leaf(&mut root, &new_expiring_leaf(0, 5000))
             .unwrap();
         assert_eq!(
-            bids.find_earliest_expiry(&root).unwrap(),
+            bids.borrow().find_earliest_expiry(&root).unwrap(),
             (root.maybe_node, 5000)
         );
-        verify_order_tree(&bids, &root);
+        verify_order_tree(bids.borrow(), &root);
 
         let (new4000_h, _) = bids
             .insert_leaf(&mut root, &new_expiring_leaf(1, 4000))
             .unwrap();
-        assert_eq!(bids.find_earliest_expiry(&root).unwrap(), (new4000_h, 4000));
-        verify_order_tree(&bids, &root);
+        assert_eq!(bids.borrow().find_earliest_expiry(&root).unwrap(), (new4000_h, 4000));
+        verify_order_tree(bids.borrow(), &root);
 
         let (_new4500_h, _) = bids
             .insert_leaf(&mut root, &new_expiring_leaf(2, 4500))
             .unwrap();
-        assert_eq!(bids.find_earliest_expiry(&root).unwrap(), (new4000_h, 4000));
-        verify_order_tree(&bids, &root);
+        assert_eq!(bids.borrow().find_earliest_expiry(&root).unwrap(), (new4000_h, 4000));
+        verify_order_tree(bids.borrow(), &root);
 
         let (new3500_h, _) = bids
             .insert_leaf(&mut root, &new_expiring_leaf(3, 3500))
             .unwrap();
-        assert_eq!(bids.find_earliest_expiry(&root).unwrap(), (new3500_h, 3500));
-        verify_order_tree(&bids, &root);
+        assert_eq!(bids.borrow().find_earliest_expiry(&root).unwrap(), (new3500_h, 3500));
+        verify_order_tree(bids.borrow(), &root);
         // the first two levels of the tree are innernodes, with 0;1 on one side and 2;3 on the other
         assert_eq!(
             bids.node_mut(root.maybe_node)
@@ -10308,7 +15509,7 @@ mod tests {
         );
 
         bids.remove_by_key(&mut root, 3).unwrap();
-        verify_order_tree(&bids, &root);
+        verify_order_tree(bids.borrow(), &root);
         assert_eq!(
             bids.node_mut(root.maybe_node)
                 .unwrap()
@@ -10317,10 +15518,10 @@ mod tests {
                 .child_earliest_expiry,
             [4000, 4500]
         );
-        assert_eq!(bids.find_earliest_expiry(&root).unwrap().1, 4000);
+        assert_eq!(bids.borrow().find_earliest_expiry(&root).unwrap().1, 4000);
 
         bids.remove_by_key(&mut root, 0).unwrap();
-        verify_order_tree(&bids, &root);
+        verify_order_tree(bids.borrow(), &root);
         assert_eq!(
             bids.node_mut(root.maybe_node)
                 .unwrap()
@@ -10329,15 +15530,15 @@ mod tests {
                 .child_earliest_expiry,
             [4000, 4500]
         );
-        assert_eq!(bids.find_earliest_expiry(&root).unwrap().1, 4000);
+        assert_eq!(bids.borrow().find_earliest_expiry(&root).unwrap().1, 4000);
 
         bids.remove_by_key(&mut root, 1).unwrap();
-        verify_order_tree(&bids, &root);
-        assert_eq!(bids.find_earliest_expiry(&root).unwrap().1, 4500);
+        verify_order_tree(bids.borrow(), &root);
+        assert_eq!(bids.borrow().find_earliest_expiry(&root).unwrap().1, 4500);
 
         bids.remove_by_key(&mut root, 2).unwrap();
-        verify_order_tree(&bids, &root);
-        assert!(bids.find_earliest_expiry(&root).is_none());
+        verify_order_tree(bids.borrow(), &root);
+        assert!(bids.borrow().find_earliest_expiry(&root).is_none());
     }
 
     #[test]
@@ -10346,7 +15547,11 @@ mod tests {
         let mut rng = rand::thread_rng();
 
         let mut root = OrderTreeRoot::zeroed();
-        let mut bids = new_order_tree(OrderTreeType::Bids);
+        let (mut header, mut nodes) = new_order_tree(OrderTreeType::Bids);
+        let mut bids = OrderTreeNodesRefMut {
+            fixed: &mut header,
+            dynamic: &mut nodes,
+        };
         let new_expiring_leaf = |key: u128, expiry: u64| {
             LeafNode::new(0, key, Pubkey::default(), 0, expiry - 1, 1, -1, 0)
         };
@@ -10362,7 +15567,7 @@ mod tests {
             keys.push(key);
             bids.insert_leaf(&mut root, &new_expiring_leaf(key, expiry))
                 .unwrap();
-            verify_order_tree(&bids, &root);
+            verify_order_tree(bids.borrow(), &root);
         }
 
         // remove 50 at random
@@ -10373,7 +15578,7 @@ mod tests {
             let k = keys[rng.gen_range(0..keys.len())];
             bids.remove_by_key(&mut root, k).unwrap();
             keys.retain(|v| *v != k);
-            verify_order_tree(&bids, &root);
+            verify_order_tree(bids.borrow(), &root);
         }
     }
 }
@@ -10384,7 +15589,7 @@ use super::*;
 
 /// Iterate over orders in order (bids=descending, asks=ascending)
 pub struct OrderTreeIter<'a> {
-    order_tree: &'a OrderTreeNodes,
+    order_tree: OrderTreeNodesRef<'a>,
     /// InnerNodes where the right side still needs to be iterated on
     stack: Vec<&'a InnerNode>,
     /// To be returned on `next()`
@@ -10396,7 +15601,7 @@ pub struct OrderTreeIter<'a> {
 }
 
 impl<'a> OrderTreeIter<'a> {
-    pub fn new(order_tree: &'a OrderTreeNodes, root: &OrderTreeRoot) -> Self {
+    pub fn new(order_tree: OrderTreeNodesRef<'a>, root: &OrderTreeRoot) -> Self {
         let (left, right) = if order_tree.order_tree_type() == OrderTreeType::Bids {
             (1, 0)
         } else {
@@ -10603,7 +15808,9 @@ pub struct RewardInfo {
 // File: openbook-v2/programs/openbook-v2/src/token_utils.rs
 use super::*;
 use anchor_lang::system_program;
-use anchor_spl::token;
+use anchor_spl::token::{self, Token, TokenAccount};
+
+use crate::error::OpenBookError;
 
 pub fn token_transfer<
     'info,
@@ -10692,73 +15899,236 @@ pub fn system_program_transfer<
     }
 }
 
-
-// File: openbook-v2/programs/openbook-v2/src/types.rs
-use anchor_lang::prelude::*;
-/// Nothing in Rust shall use these types. They only exist so that the Anchor IDL
-/// knows about them and typescript can deserialize it.
-
-#[derive(AnchorSerialize, AnchorDeserialize, Default)]
-pub struct I80F48 {
-    val: i128,
+/// The system program is only required by the accounts that move native SOL
+pub fn system_program_for_native<'a, 'info>(
+    system_program: &'a Option<Program<'info, System>>,
+) -> Result<&'a Program<'info, System>> {
+    system_program
+        .as_ref()
+        .ok_or_else(|| error!(OpenBookError::InvalidNativeTransfer))
 }
 
-
-// File: openbook-v2/programs/openbook-v2/src/util.rs
-use crate::error::OpenBookError;
-use anchor_lang::prelude::*;
-
-pub fn fill_from_str<const N: usize>(name: &str) -> Result<[u8; N]> {
-    let name_bytes = name.as_bytes();
-    require!(name_bytes.len() <= N, OpenBookError::InvalidInputNameLength);
-    let mut name_ = [0u8; N];
-    name_[..name_bytes.len()].copy_from_slice(name_bytes);
-    Ok(name_)
+/// The payer of native SOL is only required when a token account of a wrapped SOL side is left
+/// out
+pub fn native_payer<'a, 'info>(
+    native_payer: &'a Option<Signer<'info>>,
+) -> Result<&'a Signer<'info>> {
+    native_payer
+        .as_ref()
+        .ok_or_else(|| error!(OpenBookError::InvalidNativeTransfer))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_fill_from_str() {
-        assert_eq!(fill_from_str::<4>(""), Ok([0, 0, 0, 0]));
-        assert_eq!(fill_from_str::<4>("abc"), Ok([b'a', b'b', b'c', 0]));
-        assert_eq!(fill_from_str::<4>("abcd"), Ok([b'a', b'b', b'c', b'd']));
-        assert!(fill_from_str::<4>("abcde").is_err());
+/// Wraps `amount` lamports of `from` into the wrapped SOL `vault`
+pub fn native_transfer<
+    'info,
+    S: ToAccountInfo<'info>,
+    P: ToAccountInfo<'info>,
+    A: ToAccountInfo<'info>,
+>(
+    amount: u64,
+    system_program: &S,
+    token_program: &P,
+    from: &A,
+    vault: &Account<'info, TokenAccount>,
+) -> Result<()> {
+    require!(vault.is_native(), OpenBookError::InvalidNativeTransfer);
+    if amount > 0 {
+        system_program_transfer(amount, system_program, from, vault)?;
+        token::sync_native(CpiContext::new(
+            token_program.to_account_info(),
+            token::SyncNative {
+                account: vault.to_account_info(),
+            },
+        ))
+    } else {
+        Ok(())
     }
 }
 
+/// Unwraps `amount` of the wrapped SOL `vault` into lamports of `destination`.
+///
+/// Token accounts can only be unwrapped by closing them, so the tokens go through
+/// `native_settle_account`, a temporary account at the `native_settle_seeds` PDA whose
+/// rent `payer` advances. Lamports anyone sent to that address end up with `payer`.
+#[allow(clippy::too_many_arguments)]
+pub fn native_transfer_signed<'info>(
+    amount: u64,
+    token_program: &Program<'info, Token>,
+    system_program: &Program<'info, System>,
+    vault: &Account<'info, TokenAccount>,
+    native_mint: &AccountInfo<'info>,
+    native_settle_account: &AccountInfo<'info>,
+    native_settle_seeds: &[&[u8]],
+    payer: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+    authority: &AccountInfo<'info>,
+    seeds: &[&[u8]],
+) -> Result<()> {
+    require!(vault.is_native(), OpenBookError::InvalidNativeTransfer);
+    if amount == 0 {
+        return Ok(());
+    }
+
+    let rent = Rent::get()?.minimum_balance(TokenAccount::LEN);
+    let current_lamports = native_settle_account.lamports();
+    if current_lamports == 0 {
+        system_program::create_account(
+            CpiContext::new_with_signer(
+                system_program.to_account_info(),
+                system_program::CreateAccount {
+                    from: payer.clone(),
+                    to: native_settle_account.clone(),
+                },
+                &[native_settle_seeds],
+            ),
+            rent,
+            TokenAccount::LEN as u64,
+            &token::ID,
+        )?;
+    } else {
+        // create_account fails on funded addresses, so set the account up step by step
+        system_program_transfer(
+            rent.saturating_sub(current_lamports),
+            system_program,
+            payer,
+            native_settle_account,
+        )?;
+        system_program::allocate(
+            CpiContext::new_with_signer(
+                system_program.to_account_info(),
+                system_program::Allocate {
+                    account_to_allocate: native_settle_account.clone(),
+                },
+                &[native_settle_seeds],
+            ),
+            TokenAccount::LEN as u64,
+        )?;
+        system_program::assign(
+            CpiContext::new_with_signer(
+                system_program.to_account_info(),
+                system_program::Assign {
+                    account_to_assign: native_settle_account.clone(),
+                },
+                &[native_settle_seeds],
+            ),
+            &token::ID,
+        )?;
+    }
+    token::initialize_account3(CpiContext::new(
+        token_program.to_account_info(),
+        token::InitializeAccount3 {
+            account: native_settle_account.clone(),
+            mint: native_mint.clone(),
+            authority: authority.clone(),
+        },
+    ))?;
+
+    token_transfer_signed(
+        amount,
+        token_program,
+        &vault.to_account_info(),
+        native_settle_account,
+        authority,
+        seeds,
+    )?;
+    token::close_account(CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        token::CloseAccount {
+            account: native_settle_account.clone(),
+            destination: payer.clone(),
+            authority: authority.clone(),
+        },
+        &[seeds],
+    ))?;
+
+    // payer got its rent back along with the unwrapped amount
+    if payer.key != destination.key {
+        system_program_transfer(amount, system_program, payer, destination)?;
+    }
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/types.rs
+use anchor_lang::prelude::*;
+/// Nothing in Rust shall use these types. They only exist so that the Anchor IDL
+/// knows about them and typescript can deserialize it.
+
+#[derive(AnchorSerialize, AnchorDeserialize, Default)]
+pub struct I80F48 {
+    val: i128,
+}
+
+
+// File: openbook-v2/programs/openbook-v2/src/util.rs
+use crate::error::OpenBookError;
+use anchor_lang::prelude::*;
+
+pub fn fill_from_str<const N: usize>(name: &str) -> Result<[u8; N]> {
+    let name_bytes = name.as_bytes();
+    require!(name_bytes.len() <= N, OpenBookError::InvalidInputNameLength);
+    let mut name_ = [0u8; N];
+    name_[..name_bytes.len()].copy_from_slice(name_bytes);
+    Ok(name_)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_from_str() {
+        assert_eq!(fill_from_str::<4>(""), Ok([0, 0, 0, 0]));
+        assert_eq!(fill_from_str::<4>("abc"), Ok([b'a', b'b', b'c', 0]));
+        assert_eq!(fill_from_str::<4>("abcd"), Ok([b'a', b'b', b'c', b'd']));
+        assert!(fill_from_str::<4>("abcde").is_err());
+    }
+}
+
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/mod.rs
+pub use anchor_lang::prelude::Pubkey;
+pub use anchor_spl::token::TokenAccount;
+pub use fixed::types::I80F48;
+pub use solana_program_test::*;
+pub use solana_sdk::transport::TransportError;
+
+pub use openbook_v2::{error::OpenBookError, state::*};
+pub use program_test::*;
+pub use setup::*;
 
-// File: openbook-v2/programs/openbook-v2/tests/cases/mod.rs
-pub use anchor_lang::prelude::Pubkey;
-pub use anchor_spl::token::TokenAccount;
-pub use fixed::types::I80F48;
-pub use solana_program_test::*;
-pub use solana_sdk::transport::TransportError;
-
-pub use openbook_v2::{error::OpenBookError, state::*};
-pub use program_test::*;
-pub use setup::*;
-
 pub use super::program_test;
 
 pub use utils::assert_equal_fixed_f64 as assert_equal;
 
 mod test;
+mod test_cpi_events;
 mod test_crank;
 mod test_create_market;
+mod test_delegate_permissions;
 mod test_edit_order;
+mod test_eviction;
+mod test_exposure_limits;
 mod test_fees;
 mod test_fill_or_kill_order;
 mod test_indexer;
+mod test_min_order_size;
 mod test_multiple_orders;
+mod test_native_sol;
 mod test_oracle_peg;
+mod test_order_logs;
 mod test_order_types;
 mod test_permissioned;
 mod test_place_order_remaining;
+mod test_resize_open_orders_account;
 mod test_self_trade;
+mod test_swap;
+mod test_swap_route;
 mod test_take_order;
+mod test_transfer_free_balance;
+mod test_view_book;
+mod test_withdraw;
 
 
 // File: openbook-v2/programs/openbook-v2/tests/cases/test.rs
@@ -10788,6 +16158,7 @@ async fn test_simple_settle() -> Result<(), TransportError> {
     })
     .await?;
     let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
 
     //
     // TEST: Create another market
@@ -10984,6 +16355,7 @@ async fn test_delegate_settle() -> Result<(), TransportError> {
     })
     .await?;
     let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
     let payer_token_0 = context.users[1].token_accounts[0];
     let payer_token_1 = context.users[1].token_accounts[1];
 
@@ -11144,6 +16516,7 @@ async fn test_cancel_orders() -> Result<(), TransportError> {
     })
     .await?;
     let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
 
     send_tx(
         solana,
@@ -11492,9 +16865,7 @@ async fn test_expired_orders() -> Result<(), TransportError> {
     .unwrap();
     {
         let market_acc = solana.get_account_boxed::<Market>(market).await;
-        let event_heap = solana
-            .get_account_boxed::<EventHeap>(market_acc.event_heap)
-            .await;
+        let event_heap = solana.get_event_heap(market_acc.event_heap).await;
         assert_eq!(event_heap.header.count(), 1);
     }
     {
@@ -11538,7 +16909,7 @@ async fn test_expired_orders() -> Result<(), TransportError> {
     // No more events on event_heap
     {
         let market_acc = solana.get_account::<Market>(market).await;
-        let event_heap = solana.get_account::<EventHeap>(market_acc.event_heap).await;
+        let event_heap = solana.get_event_heap(market_acc.event_heap).await;
 
         assert_eq!(event_heap.header.count(), 0);
     }
@@ -11547,9 +16918,180 @@ async fn test_expired_orders() -> Result<(), TransportError> {
 }
 
 
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_cpi_events.rs
+#![cfg(feature = "cpi-events")]
+
+use super::*;
+use openbook_v2::logs::{TakerSignatureLog, CPI_EVENTS_LIMIT};
+
+#[tokio::test]
+async fn test_matching_limit_with_cpi_events() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        account_2,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
+
+    let place_ask_ix = PlaceOrderInstruction {
+        open_orders_account: account_1,
+        open_orders_admin: None,
+        market,
+        signer: owner,
+        user_token_account: owner_token_0,
+        market_vault: market_base_vault,
+        side: Side::Ask,
+        price_lots,
+        max_base_lots: 1,
+        max_quote_lots_including_fees: 10000,
+        client_order_id: 0,
+        expiry_timestamp: 0,
+        order_type: PlaceOrderType::Limit,
+        self_trade_behavior: SelfTradeBehavior::default(),
+        remainings: vec![],
+    };
+
+    for client_order_id in 0..10 {
+        send_tx(
+            solana,
+            PlaceOrderInstruction {
+                client_order_id,
+                ..place_ask_ix.clone()
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    // every matched order sends a few events through self-CPIs, the client's limit of 10
+    // would exceed the instruction trace
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            max_base_lots: 10,
+            max_quote_lots_including_fees: 100000,
+            order_type: PlaceOrderType::ImmediateOrCancel,
+            remainings: vec![account_1],
+            ..place_ask_ix.clone()
+        },
+    )
+    .await
+    .unwrap();
+
+    let events = solana.program_log_events::<TakerSignatureLog>();
+    assert_eq!(events.len(), CPI_EVENTS_LIMIT as usize);
+
+    let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+    let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+    assert_eq!(
+        open_orders_account_1.position.asks_base_lots,
+        10 - CPI_EVENTS_LIMIT as i64
+    );
+    assert_eq!(
+        open_orders_account_2.position.base_free_native,
+        CPI_EVENTS_LIMIT as u64 * 100
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_matching_limit_shared_by_orders_with_cpi_events() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        price_lots,
+        account_1,
+        account_2,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
+
+    for client_order_id in 0..2 * CPI_EVENTS_LIMIT as u64 {
+        send_tx(
+            solana,
+            PlaceOrderInstruction {
+                open_orders_account: account_1,
+                open_orders_admin: None,
+                market,
+                signer: owner,
+                user_token_account: owner_token_0,
+                market_vault: market_base_vault,
+                side: Side::Ask,
+                price_lots,
+                max_base_lots: 1,
+                max_quote_lots_including_fees: 10000,
+                client_order_id,
+                expiry_timestamp: 0,
+                order_type: PlaceOrderType::Limit,
+                self_trade_behavior: SelfTradeBehavior::default(),
+                remainings: vec![],
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    // each bid could take all asks on its own, the second one only gets what the first
+    // one left of the instruction's limit
+    let bid = openbook_v2::PlaceMultipleOrdersArgs {
+        price_lots,
+        max_quote_lots_including_fees: 100000,
+        expiry_timestamp: 0,
+    };
+    send_tx(
+        solana,
+        CancelAllAndPlaceOrdersInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            orders_type: PlaceOrderType::Limit,
+            bids: vec![bid, bid],
+            asks: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    let events = solana.program_log_events::<TakerSignatureLog>();
+    assert!(events.len() > CPI_EVENTS_LIMIT as usize);
+    assert!(events.len() < 2 * CPI_EVENTS_LIMIT as usize);
+
+    let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+    assert_eq!(
+        open_orders_account_1.position.asks_base_lots,
+        2 * CPI_EVENTS_LIMIT as i64 - events.len() as i64
+    );
+
+    Ok(())
+}
+
+
 // File: openbook-v2/programs/openbook-v2/tests/cases/test_crank.rs
 use super::*;
 use bytemuck::cast_ref;
+use openbook_v2::logs::OrderExpiredLog;
 
 #[tokio::test]
 async fn test_skip_missing_accounts() -> Result<(), TransportError> {
@@ -11610,6 +17152,7 @@ async fn test_skip_missing_accounts() -> Result<(), TransportError> {
         )
     };
     let taker = create_open_orders_account(solana, owner, market, 4, &context.users[1], None).await;
+    set_account_self_trade_scope(solana, owner, taker).await;
 
     send_tx(
         solana,
@@ -11704,7 +17247,7 @@ async fn test_skip_missing_accounts() -> Result<(), TransportError> {
     .unwrap();
 
     {
-        let event_heap = solana.get_account_boxed::<EventHeap>(event_heap).await;
+        let event_heap = solana.get_event_heap(event_heap).await;
         assert_eq!(event_heap.header.count(), 3);
         assert_eq!(fill_maker(event_heap.at_slot(0).unwrap()), maker_1);
         assert_eq!(fill_maker(event_heap.at_slot(1).unwrap()), maker_2);
@@ -11723,7 +17266,7 @@ async fn test_skip_missing_accounts() -> Result<(), TransportError> {
     .unwrap();
 
     {
-        let event_heap = solana.get_account_boxed::<EventHeap>(event_heap).await;
+        let event_heap = solana.get_event_heap(event_heap).await;
         assert_eq!(event_heap.header.count(), 1);
         assert_eq!(fill_maker(event_heap.front().unwrap()), maker_1);
     }
@@ -11790,6 +17333,7 @@ async fn test_crank_given_events() -> Result<(), TransportError> {
         )
     };
     let taker = create_open_orders_account(solana, owner, market, 4, &context.users[1], None).await;
+    set_account_self_trade_scope(solana, owner, taker).await;
 
     send_tx(
         solana,
@@ -11884,7 +17428,7 @@ async fn test_crank_given_events() -> Result<(), TransportError> {
     .unwrap();
 
     {
-        let event_heap = solana.get_account_boxed::<EventHeap>(event_heap).await;
+        let event_heap = solana.get_event_heap(event_heap).await;
         assert_eq!(event_heap.header.count(), 3);
         assert_eq!(fill_maker(event_heap.at_slot(0).unwrap()), maker_1);
         assert_eq!(fill_maker(event_heap.at_slot(1).unwrap()), maker_2);
@@ -11904,7 +17448,7 @@ async fn test_crank_given_events() -> Result<(), TransportError> {
     .unwrap();
 
     {
-        let event_heap = solana.get_account_boxed::<EventHeap>(event_heap).await;
+        let event_heap = solana.get_event_heap(event_heap).await;
         assert_eq!(event_heap.header.count(), 1);
         assert_eq!(fill_maker(event_heap.front().unwrap()), maker_2);
     }
@@ -11915,7 +17459,7 @@ async fn test_crank_given_events() -> Result<(), TransportError> {
         ConsumeGivenEventsInstruction {
             consume_events_admin: None,
             market,
-            slots: vec![openbook_v2::state::MAX_NUM_EVENTS.into()],
+            slots: vec![openbook_v2::state::DEFAULT_NUM_EVENTS.into()],
             open_orders_accounts: vec![maker_2],
         },
     )
@@ -11936,177 +17480,245 @@ async fn test_crank_given_events() -> Result<(), TransportError> {
     .unwrap();
 
     {
-        let event_heap = solana.get_account_boxed::<EventHeap>(event_heap).await;
+        let event_heap = solana.get_event_heap(event_heap).await;
         assert_eq!(event_heap.header.count(), 0);
     }
 
     Ok(())
 }
 
-fn fill_maker(anyevent: &AnyEvent) -> Pubkey {
-    let event: &FillEvent = cast_ref(anyevent);
-    event.maker
-}
-
-
-// File: openbook-v2/programs/openbook-v2/tests/cases/test_create_market.rs
-use super::*;
-
 #[tokio::test]
-async fn test_with_single_oracle() -> Result<(), TransportError> {
-    let context = TestContextBuilder::new().start_default().await;
+async fn test_crank_reward() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
     let solana = &context.solana.clone();
 
-    let payer = context.users[0].key;
-    let mints = &context.mints[0..=2];
-    let tokens = Token::create(mints.to_vec(), solana, payer, payer).await;
-
-    let market_a = TestKeypair::new();
-    let market_b = TestKeypair::new();
+    let taker = context.users[2].key;
+    let cranker = context.users[3].key;
 
-    assert!(send_tx(
+    send_tx(
         solana,
-        CreateMarketInstruction {
-            payer,
-            market: market_a,
-            quote_lot_size: 100,
-            base_lot_size: 100,
-            base_mint: mints[0].pubkey,
-            quote_mint: mints[1].pubkey,
-            ..CreateMarketInstruction::with_new_book_and_heap(solana, Some(tokens[0].oracle), None,)
-                .await
-        },
-    )
-    .await
-    .is_ok());
-
-    assert_eq!(
-        send_tx_and_get_ix_custom_error(
-            solana,
-            CreateMarketInstruction {
-                payer,
-                market: market_b,
-                quote_lot_size: 100,
-                base_lot_size: 100,
-                base_mint: mints[0].pubkey,
-                quote_mint: mints[1].pubkey,
-                ..CreateMarketInstruction::with_new_book_and_heap(
-                    solana,
-                    None,
-                    Some(tokens[1].oracle)
-                )
-                .await
-            },
-        )
-        .await,
-        Some(openbook_v2::error::OpenBookError::InvalidSecondOracle.into())
-    );
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
 
-    Ok(())
-}
+    // the fill event stays on the heap, so the taker pays the penalty into the reward pool
+    send_tx(
+        solana,
+        PlaceTakeOrderInstruction {
+            market,
+            signer: taker,
+            user_base_account: context.users[2].token_accounts[0],
+            user_quote_account: context.users[2].token_accounts[1],
+            market_base_vault,
+            market_quote_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            open_orders_admin: None,
+        },
+    )
+    .await
+    .unwrap();
 
-#[tokio::test]
-async fn test_with_same_oracles() -> Result<(), TransportError> {
-    let context = TestContextBuilder::new().start_default().await;
-    let solana = &context.solana.clone();
+    {
+        let market = solana.get_account::<Market>(market).await;
+        assert_eq!(market.crank_reward_pool, PENALTY_EVENT_HEAP);
+    }
 
-    let payer = context.users[0].key;
-    let mints = &context.mints[0..=2];
+    let cranker_lamports_before = solana.get_lamports(cranker.pubkey()).await;
+    let market_lamports_before = solana.get_lamports(market).await;
 
-    let market = TestKeypair::new();
-    let fake_oracle_a = solana.create_account_from_len(&payer.pubkey(), 100).await;
+    // without the maker's account the event is skipped, nothing to earn
+    send_tx(
+        solana,
+        ConsumeEventsWithRewardInstruction {
+            market,
+            open_orders_accounts: vec![],
+            cranker,
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let market_acc = solana.get_account::<Market>(market).await;
+        assert_eq!(market_acc.crank_reward_pool, PENALTY_EVENT_HEAP);
+        assert_eq!(
+            solana.get_lamports(cranker.pubkey()).await,
+            cranker_lamports_before
+        );
+    }
+
+    send_tx(
+        solana,
+        ConsumeEventsWithRewardInstruction {
+            market,
+            open_orders_accounts: vec![account_1],
+            cranker,
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let market_acc = solana.get_account::<Market>(market).await;
+        assert_eq!(
+            market_acc.crank_reward_pool,
+            PENALTY_EVENT_HEAP - CRANK_REWARD_PER_EVENT
+        );
+        assert_eq!(
+            solana.get_lamports(cranker.pubkey()).await,
+            cranker_lamports_before + CRANK_REWARD_PER_EVENT
+        );
+        assert_eq!(
+            solana.get_lamports(market).await,
+            market_lamports_before - CRANK_REWARD_PER_EVENT
+        );
+    }
+
+    // nothing left to consume, nothing to earn
+    send_tx(
+        solana,
+        ConsumeEventsWithRewardInstruction {
+            market,
+            open_orders_accounts: vec![account_1],
+            cranker,
+        },
+    )
+    .await
+    .unwrap();
 
     assert_eq!(
-        send_tx_and_get_ix_custom_error(
-            solana,
-            CreateMarketInstruction {
-                payer,
-                market,
-                quote_lot_size: 100,
-                base_lot_size: 100,
-                base_mint: mints[0].pubkey,
-                quote_mint: mints[1].pubkey,
-                ..CreateMarketInstruction::with_new_book_and_heap(
-                    solana,
-                    Some(fake_oracle_a),
-                    Some(fake_oracle_a),
-                )
-                .await
-            },
-        )
-        .await,
-        Some(anchor_lang::error::ErrorCode::RequireKeysNeqViolated.into())
+        solana.get_lamports(cranker.pubkey()).await,
+        cranker_lamports_before + CRANK_REWARD_PER_EVENT
     );
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_with_wrong_oracle_types() -> Result<(), TransportError> {
-    let context = TestContextBuilder::new().start_default().await;
+async fn test_crank_reward_of_multiple_fills() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
     let solana = &context.solana.clone();
 
-    let payer = context.users[0].key;
-    let mints = &context.mints[0..=2];
-
-    let market_a = TestKeypair::new();
-    let market_ab = TestKeypair::new();
-
-    let fake_oracle_a = solana.create_account_from_len(&payer.pubkey(), 100).await;
-    let fake_oracle_b = solana.create_account_from_len(&payer.pubkey(), 100).await;
+    let taker = context.users[2].key;
+    let cranker = context.users[3].key;
 
-    assert_eq!(
-        send_tx_and_get_ix_custom_error(
+    for client_order_id in 0..10 {
+        send_tx(
             solana,
-            CreateMarketInstruction {
-                payer,
-                market: market_a,
-                quote_lot_size: 100,
-                base_lot_size: 100,
-                base_mint: mints[0].pubkey,
-                quote_mint: mints[1].pubkey,
-                ..CreateMarketInstruction::with_new_book_and_heap(solana, Some(fake_oracle_a), None)
-                    .await
+            PlaceOrderInstruction {
+                open_orders_account: account_1,
+                open_orders_admin: None,
+                market,
+                signer: owner,
+                user_token_account: owner_token_1,
+                market_vault: market_quote_vault,
+                side: Side::Bid,
+                price_lots,
+                max_base_lots: 1,
+                max_quote_lots_including_fees: 10000,
+                client_order_id,
+                expiry_timestamp: 0,
+                order_type: PlaceOrderType::Limit,
+                self_trade_behavior: SelfTradeBehavior::default(),
+                remainings: vec![],
             },
         )
-        .await,
-        Some(openbook_v2::error::OpenBookError::UnknownOracleType.into())
+        .await
+        .unwrap();
+    }
+
+    // one take adds ten fill events but pays the penalty once
+    send_tx(
+        solana,
+        PlaceTakeOrderInstruction {
+            market,
+            signer: taker,
+            user_base_account: context.users[2].token_accounts[0],
+            user_quote_account: context.users[2].token_accounts[1],
+            market_base_vault,
+            market_quote_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 10,
+            max_quote_lots_including_fees: 100000,
+            open_orders_admin: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    let cranker_lamports_before = solana.get_lamports(cranker.pubkey()).await;
+    let consume = ConsumeEventsWithRewardInstruction {
+        market,
+        open_orders_accounts: vec![account_1],
+        cranker,
+    };
+
+    // the penalty is shared among the events instead of paying CRANK_REWARD_PER_EVENT each
+    send_tx(solana, consume.clone()).await.unwrap();
+    assert_eq!(
+        solana.get_lamports(cranker.pubkey()).await,
+        cranker_lamports_before + 8 * PENALTY_EVENT_HEAP / 10
     );
 
+    send_tx(solana, consume).await.unwrap();
     assert_eq!(
-        send_tx_and_get_ix_custom_error(
-            solana,
-            CreateMarketInstruction {
-                payer,
-                market: market_ab,
-                quote_lot_size: 100,
-                base_lot_size: 100,
-                base_mint: mints[0].pubkey,
-                quote_mint: mints[1].pubkey,
-                ..CreateMarketInstruction::with_new_book_and_heap(
-                    solana,
-                    Some(fake_oracle_a),
-                    Some(fake_oracle_b)
-                )
-                .await
-            },
-        )
-        .await,
-        Some(openbook_v2::error::OpenBookError::UnknownOracleType.into())
+        solana.get_lamports(cranker.pubkey()).await,
+        cranker_lamports_before + PENALTY_EVENT_HEAP
+    );
+    assert_eq!(
+        solana.get_account::<Market>(market).await.crank_reward_pool,
+        0
     );
 
     Ok(())
 }
 
-
-// File: openbook-v2/programs/openbook-v2/tests/cases/test_edit_order.rs
-use super::*;
-
 #[tokio::test]
-async fn test_edit_order() -> Result<(), TransportError> {
+async fn test_purge_expired_orders() -> Result<(), TransportError> {
+    let posting_bond = 10_000;
     let TestInitialize {
         context,
-        collect_fee_admin,
         owner,
         owner_token_0,
         owner_token_1,
@@ -12114,25 +17726,27 @@ async fn test_edit_order() -> Result<(), TransportError> {
         market_base_vault,
         market_quote_vault,
         price_lots,
-        tokens,
         account_1,
         account_2,
+        bids,
         ..
     } = TestContext::new_with_market(TestNewMarketInitialize {
-        maker_fee: -100,
-        taker_fee: 200,
+        posting_bond,
         ..TestNewMarketInitialize::default()
     })
     .await?;
     let solana = &context.solana.clone();
 
-    // Set the initial oracle price
-    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+    let taker = context.users[2].key;
+    let cranker = context.users[3].key;
+    solana.transfer_lamports(owner, account_1, 3 * posting_bond).await;
+    solana.transfer_lamports(owner, account_2, posting_bond).await;
 
+    // a taker fill funds the crank reward pool
     send_tx(
         solana,
         PlaceOrderInstruction {
-            open_orders_account: account_1,
+            open_orders_account: account_2,
             open_orders_admin: None,
             market,
             signer: owner,
@@ -12140,9 +17754,9 @@ async fn test_edit_order() -> Result<(), TransportError> {
             market_vault: market_quote_vault,
             side: Side::Bid,
             price_lots,
-            max_base_lots: 2,
-            max_quote_lots_including_fees: 20004,
-            client_order_id: 12,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 20_000,
+            client_order_id: 0,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
@@ -12151,66 +17765,29 @@ async fn test_edit_order() -> Result<(), TransportError> {
     )
     .await
     .unwrap();
-
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 2);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-    }
-
-    // No client Id found, is treated as if order was fully filled
     send_tx(
         solana,
-        EditOrderInstruction {
-            open_orders_account: account_1,
-            open_orders_admin: None,
+        PlaceTakeOrderInstruction {
             market,
-            signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10002,
-            client_order_id: 11,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
-            expected_cancel_size: 1,
-        },
-    )
-    .await
-    .unwrap();
-
-    // take 1. send remaining to crank and remove 1 bids_base_lots
-    send_tx(
-        solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_2,
-            open_orders_admin: None,
-            market,
-            signer: owner,
-            user_token_account: owner_token_0,
-            market_vault: market_base_vault,
-            side: Side::Ask,
+            signer: taker,
+            user_base_account: context.users[2].token_accounts[0],
+            user_quote_account: context.users[2].token_accounts[1],
+            market_base_vault,
+            market_quote_vault,
+            side: Side::Ask,
             price_lots,
             max_base_lots: 1,
             max_quote_lots_including_fees: 10000,
-            client_order_id: 12,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![account_1],
+            open_orders_admin: None,
         },
     )
     .await
     .unwrap();
 
-    // 1 base_lot has been taken, post only 1
+    let expiry = solana.get_clock().await.unix_timestamp as u64 + 10;
     send_tx(
         solana,
-        EditOrderInstruction {
+        PlaceOrderInstruction {
             open_orders_account: account_1,
             open_orders_admin: None,
             market,
@@ -12218,62 +17795,19 @@ async fn test_edit_order() -> Result<(), TransportError> {
             user_token_account: owner_token_1,
             market_vault: market_quote_vault,
             side: Side::Bid,
-            price_lots,
-            max_base_lots: 2,
-            max_quote_lots_including_fees: 20004,
-            client_order_id: 12,
-            expiry_timestamp: 0,
+            price_lots: price_lots - 1,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 20_000,
+            client_order_id: 0,
+            expiry_timestamp: expiry,
             order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
             remainings: vec![],
-            expected_cancel_size: 2,
         },
     )
     .await
     .unwrap();
 
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-    }
-
-    Ok(())
-}
-
-
-// File: openbook-v2/programs/openbook-v2/tests/cases/test_fees.rs
-use super::*;
-
-#[tokio::test]
-async fn test_fees_accrued() -> Result<(), TransportError> {
-    let TestInitialize {
-        context,
-        collect_fee_admin,
-        owner,
-        mints,
-        owner_token_0,
-        owner_token_1,
-        market,
-
-        market_base_vault,
-        market_quote_vault,
-        price_lots,
-        tokens,
-        account_1,
-        account_2,
-        ..
-    } = TestContext::new_with_market(TestNewMarketInitialize {
-        maker_fee: -100,
-        taker_fee: 200,
-        ..TestNewMarketInitialize::default()
-    })
-    .await?;
-    let solana = &context.solana.clone();
-
-    // Set the initial oracle price
-    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
-
     send_tx(
         solana,
         PlaceOrderInstruction {
@@ -12284,9 +17818,9 @@ async fn test_fees_accrued() -> Result<(), TransportError> {
             user_token_account: owner_token_1,
             market_vault: market_quote_vault,
             side: Side::Bid,
-            price_lots,
+            price_lots: price_lots - 2,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
+            max_quote_lots_including_fees: 20_000,
             client_order_id: 0,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
@@ -12300,18 +17834,18 @@ async fn test_fees_accrued() -> Result<(), TransportError> {
     send_tx(
         solana,
         PlaceOrderInstruction {
-            open_orders_account: account_2,
+            open_orders_account: account_1,
             open_orders_admin: None,
             market,
             signer: owner,
             user_token_account: owner_token_0,
             market_vault: market_base_vault,
             side: Side::Ask,
-            price_lots,
+            price_lots: price_lots + 1,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
+            max_quote_lots_including_fees: 20_000,
             client_order_id: 0,
-            expiry_timestamp: 0,
+            expiry_timestamp: expiry,
             order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
             remainings: vec![],
@@ -12320,122 +17854,95 @@ async fn test_fees_accrued() -> Result<(), TransportError> {
     .await
     .unwrap();
 
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 0);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 99980);
-    }
-
-    send_tx(
-        solana,
-        ConsumeEventsInstruction {
-            consume_events_admin: None,
-            market,
-            open_orders_accounts: vec![account_1, account_2],
-        },
-    )
-    .await
-    .unwrap();
-
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 100);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 10);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 99980);
-    }
+    let purge = |limit| PurgeExpiredOrdersInstruction {
+        cranker,
+        market,
+        limit,
+    };
 
-    let admin_token_1 = solana
-        .create_associated_token_account(&collect_fee_admin.pubkey(), mints[1].pubkey)
-        .await;
+    // nothing has expired yet
+    send_tx(solana, purge(10)).await.unwrap();
+    assert_eq!(solana.get_book_side(bids).await.roots[0].leaf_count, 2);
 
-    send_tx(
-        solana,
-        SettleFundsInstruction {
-            owner,
-            market,
-            open_orders_account: account_2,
-            market_base_vault,
-            market_quote_vault,
-            user_base_account: owner_token_0,
-            user_quote_account: owner_token_1,
-            referrer_account: None,
-        },
-    )
-    .await
-    .unwrap();
+    solana.advance_clock(11).await;
+    let cranker_lamports_before = solana.get_lamports(cranker.pubkey()).await;
 
+    send_tx(solana, purge(u8::MAX)).await.unwrap();
+    let expired = solana.program_log_events::<OrderExpiredLog>();
+    assert_eq!(expired.len(), 2);
+    assert!(expired.iter().all(|log| log.owner == account_1));
+    assert_eq!(solana.get_book_side(bids).await.roots[0].leaf_count, 1);
     {
+        // the bounty comes out of the purged orders' bonds, not the penalties of other users
         let market = solana.get_account::<Market>(market).await;
-        assert_eq!(market.fees_available, 10);
-        assert_eq!(market.fees_accrued, 10);
-        assert_eq!(market.fees_to_referrers, 0);
+        assert_eq!(market.crank_reward_pool, PENALTY_EVENT_HEAP);
+        assert_eq!(
+            solana.get_book_side(market.asks).await.roots[0].leaf_count,
+            0
+        );
+        assert_eq!(
+            solana.get_event_heap(market.event_heap).await.header.count(),
+            3
+        );
     }
+    assert_eq!(
+        solana.get_lamports(cranker.pubkey()).await,
+        cranker_lamports_before + 2 * CRANK_REWARD_PER_EVENT
+    );
 
+    // the owner gets the funds of the purged orders and the rest of their bonds back once the
+    // events are consumed, the pool only pays for the taker's fill event
     send_tx(
         solana,
-        SweepFeesInstruction {
-            collect_fee_admin,
+        ConsumeEventsWithRewardInstruction {
             market,
-            market_quote_vault,
-            token_receiver_account: admin_token_1,
+            open_orders_accounts: vec![account_1, account_2],
+            cranker,
         },
     )
     .await
     .unwrap();
-
+    assert_eq!(
+        solana.get_account::<Market>(market).await.crank_reward_pool,
+        PENALTY_EVENT_HEAP - CRANK_REWARD_PER_EVENT
+    );
     {
-        let market = solana.get_account::<Market>(market).await;
-        assert_eq!(market.fees_available, 0);
-        assert_eq!(market.fees_accrued, 10);
-        assert_eq!(market.fees_to_referrers, 0);
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 100);
+        assert_eq!(
+            open_orders_account_1.position.posting_bonds_refundable,
+            2 * (posting_bond - CRANK_REWARD_PER_EVENT)
+        );
     }
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_maker_fees() -> Result<(), TransportError> {
+async fn test_immediate_event_heap_penalty() -> Result<(), TransportError> {
+    let event_heap_penalty = 1_000;
     let TestInitialize {
         context,
-        collect_fee_admin,
         owner,
-        mints,
         owner_token_0,
         owner_token_1,
         market,
-
         market_base_vault,
         market_quote_vault,
         price_lots,
-        tokens,
         account_1,
         account_2,
         ..
     } = TestContext::new_with_market(TestNewMarketInitialize {
-        maker_fee: 200,
-        taker_fee: 400,
+        event_heap_penalty,
+        event_heap_penalty_policy: EventHeapPenaltyPolicy::Immediate,
         ..TestNewMarketInitialize::default()
     })
     .await?;
     let solana = &context.solana.clone();
-
-    // Set the initial oracle price
-    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+    set_account_self_trade_scope(solana, owner, account_2).await;
 
     send_tx(
         solana,
@@ -12449,8 +17956,8 @@ async fn test_maker_fees() -> Result<(), TransportError> {
             side: Side::Bid,
             price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 10020,
-            client_order_id: 30,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
@@ -12460,25 +17967,46 @@ async fn test_maker_fees() -> Result<(), TransportError> {
     .await
     .unwrap();
 
+    // the fill event is paid for by the signer right away instead of at settle time
+    let owner_lamports_before = solana.get_lamports(owner.pubkey()).await;
+    let market_lamports_before = solana.get_lamports(market).await;
+
     send_tx(
         solana,
-        CancelOrderByClientOrderIdInstruction {
-            open_orders_account: account_1,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
             market,
             signer: owner,
-            client_order_id: 30,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
         },
     )
     .await
     .unwrap();
 
     {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 100020);
+        let market_acc = solana.get_account::<Market>(market).await;
+        assert_eq!(market_acc.crank_reward_pool, event_heap_penalty);
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+        assert_eq!(open_orders_account_2.position.penalty_heap_count, 0);
+        assert_eq!(
+            solana.get_lamports(owner.pubkey()).await,
+            owner_lamports_before - event_heap_penalty
+        );
+        assert_eq!(
+            solana.get_lamports(market).await,
+            market_lamports_before + event_heap_penalty
+        );
     }
 
     send_tx(
@@ -12493,7 +18021,7 @@ async fn test_maker_fees() -> Result<(), TransportError> {
             side: Side::Bid,
             price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 10020,
+            max_quote_lots_including_fees: 10000,
             client_order_id: 0,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
@@ -12504,333 +18032,469 @@ async fn test_maker_fees() -> Result<(), TransportError> {
     .await
     .unwrap();
 
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-    }
+    // placing multiple orders pays right away as well
+    let owner_lamports_before = solana.get_lamports(owner.pubkey()).await;
 
     send_tx(
         solana,
-        PlaceOrderInstruction {
+        CancelAllAndPlaceOrdersInstruction {
             open_orders_account: account_2,
             open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_token_0,
-            market_vault: market_base_vault,
-            side: Side::Ask,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-            client_order_id: 0,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
+            orders_type: PlaceOrderType::Limit,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            bids: vec![],
+            asks: vec![openbook_v2::PlaceMultipleOrdersArgs {
+                price_lots,
+                max_quote_lots_including_fees: 10000,
+                expiry_timestamp: 0,
+            }],
         },
     )
     .await
     .unwrap();
 
     {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let market_acc = solana.get_account::<Market>(market).await;
+        assert_eq!(market_acc.crank_reward_pool, 2 * event_heap_penalty);
         let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+        assert_eq!(open_orders_account_2.position.penalty_heap_count, 0);
+        assert_eq!(
+            solana.get_lamports(owner.pubkey()).await,
+            owner_lamports_before - event_heap_penalty
+        );
+    }
 
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 0);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 99960);
-    }
+    Ok(())
+}
 
-    send_tx(
-        solana,
-        ConsumeEventsInstruction {
-            consume_events_admin: None,
-            market,
-            open_orders_accounts: vec![account_1, account_2],
-        },
-    )
-    .await
-    .unwrap();
+fn fill_maker(anyevent: &AnyEvent) -> Pubkey {
+    let event: &FillEvent = cast_ref(anyevent);
+    event.maker
+}
 
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
 
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 100);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 99960);
-    }
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_create_market.rs
+use super::*;
 
-    let admin_token_1 = solana
-        .create_associated_token_account(&collect_fee_admin.pubkey(), mints[1].pubkey)
-        .await;
+#[tokio::test]
+async fn test_with_single_oracle() -> Result<(), TransportError> {
+    let context = TestContextBuilder::new().start_default().await;
+    let solana = &context.solana.clone();
 
-    send_tx(
-        solana,
-        SettleFundsInstruction {
-            owner,
-            market,
-            open_orders_account: account_2,
-            market_base_vault,
-            market_quote_vault,
-            user_base_account: owner_token_0,
-            user_quote_account: owner_token_1,
-            referrer_account: None,
-        },
-    )
-    .await
-    .unwrap();
+    let payer = context.users[0].key;
+    let mints = &context.mints[0..=2];
+    let tokens = Token::create(mints.to_vec(), solana, payer, payer).await;
 
-    {
-        let market = solana.get_account::<Market>(market).await;
-        assert_eq!(market.fees_available, 40);
-        assert_eq!(market.fees_accrued, 60);
-        assert_eq!(market.fees_to_referrers, 0);
-    }
+    let market_a = TestKeypair::new();
+    let market_b = TestKeypair::new();
 
-    send_tx(
+    assert!(send_tx(
         solana,
-        SettleFundsInstruction {
-            owner,
-            market,
-            open_orders_account: account_1,
-            market_base_vault,
-            market_quote_vault,
-            user_base_account: owner_token_0,
-            user_quote_account: owner_token_1,
-            referrer_account: Some(owner_token_1),
+        CreateMarketInstruction {
+            payer,
+            market: market_a,
+            quote_lot_size: 100,
+            base_lot_size: 100,
+            base_mint: mints[0].pubkey,
+            quote_mint: mints[1].pubkey,
+            ..CreateMarketInstruction::with_new_book_and_heap(solana, Some(tokens[0].oracle), None,)
+                .await
         },
     )
     .await
-    .unwrap();
+    .is_ok());
 
-    {
-        let market = solana.get_account::<Market>(market).await;
-        assert_eq!(market.fees_available, 40);
-        assert_eq!(market.fees_accrued, 60);
-        assert_eq!(market.fees_to_referrers, 20);
-    }
+    assert_eq!(
+        send_tx_and_get_ix_custom_error(
+            solana,
+            CreateMarketInstruction {
+                payer,
+                market: market_b,
+                quote_lot_size: 100,
+                base_lot_size: 100,
+                base_mint: mints[0].pubkey,
+                quote_mint: mints[1].pubkey,
+                ..CreateMarketInstruction::with_new_book_and_heap(
+                    solana,
+                    None,
+                    Some(tokens[1].oracle)
+                )
+                .await
+            },
+        )
+        .await,
+        Some(openbook_v2::error::OpenBookError::InvalidSecondOracle.into())
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_with_same_oracles() -> Result<(), TransportError> {
+    let context = TestContextBuilder::new().start_default().await;
+    let solana = &context.solana.clone();
+
+    let payer = context.users[0].key;
+    let mints = &context.mints[0..=2];
+
+    let market = TestKeypair::new();
+    let fake_oracle_a = solana.create_account_from_len(&payer.pubkey(), 100).await;
+
+    assert_eq!(
+        send_tx_and_get_ix_custom_error(
+            solana,
+            CreateMarketInstruction {
+                payer,
+                market,
+                quote_lot_size: 100,
+                base_lot_size: 100,
+                base_mint: mints[0].pubkey,
+                quote_mint: mints[1].pubkey,
+                ..CreateMarketInstruction::with_new_book_and_heap(
+                    solana,
+                    Some(fake_oracle_a),
+                    Some(fake_oracle_a),
+                )
+                .await
+            },
+        )
+        .await,
+        Some(anchor_lang::error::ErrorCode::RequireKeysNeqViolated.into())
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_with_wrong_oracle_types() -> Result<(), TransportError> {
+    let context = TestContextBuilder::new().start_default().await;
+    let solana = &context.solana.clone();
+
+    let payer = context.users[0].key;
+    let mints = &context.mints[0..=2];
+
+    let market_a = TestKeypair::new();
+    let market_ab = TestKeypair::new();
+
+    let fake_oracle_a = solana.create_account_from_len(&payer.pubkey(), 100).await;
+    let fake_oracle_b = solana.create_account_from_len(&payer.pubkey(), 100).await;
+
+    assert_eq!(
+        send_tx_and_get_ix_custom_error(
+            solana,
+            CreateMarketInstruction {
+                payer,
+                market: market_a,
+                quote_lot_size: 100,
+                base_lot_size: 100,
+                base_mint: mints[0].pubkey,
+                quote_mint: mints[1].pubkey,
+                ..CreateMarketInstruction::with_new_book_and_heap(solana, Some(fake_oracle_a), None)
+                    .await
+            },
+        )
+        .await,
+        Some(openbook_v2::error::OpenBookError::UnknownOracleType.into())
+    );
+
+    assert_eq!(
+        send_tx_and_get_ix_custom_error(
+            solana,
+            CreateMarketInstruction {
+                payer,
+                market: market_ab,
+                quote_lot_size: 100,
+                base_lot_size: 100,
+                base_mint: mints[0].pubkey,
+                quote_mint: mints[1].pubkey,
+                ..CreateMarketInstruction::with_new_book_and_heap(
+                    solana,
+                    Some(fake_oracle_a),
+                    Some(fake_oracle_b)
+                )
+                .await
+            },
+        )
+        .await,
+        Some(openbook_v2::error::OpenBookError::UnknownOracleType.into())
+    );
+
+    Ok(())
+}
+
+
+#[tokio::test]
+async fn test_market_metadata_event() -> Result<(), TransportError> {
+    let context = TestContextBuilder::new().start_default().await;
+    let solana = &context.solana.clone();
+
+    let payer = context.users[0].key;
+    let mints = &context.mints[0..=2];
+    let market = TestKeypair::new();
 
     send_tx(
         solana,
-        SweepFeesInstruction {
-            collect_fee_admin,
+        CreateMarketInstruction {
+            payer,
             market,
-            market_quote_vault,
-            token_receiver_account: admin_token_1,
+            quote_lot_size: 10,
+            base_lot_size: 100,
+            base_mint: mints[0].pubkey,
+            quote_mint: mints[1].pubkey,
+            ..CreateMarketInstruction::with_new_book_and_heap(solana, None, None).await
         },
     )
     .await
     .unwrap();
 
-    {
-        let market = solana.get_account::<Market>(market).await;
-        assert_eq!(market.fees_available, 0);
-    }
+    // MarketMetaDataLog is always sent through a self-CPI instead of the program log
+    let events = solana.program_log_events::<openbook_v2::logs::MarketMetaDataLog>();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].market, market.pubkey());
+    assert_eq!(events[0].base_mint, mints[0].pubkey);
+    assert_eq!(events[0].quote_mint, mints[1].pubkey);
+    assert_eq!(events[0].base_lot_size, 100);
+    assert_eq!(events[0].quote_lot_size, 10);
+    assert_eq!(events[0].event_heap_penalty, PENALTY_EVENT_HEAP);
+    assert_eq!(
+        events[0].event_heap_penalty_policy,
+        u8::from(EventHeapPenaltyPolicy::Deferred)
+    );
 
     Ok(())
 }
 
-// Real simulation. Market maker pays fees but get them back on referral. Jupiter users don't pay fees.
-// Only users paying fees are users using a UI limit orders.
 #[tokio::test]
-async fn test_market_maker_fees() -> Result<(), TransportError> {
-    let market_base_lot_size = 100;
-    let market_quote_lot_size = 10;
-    let TestInitialize {
-        context,
-        owner,
-        owner_token_0,
-        owner_token_1,
-        market,
-        market_base_vault,
-        market_quote_vault,
-        price_lots,
-        account_1,
-        ..
-    } = TestContext::new_with_market(TestNewMarketInitialize {
-        quote_lot_size: market_quote_lot_size,
-        base_lot_size: market_base_lot_size,
-        maker_fee: 400,
-        taker_fee: 400,
-        ..TestNewMarketInitialize::default()
-    })
-    .await?;
+async fn test_with_oversized_event_heap_penalty() -> Result<(), TransportError> {
+    let context = TestContextBuilder::new().start_default().await;
     let solana = &context.solana.clone();
 
-    let balance_owner_0_before = solana.token_account_balance(owner_token_0).await;
-    let balance_owner_1_before = solana.token_account_balance(owner_token_1).await;
+    let payer = context.users[0].key;
+    let mints = &context.mints[0..=2];
+
+    // anything above MAX_EVENT_HEAP_PENALTY could overflow the penalties charged per event
+    assert_eq!(
+        send_tx_and_get_ix_custom_error(
+            solana,
+            CreateMarketInstruction {
+                payer,
+                market: TestKeypair::new(),
+                quote_lot_size: 10,
+                base_lot_size: 100,
+                base_mint: mints[0].pubkey,
+                quote_mint: mints[1].pubkey,
+                event_heap_penalty: MAX_EVENT_HEAP_PENALTY + 1,
+                ..CreateMarketInstruction::with_new_book_and_heap(solana, None, None).await
+            },
+        )
+        .await,
+        Some(OpenBookError::InvalidEventHeapPenalty.into())
+    );
 
-    // Account_1 is a MM and pays fee which will get back later
     send_tx(
         solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_1,
-            open_orders_admin: None,
-            market,
-            signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10040,
-            client_order_id: 0,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
+        CreateMarketInstruction {
+            payer,
+            market: TestKeypair::new(),
+            quote_lot_size: 10,
+            base_lot_size: 100,
+            base_mint: mints[0].pubkey,
+            quote_mint: mints[1].pubkey,
+            event_heap_penalty: MAX_EVENT_HEAP_PENALTY,
+            ..CreateMarketInstruction::with_new_book_and_heap(solana, None, None).await
         },
     )
     .await
     .unwrap();
 
-    {
-        let balance_owner_0_after = solana.token_account_balance(owner_token_0).await;
-        let balance_owner_1_after = solana.token_account_balance(owner_token_1).await;
-        assert_eq!(balance_owner_0_before, balance_owner_0_after);
-        assert_eq!(
-            balance_owner_1_before,
-            balance_owner_1_after + 10000 * (market_quote_lot_size as u64) + 40
-        );
-    }
-
-    let jup_user = context.users[2].key;
-    let jup_user_token_0 = context.users[2].token_accounts[0];
-    let jup_user_token_1 = context.users[2].token_accounts[1];
+    Ok(())
+}
 
-    let balance_jup_0_before = solana.token_account_balance(jup_user_token_0).await;
-    let balance_jup_1_before = solana.token_account_balance(jup_user_token_1).await;
+#[tokio::test]
+async fn test_with_custom_capacities() -> Result<(), TransportError> {
+    let context = TestContextBuilder::new().start_default().await;
+    let solana = &context.solana.clone();
 
-    // Jupiter user, takes and don't pay fees
-    send_tx(
-        solana,
-        PlaceTakeOrderInstruction {
-            market,
-            signer: jup_user,
-            user_base_account: jup_user_token_0,
-            user_quote_account: jup_user_token_1,
-            market_base_vault,
-            market_quote_vault,
-            side: Side::Ask,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10040,
-            open_orders_admin: None,
-        },
-    )
-    .await
-    .unwrap();
+    let payer = context.users[0].key;
+    let mints = &context.mints[0..=2];
 
-    {
-        let balance_jup_0_after = solana.token_account_balance(jup_user_token_0).await;
-        let balance_jup_1_after = solana.token_account_balance(jup_user_token_1).await;
-        assert_eq!(
-            balance_jup_0_before,
-            balance_jup_0_after + (market_base_lot_size as u64)
-        );
-        assert_eq!(
-            balance_jup_1_before + 10000 * (market_quote_lot_size as u64),
-            balance_jup_1_after
+    for (book_side_capacity, event_heap_capacity) in [
+        (MIN_ORDERTREE_NODES, MIN_NUM_EVENTS as usize),
+        (4 * DEFAULT_ORDERTREE_NODES, 2 * DEFAULT_NUM_EVENTS as usize),
+    ] {
+        let create_market_ix = CreateMarketInstruction {
+            payer,
+            market: TestKeypair::new(),
+            quote_lot_size: 10,
+            base_lot_size: 100,
+            base_mint: mints[0].pubkey,
+            quote_mint: mints[1].pubkey,
+            ..CreateMarketInstruction::with_new_book_and_heap_capacities(
+                solana,
+                None,
+                None,
+                book_side_capacity,
+                event_heap_capacity,
+            )
+            .await
+        };
+        let (bids, asks, event_heap) = (
+            create_market_ix.bids,
+            create_market_ix.asks,
+            create_market_ix.event_heap,
         );
+        send_tx(solana, create_market_ix).await.unwrap();
+
+        for book_side in [bids, asks] {
+            let book_side = solana.get_book_side(book_side).await;
+            assert_eq!(book_side.capacity(), book_side_capacity);
+            assert!(book_side.is_empty());
+        }
+        let event_heap = solana.get_event_heap(event_heap).await;
+        assert_eq!(event_heap.capacity(), event_heap_capacity);
+        assert!(event_heap.is_empty());
     }
 
-    send_tx(
-        solana,
-        ConsumeEventsInstruction {
-            consume_events_admin: None,
-            market,
-            open_orders_accounts: vec![account_1],
-        },
-    )
-    .await
-    .unwrap();
-    send_tx(
-        solana,
-        SettleFundsInstruction {
-            owner,
-            market,
-            open_orders_account: account_1,
-            market_base_vault,
-            market_quote_vault,
-            user_base_account: owner_token_0,
-            user_quote_account: owner_token_1,
-            referrer_account: Some(owner_token_1),
-        },
-    )
-    .await
-    .unwrap();
+    // book sides below the minimum capacity are rejected
+    assert_eq!(
+        send_tx_and_get_ix_custom_error(
+            solana,
+            CreateMarketInstruction {
+                payer,
+                market: TestKeypair::new(),
+                quote_lot_size: 10,
+                base_lot_size: 100,
+                base_mint: mints[0].pubkey,
+                quote_mint: mints[1].pubkey,
+                ..CreateMarketInstruction::with_new_book_and_heap_capacities(
+                    solana,
+                    None,
+                    None,
+                    MIN_ORDERTREE_NODES - 1,
+                    DEFAULT_NUM_EVENTS as usize,
+                )
+                .await
+            },
+        )
+        .await,
+        Some(OpenBookError::InvalidBookSideCapacity.into())
+    );
 
-    // MM gets back the fee
-    {
-        let balance_owner_0_after = solana.token_account_balance(owner_token_0).await;
-        let balance_owner_1_after = solana.token_account_balance(owner_token_1).await;
-        assert_eq!(
-            balance_owner_0_before + (market_base_lot_size as u64),
-            balance_owner_0_after
-        );
-        assert_eq!(
-            balance_owner_1_before,
-            balance_owner_1_after + 10000 * (market_quote_lot_size as u64)
-        );
-    }
+    // so are event heaps
+    assert_eq!(
+        send_tx_and_get_ix_custom_error(
+            solana,
+            CreateMarketInstruction {
+                payer,
+                market: TestKeypair::new(),
+                quote_lot_size: 10,
+                base_lot_size: 100,
+                base_mint: mints[0].pubkey,
+                quote_mint: mints[1].pubkey,
+                ..CreateMarketInstruction::with_new_book_and_heap_capacities(
+                    solana,
+                    None,
+                    None,
+                    DEFAULT_ORDERTREE_NODES,
+                    MIN_NUM_EVENTS as usize - 1,
+                )
+                .await
+            },
+        )
+        .await,
+        Some(OpenBookError::InvalidEventHeapCapacity.into())
+    );
+
+    // eviction policies that scan the whole book side need a small one
+    assert_eq!(
+        send_tx_and_get_ix_custom_error(
+            solana,
+            CreateMarketInstruction {
+                payer,
+                market: TestKeypair::new(),
+                quote_lot_size: 10,
+                base_lot_size: 100,
+                base_mint: mints[0].pubkey,
+                quote_mint: mints[1].pubkey,
+                eviction_policy: EvictionPolicy::Oldest,
+                ..CreateMarketInstruction::with_new_book_and_heap_capacities(
+                    solana,
+                    None,
+                    None,
+                    MAX_SCANNED_EVICTION_NODES + 1,
+                    DEFAULT_NUM_EVENTS as usize,
+                )
+                .await
+            },
+        )
+        .await,
+        Some(OpenBookError::InvalidBookSideCapacity.into())
+    );
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_no_maker_fees_ask() -> Result<(), TransportError> {
+async fn test_migrate_market() -> Result<(), TransportError> {
     let TestInitialize {
         context,
-        collect_fee_admin,
         owner,
-        mints,
-        owner_token_0,
+        payer,
         owner_token_1,
         market,
-
-        market_base_vault,
         market_quote_vault,
         price_lots,
-        tokens,
         account_1,
-        account_2,
         ..
-    } = TestContext::new_with_market(TestNewMarketInitialize {
-        maker_fee: -200,
-        taker_fee: 400,
-        ..TestNewMarketInitialize::default()
-    })
-    .await?;
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
     let solana = &context.solana.clone();
 
-    // Set the initial oracle price
-    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+    // Turn the market into one created before the upgrade: 840 bytes after the
+    // discriminator, ending with 128 zeroed reserved bytes
+    let mut data = solana.get_account_data(market).await.unwrap();
+    data.truncate(8 + 840);
+    data[8 + 840 - 128..].fill(0);
+    solana.set_account_data(market, data).await;
+
+    let payer_lamports_before = solana.get_lamports(payer.pubkey()).await;
+    send_tx(solana, MigrateMarketInstruction { payer, market })
+        .await
+        .unwrap();
+
+    let space = 8 + std::mem::size_of::<Market>();
+    let top_up = solana.rent.minimum_balance(space) - solana.rent.minimum_balance(8 + 840);
+    assert_eq!(solana.get_account_data(market).await.unwrap().len(), space);
+    assert_eq!(solana.get_lamports(market).await, solana.rent.minimum_balance(space));
+    assert_eq!(
+        solana.get_lamports(payer.pubkey()).await,
+        payer_lamports_before - top_up
+    );
+    {
+        let market_acc = solana.get_account::<Market>(market).await;
+        assert_eq!(market_acc.event_heap_penalty, PENALTY_EVENT_HEAP);
+        assert_eq!(market_acc.posting_bond, 0);
+    }
+
+    assert_openbook_error(
+        &send_tx(solana, MigrateMarketInstruction { payer, market }).await,
+        OpenBookError::MarketAlreadyMigrated.error_code(),
+        "the market was already migrated".into(),
+    );
 
+    // the migrated market trades again
     send_tx(
         solana,
         PlaceOrderInstruction {
-            open_orders_account: account_2,
+            open_orders_account: account_1,
             open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_token_0,
-            market_vault: market_base_vault,
-            side: Side::Ask,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
             price_lots,
             max_base_lots: 1,
             max_quote_lots_including_fees: 10000,
@@ -12844,158 +18508,319 @@ async fn test_no_maker_fees_ask() -> Result<(), TransportError> {
     .await
     .unwrap();
 
-    {
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+    Ok(())
+}
 
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 1);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
-    }
 
-    send_tx(
-        solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_1,
-            open_orders_admin: None,
-            market,
-            signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10004,
-            client_order_id: 0,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
-        },
-    )
-    .await
-    .unwrap();
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_delegate_permissions.rs
+use super::*;
+use openbook_v2::logs::{AddDelegateLog, RemoveDelegateLog, SetDelegatePermissionsLog};
 
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+#[tokio::test]
+async fn test_delegate_permissions() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        payer,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        payer_as_delegate: true,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
+    let solana = &context.solana.clone();
+    let payer_token_1 = context.users[1].token_accounts[1];
 
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 1);
-        assert_eq!(open_orders_account_1.position.base_free_native, 100);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+    let place_bid = |signer, user_token_account| PlaceOrderInstruction {
+        open_orders_account: account_1,
+        open_orders_admin: None,
+        market,
+        signer,
+        user_token_account,
+        market_vault: market_quote_vault,
+        side: Side::Bid,
+        price_lots,
+        max_base_lots: 1,
+        max_quote_lots_including_fees: 10000,
+        client_order_id: 0,
+        expiry_timestamp: 0,
+        order_type: PlaceOrderType::Limit,
+        self_trade_behavior: SelfTradeBehavior::default(),
+        remainings: vec![],
+    };
+    let set_permissions = |permissions, expiry_timestamp| SetDelegatePermissionsInstruction {
+        owner,
+        open_orders_account: account_1,
+        permissions,
+        expiry_timestamp,
+    };
+
+    for permissions in [0, 1 << 3, 1 << 7] {
+        assert_openbook_error(
+            &send_tx(solana, set_permissions(permissions, 0)).await,
+            OpenBookError::InvalidDelegatePermissions.error_code(),
+            "unknown or empty permissions".into(),
+        );
     }
 
+    // a cancel-only delegate, like a bot's hot key
+    send_tx(solana, set_permissions(delegate_permissions::CANCEL, 0))
+        .await
+        .unwrap();
+    let logs = solana.program_log_events::<SetDelegatePermissionsLog>();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].delegate, Some(payer.pubkey()));
+    assert_eq!(logs[0].permissions, delegate_permissions::CANCEL);
+
+    assert_openbook_error(
+        &send_tx(solana, place_bid(payer, payer_token_1)).await,
+        OpenBookError::NoOwnerOrDelegate.error_code(),
+        "delegate may not place".into(),
+    );
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            SettleFundsInstruction {
+                owner: payer,
+                market,
+                open_orders_account: account_1,
+                market_base_vault,
+                market_quote_vault,
+                user_base_account: owner_token_0,
+                user_quote_account: owner_token_1,
+                referrer_account: None,
+            },
+        )
+        .await,
+        OpenBookError::NoOwnerOrDelegate.error_code(),
+        "delegate may not settle".into(),
+    );
+
+    // a place-only delegate can't cancel on its way to placing
+    send_tx(solana, set_permissions(delegate_permissions::PLACE, 0))
+        .await
+        .unwrap();
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            CancelAllAndPlaceOrdersInstruction {
+                open_orders_account: account_1,
+                open_orders_admin: None,
+                market,
+                signer: payer,
+                user_base_account: context.users[1].token_accounts[0],
+                user_quote_account: payer_token_1,
+                orders_type: PlaceOrderType::Limit,
+                bids: vec![],
+                asks: vec![],
+            },
+        )
+        .await,
+        OpenBookError::NoOwnerOrDelegate.error_code(),
+        "delegate may not cancel".into(),
+    );
+    send_tx(solana, set_permissions(delegate_permissions::CANCEL, 0))
+        .await
+        .unwrap();
+
+    send_tx(solana, place_bid(owner, owner_token_1))
+        .await
+        .unwrap();
+    let order_id = solana
+        .get_account::<OpenOrdersAccount>(account_1)
+        .await
+        .open_orders[0]
+        .id;
     send_tx(
         solana,
-        ConsumeEventsInstruction {
-            consume_events_admin: None,
+        CancelOrderInstruction {
+            signer: payer,
             market,
-            open_orders_accounts: vec![account_1, account_2],
+            open_orders_account: account_1,
+            order_id,
         },
     )
     .await
     .unwrap();
 
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+    // once expired the delegate can't do anything
+    send_tx(solana, place_bid(owner, owner_token_1))
+        .await
+        .unwrap();
+    let now_ts = solana.get_clock().await.unix_timestamp as u64;
+    send_tx(solana, set_permissions(delegate_permissions::ALL, now_ts))
+        .await
+        .unwrap();
+    let order_id = solana
+        .get_account::<OpenOrdersAccount>(account_1)
+        .await
+        .open_orders[0]
+        .id;
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            CancelOrderInstruction {
+                signer: payer,
+                market,
+                open_orders_account: account_1,
+                order_id,
+            },
+        )
+        .await,
+        OpenBookError::NoOwnerOrDelegate.error_code(),
+        "delegate has expired".into(),
+    );
 
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 100);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 100020);
-    }
+    Ok(())
+}
 
-    let admin_token_1 = solana
-        .create_associated_token_account(&collect_fee_admin.pubkey(), mints[1].pubkey)
-        .await;
+#[tokio::test]
+async fn test_add_and_remove_delegate() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        payer,
+        owner_token_1,
+        market,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+    let bot_1 = TestKeypair::new();
+    let bot_2 = TestKeypair::new();
+
+    let place_bid = || PlaceOrderInstruction {
+        open_orders_account: account_1,
+        open_orders_admin: None,
+        market,
+        signer: owner,
+        user_token_account: owner_token_1,
+        market_vault: market_quote_vault,
+        side: Side::Bid,
+        price_lots,
+        max_base_lots: 1,
+        max_quote_lots_including_fees: 10000,
+        client_order_id: 0,
+        expiry_timestamp: 0,
+        order_type: PlaceOrderType::Limit,
+        self_trade_behavior: SelfTradeBehavior::default(),
+        remainings: vec![],
+    };
+    let cancel = |signer, order_id| CancelOrderInstruction {
+        signer,
+        market,
+        open_orders_account: account_1,
+        order_id,
+    };
+    let add_delegate = |delegate: TestKeypair, label: &str, permissions| AddDelegateInstruction {
+        payer,
+        owner,
+        open_orders_account: account_1,
+        delegate: delegate.pubkey(),
+        label: label.to_string(),
+        permissions,
+        expiry_timestamp: 0,
+    };
+
+    send_tx(solana, place_bid()).await.unwrap();
+    let order_id = solana
+        .get_account::<OpenOrdersAccount>(account_1)
+        .await
+        .open_orders[0]
+        .id;
+
+    assert_openbook_error(
+        &send_tx(solana, cancel(bot_1, order_id)).await,
+        OpenBookError::NoOwnerOrDelegate.error_code(),
+        "not a delegate yet".into(),
+    );
 
     send_tx(
         solana,
-        SettleFundsInstruction {
-            owner,
-            market,
-            open_orders_account: account_2,
-            market_base_vault,
-            market_quote_vault,
-            user_base_account: owner_token_0,
-            user_quote_account: owner_token_1,
-            referrer_account: None,
-        },
+        add_delegate(bot_1, "bot-1", delegate_permissions::CANCEL),
     )
     .await
     .unwrap();
-
-    {
-        let market = solana.get_account::<Market>(market).await;
-        assert_eq!(market.fees_available, 0);
-        assert_eq!(market.fees_accrued, 20);
-        assert_eq!(market.fees_to_referrers, 0);
-    }
+    let logs = solana.program_log_events::<AddDelegateLog>();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].delegate, bot_1.pubkey());
+    assert_eq!(logs[0].label, "bot-1");
 
     send_tx(
         solana,
-        SettleFundsInstruction {
-            owner,
-            market,
-            open_orders_account: account_1,
-            market_base_vault,
-            market_quote_vault,
-            user_base_account: owner_token_0,
-            user_quote_account: owner_token_1,
-            referrer_account: Some(owner_token_1),
-        },
+        add_delegate(bot_2, "bot-2", delegate_permissions::PLACE),
     )
     .await
     .unwrap();
+    let record = solana
+        .get_account::<OpenOrdersDelegate>(get_open_orders_delegate_address(
+            account_1,
+            bot_2.pubkey(),
+        ))
+        .await;
+    assert_eq!(record.open_orders_account, account_1);
+    assert_eq!(record.label(), "bot-2");
+    assert_eq!(record.permissions, delegate_permissions::PLACE);
 
-    {
-        let market = solana.get_account::<Market>(market).await;
-        assert_eq!(market.fees_available, 0);
-        assert_eq!(market.fees_accrued, 20);
-        assert_eq!(market.fees_to_referrers, 20);
-    }
+    assert_openbook_error(
+        &send_tx(solana, cancel(bot_2, order_id)).await,
+        OpenBookError::NoOwnerOrDelegate.error_code(),
+        "bot-2 may only place".into(),
+    );
+    send_tx(solana, cancel(bot_1, order_id)).await.unwrap();
 
     send_tx(
         solana,
-        SweepFeesInstruction {
-            collect_fee_admin,
-            market,
-            market_quote_vault,
-            token_receiver_account: admin_token_1,
+        RemoveDelegateInstruction {
+            owner,
+            open_orders_account: account_1,
+            delegate: bot_1.pubkey(),
+            sol_destination: payer.pubkey(),
         },
     )
     .await
     .unwrap();
+    let logs = solana.program_log_events::<RemoveDelegateLog>();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].delegate, bot_1.pubkey());
 
-    {
-        let market = solana.get_account::<Market>(market).await;
-        assert_eq!(market.fees_available, 0);
-    }
+    send_tx(solana, place_bid()).await.unwrap();
+    let order_id = solana
+        .get_account::<OpenOrdersAccount>(account_1)
+        .await
+        .open_orders[0]
+        .id;
+    assert_openbook_error(
+        &send_tx(solana, cancel(bot_1, order_id)).await,
+        OpenBookError::NoOwnerOrDelegate.error_code(),
+        "bot-1 was removed".into(),
+    );
 
     Ok(())
 }
 
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_edit_order.rs
+use super::*;
+
 #[tokio::test]
-async fn test_maker_fees_ask() -> Result<(), TransportError> {
+async fn test_edit_order() -> Result<(), TransportError> {
     let TestInitialize {
         context,
         collect_fee_admin,
         owner,
-        mints,
         owner_token_0,
         owner_token_1,
         market,
-
         market_base_vault,
         market_quote_vault,
         price_lots,
@@ -13004,12 +18829,13 @@ async fn test_maker_fees_ask() -> Result<(), TransportError> {
         account_2,
         ..
     } = TestContext::new_with_market(TestNewMarketInitialize {
-        maker_fee: 200,
-        taker_fee: 400,
+        maker_fee: -100,
+        taker_fee: 200,
         ..TestNewMarketInitialize::default()
     })
     .await?;
     let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
 
     // Set the initial oracle price
     set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
@@ -13017,17 +18843,17 @@ async fn test_maker_fees_ask() -> Result<(), TransportError> {
     send_tx(
         solana,
         PlaceOrderInstruction {
-            open_orders_account: account_2,
+            open_orders_account: account_1,
             open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_token_0,
-            market_vault: market_base_vault,
-            side: Side::Ask,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
             price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10020,
-            client_order_id: 0,
+            max_base_lots: 2,
+            max_quote_lots_including_fees: 20004,
+            client_order_id: 12,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
@@ -13038,17 +18864,15 @@ async fn test_maker_fees_ask() -> Result<(), TransportError> {
     .unwrap();
 
     {
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 1);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 2);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
     }
 
+    // No client Id found, is treated as if order was fully filled
     send_tx(
         solana,
-        PlaceOrderInstruction {
+        EditOrderInstruction {
             open_orders_account: account_1,
             open_orders_admin: None,
             market,
@@ -13058,37 +18882,62 @@ async fn test_maker_fees_ask() -> Result<(), TransportError> {
             side: Side::Bid,
             price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 10004,
-            client_order_id: 0,
+            max_quote_lots_including_fees: 10002,
+            client_order_id: 11,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
             remainings: vec![],
+            expected_cancel_size: 1,
         },
     )
     .await
     .unwrap();
 
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 1);
-        assert_eq!(open_orders_account_1.position.base_free_native, 100);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
-    }
+    // take 1. send remaining to crank and remove 1 bids_base_lots
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 12,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![account_1],
+        },
+    )
+    .await
+    .unwrap();
 
+    // 1 base_lot has been taken, post only 1
     send_tx(
         solana,
-        ConsumeEventsInstruction {
-            consume_events_admin: None,
+        EditOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
             market,
-            open_orders_accounts: vec![account_1, account_2],
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 2,
+            max_quote_lots_including_fees: 20004,
+            client_order_id: 12,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+            expected_cancel_size: 2,
         },
     )
     .await
@@ -13096,118 +18945,220 @@ async fn test_maker_fees_ask() -> Result<(), TransportError> {
 
     {
         let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
         assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 100);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 99980);
     }
 
-    let admin_token_1 = solana
-        .create_associated_token_account(&collect_fee_admin.pubkey(), mints[1].pubkey)
-        .await;
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_eviction.rs
+use super::*;
+use openbook_v2::logs::OrderEvictedLog;
+
+const POSTING_BOND: u64 = 10_000;
+
+#[tokio::test]
+async fn test_eviction_policy_and_posting_bond() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        account_2,
+        bids,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        posting_bond: POSTING_BOND,
+        eviction_policy: EvictionPolicy::Oldest,
+        book_side_capacity: MIN_ORDERTREE_NODES,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
+    let solana = &context.solana.clone();
+
+    let place_bid = |open_orders_account, price_lots| PlaceOrderInstruction {
+        open_orders_account,
+        open_orders_admin: None,
+        market,
+        signer: owner,
+        user_token_account: owner_token_1,
+        market_vault: market_quote_vault,
+        side: Side::Bid,
+        price_lots,
+        max_base_lots: 1,
+        max_quote_lots_including_fees: 20_000,
+        client_order_id: 0,
+        expiry_timestamp: 0,
+        order_type: PlaceOrderType::Limit,
+        self_trade_behavior: SelfTradeBehavior::default(),
+        remainings: vec![],
+    };
+    // bonds are paid out of the lamports the open orders account holds above its rent
+    assert_openbook_error(
+        &send_tx(solana, place_bid(account_1, price_lots)).await,
+        OpenBookError::InsufficientBondLamports.error_code(),
+        "the open orders account has no lamports to spare".into(),
+    );
+    solana.transfer_lamports(owner, account_1, 24 * POSTING_BOND).await;
+    solana.transfer_lamports(owner, account_2, 10 * POSTING_BOND).await;
+    let market_lamports_before = solana.get_lamports(market).await;
+
+    // the oldest order has the worst price, the second oldest the best one
+    send_tx(solana, place_bid(account_1, price_lots - 100)).await.unwrap();
+    solana.advance_clock(1).await;
+    send_tx(solana, place_bid(account_1, price_lots + 100)).await.unwrap();
+    solana.advance_clock(1).await;
+
+    // fill the book, 32 orders fit in the minimum capacity
+    for i in 0..22 {
+        send_tx(solana, place_bid(account_1, price_lots - i)).await.unwrap();
+    }
+    for i in 22..30 {
+        send_tx(solana, place_bid(account_2, price_lots - i)).await.unwrap();
+    }
+    assert!(solana.get_book_side(bids).await.is_full());
+    assert_eq!(
+        solana.get_lamports(market).await,
+        market_lamports_before + 32 * POSTING_BOND
+    );
+
+    // evicting the oldest order, which is also the worst priced, forfeits its bond
+    send_tx(solana, place_bid(account_2, price_lots - 50)).await.unwrap();
+    let evicted = solana.program_log_events::<OrderEvictedLog>();
+    assert_eq!(evicted.len(), 1);
+    assert_eq!(evicted[0].owner, account_1);
+    assert_eq!(evicted[0].price_lots, price_lots - 100);
+    assert_eq!(
+        solana.get_account::<Market>(market).await.crank_reward_pool,
+        POSTING_BOND
+    );
+
+    // a worse priced order can't evict the oldest one
+    assert_openbook_error(
+        &send_tx(solana, place_bid(account_2, price_lots - 50)).await,
+        OpenBookError::SomeError.error_code(),
+        "the new order is priced worse than the oldest one".into(),
+    );
+
+    // an equally priced order can, and the evicted order keeps its bond
+    send_tx(solana, place_bid(account_2, price_lots + 100)).await.unwrap();
+    let evicted = solana.program_log_events::<OrderEvictedLog>();
+    assert_eq!(evicted.len(), 1);
+    assert_eq!(evicted[0].owner, account_1);
+    assert_eq!(evicted[0].price_lots, price_lots + 100);
+    assert_eq!(
+        solana.get_account::<Market>(market).await.crank_reward_pool,
+        POSTING_BOND
+    );
 
     send_tx(
         solana,
-        SettleFundsInstruction {
-            owner,
+        ConsumeEventsInstruction {
+            consume_events_admin: None,
             market,
-            open_orders_account: account_2,
-            market_base_vault,
-            market_quote_vault,
-            user_base_account: owner_token_0,
-            user_quote_account: owner_token_1,
-            referrer_account: None,
+            open_orders_accounts: vec![account_1],
         },
     )
     .await
     .unwrap();
-
-    {
-        let market = solana.get_account::<Market>(market).await;
-        assert_eq!(market.fees_available, 20);
-        assert_eq!(market.fees_accrued, 60);
-        assert_eq!(market.fees_to_referrers, 0);
-    }
-
     send_tx(
         solana,
-        SettleFundsInstruction {
-            owner,
-            market,
+        CancelAllOrdersInstruction {
             open_orders_account: account_1,
-            market_base_vault,
-            market_quote_vault,
-            user_base_account: owner_token_0,
-            user_quote_account: owner_token_1,
-            referrer_account: Some(owner_token_1),
+            market,
+            signer: owner,
         },
     )
     .await
     .unwrap();
 
-    {
-        let market = solana.get_account::<Market>(market).await;
-        assert_eq!(market.fees_available, 20);
-        assert_eq!(market.fees_accrued, 60);
-        assert_eq!(market.fees_to_referrers, 40);
-    }
+    // the bonds of the cancelled orders and of the order that wasn't the worst priced are
+    // refunded, the client cancels 5 at a time
+    let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+    assert_eq!(
+        open_orders_account_1.position.posting_bonds_refundable,
+        6 * POSTING_BOND
+    );
 
+    let account_1_lamports_before = solana.get_lamports(account_1).await;
     send_tx(
         solana,
-        SweepFeesInstruction {
-            collect_fee_admin,
+        SettleFundsInstruction {
+            owner,
+            open_orders_account: account_1,
             market,
+            market_base_vault,
             market_quote_vault,
-            token_receiver_account: admin_token_1,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            referrer_account: None,
         },
     )
     .await
     .unwrap();
-
-    {
-        let market = solana.get_account::<Market>(market).await;
-        assert_eq!(market.fees_available, 0);
-    }
+    assert_eq!(
+        solana.get_lamports(account_1).await,
+        account_1_lamports_before + 6 * POSTING_BOND
+    );
+    assert_eq!(
+        solana.get_lamports(market).await,
+        market_lamports_before + 28 * POSTING_BOND
+    );
+    let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+    assert_eq!(open_orders_account_1.position.posting_bonds_refundable, 0);
 
     Ok(())
 }
 
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_exposure_limits.rs
+use super::*;
+
 #[tokio::test]
-async fn test_fees_half() -> Result<(), TransportError> {
+async fn test_exposure_limits() -> Result<(), TransportError> {
     let TestInitialize {
         context,
-        collect_fee_admin,
+        exposure_admin,
         owner,
-        mints,
         owner_token_0,
         owner_token_1,
         market,
-
         market_base_vault,
         market_quote_vault,
         price_lots,
-        tokens,
         account_1,
         account_2,
         ..
     } = TestContext::new_with_market(TestNewMarketInitialize {
-        maker_fee: -3700,
-        taker_fee: 7400,
+        exposure_admin_bool: true,
+        max_asks_base_lots: 2,
+        max_open_orders: 3,
         ..TestNewMarketInitialize::default()
     })
     .await?;
     let solana = &context.solana.clone();
 
-    // Set the initial oracle price
-    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
-    let initial_quote_amount = 10000;
+    let bid_price_lots = price_lots / 2;
+    let set_limits =
+        |open_orders_account, max_bids_quote_lots, max_asks_base_lots, max_open_orders| {
+            SetExposureLimitsInstruction {
+                exposure_admin,
+                market,
+                open_orders_account,
+                max_bids_quote_lots,
+                max_asks_base_lots,
+                max_open_orders,
+            }
+        };
 
+    // market limits
     send_tx(
         solana,
         PlaceOrderInstruction {
@@ -13215,12 +19166,12 @@ async fn test_fees_half() -> Result<(), TransportError> {
             open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
             price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: initial_quote_amount,
+            max_quote_lots_including_fees: 10000,
             client_order_id: 0,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
@@ -13234,7 +19185,7 @@ async fn test_fees_half() -> Result<(), TransportError> {
     send_tx(
         solana,
         PlaceOrderInstruction {
-            open_orders_account: account_2,
+            open_orders_account: account_1,
             open_orders_admin: None,
             market,
             signer: owner,
@@ -13243,7 +19194,7 @@ async fn test_fees_half() -> Result<(), TransportError> {
             side: Side::Ask,
             price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: initial_quote_amount,
+            max_quote_lots_including_fees: 10000,
             client_order_id: 0,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
@@ -13254,49 +19205,120 @@ async fn test_fees_half() -> Result<(), TransportError> {
     .await
     .unwrap();
 
-    send_tx(
-        solana,
-        ConsumeEventsInstruction {
-            consume_events_admin: None,
-            market,
-            open_orders_accounts: vec![account_1, account_2],
-        },
-    )
-    .await
-    .unwrap();
-
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-        let market = solana.get_account::<Market>(market).await;
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            PlaceOrderInstruction {
+                open_orders_account: account_1,
+                open_orders_admin: None,
+                market,
+                signer: owner,
+                user_token_account: owner_token_0,
+                market_vault: market_base_vault,
+                side: Side::Ask,
+                price_lots,
+                max_base_lots: 1,
+                max_quote_lots_including_fees: 10000,
+                client_order_id: 0,
+                expiry_timestamp: 0,
+                order_type: PlaceOrderType::Limit,
+                self_trade_behavior: SelfTradeBehavior::default(),
+                remainings: vec![],
+            },
+        )
+        .await,
+        OpenBookError::ExposureLimitExceeded.error_code(),
+        "max_asks_base_lots reached".into(),
+    );
 
-        assert_eq!(open_orders_account_1.position.quote_free_native, 370);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 99260);
-        assert_eq!(market.referrer_rebates_accrued, 370);
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots: bid_price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
 
-        assert_eq!(
-            (market.referrer_rebates_accrued
-                + open_orders_account_2.position.quote_free_native
-                + open_orders_account_1.position.quote_free_native) as i64,
-            initial_quote_amount * 10 // as it's in native quote
-        );
-    }
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            PlaceOrderInstruction {
+                open_orders_account: account_1,
+                open_orders_admin: None,
+                market,
+                signer: owner,
+                user_token_account: owner_token_1,
+                market_vault: market_quote_vault,
+                side: Side::Bid,
+                price_lots: bid_price_lots,
+                max_base_lots: 1,
+                max_quote_lots_including_fees: 10000,
+                client_order_id: 0,
+                expiry_timestamp: 0,
+                order_type: PlaceOrderType::Limit,
+                self_trade_behavior: SelfTradeBehavior::default(),
+                remainings: vec![],
+            },
+        )
+        .await,
+        OpenBookError::ExposureLimitExceeded.error_code(),
+        "max_open_orders reached".into(),
+    );
 
-    let admin_token_1 = solana
-        .create_associated_token_account(&collect_fee_admin.pubkey(), mints[1].pubkey)
-        .await;
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            SetExposureLimitsInstruction {
+                exposure_admin: owner,
+                ..set_limits(account_1, 0, 0, 0)
+            },
+        )
+        .await,
+        OpenBookError::InvalidExposureAdmin.error_code(),
+        "only the exposure admin sets exposure limits".into(),
+    );
+
+    // overrides can loosen the market's limits...
+    send_tx(solana, set_limits(account_1, 0, i64::MAX, 5)).await.unwrap();
+    let logs = solana.program_log_events::<openbook_v2::logs::SetExposureLimitsLog>();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].open_orders_account, account_1);
+    assert_eq!(logs[0].max_asks_base_lots, i64::MAX);
+    assert_eq!(logs[0].max_open_orders, 5);
 
     send_tx(
         solana,
-        SettleFundsInstruction {
-            owner,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
             market,
-            open_orders_account: account_2,
-            market_base_vault,
-            market_quote_vault,
-            user_base_account: owner_token_0,
-            user_quote_account: owner_token_1,
-            referrer_account: None,
+            signer: owner,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
         },
     )
     .await
@@ -13304,44 +19326,118 @@ async fn test_fees_half() -> Result<(), TransportError> {
 
     send_tx(
         solana,
-        SettleFundsInstruction {
-            owner,
-            market,
+        PlaceOrderInstruction {
             open_orders_account: account_1,
-            market_base_vault,
-            market_quote_vault,
-            user_base_account: owner_token_0,
-            user_quote_account: owner_token_1,
-            referrer_account: None,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots: bid_price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
         },
     )
     .await
     .unwrap();
-
     {
-        let balance_quote = solana.token_account_balance(market_quote_vault).await;
-        assert_eq!(balance_quote, 370);
+        let position = solana
+            .get_account::<OpenOrdersAccount>(account_1)
+            .await
+            .position;
+        assert_eq!(position.asks_base_lots, 3);
+        assert_eq!(position.bids_quote_lots, 2 * bid_price_lots);
     }
 
+    // ...or tighten them
+    send_tx(solana, set_limits(account_2, bid_price_lots, 0, 0)).await.unwrap();
     send_tx(
         solana,
-        SweepFeesInstruction {
-            collect_fee_admin,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
             market,
-            market_quote_vault,
-            token_receiver_account: admin_token_1,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots: bid_price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
         },
     )
     .await
     .unwrap();
 
-    {
-        let balance_quote = solana.token_account_balance(market_quote_vault).await;
-        assert_eq!(balance_quote, 0);
-    }
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            PlaceOrderInstruction {
+                open_orders_account: account_2,
+                open_orders_admin: None,
+                market,
+                signer: owner,
+                user_token_account: owner_token_1,
+                market_vault: market_quote_vault,
+                side: Side::Bid,
+                price_lots: bid_price_lots,
+                max_base_lots: 1,
+                max_quote_lots_including_fees: 10000,
+                client_order_id: 0,
+                expiry_timestamp: 0,
+                order_type: PlaceOrderType::Limit,
+                self_trade_behavior: SelfTradeBehavior::default(),
+                remainings: vec![],
+            },
+        )
+        .await,
+        OpenBookError::ExposureLimitExceeded.error_code(),
+        "max_bids_quote_lots override reached".into(),
+    );
 
-    // Different fees
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_fees.rs
+use super::*;
 
+#[tokio::test]
+async fn test_fees_accrued() -> Result<(), TransportError> {
     let TestInitialize {
         context,
         collect_fee_admin,
@@ -13359,16 +19455,16 @@ async fn test_fees_half() -> Result<(), TransportError> {
         account_2,
         ..
     } = TestContext::new_with_market(TestNewMarketInitialize {
-        maker_fee: -3200,
-        taker_fee: 6400,
+        maker_fee: -100,
+        taker_fee: 200,
         ..TestNewMarketInitialize::default()
     })
     .await?;
     let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
 
     // Set the initial oracle price
     set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
-    let initial_quote_amount = 10000;
 
     send_tx(
         solana,
@@ -13382,7 +19478,7 @@ async fn test_fees_half() -> Result<(), TransportError> {
             side: Side::Bid,
             price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: initial_quote_amount,
+            max_quote_lots_including_fees: 10000,
             client_order_id: 0,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
@@ -13405,7 +19501,7 @@ async fn test_fees_half() -> Result<(), TransportError> {
             side: Side::Ask,
             price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: initial_quote_amount,
+            max_quote_lots_including_fees: 10000,
             client_order_id: 0,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
@@ -13416,6 +19512,20 @@ async fn test_fees_half() -> Result<(), TransportError> {
     .await
     .unwrap();
 
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 99980);
+    }
+
     send_tx(
         solana,
         ConsumeEventsInstruction {
@@ -13430,18 +19540,15 @@ async fn test_fees_half() -> Result<(), TransportError> {
     {
         let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
         let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-        let market = solana.get_account::<Market>(market).await;
-
-        assert_eq!(open_orders_account_1.position.quote_free_native, 320);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 99360);
-        assert_eq!(market.referrer_rebates_accrued, 320);
 
-        assert_eq!(
-            (market.referrer_rebates_accrued
-                + open_orders_account_2.position.quote_free_native
-                + open_orders_account_1.position.quote_free_native) as i64,
-            initial_quote_amount * 10 // as it's in native quote
-        );
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 100);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 10);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 99980);
     }
 
     let admin_token_1 = solana
@@ -13464,25 +19571,11 @@ async fn test_fees_half() -> Result<(), TransportError> {
     .await
     .unwrap();
 
-    send_tx(
-        solana,
-        SettleFundsInstruction {
-            owner,
-            market,
-            open_orders_account: account_1,
-            market_base_vault,
-            market_quote_vault,
-            user_base_account: owner_token_0,
-            user_quote_account: owner_token_1,
-            referrer_account: None,
-        },
-    )
-    .await
-    .unwrap();
-
     {
-        let balance_quote = solana.token_account_balance(market_quote_vault).await;
-        assert_eq!(balance_quote, 320);
+        let market = solana.get_account::<Market>(market).await;
+        assert_eq!(market.fees_available, 10);
+        assert_eq!(market.fees_accrued, 10);
+        assert_eq!(market.fees_to_referrers, 0);
     }
 
     send_tx(
@@ -13498,314 +19591,158 @@ async fn test_fees_half() -> Result<(), TransportError> {
     .unwrap();
 
     {
-        let balance_quote = solana.token_account_balance(market_quote_vault).await;
-        assert_eq!(balance_quote, 0);
+        let market = solana.get_account::<Market>(market).await;
+        assert_eq!(market.fees_available, 0);
+        assert_eq!(market.fees_accrued, 10);
+        assert_eq!(market.fees_to_referrers, 0);
     }
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_locked_maker_fees() -> Result<(), TransportError> {
-    let maker_fee = 350;
-    let taker_fee = 0;
-
+async fn test_maker_fees() -> Result<(), TransportError> {
     let TestInitialize {
         context,
+        collect_fee_admin,
         owner,
-        owner_token_0: owner_base_ata,
-        owner_token_1: owner_quote_ata,
+        mints,
+        owner_token_0,
+        owner_token_1,
         market,
 
         market_base_vault,
         market_quote_vault,
-        account_1: maker,
-        account_2: taker,
+        price_lots,
+        tokens,
+        account_1,
+        account_2,
         ..
     } = TestContext::new_with_market(TestNewMarketInitialize {
-        maker_fee,
-        taker_fee,
+        maker_fee: 200,
+        taker_fee: 400,
         ..TestNewMarketInitialize::default()
     })
     .await?;
     let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
 
-    let place_maker_bid = PlaceOrderInstruction {
-        open_orders_account: maker,
-        open_orders_admin: None,
-        market,
-        signer: owner,
-        user_token_account: owner_quote_ata,
-        market_vault: market_quote_vault,
-        side: Side::Bid,
-        price_lots: 1_000,
-        max_base_lots: 5,
-        max_quote_lots_including_fees: 1_000_000_000,
-        client_order_id: 0,
-        expiry_timestamp: 0,
-        order_type: PlaceOrderType::Limit,
-        self_trade_behavior: SelfTradeBehavior::default(),
-        remainings: vec![],
-    };
-
-    let place_taker_ask = PlaceOrderInstruction {
-        side: Side::Ask,
-        market_vault: market_base_vault,
-        open_orders_account: taker,
-        user_token_account: owner_base_ata,
-        max_base_lots: 3,
-        ..place_maker_bid.clone()
-    };
-
-    let cancel_maker_orders_ix = CancelAllOrdersInstruction {
-        open_orders_account: maker,
-        signer: owner,
-        market,
-    };
-
-    let settle_maker_funds_ix = SettleFundsInstruction {
-        owner,
-        market,
-        open_orders_account: maker,
-        market_base_vault,
-        market_quote_vault,
-        user_base_account: owner_base_ata,
-        user_quote_account: owner_quote_ata,
-        referrer_account: None,
-    };
-
-    send_tx(solana, place_maker_bid.clone()).await.unwrap();
-    {
-        let oo = solana.get_account::<OpenOrdersAccount>(maker).await;
-        assert_eq!(oo.position.locked_maker_fees, 18);
-    }
+    // Set the initial oracle price
+    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
 
-    send_tx(solana, place_taker_ask).await.unwrap();
     send_tx(
         solana,
-        ConsumeEventsInstruction {
-            consume_events_admin: None,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
             market,
-            open_orders_accounts: vec![maker],
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10020,
+            client_order_id: 30,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
         },
     )
     .await
     .unwrap();
 
-    {
-        let oo = solana.get_account::<OpenOrdersAccount>(maker).await;
-        assert_eq!(oo.position.locked_maker_fees, 8);
-    }
-
-    send_tx(solana, cancel_maker_orders_ix.clone())
-        .await
-        .unwrap();
-
-    // one lamport is still locked due rounding
-    {
-        let oo = solana.get_account::<OpenOrdersAccount>(maker).await;
-        assert_eq!(oo.position.locked_maker_fees, 1);
-    }
-
-    send_tx(solana, place_maker_bid.clone()).await.unwrap();
-    send_tx(solana, settle_maker_funds_ix.clone())
-        .await
-        .unwrap();
-
-    // which cannot be claimed yet because there're still bids on the book
-    {
-        let oo = solana.get_account::<OpenOrdersAccount>(maker).await;
-        assert_eq!(oo.position.locked_maker_fees, 1 + 18);
-    }
-
-    // but now if we don't have any pending bid order
-    send_tx(solana, cancel_maker_orders_ix.clone())
-        .await
-        .unwrap();
+    send_tx(
+        solana,
+        CancelOrderByClientOrderIdInstruction {
+            open_orders_account: account_1,
+            market,
+            signer: owner,
+            client_order_id: 30,
+        },
+    )
+    .await
+    .unwrap();
 
     {
-        let oo = solana.get_account::<OpenOrdersAccount>(maker).await;
-        assert_eq!(oo.position.locked_maker_fees, 1);
-    }
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
 
-    // it's gone!
-    send_tx(solana, settle_maker_funds_ix.clone())
-        .await
-        .unwrap();
-    {
-        let oo = solana.get_account::<OpenOrdersAccount>(maker).await;
-        assert_eq!(oo.position.locked_maker_fees, 0);
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 100020);
     }
 
-    Ok(())
-}
-
-
-// File: openbook-v2/programs/openbook-v2/tests/cases/test_fill_or_kill_order.rs
-use super::*;
-use std::sync::Arc;
-
-#[tokio::test]
-async fn test_fill_or_kill_order() -> Result<(), TransportError> {
-    let TestInitialize {
-        context,
-        collect_fee_admin,
-        owner,
-        owner_token_0,
-        owner_token_1,
-        market,
-
-        market_base_vault,
-        market_quote_vault,
-        price_lots,
-        tokens,
-        account_1,
-        account_2,
-        ..
-    } = TestContext::new_with_market(TestNewMarketInitialize {
-        maker_fee: 0,
-        taker_fee: 0,
-        ..TestNewMarketInitialize::default()
-    })
-    .await?;
-    let default = PlaceOrderInstruction {
-        open_orders_account: Default::default(),
-        open_orders_admin: None,
-        market,
-        signer: owner,
-        market_vault: Default::default(),
-        user_token_account: Default::default(),
-        side: Side::Bid,
-        price_lots,
-        max_base_lots: 0,
-        max_quote_lots_including_fees: 0,
-        client_order_id: 0,
-        expiry_timestamp: 0,
-        order_type: PlaceOrderType::Limit,
-        self_trade_behavior: Default::default(),
-        remainings: vec![],
-    };
-    let solana = &context.solana.clone();
-
-    // Set the initial oracle price
-    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
-
-    let initial_balance_base = solana.token_account_balance(owner_token_0).await;
-    let initial_balance_quote = solana.token_account_balance(owner_token_1).await;
-
     send_tx(
         solana,
         PlaceOrderInstruction {
             open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
             user_token_account: owner_token_1,
             market_vault: market_quote_vault,
             side: Side::Bid,
-            max_base_lots: 5,
-            max_quote_lots_including_fees: 50_000,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10020,
+            client_order_id: 0,
+            expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
-            ..default.clone()
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
         },
     )
     .await
     .unwrap();
 
-    assert_open_orders(account_1, solana, 5, 0).await;
-    assert_open_orders(account_2, solana, 0, 0).await;
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+    }
 
-    // small order -> no problem
     send_tx(
         solana,
         PlaceOrderInstruction {
             open_orders_account: account_2,
+            open_orders_admin: None,
+            market,
+            signer: owner,
             user_token_account: owner_token_0,
             market_vault: market_base_vault,
             side: Side::Ask,
+            price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 10_000,
-            order_type: PlaceOrderType::FillOrKill,
-            ..default.clone()
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
         },
     )
     .await
     .unwrap();
 
-    consume_and_settle(
-        owner,
-        owner_token_0,
-        owner_token_1,
-        market,
-        market_base_vault,
-        market_quote_vault,
-        account_1,
-        account_2,
-        solana,
-    )
-    .await;
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
 
-    assert_open_orders(account_1, solana, 4, 0).await;
-    assert_open_orders(account_2, solana, 0, 0).await;
-
-    assert_balance(owner_token_0, solana, initial_balance_base).await;
-    assert_balance(owner_token_1, solana, initial_balance_quote - 400_000).await;
-
-    // big order -> should fail
-    let result = send_tx(
-        solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_2,
-            user_token_account: owner_token_0,
-            market_vault: market_base_vault,
-            side: Side::Ask,
-            max_base_lots: 6,
-            max_quote_lots_including_fees: 60_000,
-            order_type: PlaceOrderType::FillOrKill,
-            ..default.clone()
-        },
-    )
-    .await;
-
-    assert_openbook_error(
-        &result,
-        OpenBookError::WouldExecutePartially.error_code(),
-        "Should kill order".into(),
-    );
-    Ok(())
-}
-
-async fn assert_open_orders(
-    account: Pubkey,
-    solana: &Arc<SolanaCookie>,
-    bids_base_lots: i64,
-    asks_base_lots: i64,
-) {
-    let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account).await;
-    assert_eq!(
-        open_orders_account_1.position.bids_base_lots,
-        bids_base_lots
-    );
-    assert_eq!(
-        open_orders_account_1.position.asks_base_lots,
-        asks_base_lots
-    );
-}
-
-async fn assert_balance(token: Pubkey, solana: &Arc<SolanaCookie>, expected_balance: u64) {
-    let balance_base = solana.token_account_balance(token).await;
-    assert_eq!(balance_base, expected_balance);
-}
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 99960);
+    }
 
-#[allow(clippy::too_many_arguments)]
-async fn consume_and_settle(
-    owner: TestKeypair,
-    owner_token_0: Pubkey,
-    owner_token_1: Pubkey,
-    market: Pubkey,
-    market_base_vault: Pubkey,
-    market_quote_vault: Pubkey,
-    account_1: Pubkey,
-    account_2: Pubkey,
-    solana: &Arc<SolanaCookie>,
-) {
     send_tx(
         solana,
         ConsumeEventsInstruction {
@@ -13817,12 +19754,30 @@ async fn consume_and_settle(
     .await
     .unwrap();
 
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 100);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 99960);
+    }
+
+    let admin_token_1 = solana
+        .create_associated_token_account(&collect_fee_admin.pubkey(), mints[1].pubkey)
+        .await;
+
     send_tx(
         solana,
         SettleFundsInstruction {
             owner,
             market,
-            open_orders_account: account_1,
+            open_orders_account: account_2,
             market_base_vault,
             market_quote_vault,
             user_base_account: owner_token_0,
@@ -13833,214 +19788,214 @@ async fn consume_and_settle(
     .await
     .unwrap();
 
+    {
+        let market = solana.get_account::<Market>(market).await;
+        assert_eq!(market.fees_available, 40);
+        assert_eq!(market.fees_accrued, 60);
+        assert_eq!(market.fees_to_referrers, 0);
+    }
+
     send_tx(
         solana,
         SettleFundsInstruction {
             owner,
             market,
-            open_orders_account: account_2,
+            open_orders_account: account_1,
             market_base_vault,
             market_quote_vault,
             user_base_account: owner_token_0,
             user_quote_account: owner_token_1,
-            referrer_account: None,
+            referrer_account: Some(owner_token_1),
         },
     )
     .await
     .unwrap();
-}
 
+    {
+        let market = solana.get_account::<Market>(market).await;
+        assert_eq!(market.fees_available, 40);
+        assert_eq!(market.fees_accrued, 60);
+        assert_eq!(market.fees_to_referrers, 20);
+    }
 
-// File: openbook-v2/programs/openbook-v2/tests/cases/test_indexer.rs
-use super::*;
+    send_tx(
+        solana,
+        SweepFeesInstruction {
+            collect_fee_admin,
+            market,
+            market_quote_vault,
+            token_receiver_account: admin_token_1,
+        },
+    )
+    .await
+    .unwrap();
 
-#[tokio::test]
-async fn test_indexer() -> Result<(), TransportError> {
-    let context = TestContext::new().await;
-    let solana = &context.solana.clone();
+    {
+        let market = solana.get_account::<Market>(market).await;
+        assert_eq!(market.fees_available, 0);
+    }
 
-    let collect_fee_admin = TestKeypair::new();
-    let close_market_admin = TestKeypair::new();
-    let owner = context.users[0].key;
-    let payer = context.users[1].key;
-    let mints = &context.mints[0..=2];
+    Ok(())
+}
 
-    let tokens = Token::create(mints.to_vec(), solana, collect_fee_admin, payer).await;
+// Real simulation. Market maker pays fees but get them back on referral. Jupiter users don't pay fees.
+// Only users paying fees are users using a UI limit orders.
+#[tokio::test]
+async fn test_market_maker_fees() -> Result<(), TransportError> {
+    let market_base_lot_size = 100;
+    let market_quote_lot_size = 10;
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        quote_lot_size: market_quote_lot_size,
+        base_lot_size: market_base_lot_size,
+        maker_fee: 400,
+        taker_fee: 400,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
+    let solana = &context.solana.clone();
 
-    let market = TestKeypair::new();
+    let balance_owner_0_before = solana.token_account_balance(owner_token_0).await;
+    let balance_owner_1_before = solana.token_account_balance(owner_token_1).await;
 
-    let openbook_v2::accounts::CreateMarket { market, .. } = send_tx(
+    // Account_1 is a MM and pays fee which will get back later
+    send_tx(
         solana,
-        CreateMarketInstruction {
-            collect_fee_admin: collect_fee_admin.pubkey(),
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
             open_orders_admin: None,
-            close_market_admin: Some(close_market_admin.pubkey()),
-            payer,
             market,
-            quote_lot_size: 10,
-            base_lot_size: 100,
-            maker_fee: -200,
-            taker_fee: 400,
-            base_mint: mints[0].pubkey,
-            quote_mint: mints[1].pubkey,
-            ..CreateMarketInstruction::with_new_book_and_heap(solana, Some(tokens[1].oracle), None)
-                .await
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10040,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
         },
     )
     .await
     .unwrap();
 
-    let indexer = create_open_orders_indexer(solana, &context.users[1], owner, market).await;
-    let maker_1 =
-        create_open_orders_account(solana, owner, market, 1, &context.users[1], None).await;
-
     {
-        let indexer = solana.get_account::<OpenOrdersIndexer>(indexer).await;
-        assert_eq!(indexer.created_counter, 1);
-        assert!(indexer.addresses.contains(&maker_1));
+        let balance_owner_0_after = solana.token_account_balance(owner_token_0).await;
+        let balance_owner_1_after = solana.token_account_balance(owner_token_1).await;
+        assert_eq!(balance_owner_0_before, balance_owner_0_after);
+        assert_eq!(
+            balance_owner_1_before,
+            balance_owner_1_after + 10000 * (market_quote_lot_size as u64) + 40
+        );
     }
 
-    let (maker_2, maker_3) = {
-        (
-            create_open_orders_account(solana, owner, market, 2, &context.users[1], None).await,
-            create_open_orders_account(solana, owner, market, 3, &context.users[1], None).await,
-        )
-    };
-
-    {
-        let indexer = solana.get_account::<OpenOrdersIndexer>(indexer).await;
+    let jup_user = context.users[2].key;
+    let jup_user_token_0 = context.users[2].token_accounts[0];
+    let jup_user_token_1 = context.users[2].token_accounts[1];
 
-        assert_eq!(indexer.created_counter, 3);
-        assert_eq!(indexer.addresses.len(), 3);
-        assert!(indexer.addresses.contains(&maker_1));
-        assert!(indexer.addresses.contains(&maker_2));
-        assert!(indexer.addresses.contains(&maker_3));
-    }
+    let balance_jup_0_before = solana.token_account_balance(jup_user_token_0).await;
+    let balance_jup_1_before = solana.token_account_balance(jup_user_token_1).await;
 
+    // Jupiter user, takes and don't pay fees
     send_tx(
         solana,
-        CloseOpenOrdersAccountInstruction {
-            account_num: 2,
+        PlaceTakeOrderInstruction {
             market,
-            owner,
-            sol_destination: owner.pubkey(),
+            signer: jup_user,
+            user_base_account: jup_user_token_0,
+            user_quote_account: jup_user_token_1,
+            market_base_vault,
+            market_quote_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10040,
+            open_orders_admin: None,
         },
     )
     .await
     .unwrap();
 
     {
-        let indexer = solana.get_account::<OpenOrdersIndexer>(indexer).await;
-
-        assert_eq!(indexer.created_counter, 3);
-        assert_eq!(indexer.addresses.len(), 2);
-        assert!(indexer.addresses.contains(&maker_1));
-        assert!(indexer.addresses.contains(&maker_3));
-    }
-
-    let maker_4 =
-        create_open_orders_account(solana, owner, market, 4, &context.users[1], None).await;
-
-    {
-        let indexer = solana.get_account::<OpenOrdersIndexer>(indexer).await;
-        assert_eq!(indexer.created_counter, 4);
-        assert_eq!(indexer.addresses.len(), 3);
-        assert!(indexer.addresses.contains(&maker_1));
-        assert!(indexer.addresses.contains(&maker_3));
-        assert!(indexer.addresses.contains(&maker_4));
+        let balance_jup_0_after = solana.token_account_balance(jup_user_token_0).await;
+        let balance_jup_1_after = solana.token_account_balance(jup_user_token_1).await;
+        assert_eq!(
+            balance_jup_0_before,
+            balance_jup_0_after + (market_base_lot_size as u64)
+        );
+        assert_eq!(
+            balance_jup_1_before + 10000 * (market_quote_lot_size as u64),
+            balance_jup_1_after
+        );
     }
 
-    Ok(())
-}
-
-#[tokio::test]
-async fn test_size_vector() -> Result<(), TransportError> {
-    let context = TestContext::new().await;
-    let solana = &context.solana.clone();
-
-    let collect_fee_admin = TestKeypair::new();
-    let close_market_admin = TestKeypair::new();
-    let owner = context.users[0].key;
-    let payer = context.users[1].key;
-    let mints = &context.mints[0..=2];
-
-    let tokens = Token::create(mints.to_vec(), solana, collect_fee_admin, payer).await;
-
-    let market = TestKeypair::new();
-
-    let openbook_v2::accounts::CreateMarket { market, .. } = send_tx(
+    send_tx(
         solana,
-        CreateMarketInstruction {
-            collect_fee_admin: collect_fee_admin.pubkey(),
-            open_orders_admin: None,
-            close_market_admin: Some(close_market_admin.pubkey()),
-            payer,
+        ConsumeEventsInstruction {
+            consume_events_admin: None,
             market,
-            quote_lot_size: 10,
-            base_lot_size: 100,
-            maker_fee: -200,
-            taker_fee: 400,
-            base_mint: mints[0].pubkey,
-            quote_mint: mints[1].pubkey,
-            ..CreateMarketInstruction::with_new_book_and_heap(solana, Some(tokens[1].oracle), None)
-                .await
+            open_orders_accounts: vec![account_1],
         },
     )
     .await
     .unwrap();
-
-    let indexer = create_open_orders_indexer(solana, &context.users[1], owner, market).await;
-
-    let mut makers = vec![];
-    let max = 256;
-    for n in 0..max {
-        makers.push(
-            create_open_orders_account(solana, owner, market, n + 1, &context.users[1], None).await,
-        )
-    }
-
-    {
-        let indexer = solana.get_account::<OpenOrdersIndexer>(indexer).await;
-
-        assert_eq!(indexer.created_counter, max);
-        assert_eq!(indexer.addresses.len(), max as usize);
-        assert!(indexer.addresses.contains(&makers[(max - 1) as usize]));
-        assert!(indexer.addresses.contains(&makers[(max / 2) as usize]));
-        assert!(indexer.addresses.contains(&makers[1]));
-    }
-
-    // Can't create more than 256
-    assert!(send_tx(
+    send_tx(
         solana,
-        CreateOpenOrdersAccountInstruction {
-            account_num: 257,
-            market,
+        SettleFundsInstruction {
             owner,
-            payer: context.users[1].key,
-            delegate: None,
+            market,
+            open_orders_account: account_1,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            referrer_account: Some(owner_token_1),
         },
     )
     .await
-    .is_err());
+    .unwrap();
+
+    // MM gets back the fee
+    {
+        let balance_owner_0_after = solana.token_account_balance(owner_token_0).await;
+        let balance_owner_1_after = solana.token_account_balance(owner_token_1).await;
+        assert_eq!(
+            balance_owner_0_before + (market_base_lot_size as u64),
+            balance_owner_0_after
+        );
+        assert_eq!(
+            balance_owner_1_before,
+            balance_owner_1_after + 10000 * (market_quote_lot_size as u64)
+        );
+    }
 
     Ok(())
 }
 
-
-// File: openbook-v2/programs/openbook-v2/tests/cases/test_ioc.rs
-use super::*;
-
 #[tokio::test]
-async fn test_ioc() -> Result<(), TransportError> {
+async fn test_no_maker_fees_ask() -> Result<(), TransportError> {
     let TestInitialize {
         context,
         collect_fee_admin,
         owner,
-        payer,
         mints,
         owner_token_0,
         owner_token_1,
         market,
+
         market_base_vault,
         market_quote_vault,
         price_lots,
@@ -14049,28 +20004,33 @@ async fn test_ioc() -> Result<(), TransportError> {
         account_2,
         ..
     } = TestContext::new_with_market(TestNewMarketInitialize {
-        maker_fee: 200,
-        taker_fee: 200,
+        maker_fee: -200,
+        taker_fee: 400,
         ..TestNewMarketInitialize::default()
     })
     .await?;
     let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
+
+    // Set the initial oracle price
+    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
 
     send_tx(
         solana,
         PlaceOrderInstruction {
-            open_orders_account: account_1,
+            open_orders_account: account_2,
+            open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
             price_lots,
             max_base_lots: 1,
             max_quote_lots_including_fees: 10000,
-            reduce_only: false,
             client_order_id: 0,
             expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
             remainings: vec![],
         },
@@ -14078,21 +20038,31 @@ async fn test_ioc() -> Result<(), TransportError> {
     .await
     .unwrap();
 
+    {
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 1);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+    }
+
     send_tx(
         solana,
         PlaceOrderInstruction {
-            open_orders_account: account_2,
+            open_orders_account: account_1,
+            open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_token_0,
-            market_vault: market_base_vault,
-            side: Side::Ask,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
             price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-            reduce_only: false,
+            max_quote_lots_including_fees: 10004,
             client_order_id: 0,
             expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
             remainings: vec![],
         },
@@ -14104,79 +20074,71 @@ async fn test_ioc() -> Result<(), TransportError> {
         let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
         let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
 
-        assert_eq!(open_orders_account_1.position.base_position_lots(), 0);
-        assert_eq!(open_orders_account_2.position.base_position_lots(), 0);
-        assert_eq!(open_orders_account_1.position.quote_position_native(), 0);
-        // assert_eq!(open_orders_account_2.position.quote_position_native(), 0);
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
         assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
         assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.taker_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.taker_quote_lots, 10000);
-        assert_eq!(open_orders_account_1.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 1);
+        assert_eq!(open_orders_account_1.position.base_free_native, 100);
         assert_eq!(open_orders_account_2.position.base_free_native, 0);
         assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 99960);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
     }
 
-    Ok(())
-}
-
-
-// File: openbook-v2/programs/openbook-v2/tests/cases/test_multiple_orders.rs
-use super::*;
+    send_tx(
+        solana,
+        ConsumeEventsInstruction {
+            consume_events_admin: None,
+            market,
+            open_orders_accounts: vec![account_1, account_2],
+        },
+    )
+    .await
+    .unwrap();
 
-#[tokio::test]
-async fn insufficient_funds() -> Result<(), TransportError> {
-    let TestInitialize {
-        context,
-        owner,
-        owner_token_0,
-        owner_token_1,
-        account_1,
-        account_2,
-        market,
-        market_base_vault,
-        market_quote_vault,
-        ..
-    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
 
-    let solana = &context.solana.clone();
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 100);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 100020);
+    }
 
-    let max_quote_lots_including_fees = 104;
+    let admin_token_1 = solana
+        .create_associated_token_account(&collect_fee_admin.pubkey(), mints[1].pubkey)
+        .await;
 
-    // there's an ask on the book
     send_tx(
         solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_2,
-            open_orders_admin: None,
+        SettleFundsInstruction {
+            owner,
             market,
-            signer: owner,
-            user_token_account: owner_token_0,
-            market_vault: market_base_vault,
-            side: Side::Ask,
-            price_lots: 1,
-            max_base_lots: i64::MAX / 1_000,
-            max_quote_lots_including_fees,
-            client_order_id: 0,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
+            open_orders_account: account_2,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            referrer_account: None,
         },
     )
     .await
     .unwrap();
 
-    solana.set_account_balance(owner_token_0, 2_500).await;
-    solana.set_account_balance(owner_token_1, 110).await;
+    {
+        let market = solana.get_account::<Market>(market).await;
+        assert_eq!(market.fees_available, 0);
+        assert_eq!(market.fees_accrued, 20);
+        assert_eq!(market.fees_to_referrers, 0);
+    }
 
-    // some lamports are already deposited
     send_tx(
         solana,
-        DepositInstruction {
+        SettleFundsInstruction {
             owner,
             market,
             open_orders_account: account_1,
@@ -14184,276 +20146,278 @@ async fn insufficient_funds() -> Result<(), TransportError> {
             market_quote_vault,
             user_base_account: owner_token_0,
             user_quote_account: owner_token_1,
-            base_amount: 1_200,
-            quote_amount: 0,
+            referrer_account: Some(owner_token_1),
         },
     )
     .await
     .unwrap();
 
-    // note that a priori, we only have enough lamports to place 2.5 Ask. But as the bid will be
-    // filled & the taker executed immediately, we will have 10 extra base lots available
-    let order = openbook_v2::PlaceMultipleOrdersArgs {
-        price_lots: 1,
-        max_quote_lots_including_fees,
-        expiry_timestamp: 0,
-    };
-
-    let bids = vec![order];
-    let asks = vec![order; 4];
+    {
+        let market = solana.get_account::<Market>(market).await;
+        assert_eq!(market.fees_available, 0);
+        assert_eq!(market.fees_accrued, 20);
+        assert_eq!(market.fees_to_referrers, 20);
+    }
 
     send_tx(
         solana,
-        CancelAllAndPlaceOrdersInstruction {
-            open_orders_account: account_1,
-            open_orders_admin: None,
+        SweepFeesInstruction {
+            collect_fee_admin,
             market,
-            signer: owner,
-            orders_type: PlaceOrderType::Limit,
-            user_base_account: owner_token_0,
-            user_quote_account: owner_token_1,
-            bids,
-            asks,
+            market_quote_vault,
+            token_receiver_account: admin_token_1,
         },
     )
     .await
     .unwrap();
 
-    let position = solana
-        .get_account::<OpenOrdersAccount>(account_1)
-        .await
-        .position;
-
-    assert_eq!(position.asks_base_lots, 35);
-    assert_eq!(position.bids_base_lots, 0);
-
-    assert_eq!(position.base_free_native, 0);
-    assert_eq!(position.quote_free_native, 0);
-
-    assert_eq!(position.referrer_rebates_available, 1);
-    assert_eq!(solana.token_account_balance(owner_token_1).await, 9);
-    assert_eq!(solana.token_account_balance(owner_token_0).await, 0);
+    {
+        let market = solana.get_account::<Market>(market).await;
+        assert_eq!(market.fees_available, 0);
+    }
 
     Ok(())
 }
 
-
-// File: openbook-v2/programs/openbook-v2/tests/cases/test_oracle_peg.rs
-use super::*;
-
 #[tokio::test]
-async fn test_oracle_peg_enabled() -> Result<(), TransportError> {
+async fn test_maker_fees_ask() -> Result<(), TransportError> {
     let TestInitialize {
         context,
+        collect_fee_admin,
         owner,
+        mints,
+        owner_token_0,
         owner_token_1,
         market,
+
+        market_base_vault,
         market_quote_vault,
+        price_lots,
+        tokens,
         account_1,
+        account_2,
         ..
     } = TestContext::new_with_market(TestNewMarketInitialize {
-        with_oracle: false,
+        maker_fee: 200,
+        taker_fee: 400,
         ..TestNewMarketInitialize::default()
     })
     .await?;
     let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
 
-    assert!(send_tx(
+    // Set the initial oracle price
+    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+
+    send_tx(
         solana,
-        PlaceOrderPeggedInstruction {
-            open_orders_account: account_1,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
-            price_offset: -1,
-            peg_limit: 100,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 100_000,
+            max_quote_lots_including_fees: 10020,
             client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
         },
     )
     .await
-    .is_err());
-
-    Ok(())
-}
+    .unwrap();
 
-#[tokio::test]
-async fn test_oracle_peg_invalid_oracle() -> Result<(), TransportError> {
-    let TestInitialize {
-        context,
-        owner,
-        owner_token_1,
-        market,
-        market_quote_vault,
-        account_1,
-        ..
-    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
-    let solana = &context.solana.clone();
+    {
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
 
-    solana.advance_clock(200).await;
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 1);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+    }
 
-    assert!(send_tx(
+    send_tx(
         solana,
-        PlaceOrderPeggedInstruction {
+        PlaceOrderInstruction {
             open_orders_account: account_1,
+            open_orders_admin: None,
             market,
             signer: owner,
             user_token_account: owner_token_1,
             market_vault: market_quote_vault,
             side: Side::Bid,
-            price_offset: -1,
-            peg_limit: 100,
+            price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 100_000,
+            max_quote_lots_including_fees: 10004,
             client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
         },
     )
     .await
-    .is_err());
-
-    Ok(())
-}
-
-#[tokio::test]
-async fn test_oracle_peg() -> Result<(), TransportError> {
-    let market_base_lot_size = 10000;
-    let market_quote_lot_size = 10;
-
-    let TestInitialize {
-        context,
-        owner,
-        owner_token_0,
-        owner_token_1,
-        market,
-        market_base_vault,
-        market_quote_vault,
-        account_1,
-        account_2,
-        tokens,
-        collect_fee_admin,
-        bids,
-        ..
-    } = TestContext::new_with_market(TestNewMarketInitialize {
-        quote_lot_size: market_quote_lot_size,
-        base_lot_size: market_base_lot_size,
-        maker_fee: -0,
-        taker_fee: 0,
-        ..TestNewMarketInitialize::default()
-    })
-    .await?;
-    let solana = &context.solana.clone();
-
-    let price_lots = {
-        let market = solana.get_account::<Market>(market).await;
-        market.native_price_to_lot(I80F48::ONE).unwrap()
-    };
-    assert_eq!(price_lots, market_base_lot_size / market_quote_lot_size);
-
-    let place_pegged_ix = PlaceOrderPeggedInstruction {
-        open_orders_account: account_1,
-        market,
-        signer: owner,
-        user_token_account: owner_token_1,
-        market_vault: market_quote_vault,
-        side: Side::Bid,
-        price_offset: -1,
-        peg_limit: 1,
-        max_base_lots: 1,
-        max_quote_lots_including_fees: 100_000,
-        client_order_id: 0,
-    };
+    .unwrap();
 
-    // posting invalid orderes by peg_limit are skipped
-    send_tx(solana, place_pegged_ix.clone()).await.unwrap();
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
 
-    let bids_data = solana.get_account_boxed::<BookSide>(bids).await;
-    assert_eq!(bids_data.roots[1].leaf_count, 0);
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 1);
+        assert_eq!(open_orders_account_1.position.base_free_native, 100);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+    }
 
-    // but not if they are inside the peg_limit
     send_tx(
         solana,
-        PlaceOrderPeggedInstruction {
-            peg_limit: 1000,
-            ..place_pegged_ix
+        ConsumeEventsInstruction {
+            consume_events_admin: None,
+            market,
+            open_orders_accounts: vec![account_1, account_2],
         },
     )
     .await
     .unwrap();
 
-    let bids_data = solana.get_account_boxed::<BookSide>(bids).await;
-    assert_eq!(bids_data.roots[1].leaf_count, 1);
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
 
-    let order = solana
-        .get_account::<OpenOrdersAccount>(account_1)
-        .await
-        .open_orders[0];
-    assert_eq!(order.side_and_tree(), SideAndOrderTree::BidOraclePegged);
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 100);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 99980);
+    }
+
+    let admin_token_1 = solana
+        .create_associated_token_account(&collect_fee_admin.pubkey(), mints[1].pubkey)
+        .await;
 
     send_tx(
         solana,
-        CancelOrderInstruction {
-            signer: owner,
+        SettleFundsInstruction {
+            owner,
             market,
-            open_orders_account: account_1,
-            order_id: order.id,
+            open_orders_account: account_2,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            referrer_account: None,
         },
     )
     .await
     .unwrap();
 
-    assert_no_orders(solana, account_1).await;
-
-    let balance_before = solana.token_account_balance(owner_token_1).await;
-    let max_quote_lots_including_fees = 100_000;
+    {
+        let market = solana.get_account::<Market>(market).await;
+        assert_eq!(market.fees_available, 20);
+        assert_eq!(market.fees_accrued, 60);
+        assert_eq!(market.fees_to_referrers, 0);
+    }
 
-    // TEST: Place a pegged bid, take it with a direct and pegged ask, and consume events
     send_tx(
         solana,
-        PlaceOrderPeggedInstruction {
-            open_orders_account: account_1,
+        SettleFundsInstruction {
+            owner,
             market,
-            signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
-            price_offset: 0,
-            peg_limit: price_lots,
-            max_base_lots: 2,
-            max_quote_lots_including_fees,
-            client_order_id: 5,
+            open_orders_account: account_1,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            referrer_account: Some(owner_token_1),
         },
     )
     .await
     .unwrap();
 
-    let balance_after = solana.token_account_balance(owner_token_1).await;
+    {
+        let market = solana.get_account::<Market>(market).await;
+        assert_eq!(market.fees_available, 20);
+        assert_eq!(market.fees_accrued, 60);
+        assert_eq!(market.fees_to_referrers, 40);
+    }
+
+    send_tx(
+        solana,
+        SweepFeesInstruction {
+            collect_fee_admin,
+            market,
+            market_quote_vault,
+            token_receiver_account: admin_token_1,
+        },
+    )
+    .await
+    .unwrap();
 
-    // Max quantity being subtracted from owner is max_quote_lots_including_fees
     {
-        assert!(
-            balance_before
-                - ((max_quote_lots_including_fees as u64) * (market_quote_lot_size as u64))
-                <= balance_after
-        );
+        let market = solana.get_account::<Market>(market).await;
+        assert_eq!(market.fees_available, 0);
     }
 
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_fees_half() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        collect_fee_admin,
+        owner,
+        mints,
+        owner_token_0,
+        owner_token_1,
+        market,
+
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        tokens,
+        account_1,
+        account_2,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        maker_fee: -3700,
+        taker_fee: 7400,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
+    let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
+
+    // Set the initial oracle price
+    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+    let initial_quote_amount = 10000;
+
     send_tx(
         solana,
         PlaceOrderInstruction {
-            open_orders_account: account_2,
+            open_orders_account: account_1,
             open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_token_0,
-            market_vault: market_base_vault,
-            side: Side::Ask,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
             price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 100_000,
-            client_order_id: 6,
+            max_quote_lots_including_fees: initial_quote_amount,
+            client_order_id: 0,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
@@ -14465,18 +20429,22 @@ async fn test_oracle_peg() -> Result<(), TransportError> {
 
     send_tx(
         solana,
-        PlaceOrderPeggedInstruction {
+        PlaceOrderInstruction {
             open_orders_account: account_2,
+            open_orders_admin: None,
             market,
             signer: owner,
             user_token_account: owner_token_0,
             market_vault: market_base_vault,
             side: Side::Ask,
-            price_offset: 0,
-            peg_limit: price_lots,
+            price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 100_000,
-            client_order_id: 7,
+            max_quote_lots_including_fees: initial_quote_amount,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
         },
     )
     .await
@@ -14493,106 +20461,134 @@ async fn test_oracle_peg() -> Result<(), TransportError> {
     .await
     .unwrap();
 
-    assert_no_orders(solana, account_1).await;
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+        let market = solana.get_account::<Market>(market).await;
 
-    // TEST: an ask at current oracle price does not match
-    send_tx(
-        solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_2,
-            open_orders_admin: None,
-            market,
-            signer: owner,
-            user_token_account: owner_token_0,
-            market_vault: market_base_vault,
-            side: Side::Ask,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 100_000,
+        assert_eq!(open_orders_account_1.position.quote_free_native, 370);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 99260);
+        assert_eq!(market.referrer_rebates_accrued, 370);
+
+        assert_eq!(
+            (market.referrer_rebates_accrued
+                + open_orders_account_2.position.quote_free_native
+                + open_orders_account_1.position.quote_free_native) as i64,
+            initial_quote_amount * 10 // as it's in native quote
+        );
+    }
+
+    let admin_token_1 = solana
+        .create_associated_token_account(&collect_fee_admin.pubkey(), mints[1].pubkey)
+        .await;
 
-            client_order_id: 60,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
-        },
-    )
-    .await
-    .unwrap();
     send_tx(
         solana,
-        CancelOrderByClientOrderIdInstruction {
-            open_orders_account: account_2,
+        SettleFundsInstruction {
+            owner,
             market,
-            signer: owner,
-            client_order_id: 60,
+            open_orders_account: account_2,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            referrer_account: None,
         },
     )
     .await
     .unwrap();
 
-    // TEST: Change the oracle, now the ask matches
-    set_stub_oracle_price(solana, &tokens[0], collect_fee_admin, 1.002).await;
     send_tx(
         solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_2,
-            open_orders_admin: None,
+        SettleFundsInstruction {
+            owner,
             market,
-            signer: owner,
-            user_token_account: owner_token_0,
-            market_vault: market_base_vault,
-            side: Side::Ask,
-            price_lots,
-            max_base_lots: 2,
-            max_quote_lots_including_fees: 100_000,
-
-            client_order_id: 61,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
+            open_orders_account: account_1,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            referrer_account: None,
         },
     )
     .await
     .unwrap();
+
+    {
+        let balance_quote = solana.token_account_balance(market_quote_vault).await;
+        assert_eq!(balance_quote, 370);
+    }
+
     send_tx(
         solana,
-        ConsumeEventsInstruction {
-            consume_events_admin: None,
+        SweepFeesInstruction {
+            collect_fee_admin,
             market,
-            open_orders_accounts: vec![account_1, account_2],
+            market_quote_vault,
+            token_receiver_account: admin_token_1,
         },
     )
     .await
     .unwrap();
-    assert_no_orders(solana, account_1).await;
 
-    // restore the oracle to default
-    set_stub_oracle_price(solana, &tokens[0], collect_fee_admin, 1.0).await;
+    {
+        let balance_quote = solana.token_account_balance(market_quote_vault).await;
+        assert_eq!(balance_quote, 0);
+    }
+
+    // Different fees
+
+    let TestInitialize {
+        context,
+        collect_fee_admin,
+        owner,
+        mints,
+        owner_token_0,
+        owner_token_1,
+        market,
+
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        tokens,
+        account_1,
+        account_2,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        maker_fee: -3200,
+        taker_fee: 6400,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
+    let solana = &context.solana.clone();
+
+    // Set the initial oracle price
+    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+    let initial_quote_amount = 10000;
 
-    // TEST: order is cancelled when the price exceeds the peg limit
     send_tx(
         solana,
-        PlaceOrderPeggedInstruction {
+        PlaceOrderInstruction {
             open_orders_account: account_1,
+            open_orders_admin: None,
             market,
             signer: owner,
             user_token_account: owner_token_1,
             market_vault: market_quote_vault,
             side: Side::Bid,
-            price_offset: -1,
-            peg_limit: price_lots + 2,
-            max_base_lots: 2,
-            max_quote_lots_including_fees: 100_000,
-            client_order_id: 5,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: initial_quote_amount,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
         },
     )
     .await
     .unwrap();
 
-    // order is still matchable when exactly at the peg limit
-    set_stub_oracle_price(solana, &tokens[0], collect_fee_admin, 1.003).await;
     send_tx(
         solana,
         PlaceOrderInstruction {
@@ -14603,11 +20599,10 @@ async fn test_oracle_peg() -> Result<(), TransportError> {
             user_token_account: owner_token_0,
             market_vault: market_base_vault,
             side: Side::Ask,
-            price_lots: price_lots + 2,
+            price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 100_000,
-
-            client_order_id: 62,
+            max_quote_lots_including_fees: initial_quote_amount,
+            client_order_id: 0,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
@@ -14617,47 +20612,6 @@ async fn test_oracle_peg() -> Result<(), TransportError> {
     .await
     .unwrap();
 
-    {
-        let oo = solana.get_account::<OpenOrdersAccount>(account_2).await;
-        assert!(oo.find_order_with_client_order_id(62).is_none());
-    }
-
-    // but once the adjusted price is > the peg limit, it's gone
-    set_stub_oracle_price(solana, &tokens[0], collect_fee_admin, 1.004).await;
-    send_tx(
-        solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_2,
-            open_orders_admin: None,
-            market,
-            signer: owner,
-            user_token_account: owner_token_0,
-            market_vault: market_base_vault,
-            side: Side::Ask,
-            price_lots: price_lots + 3,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 100_000,
-
-            client_order_id: 63,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
-        },
-    )
-    .await
-    .unwrap();
-    send_tx(
-        solana,
-        CancelOrderByClientOrderIdInstruction {
-            open_orders_account: account_2,
-            market,
-            signer: owner,
-            client_order_id: 63,
-        },
-    )
-    .await
-    .unwrap();
     send_tx(
         solana,
         ConsumeEventsInstruction {
@@ -14668,193 +20622,89 @@ async fn test_oracle_peg() -> Result<(), TransportError> {
     )
     .await
     .unwrap();
-    assert_no_orders(solana, account_1).await;
 
-    Ok(())
-}
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+        let market = solana.get_account::<Market>(market).await;
 
-#[tokio::test]
-async fn test_take_peg_invalid_oracle() -> Result<(), TransportError> {
-    let TestInitialize {
-        context,
-        owner,
-        owner_token_0,
-        owner_token_1,
-        market,
-        market_base_vault,
-        market_quote_vault,
-        account_1,
-        account_2,
-        price_lots,
-        tokens,
-        collect_fee_admin,
-        ..
-    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
-    let solana = &context.solana.clone();
+        assert_eq!(open_orders_account_1.position.quote_free_native, 320);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 99360);
+        assert_eq!(market.referrer_rebates_accrued, 320);
+
+        assert_eq!(
+            (market.referrer_rebates_accrued
+                + open_orders_account_2.position.quote_free_native
+                + open_orders_account_1.position.quote_free_native) as i64,
+            initial_quote_amount * 10 // as it's in native quote
+        );
+    }
+
+    let admin_token_1 = solana
+        .create_associated_token_account(&collect_fee_admin.pubkey(), mints[1].pubkey)
+        .await;
 
     send_tx(
         solana,
-        PlaceOrderPeggedInstruction {
-            open_orders_account: account_1,
+        SettleFundsInstruction {
+            owner,
             market,
-            signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
-            price_offset: -1,
-            peg_limit: 100,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 100_000,
-            client_order_id: 0,
+            open_orders_account: account_2,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            referrer_account: None,
         },
     )
     .await
     .unwrap();
 
-    {
-        let oo = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        assert_eq!(oo.position.bids_base_lots, 1);
-    }
-
-    let take_order_ix = PlaceOrderInstruction {
-        open_orders_account: account_2,
-        open_orders_admin: None,
-        market,
-        signer: owner,
-        user_token_account: owner_token_0,
-        market_vault: market_base_vault,
-        side: Side::Ask,
-        price_lots,
-        max_base_lots: 1,
-        max_quote_lots_including_fees: 100_000,
-        client_order_id: 6,
-        expiry_timestamp: 0,
-        order_type: PlaceOrderType::Limit,
-        self_trade_behavior: SelfTradeBehavior::default(),
-        remainings: vec![account_1],
-    };
-
-    solana.advance_clock(200).await;
-
-    // stale oracle, order will be posted since matching with the oracle peg component of the book
-    // is not possible
-    send_tx(solana, take_order_ix.clone()).await.unwrap();
-    {
-        let oo_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let oo_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-        assert_eq!(oo_1.position.bids_base_lots, 1);
-        assert_eq!(oo_2.position.asks_base_lots, 1);
-    }
-
-    // but once the oracle is back, the match will be made
-    set_stub_oracle_price(solana, &tokens[0], collect_fee_admin, 1000.0).await;
-    send_tx(solana, take_order_ix.clone()).await.unwrap();
-    {
-        let oo = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        assert_eq!(oo.position.bids_base_lots, 0);
-    }
-
-    Ok(())
-}
-
-#[tokio::test]
-async fn test_oracle_peg_limit() -> Result<(), TransportError> {
-    let market_base_lot_size = 10000;
-    let market_quote_lot_size = 10;
-
-    let TestInitialize {
-        context,
-        owner,
-        owner_token_1,
-        market,
-        market_quote_vault,
-        account_1,
-        bids,
-        ..
-    } = TestContext::new_with_market(TestNewMarketInitialize {
-        quote_lot_size: market_quote_lot_size,
-        base_lot_size: market_base_lot_size,
-        maker_fee: -0,
-        taker_fee: 0,
-        ..TestNewMarketInitialize::default()
-    })
-    .await?;
-    let solana = &context.solana.clone();
-
-    let price_lots = {
-        let market = solana.get_account::<Market>(market).await;
-        market.native_price_to_lot(I80F48::ONE).unwrap()
-    };
-    assert_eq!(price_lots, market_base_lot_size / market_quote_lot_size);
-
-    let balance_before = solana.token_account_balance(owner_token_1).await;
-    let max_quote_lots_including_fees = 100_000;
-
-    // TEST: Place a pegged bid, can't post in book due insufficient funds
     send_tx(
         solana,
-        PlaceOrderPeggedInstruction {
-            open_orders_account: account_1,
+        SettleFundsInstruction {
+            owner,
             market,
-            signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
-            price_offset: -100,
-            peg_limit: price_lots + 100_000,
-            max_base_lots: 2,
-            max_quote_lots_including_fees,
-            client_order_id: 5,
+            open_orders_account: account_1,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            referrer_account: None,
         },
     )
     .await
     .unwrap();
-    assert_no_orders(solana, account_1).await;
 
-    // Upgrade max quantity
-    let max_quote_lots_including_fees = 101_000;
+    {
+        let balance_quote = solana.token_account_balance(market_quote_vault).await;
+        assert_eq!(balance_quote, 320);
+    }
 
     send_tx(
         solana,
-        PlaceOrderPeggedInstruction {
-            open_orders_account: account_1,
+        SweepFeesInstruction {
+            collect_fee_admin,
             market,
-            signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
-            price_offset: -100,
-            peg_limit: price_lots + 100_000,
-            max_base_lots: 2,
-            max_quote_lots_including_fees,
-            client_order_id: 5,
+            market_quote_vault,
+            token_receiver_account: admin_token_1,
         },
     )
     .await
     .unwrap();
 
-    let bids_data = solana.get_account_boxed::<BookSide>(bids).await;
-    assert_eq!(bids_data.roots[1].leaf_count, 1);
-
-    let balance_after = solana.token_account_balance(owner_token_1).await;
-
-    // Max quantity being subtracted from owner is max_quote_lots_including_fees
     {
-        assert_eq!(
-            balance_before
-                - ((max_quote_lots_including_fees as u64) * (market_quote_lot_size as u64)),
-            balance_after
-        );
+        let balance_quote = solana.token_account_balance(market_quote_vault).await;
+        assert_eq!(balance_quote, 0);
     }
+
     Ok(())
 }
 
 #[tokio::test]
-async fn test_locked_amounts() -> Result<(), TransportError> {
-    let quote_lot_size = 10;
-    let base_lot_size = 100;
-    let maker_fee = 200;
-    let taker_fee = 400;
+async fn test_locked_maker_fees() -> Result<(), TransportError> {
+    let maker_fee = 350;
+    let taker_fee = 0;
 
     let TestInitialize {
         context,
@@ -14865,46 +20715,55 @@ async fn test_locked_amounts() -> Result<(), TransportError> {
 
         market_base_vault,
         market_quote_vault,
-        account_1,
-        account_2,
+        account_1: maker,
+        account_2: taker,
         ..
     } = TestContext::new_with_market(TestNewMarketInitialize {
-        quote_lot_size,
-        base_lot_size,
         maker_fee,
         taker_fee,
         ..TestNewMarketInitialize::default()
     })
     .await?;
     let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, taker).await;
 
-    let place_bid_0_ix = PlaceOrderPeggedInstruction {
-        open_orders_account: account_1,
+    let place_maker_bid = PlaceOrderInstruction {
+        open_orders_account: maker,
+        open_orders_admin: None,
         market,
         signer: owner,
         user_token_account: owner_quote_ata,
         market_vault: market_quote_vault,
         side: Side::Bid,
-        price_offset: 0,
-        peg_limit: 30,
-        max_base_lots: 1_000,
-        max_quote_lots_including_fees: 100_000_000,
-        client_order_id: 0,
-    };
+        price_lots: 1_000,
+        max_base_lots: 5,
+        max_quote_lots_including_fees: 1_000_000_000,
+        client_order_id: 0,
+        expiry_timestamp: 0,
+        order_type: PlaceOrderType::Limit,
+        self_trade_behavior: SelfTradeBehavior::default(),
+        remainings: vec![],
+    };
 
-    let place_ask_1_ix = PlaceOrderPeggedInstruction {
+    let place_taker_ask = PlaceOrderInstruction {
         side: Side::Ask,
-        peg_limit: 10,
         market_vault: market_base_vault,
+        open_orders_account: taker,
         user_token_account: owner_base_ata,
-        open_orders_account: account_2,
-        ..place_bid_0_ix.clone()
+        max_base_lots: 3,
+        ..place_maker_bid.clone()
     };
 
-    let settle_funds_0_ix = SettleFundsInstruction {
+    let cancel_maker_orders_ix = CancelAllOrdersInstruction {
+        open_orders_account: maker,
+        signer: owner,
+        market,
+    };
+
+    let settle_maker_funds_ix = SettleFundsInstruction {
         owner,
         market,
-        open_orders_account: account_1,
+        open_orders_account: maker,
         market_base_vault,
         market_quote_vault,
         user_base_account: owner_base_ata,
@@ -14912,216 +20771,239 @@ async fn test_locked_amounts() -> Result<(), TransportError> {
         referrer_account: None,
     };
 
-    let settle_funds_1_ix = SettleFundsInstruction {
-        open_orders_account: account_2,
-        ..settle_funds_0_ix.clone()
-    };
-
-    let consume_events_ix = ConsumeEventsInstruction {
-        consume_events_admin: None,
-        market,
-        open_orders_accounts: vec![account_1, account_2],
-    };
-
-    let init_balances = (
-        solana.token_account_balance(owner_base_ata).await,
-        solana.token_account_balance(owner_quote_ata).await,
-    );
-
-    // Cancel bid order
+    send_tx(solana, place_maker_bid.clone()).await.unwrap();
     {
-        send_tx(solana, place_bid_0_ix.clone()).await.unwrap();
+        let oo = solana.get_account::<OpenOrdersAccount>(maker).await;
+        assert_eq!(oo.position.locked_maker_fees, 18);
+    }
 
-        let balances = (
-            solana.token_account_balance(owner_base_ata).await,
-            solana.token_account_balance(owner_quote_ata).await + 300_000 + 60,
-        );
+    send_tx(solana, place_taker_ask).await.unwrap();
+    send_tx(
+        solana,
+        ConsumeEventsInstruction {
+            consume_events_admin: None,
+            market,
+            open_orders_accounts: vec![maker],
+        },
+    )
+    .await
+    .unwrap();
 
-        assert_eq!(init_balances, balances);
+    {
+        let oo = solana.get_account::<OpenOrdersAccount>(maker).await;
+        assert_eq!(oo.position.locked_maker_fees, 8);
+    }
 
-        send_tx(
-            solana,
-            CancelAllOrdersInstruction {
-                open_orders_account: account_1,
-                market,
-                signer: owner,
-            },
-        )
+    send_tx(solana, cancel_maker_orders_ix.clone())
         .await
         .unwrap();
-        send_tx(solana, settle_funds_0_ix.clone()).await.unwrap();
-
-        let balances = (
-            solana.token_account_balance(owner_base_ata).await,
-            solana.token_account_balance(owner_quote_ata).await,
-        );
 
-        assert_eq!(init_balances, balances);
-    }
-
-    // Cancel ask order
+    // one lamport is still locked due rounding
     {
-        send_tx(solana, place_ask_1_ix.clone()).await.unwrap();
+        let oo = solana.get_account::<OpenOrdersAccount>(maker).await;
+        assert_eq!(oo.position.locked_maker_fees, 1);
+    }
 
-        let balances = (
-            solana.token_account_balance(owner_base_ata).await + 100_000,
-            solana.token_account_balance(owner_quote_ata).await,
-        );
+    send_tx(solana, place_maker_bid.clone()).await.unwrap();
+    send_tx(solana, settle_maker_funds_ix.clone())
+        .await
+        .unwrap();
 
-        assert_eq!(init_balances, balances);
+    // which cannot be claimed yet because there're still bids on the book
+    {
+        let oo = solana.get_account::<OpenOrdersAccount>(maker).await;
+        assert_eq!(oo.position.locked_maker_fees, 1 + 18);
+    }
 
-        send_tx(
-            solana,
-            CancelAllOrdersInstruction {
-                open_orders_account: account_2,
-                market,
-                signer: owner,
-            },
-        )
+    // but now if we don't have any pending bid order
+    send_tx(solana, cancel_maker_orders_ix.clone())
         .await
         .unwrap();
-        send_tx(solana, settle_funds_1_ix.clone()).await.unwrap();
-
-        let balances = (
-            solana.token_account_balance(owner_base_ata).await,
-            solana.token_account_balance(owner_quote_ata).await,
-        );
 
-        assert_eq!(init_balances, balances);
+    {
+        let oo = solana.get_account::<OpenOrdersAccount>(maker).await;
+        assert_eq!(oo.position.locked_maker_fees, 1);
     }
 
-    // Place & take a bid
+    // it's gone!
+    send_tx(solana, settle_maker_funds_ix.clone())
+        .await
+        .unwrap();
     {
-        send_tx(solana, place_bid_0_ix.clone()).await.unwrap();
-        send_tx(solana, place_ask_1_ix.clone()).await.unwrap();
-        send_tx(solana, consume_events_ix.clone()).await.unwrap();
-
-        let (position_0, position_1) = {
-            let oo_0 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-            let oo_1 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-            (oo_0.position, oo_1.position)
-        };
-
-        assert_eq!(position_0.quote_free_native, 200_000 + 40);
-        assert_eq!(position_0.base_free_native, 100_000);
-
-        assert_eq!(position_1.quote_free_native, 100_000 - 40);
-        assert_eq!(position_1.base_free_native, 0);
+        let oo = solana.get_account::<OpenOrdersAccount>(maker).await;
+        assert_eq!(oo.position.locked_maker_fees, 0);
     }
 
     Ok(())
 }
 
-#[tokio::test]
-async fn test_bids_quote_lots() -> Result<(), TransportError> {
-    let quote_lot_size = 10;
-    let base_lot_size = 100;
-    let maker_fee = 200;
-    let taker_fee = 400;
 
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_fill_or_kill_order.rs
+use super::*;
+use std::sync::Arc;
+
+#[tokio::test]
+async fn test_fill_or_kill_order() -> Result<(), TransportError> {
     let TestInitialize {
         context,
+        collect_fee_admin,
         owner,
-        owner_token_0: owner_base_ata,
-        owner_token_1: owner_quote_ata,
+        owner_token_0,
+        owner_token_1,
         market,
 
         market_base_vault,
         market_quote_vault,
+        price_lots,
+        tokens,
         account_1,
         account_2,
         ..
     } = TestContext::new_with_market(TestNewMarketInitialize {
-        quote_lot_size,
-        base_lot_size,
-        maker_fee,
-        taker_fee,
+        maker_fee: 0,
+        taker_fee: 0,
         ..TestNewMarketInitialize::default()
     })
     .await?;
+    let default = PlaceOrderInstruction {
+        open_orders_account: Default::default(),
+        open_orders_admin: None,
+        market,
+        signer: owner,
+        market_vault: Default::default(),
+        user_token_account: Default::default(),
+        side: Side::Bid,
+        price_lots,
+        max_base_lots: 0,
+        max_quote_lots_including_fees: 0,
+        client_order_id: 0,
+        expiry_timestamp: 0,
+        order_type: PlaceOrderType::Limit,
+        self_trade_behavior: Default::default(),
+        remainings: vec![],
+    };
     let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
+
+    // Set the initial oracle price
+    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+
+    let initial_balance_base = solana.token_account_balance(owner_token_0).await;
+    let initial_balance_quote = solana.token_account_balance(owner_token_1).await;
 
     send_tx(
         solana,
-        PlaceOrderPeggedInstruction {
+        PlaceOrderInstruction {
             open_orders_account: account_1,
-            market,
-            signer: owner,
-            user_token_account: owner_quote_ata,
+            user_token_account: owner_token_1,
             market_vault: market_quote_vault,
             side: Side::Bid,
-            price_offset: 0,
-            peg_limit: 20,
-            max_base_lots: 100,
-            max_quote_lots_including_fees: 100_000_000,
-            client_order_id: 0,
+            max_base_lots: 5,
+            max_quote_lots_including_fees: 50_000,
+            order_type: PlaceOrderType::Limit,
+            ..default.clone()
         },
     )
     .await
     .unwrap();
 
-    // first partial match with another oracle peg order
+    assert_open_orders(account_1, solana, 5, 0).await;
+    assert_open_orders(account_2, solana, 0, 0).await;
+
+    // small order -> no problem
     send_tx(
         solana,
-        PlaceOrderPeggedInstruction {
+        PlaceOrderInstruction {
             open_orders_account: account_2,
-            market,
-            signer: owner,
-            user_token_account: owner_base_ata,
+            user_token_account: owner_token_0,
             market_vault: market_base_vault,
             side: Side::Ask,
-            price_offset: 0,
-            peg_limit: 20,
-            max_base_lots: 30,
-            max_quote_lots_including_fees: 100_000_000,
-            client_order_id: 0,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10_000,
+            order_type: PlaceOrderType::FillOrKill,
+            ..default.clone()
         },
     )
     .await
     .unwrap();
 
-    // not yet unlocked!
-    let oo_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-    assert_eq!(oo_1.position.bids_quote_lots, 2_000);
-
-    send_tx(
+    consume_and_settle(
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        account_1,
+        account_2,
         solana,
-        ConsumeEventsInstruction {
-            consume_events_admin: None,
-            market,
-            open_orders_accounts: vec![account_1, account_2],
-        },
     )
-    .await
-    .unwrap();
+    .await;
 
-    let oo_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-    assert_eq!(oo_1.position.bids_quote_lots, 1_400);
+    assert_open_orders(account_1, solana, 4, 0).await;
+    assert_open_orders(account_2, solana, 0, 0).await;
 
-    // and fill the rest of the order with a normal ask
-    send_tx(
+    assert_balance(owner_token_0, solana, initial_balance_base).await;
+    assert_balance(owner_token_1, solana, initial_balance_quote - 400_000).await;
+
+    // big order -> should fail
+    let result = send_tx(
         solana,
         PlaceOrderInstruction {
             open_orders_account: account_2,
-            open_orders_admin: None,
-            market,
-            signer: owner,
-            user_token_account: owner_base_ata,
+            user_token_account: owner_token_0,
             market_vault: market_base_vault,
             side: Side::Ask,
-            price_lots: 1,
-            max_base_lots: 70,
-            max_quote_lots_including_fees: 100_000_000,
-            client_order_id: 0,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
+            max_base_lots: 6,
+            max_quote_lots_including_fees: 60_000,
+            order_type: PlaceOrderType::FillOrKill,
+            ..default.clone()
         },
     )
-    .await
-    .unwrap();
+    .await;
+
+    assert_openbook_error(
+        &result,
+        OpenBookError::WouldExecutePartially.error_code(),
+        "Should kill order".into(),
+    );
+    Ok(())
+}
+
+async fn assert_open_orders(
+    account: Pubkey,
+    solana: &Arc<SolanaCookie>,
+    bids_base_lots: i64,
+    asks_base_lots: i64,
+) {
+    let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account).await;
+    assert_eq!(
+        open_orders_account_1.position.bids_base_lots,
+        bids_base_lots
+    );
+    assert_eq!(
+        open_orders_account_1.position.asks_base_lots,
+        asks_base_lots
+    );
+}
+
+async fn assert_balance(token: Pubkey, solana: &Arc<SolanaCookie>, expected_balance: u64) {
+    let balance_base = solana.token_account_balance(token).await;
+    assert_eq!(balance_base, expected_balance);
+}
 
+#[allow(clippy::too_many_arguments)]
+async fn consume_and_settle(
+    owner: TestKeypair,
+    owner_token_0: Pubkey,
+    owner_token_1: Pubkey,
+    market: Pubkey,
+    market_base_vault: Pubkey,
+    market_quote_vault: Pubkey,
+    account_1: Pubkey,
+    account_2: Pubkey,
+    solana: &Arc<SolanaCookie>,
+) {
     send_tx(
         solana,
         ConsumeEventsInstruction {
@@ -15133,175 +21015,250 @@ async fn test_bids_quote_lots() -> Result<(), TransportError> {
     .await
     .unwrap();
 
-    let oo_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-    assert_eq!(oo_1.position.bids_quote_lots, 0);
-
-    Ok(())
-}
-
-async fn assert_no_orders(solana: &SolanaCookie, account_1: Pubkey) {
-    let open_orders_account = solana.get_account::<OpenOrdersAccount>(account_1).await;
+    send_tx(
+        solana,
+        SettleFundsInstruction {
+            owner,
+            market,
+            open_orders_account: account_1,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            referrer_account: None,
+        },
+    )
+    .await
+    .unwrap();
 
-    for oo in open_orders_account.open_orders.iter() {
-        assert!(oo.id == 0);
-        assert!(oo.side_and_tree() == SideAndOrderTree::BidFixed);
-        assert!(oo.client_id == 0);
-    }
+    send_tx(
+        solana,
+        SettleFundsInstruction {
+            owner,
+            market,
+            open_orders_account: account_2,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            referrer_account: None,
+        },
+    )
+    .await
+    .unwrap();
 }
 
 
-// File: openbook-v2/programs/openbook-v2/tests/cases/test_order_types.rs
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_indexer.rs
 use super::*;
 
 #[tokio::test]
-async fn test_immediate_order() -> Result<(), TransportError> {
-    let TestInitialize {
-        context,
-        collect_fee_admin,
-        owner,
-        owner_token_0,
-        owner_token_1,
-        market,
-
-        market_base_vault,
-        market_quote_vault,
-        price_lots,
-        tokens,
-        account_1,
-        account_2,
-        ..
-    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+async fn test_indexer() -> Result<(), TransportError> {
+    let context = TestContext::new().await;
     let solana = &context.solana.clone();
 
-    // Set the initial oracle price
-    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+    let collect_fee_admin = TestKeypair::new();
+    let close_market_admin = TestKeypair::new();
+    let owner = context.users[0].key;
+    let payer = context.users[1].key;
+    let mints = &context.mints[0..=2];
 
-    send_tx(
+    let tokens = Token::create(mints.to_vec(), solana, collect_fee_admin, payer).await;
+
+    let market = TestKeypair::new();
+
+    let openbook_v2::accounts::CreateMarket { market, .. } = send_tx(
         solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_1,
+        CreateMarketInstruction {
+            collect_fee_admin: collect_fee_admin.pubkey(),
             open_orders_admin: None,
+            close_market_admin: Some(close_market_admin.pubkey()),
+            payer,
             market,
-            signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-
-            client_order_id: 0,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
+            quote_lot_size: 10,
+            base_lot_size: 100,
+            maker_fee: -200,
+            taker_fee: 400,
+            base_mint: mints[0].pubkey,
+            quote_mint: mints[1].pubkey,
+            ..CreateMarketInstruction::with_new_book_and_heap(solana, Some(tokens[1].oracle), None)
+                .await
         },
     )
     .await
     .unwrap();
 
-    let balance_base = solana.token_account_balance(owner_token_0).await;
-    let balance_quote = solana.token_account_balance(owner_token_1).await;
+    let indexer = create_open_orders_indexer(solana, &context.users[1], owner, market).await;
+    let maker_1 =
+        create_open_orders_account(solana, owner, market, 1, &context.users[1], None).await;
 
-    send_tx(
-        solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_2,
-            open_orders_admin: None,
-            market,
-            signer: owner,
-            user_token_account: owner_token_0,
-            market_vault: market_base_vault,
-            side: Side::Ask,
-            price_lots,
-            max_base_lots: 2,
-            max_quote_lots_including_fees: 20000,
+    {
+        let indexer = solana.get_account::<OpenOrdersIndexer>(indexer).await;
+        assert_eq!(indexer.created_counter, 1);
+        assert!(indexer.addresses.contains(&maker_1));
+    }
 
-            client_order_id: 0,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::ImmediateOrCancel,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
-        },
-    )
-    .await
-    .unwrap();
+    let (maker_2, maker_3) = {
+        (
+            create_open_orders_account(solana, owner, market, 2, &context.users[1], None).await,
+            create_open_orders_account(solana, owner, market, 3, &context.users[1], None).await,
+        )
+    };
 
     {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+        let indexer = solana.get_account::<OpenOrdersIndexer>(indexer).await;
 
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 0);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 99960);
-        assert_eq!(
-            balance_base - 100,
-            solana.token_account_balance(owner_token_0).await
-        );
-        assert_eq!(
-            balance_quote,
-            solana.token_account_balance(owner_token_1).await
-        );
+        assert_eq!(indexer.created_counter, 3);
+        assert_eq!(indexer.addresses.len(), 3);
+        assert!(indexer.addresses.contains(&maker_1));
+        assert!(indexer.addresses.contains(&maker_2));
+        assert!(indexer.addresses.contains(&maker_3));
     }
 
     send_tx(
         solana,
-        ConsumeEventsInstruction {
-            consume_events_admin: None,
+        CloseOpenOrdersAccountInstruction {
+            account_num: 2,
             market,
-            open_orders_accounts: vec![account_1, account_2],
+            owner,
+            sol_destination: owner.pubkey(),
         },
     )
     .await
     .unwrap();
 
     {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+        let indexer = solana.get_account::<OpenOrdersIndexer>(indexer).await;
 
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 100);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 20);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 99960);
-    }
+        assert_eq!(indexer.created_counter, 3);
+        assert_eq!(indexer.addresses.len(), 2);
+        assert!(indexer.addresses.contains(&maker_1));
+        assert!(indexer.addresses.contains(&maker_3));
+    }
 
-    send_tx(
+    let maker_4 =
+        create_open_orders_account(solana, owner, market, 4, &context.users[1], None).await;
+
+    {
+        let indexer = solana.get_account::<OpenOrdersIndexer>(indexer).await;
+        assert_eq!(indexer.created_counter, 4);
+        assert_eq!(indexer.addresses.len(), 3);
+        assert!(indexer.addresses.contains(&maker_1));
+        assert!(indexer.addresses.contains(&maker_3));
+        assert!(indexer.addresses.contains(&maker_4));
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_size_vector() -> Result<(), TransportError> {
+    let context = TestContext::new().await;
+    let solana = &context.solana.clone();
+
+    let collect_fee_admin = TestKeypair::new();
+    let close_market_admin = TestKeypair::new();
+    let owner = context.users[0].key;
+    let payer = context.users[1].key;
+    let mints = &context.mints[0..=2];
+
+    let tokens = Token::create(mints.to_vec(), solana, collect_fee_admin, payer).await;
+
+    let market = TestKeypair::new();
+
+    let openbook_v2::accounts::CreateMarket { market, .. } = send_tx(
         solana,
-        SettleFundsInstruction {
-            owner,
+        CreateMarketInstruction {
+            collect_fee_admin: collect_fee_admin.pubkey(),
+            open_orders_admin: None,
+            close_market_admin: Some(close_market_admin.pubkey()),
+            payer,
             market,
-            open_orders_account: account_1,
-            market_base_vault,
-            market_quote_vault,
-            user_base_account: owner_token_0,
-            user_quote_account: owner_token_1,
-            referrer_account: None,
+            quote_lot_size: 10,
+            base_lot_size: 100,
+            maker_fee: -200,
+            taker_fee: 400,
+            base_mint: mints[0].pubkey,
+            quote_mint: mints[1].pubkey,
+            ..CreateMarketInstruction::with_new_book_and_heap(solana, Some(tokens[1].oracle), None)
+                .await
         },
     )
     .await
     .unwrap();
 
+    let indexer = create_open_orders_indexer(solana, &context.users[1], owner, market).await;
+
+    let mut makers = vec![];
+    let max = 256;
+    for n in 0..max {
+        makers.push(
+            create_open_orders_account(solana, owner, market, n + 1, &context.users[1], None).await,
+        )
+    }
+
     {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let indexer = solana.get_account::<OpenOrdersIndexer>(indexer).await;
 
-        assert_eq!(open_orders_account_1.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+        assert_eq!(indexer.created_counter, max);
+        assert_eq!(indexer.addresses.len(), max as usize);
+        assert!(indexer.addresses.contains(&makers[(max - 1) as usize]));
+        assert!(indexer.addresses.contains(&makers[(max / 2) as usize]));
+        assert!(indexer.addresses.contains(&makers[1]));
     }
 
+    // Can't create more than 256
+    assert!(send_tx(
+        solana,
+        CreateOpenOrdersAccountInstruction {
+            account_num: 257,
+            market,
+            owner,
+            payer: context.users[1].key,
+            delegate: None,
+        },
+    )
+    .await
+    .is_err());
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_ioc.rs
+use super::*;
+
+#[tokio::test]
+async fn test_ioc() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        collect_fee_admin,
+        owner,
+        payer,
+        mints,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        tokens,
+        account_1,
+        account_2,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        maker_fee: 200,
+        taker_fee: 200,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
+    let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
+
     send_tx(
         solana,
         PlaceOrderInstruction {
             open_orders_account: account_1,
-            open_orders_admin: None,
             market,
             signer: owner,
             user_token_account: owner_token_1,
@@ -15310,10 +21267,9 @@ async fn test_immediate_order() -> Result<(), TransportError> {
             price_lots,
             max_base_lots: 1,
             max_quote_lots_including_fees: 10000,
-
+            reduce_only: false,
             client_order_id: 0,
             expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
             remainings: vec![],
         },
@@ -15321,15 +21277,10 @@ async fn test_immediate_order() -> Result<(), TransportError> {
     .await
     .unwrap();
 
-    let balance_base = solana.token_account_balance(owner_token_0).await;
-    let balance_quote = solana.token_account_balance(owner_token_1).await;
-
-    // There is a bid in the book, post only doesn't do anything since there is a match
     send_tx(
         solana,
         PlaceOrderInstruction {
             open_orders_account: account_2,
-            open_orders_admin: None,
             market,
             signer: owner,
             user_token_account: owner_token_0,
@@ -15338,10 +21289,9 @@ async fn test_immediate_order() -> Result<(), TransportError> {
             price_lots,
             max_base_lots: 1,
             max_quote_lots_including_fees: 10000,
-
+            reduce_only: false,
             client_order_id: 0,
             expiry_timestamp: 0,
-            order_type: PlaceOrderType::PostOnly,
             self_trade_behavior: SelfTradeBehavior::default(),
             remainings: vec![],
         },
@@ -15353,42 +21303,126 @@ async fn test_immediate_order() -> Result<(), TransportError> {
         let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
         let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
 
+        assert_eq!(open_orders_account_1.position.base_position_lots(), 0);
+        assert_eq!(open_orders_account_2.position.base_position_lots(), 0);
+        assert_eq!(open_orders_account_1.position.quote_position_native(), 0);
+        // assert_eq!(open_orders_account_2.position.quote_position_native(), 0);
         assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
         assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
         assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
         assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.taker_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.taker_quote_lots, 10000);
         assert_eq!(open_orders_account_1.position.base_free_native, 0);
         assert_eq!(open_orders_account_2.position.base_free_native, 0);
         assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-        assert_eq!(
-            balance_base,
-            solana.token_account_balance(owner_token_0).await
-        );
-        assert_eq!(
-            balance_quote,
-            solana.token_account_balance(owner_token_1).await
-        );
+        assert_eq!(open_orders_account_2.position.quote_free_native, 99960);
     }
 
-    // Change the price, so no matching and order posts
-    let price_lots_2 = price_lots - 100;
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_min_order_size.rs
+use super::*;
+use openbook_v2::logs::OrderCancelledLog;
+
+#[tokio::test]
+async fn test_min_order_size() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        account_2,
+        bids,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        min_base_lots: 2,
+        // three base lots at the default price of 10_000 price lots
+        min_quote_notional: 30_000,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
+    let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
+
+    let asks = solana.get_account::<Market>(market).await.asks;
+
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            PlaceOrderInstruction {
+                open_orders_account: account_1,
+                open_orders_admin: None,
+                market,
+                signer: owner,
+                user_token_account: owner_token_0,
+                market_vault: market_base_vault,
+                side: Side::Ask,
+                price_lots,
+                max_base_lots: 1,
+                max_quote_lots_including_fees: 100_000,
+                client_order_id: 0,
+                expiry_timestamp: 0,
+                order_type: PlaceOrderType::Limit,
+                self_trade_behavior: SelfTradeBehavior::default(),
+                remainings: vec![],
+            },
+        )
+        .await,
+        OpenBookError::OrderBelowMinimumSize.error_code(),
+        "below min_base_lots".into(),
+    );
+
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            PlaceOrderInstruction {
+                open_orders_account: account_1,
+                open_orders_admin: None,
+                market,
+                signer: owner,
+                user_token_account: owner_token_1,
+                market_vault: market_quote_vault,
+                side: Side::Bid,
+                price_lots: price_lots / 2,
+                max_base_lots: 2,
+                max_quote_lots_including_fees: 100_000,
+                client_order_id: 0,
+                expiry_timestamp: 0,
+                order_type: PlaceOrderType::Limit,
+                self_trade_behavior: SelfTradeBehavior::default(),
+                remainings: vec![],
+            },
+        )
+        .await,
+        OpenBookError::OrderBelowMinimumSize.error_code(),
+        "below min_quote_notional".into(),
+    );
+
+    // a partial fill leaving enough on the book keeps the maker order
     send_tx(
         solana,
         PlaceOrderInstruction {
-            open_orders_account: account_2,
+            open_orders_account: account_1,
             open_orders_admin: None,
             market,
             signer: owner,
             user_token_account: owner_token_0,
             market_vault: market_base_vault,
             side: Side::Ask,
-            price_lots: price_lots_2,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-
+            price_lots,
+            max_base_lots: 4,
+            max_quote_lots_including_fees: 100_000,
             client_order_id: 0,
             expiry_timestamp: 0,
-            order_type: PlaceOrderType::PostOnly,
+            order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
             remainings: vec![],
         },
@@ -15396,28 +21430,6 @@ async fn test_immediate_order() -> Result<(), TransportError> {
     .await
     .unwrap();
 
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 0);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-        assert_eq!(
-            balance_base,
-            solana.token_account_balance(owner_token_0).await
-        );
-        assert_eq!(
-            balance_quote,
-            solana.token_account_balance(owner_token_1).await
-        );
-    }
-
-    // PostOnlySlide always post on book
     send_tx(
         solana,
         PlaceOrderInstruction {
@@ -15425,84 +21437,28 @@ async fn test_immediate_order() -> Result<(), TransportError> {
             open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_token_0,
-            market_vault: market_base_vault,
-            side: Side::Ask,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
             price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 10040,
-
+            max_quote_lots_including_fees: 100_000,
             client_order_id: 0,
             expiry_timestamp: 0,
-            order_type: PlaceOrderType::PostOnlySlide,
+            order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
             remainings: vec![],
         },
     )
     .await
     .unwrap();
+    assert_eq!(solana.get_book_side(asks).await.roots[0].leaf_count, 1);
 
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 1);
-        assert_eq!(open_orders_account_1.position.base_free_native, 0);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-        assert_eq!(
-            balance_base - 100,
-            solana.token_account_balance(owner_token_0).await
-        );
-        assert_eq!(
-            balance_quote,
-            solana.token_account_balance(owner_token_1).await
-        );
-    }
-
-    Ok(())
-}
-
-
-// File: openbook-v2/programs/openbook-v2/tests/cases/test_permissioned.rs
-use super::*;
-
-#[tokio::test]
-async fn test_permissioned_open_order() -> Result<(), TransportError> {
-    let TestInitialize {
-        context,
-        collect_fee_admin,
-        owner,
-        owner_token_1,
-        market,
-        market_quote_vault,
-        tokens,
-        account_1,
-        open_orders_admin,
-        ..
-    } = TestContext::new_with_market(TestNewMarketInitialize {
-        open_orders_admin_bool: true,
-        ..TestNewMarketInitialize::default()
-    })
-    .await?;
-    let solana = &context.solana.clone();
-
-    let price_lots = {
-        let market = solana.get_account::<Market>(market).await;
-        market.native_price_to_lot(I80F48::from(1000)).unwrap()
-    };
-
-    // Set the initial oracle price
-    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
-
-    // First, send in an order w/o the signature of the open order authority, expect failure
-    let result = send_tx(
+    // the remaining lot would be dust, so it's cancelled
+    send_tx(
         solana,
         PlaceOrderInstruction {
-            open_orders_account: account_1,
+            open_orders_account: account_2,
             open_orders_admin: None,
             market,
             signer: owner,
@@ -15510,8 +21466,8 @@ async fn test_permissioned_open_order() -> Result<(), TransportError> {
             market_vault: market_quote_vault,
             side: Side::Bid,
             price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
+            max_base_lots: 2,
+            max_quote_lots_including_fees: 100_000,
             client_order_id: 0,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
@@ -15519,75 +21475,45 @@ async fn test_permissioned_open_order() -> Result<(), TransportError> {
             remainings: vec![],
         },
     )
-    .await;
-
-    assert!(result.is_err());
+    .await
+    .unwrap();
+    let cancelled = solana.program_log_events::<OrderCancelledLog>();
+    assert_eq!(cancelled.len(), 1);
+    assert_eq!(cancelled[0].owner, account_1);
+    assert_eq!(cancelled[0].quantity, 1);
+    assert_eq!(solana.get_book_side(asks).await.roots[0].leaf_count, 0);
 
-    // Second, send in an order w/ the signature of the open order authority, expect success
     send_tx(
         solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_1,
-            open_orders_admin: Some(open_orders_admin),
+        ConsumeEventsInstruction {
+            consume_events_admin: None,
             market,
-            signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-            client_order_id: 0,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
+            open_orders_accounts: vec![account_1, account_2],
         },
     )
     .await
     .unwrap();
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 100);
+        assert!(open_orders_account_1.open_orders.iter().all(|oo| oo.is_free()));
+    }
 
-    Ok(())
-}
-
-#[tokio::test]
-async fn test_permissioned_open_take_order() -> Result<(), TransportError> {
-    let TestInitialize {
-        context,
-        collect_fee_admin,
-        open_orders_admin,
-        owner,
-        owner_token_1,
-        market,
-        market_quote_vault,
-        price_lots,
-        tokens,
-        account_1,
-        ..
-    } = TestContext::new_with_market(TestNewMarketInitialize {
-        open_orders_admin_bool: true,
-        ..TestNewMarketInitialize::default()
-    })
-    .await?;
-    let solana = &context.solana.clone();
-
-    // Set the initial oracle price
-    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
-
-    let result = send_tx(
+    // a taker remainder below the minimum isn't posted, without failing the fills
+    send_tx(
         solana,
         PlaceOrderInstruction {
             open_orders_account: account_1,
             open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
             price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-
+            max_base_lots: 3,
+            max_quote_lots_including_fees: 100_000,
             client_order_id: 0,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
@@ -15595,24 +21521,22 @@ async fn test_permissioned_open_take_order() -> Result<(), TransportError> {
             remainings: vec![],
         },
     )
-    .await;
-
-    assert!(result.is_err());
+    .await
+    .unwrap();
 
     send_tx(
         solana,
         PlaceOrderInstruction {
-            open_orders_account: account_1,
-            open_orders_admin: Some(open_orders_admin),
+            open_orders_account: account_2,
+            open_orders_admin: None,
             market,
             signer: owner,
             user_token_account: owner_token_1,
             market_vault: market_quote_vault,
             side: Side::Bid,
             price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-
+            max_base_lots: 4,
+            max_quote_lots_including_fees: 100_000,
             client_order_id: 0,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
@@ -15622,61 +21546,37 @@ async fn test_permissioned_open_take_order() -> Result<(), TransportError> {
     )
     .await
     .unwrap();
+    assert_eq!(solana.get_book_side(asks).await.roots[0].leaf_count, 0);
+    assert_eq!(solana.get_book_side(bids).await.roots[0].leaf_count, 0);
 
     Ok(())
 }
 
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_multiple_orders.rs
+use super::*;
+
 #[tokio::test]
-async fn test_consume_events_admin() -> Result<(), TransportError> {
+async fn insufficient_funds() -> Result<(), TransportError> {
     let TestInitialize {
         context,
-        collect_fee_admin,
-        consume_events_admin,
         owner,
         owner_token_0,
         owner_token_1,
+        account_1,
+        account_2,
         market,
         market_base_vault,
         market_quote_vault,
-        price_lots,
-        tokens,
-        account_1,
-        account_2,
         ..
-    } = TestContext::new_with_market(TestNewMarketInitialize {
-        consume_events_admin_bool: true,
-        ..TestNewMarketInitialize::default()
-    })
-    .await?;
-    let solana = &context.solana.clone();
-
-    // Set the initial oracle price
-    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
 
-    send_tx(
-        solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_1,
-            open_orders_admin: None,
-            market,
-            signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
+    let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
 
-            client_order_id: 0,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
-        },
-    )
-    .await
-    .unwrap();
+    let max_quote_lots_including_fees = 104;
 
+    // there's an ask on the book
     send_tx(
         solana,
         PlaceOrderInstruction {
@@ -15687,10 +21587,9 @@ async fn test_consume_events_admin() -> Result<(), TransportError> {
             user_token_account: owner_token_0,
             market_vault: market_base_vault,
             side: Side::Ask,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-
+            price_lots: 1,
+            max_base_lots: i64::MAX / 1_000,
+            max_quote_lots_including_fees,
             client_order_id: 0,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
@@ -15701,98 +21600,131 @@ async fn test_consume_events_admin() -> Result<(), TransportError> {
     .await
     .unwrap();
 
-    let result = send_tx(
+    solana.set_account_balance(owner_token_0, 2_500).await;
+    solana.set_account_balance(owner_token_1, 110).await;
+
+    // some lamports are already deposited
+    send_tx(
         solana,
-        ConsumeEventsInstruction {
-            consume_events_admin: None,
+        DepositInstruction {
+            owner,
             market,
-            open_orders_accounts: vec![account_1, account_2],
+            open_orders_account: account_1,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            base_amount: 1_200,
+            quote_amount: 0,
         },
     )
-    .await;
+    .await
+    .unwrap();
 
-    assert!(result.is_err());
+    // note that a priori, we only have enough lamports to place 2.5 Ask. But as the bid will be
+    // filled & the taker executed immediately, we will have 10 extra base lots available
+    let order = openbook_v2::PlaceMultipleOrdersArgs {
+        price_lots: 1,
+        max_quote_lots_including_fees,
+        expiry_timestamp: 0,
+    };
+
+    let bids = vec![order];
+    let asks = vec![order; 4];
 
     send_tx(
         solana,
-        ConsumeEventsInstruction {
-            consume_events_admin: Some(consume_events_admin),
+        CancelAllAndPlaceOrdersInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
             market,
-            open_orders_accounts: vec![account_1, account_2],
+            signer: owner,
+            orders_type: PlaceOrderType::Limit,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            bids,
+            asks,
         },
     )
     .await
     .unwrap();
 
-    Ok(())
-}
+    let position = solana
+        .get_account::<OpenOrdersAccount>(account_1)
+        .await
+        .position;
 
-#[tokio::test]
-async fn test_close_market_admin() -> Result<(), TransportError> {
-    let TestInitialize {
-        context,
-        close_market_admin,
-        collect_fee_admin,
-        owner,
-        mints,
-        owner_token_0,
-        owner_token_1,
+    assert_eq!(position.asks_base_lots, 35);
+    assert_eq!(position.bids_base_lots, 0);
+
+    assert_eq!(position.base_free_native, 0);
+    assert_eq!(position.quote_free_native, 0);
+
+    assert_eq!(position.referrer_rebates_available, 1);
+    assert_eq!(solana.token_account_balance(owner_token_1).await, 9);
+    assert_eq!(solana.token_account_balance(owner_token_0).await, 0);
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_native_sol.rs
+use super::*;
+use anchor_spl::token::spl_token;
+
+#[tokio::test]
+async fn test_native_sol() -> Result<(), TransportError> {
+    let context = TestContext::new().await;
+    let solana = &context.solana.clone();
+
+    let owner = context.users[0].key;
+    let payer = context.users[1].key;
+    let owner_token_1 = context.users[0].token_accounts[1];
+
+    let openbook_v2::accounts::CreateMarket {
         market,
         market_base_vault,
         market_quote_vault,
-        price_lots,
-        account_1,
-        account_2,
-        payer,
         ..
-    } = TestContext::new_with_market(TestNewMarketInitialize {
-        close_market_admin_bool: true,
-        ..TestNewMarketInitialize::default()
-    })
-    .await?;
-    let solana = &context.solana.clone();
-
-    let fee_admin_ata = solana
-        .create_associated_token_account(&collect_fee_admin.pubkey(), mints[1].pubkey)
-        .await;
-
-    send_tx(
+    } = send_tx(
         solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_1,
+        CreateMarketInstruction {
+            collect_fee_admin: TestKeypair::new().pubkey(),
             open_orders_admin: None,
-            market,
-            signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-            client_order_id: 0,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
+            close_market_admin: None,
+            payer,
+            market: TestKeypair::new(),
+            quote_lot_size: 10,
+            base_lot_size: 100,
+            maker_fee: -200,
+            taker_fee: 400,
+            base_mint: spl_token::native_mint::ID,
+            quote_mint: context.mints[1].pubkey,
+            ..CreateMarketInstruction::with_new_book_and_heap(solana, None, None).await
         },
     )
     .await
     .unwrap();
+    let _indexer = create_open_orders_indexer(solana, &context.users[1], owner, market).await;
+    let account =
+        create_open_orders_account(solana, owner, market, 1, &context.users[1], None).await;
 
-    // Place an order that matches
+    let lamports = solana.get_lamports(owner.pubkey()).await;
+
+    // Passing the owner's wallet as the base token account moves lamports
     send_tx(
         solana,
         PlaceOrderInstruction {
-            open_orders_account: account_2,
+            open_orders_account: account,
             open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_token_0,
+            user_token_account: owner.pubkey(),
             market_vault: market_base_vault,
             side: Side::Ask,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
+            price_lots: 10_000,
+            max_base_lots: 10,
+            max_quote_lots_including_fees: 1_000_000,
             client_order_id: 0,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
@@ -15803,138 +21735,81 @@ async fn test_close_market_admin() -> Result<(), TransportError> {
     .await
     .unwrap();
 
-    // Place another order
     send_tx(
         solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_1,
-            open_orders_admin: None,
+        DepositInstruction {
+            owner,
             market,
-            signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-            client_order_id: 0,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
+            open_orders_account: account,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner.pubkey(),
+            user_quote_account: owner_token_1,
+            base_amount: 500,
+            quote_amount: 0,
         },
     )
     .await
     .unwrap();
 
-    let close_ix = CloseMarketInstruction {
-        close_market_admin,
-        market,
-        sol_destination: owner.pubkey(),
-    };
-
-    let settle_funds_expired_ix = SettleFundsExpiredInstruction {
-        close_market_admin,
-        market,
-        owner: payer,
-        open_orders_account: account_2,
-        market_base_vault,
-        market_quote_vault,
-        user_base_account: owner_token_0,
-        user_quote_account: owner_token_1,
-        referrer_account: None,
-    };
-
-    // Can't close yet, market not market as expired
-    assert!(send_tx(solana, close_ix.clone()).await.is_err());
+    assert_eq!(solana.get_lamports(owner.pubkey()).await, lamports - 1_500);
+    assert_eq!(solana.token_account_balance(market_base_vault).await, 1_500);
 
-    // also not possible to settle in behalf of the users
-    assert!(send_tx(solana, settle_funds_expired_ix.clone())
-        .await
-        .is_err());
+    // Only the wrapped SOL side can be left out
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            DepositInstruction {
+                owner,
+                market,
+                open_orders_account: account,
+                market_base_vault,
+                market_quote_vault,
+                user_base_account: owner.pubkey(),
+                user_quote_account: owner.pubkey(),
+                base_amount: 0,
+                quote_amount: 100,
+            },
+        )
+        .await,
+        OpenBookError::InvalidNativeTransfer.error_code(),
+        "quote mint is not wrapped SOL".into(),
+    );
 
     send_tx(
         solana,
-        SetMarketExpiredInstruction {
-            close_market_admin,
-            market,
-        },
-    )
-    .await
-    .unwrap();
-
-    {
-        let market = solana.get_account::<Market>(market).await;
-        assert_eq!(market.time_expiry, -1);
-    }
-
-    // Can't post orders anymore
-    let result = send_tx(
-        solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_1,
-            open_orders_admin: None,
+        CancelAllOrdersInstruction {
+            open_orders_account: account,
             market,
             signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-            client_order_id: 0,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
-        },
-    )
-    .await;
-    assert!(result.is_err());
-
-    // Consume events
-    send_tx(
-        solana,
-        ConsumeEventsInstruction {
-            consume_events_admin: None,
-            market,
-            open_orders_accounts: vec![account_1, account_2],
-        },
-    )
-    .await
-    .unwrap();
-
-    // Can't close, have to prune orders first
-    assert!(send_tx(solana, close_ix.clone()).await.is_err());
-
-    send_tx(
-        solana,
-        PruneOrdersInstruction {
-            close_market_admin,
-            market,
-            open_orders_account: account_1,
         },
     )
     .await
     .unwrap();
 
-    // and wait until users settle funds
-    {
-        let market = solana.get_account::<Market>(market).await;
-        assert!(market.base_deposit_total != 0);
-        assert!(market.quote_deposit_total != 0);
-    }
-    assert!(send_tx(solana, close_ix.clone()).await.is_err());
+    // Lamports sent to the native settle address don't block settlement, they go to
+    // the owner paying the temporary account's rent
+    solana
+        .process_transaction(
+            &[solana_sdk::system_instruction::transfer(
+                &payer.pubkey(),
+                &get_native_settle_address(market),
+                1,
+            )],
+            Some(&[payer]),
+        )
+        .await
+        .unwrap();
 
     send_tx(
         solana,
         SettleFundsInstruction {
             owner,
+            open_orders_account: account,
             market,
-            open_orders_account: account_1,
             market_base_vault,
             market_quote_vault,
-            user_base_account: owner_token_0,
+            user_base_account: owner.pubkey(),
             user_quote_account: owner_token_1,
             referrer_account: None,
         },
@@ -15942,289 +21817,224 @@ async fn test_close_market_admin() -> Result<(), TransportError> {
     .await
     .unwrap();
 
-    // which can be even be called by the close_market_admin once the market is expired so it
-    // doesn't have to wait for the users!
-    send_tx(solana, settle_funds_expired_ix).await.unwrap();
-
-    // but wait! the're still pending fees
-    {
-        let market = solana.get_account::<Market>(market).await;
-        assert!(market.fees_available != 0);
-    }
-    assert!(send_tx(solana, close_ix.clone()).await.is_err());
-
-    send_tx(
-        solana,
-        SweepFeesInstruction {
-            collect_fee_admin,
-            market,
-            market_quote_vault,
-            token_receiver_account: fee_admin_ata,
-        },
-    )
-    .await
-    .unwrap();
-
-    // Boom
-    send_tx(solana, close_ix.clone()).await.unwrap();
+    assert_eq!(solana.get_lamports(owner.pubkey()).await, lamports + 1);
+    assert_eq!(solana.token_account_balance(market_base_vault).await, 0);
+    assert_eq!(
+        solana
+            .get_account::<OpenOrdersAccount>(account)
+            .await
+            .position
+            .base_free_native,
+        0
+    );
 
     Ok(())
 }
 
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_oracle_peg.rs
+use super::*;
+
 #[tokio::test]
-async fn test_delegate() -> Result<(), TransportError> {
+async fn test_oracle_peg_enabled() -> Result<(), TransportError> {
     let TestInitialize {
         context,
-        collect_fee_admin,
         owner,
+        owner_token_1,
         market,
         market_quote_vault,
-        price_lots,
-        tokens,
+        account_1,
         ..
     } = TestContext::new_with_market(TestNewMarketInitialize {
+        with_oracle: false,
         ..TestNewMarketInitialize::default()
     })
     .await?;
     let solana = &context.solana.clone();
 
-    let account_3_delegate = context.users[2].key;
-    let account_3 = create_open_orders_account(
+    assert!(send_tx(
         solana,
-        owner,
-        market,
-        3,
-        &context.users[0],
-        Some(account_3_delegate.pubkey()),
-    )
-    .await;
-    let delegate_token_1 = context.users[2].token_accounts[1];
-
-    // Set the initial oracle price
-    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
-
-    send_tx(
-        solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_3,
-            open_orders_admin: None,
+        PlaceOrderPeggedInstruction {
+            open_orders_account: account_1,
             market,
-            signer: account_3_delegate,
-            user_token_account: delegate_token_1,
+            signer: owner,
+            user_token_account: owner_token_1,
             market_vault: market_quote_vault,
             side: Side::Bid,
-            price_lots,
+            price_offset: -1,
+            peg_limit: 100,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-
-            client_order_id: 23,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
+            max_quote_lots_including_fees: 100_000,
+            client_order_id: 0,
         },
     )
     .await
-    .unwrap();
+    .is_err());
 
-    send_tx(
-        solana,
-        CancelOrderByClientOrderIdInstruction {
-            signer: account_3_delegate,
-            market,
-            open_orders_account: account_3,
-            client_order_id: 23,
-        },
-    )
-    .await
-    .unwrap();
+    Ok(())
+}
 
-    send_tx(
-        solana,
-        SetDelegateInstruction {
-            owner,
-            open_orders_account: account_3,
-            delegate_account: None,
-        },
-    )
-    .await
-    .unwrap();
+#[tokio::test]
+async fn test_oracle_peg_invalid_oracle() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_1,
+        market,
+        market_quote_vault,
+        account_1,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
 
-    // No delegate anymore
-    let result = send_tx(
+    solana.advance_clock(200).await;
+
+    assert!(send_tx(
         solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_3,
-            open_orders_admin: None,
+        PlaceOrderPeggedInstruction {
+            open_orders_account: account_1,
             market,
-            signer: account_3_delegate,
-            user_token_account: delegate_token_1,
+            signer: owner,
+            user_token_account: owner_token_1,
             market_vault: market_quote_vault,
             side: Side::Bid,
-            price_lots,
+            price_offset: -1,
+            peg_limit: 100,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-
-            client_order_id: 23,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
+            max_quote_lots_including_fees: 100_000,
+            client_order_id: 0,
         },
     )
-    .await;
-    assert!(result.is_err());
+    .await
+    .is_err());
 
     Ok(())
 }
 
-
-// File: openbook-v2/programs/openbook-v2/tests/cases/test_place_order_remaining.rs
-use super::*;
-
 #[tokio::test]
-async fn test_place_cancel_order_remaining() -> Result<(), TransportError> {
+async fn test_oracle_peg() -> Result<(), TransportError> {
+    let market_base_lot_size = 10000;
+    let market_quote_lot_size = 10;
+
     let TestInitialize {
         context,
-        collect_fee_admin,
         owner,
         owner_token_0,
         owner_token_1,
         market,
         market_base_vault,
         market_quote_vault,
-        price_lots,
-        tokens,
         account_1,
         account_2,
+        tokens,
+        collect_fee_admin,
         bids,
         ..
-    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        quote_lot_size: market_quote_lot_size,
+        base_lot_size: market_base_lot_size,
+        maker_fee: -0,
+        taker_fee: 0,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
     let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
 
-    // Set the initial oracle price
-    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+    let price_lots = {
+        let market = solana.get_account::<Market>(market).await;
+        market.native_price_to_lot(I80F48::ONE).unwrap()
+    };
+    assert_eq!(price_lots, market_base_lot_size / market_quote_lot_size);
+
+    let place_pegged_ix = PlaceOrderPeggedInstruction {
+        open_orders_account: account_1,
+        market,
+        signer: owner,
+        user_token_account: owner_token_1,
+        market_vault: market_quote_vault,
+        side: Side::Bid,
+        price_offset: -1,
+        peg_limit: 1,
+        max_base_lots: 1,
+        max_quote_lots_including_fees: 100_000,
+        client_order_id: 0,
+    };
+
+    // posting invalid orderes by peg_limit are skipped
+    send_tx(solana, place_pegged_ix.clone()).await.unwrap();
+
+    let bids_data = solana.get_book_side(bids).await;
+    assert_eq!(bids_data.roots[1].leaf_count, 0);
 
+    // but not if they are inside the peg_limit
     send_tx(
         solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_1,
-            open_orders_admin: None,
-            market,
-            signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-
-            client_order_id: 0,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
+        PlaceOrderPeggedInstruction {
+            peg_limit: 1000,
+            ..place_pegged_ix
         },
     )
     .await
     .unwrap();
 
-    {
-        let bids_data = solana.get_account_boxed::<BookSide>(bids).await;
-        assert_eq!(bids_data.roots[0].leaf_count, 1);
-    }
+    let bids_data = solana.get_book_side(bids).await;
+    assert_eq!(bids_data.roots[1].leaf_count, 1);
+
+    let order = solana
+        .get_account::<OpenOrdersAccount>(account_1)
+        .await
+        .open_orders[0];
+    assert_eq!(order.side_and_tree(), SideAndOrderTree::BidOraclePegged);
 
-    // Add remainings, no event on event_heap
     send_tx(
         solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_2,
-            open_orders_admin: None,
-            market,
+        CancelOrderInstruction {
             signer: owner,
-            user_token_account: owner_token_0,
-            market_vault: market_base_vault,
-            side: Side::Ask,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10004,
-
-            client_order_id: 0,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![account_1],
+            market,
+            open_orders_account: account_1,
+            order_id: order.id,
         },
     )
     .await
     .unwrap();
 
-    {
-        let bids_data = solana.get_account_boxed::<BookSide>(bids).await;
-        assert_eq!(bids_data.roots[0].leaf_count, 0);
-    }
-
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 100);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 20);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 99960);
-    }
-
-    // No events on event_heap
-    {
-        let market_acc = solana.get_account::<Market>(market).await;
-        let event_heap = solana.get_account::<EventHeap>(market_acc.event_heap).await;
+    assert_no_orders(solana, account_1).await;
 
-        assert_eq!(event_heap.header.count(), 0);
-    }
+    let balance_before = solana.token_account_balance(owner_token_1).await;
+    let max_quote_lots_including_fees = 100_000;
 
-    // Order with expiry time of 10s
-    let now_ts: u64 = solana.get_clock().await.unix_timestamp as u64;
+    // TEST: Place a pegged bid, take it with a direct and pegged ask, and consume events
     send_tx(
         solana,
-        PlaceOrderInstruction {
+        PlaceOrderPeggedInstruction {
             open_orders_account: account_1,
-            open_orders_admin: None,
             market,
             signer: owner,
             user_token_account: owner_token_1,
             market_vault: market_quote_vault,
             side: Side::Bid,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-
-            client_order_id: 35,
-            expiry_timestamp: now_ts + 10,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
+            price_offset: 0,
+            peg_limit: price_lots,
+            max_base_lots: 2,
+            max_quote_lots_including_fees,
+            client_order_id: 5,
         },
     )
     .await
     .unwrap();
-    {
-        let bids_data = solana.get_account_boxed::<BookSide>(bids).await;
-        assert_eq!(bids_data.roots[0].leaf_count, 1);
-    }
 
-    // Advance clock
-    solana.advance_clock(11).await;
+    let balance_after = solana.token_account_balance(owner_token_1).await;
 
+    // Max quantity being subtracted from owner is max_quote_lots_including_fees
     {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
+        assert!(
+            balance_before
+                - ((max_quote_lots_including_fees as u64) * (market_quote_lot_size as u64))
+                <= balance_after
+        );
     }
 
-    // Add remainings, no event on event_heap. previous order is canceled
     send_tx(
         solana,
         PlaceOrderInstruction {
@@ -16237,96 +22047,50 @@ async fn test_place_cancel_order_remaining() -> Result<(), TransportError> {
             side: Side::Ask,
             price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-
-            client_order_id: 36,
+            max_quote_lots_including_fees: 100_000,
+            client_order_id: 6,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![account_1],
+            remainings: vec![],
         },
     )
     .await
     .unwrap();
-    // bid has been canceled
-    {
-        let bids_data = solana.get_account_boxed::<BookSide>(bids).await;
-        assert_eq!(bids_data.roots[0].leaf_count, 0);
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-    }
-
-    // No events on event_heap
-    {
-        let market_acc = solana.get_account_boxed::<Market>(market).await;
-        let event_heap = solana
-            .get_account_boxed::<EventHeap>(market_acc.event_heap)
-            .await;
-
-        assert_eq!(event_heap.header.count(), 0);
-    }
-
-    Ok(())
-}
-
-#[tokio::test]
-async fn test_cancel_order_yourself() -> Result<(), TransportError> {
-    let TestInitialize {
-        context,
-        collect_fee_admin,
-        owner,
-        owner_token_0,
-        owner_token_1,
-        market,
-        market_base_vault,
-        market_quote_vault,
-        price_lots,
-        tokens,
-        account_1,
-        account_2,
-        bids,
-        ..
-    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
-    let solana = &context.solana.clone();
-
-    // Set the initial oracle price
-    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
 
-    // Order with expiry time of 10s
-    let now_ts: u64 = solana.get_clock().await.unix_timestamp as u64;
     send_tx(
         solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_1,
-            open_orders_admin: None,
+        PlaceOrderPeggedInstruction {
+            open_orders_account: account_2,
             market,
             signer: owner,
-            user_token_account: owner_token_1,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
-            price_lots,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_offset: 0,
+            peg_limit: price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-
-            client_order_id: 0,
-            expiry_timestamp: now_ts + 10,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
+            max_quote_lots_including_fees: 100_000,
+            client_order_id: 7,
         },
     )
     .await
     .unwrap();
 
-    {
-        let bids_data = solana.get_account_boxed::<BookSide>(bids).await;
-        assert_eq!(bids_data.roots[0].leaf_count, 1);
-    }
+    send_tx(
+        solana,
+        ConsumeEventsInstruction {
+            consume_events_admin: None,
+            market,
+            open_orders_accounts: vec![account_1, account_2],
+        },
+    )
+    .await
+    .unwrap();
 
-    // Advance clock
-    solana.advance_clock(11).await;
+    assert_no_orders(solana, account_1).await;
 
-    // No remainings, same account, previos bid is canceled
+    // TEST: an ask at current oracle price does not match
     send_tx(
         solana,
         PlaceOrderInstruction {
@@ -16339,60 +22103,35 @@ async fn test_cancel_order_yourself() -> Result<(), TransportError> {
             side: Side::Ask,
             price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 10004,
+            max_quote_lots_including_fees: 100_000,
 
-            client_order_id: 0,
+            client_order_id: 60,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![account_1],
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+    send_tx(
+        solana,
+        CancelOrderByClientOrderIdInstruction {
+            open_orders_account: account_2,
+            market,
+            signer: owner,
+            client_order_id: 60,
         },
     )
     .await
     .unwrap();
 
-    {
-        let bids_data = solana.get_account_boxed::<BookSide>(bids).await;
-        assert_eq!(bids_data.roots[0].leaf_count, 0);
-    }
-
-    Ok(())
-}
-
-#[tokio::test]
-async fn test_place_order_taker_fees() -> Result<(), TransportError> {
-    let TestInitialize {
-        context,
-        collect_fee_admin,
-        owner,
-        owner_token_0,
-        owner_token_1,
-        market,
-        market_base_vault,
-        market_quote_vault,
-        price_lots,
-        tokens,
-        account_1,
-        account_2,
-        bids,
-        ..
-    } = TestContext::new_with_market(TestNewMarketInitialize {
-        taker_fee: 11000, // 1.1%
-        maker_fee: 0,
-        quote_lot_size: 1000,
-        base_lot_size: 1,
-        ..Default::default()
-    })
-    .await?;
-    let solana = &context.solana.clone();
-
-    // Set the initial oracle price
-    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
-
+    // TEST: Change the oracle, now the ask matches
+    set_stub_oracle_price(solana, &tokens[0], collect_fee_admin, 1.002).await;
     send_tx(
         solana,
         PlaceOrderInstruction {
-            open_orders_account: account_1,
+            open_orders_account: account_2,
             open_orders_admin: None,
             market,
             signer: owner,
@@ -16400,9 +22139,10 @@ async fn test_place_order_taker_fees() -> Result<(), TransportError> {
             market_vault: market_base_vault,
             side: Side::Ask,
             price_lots,
-            max_base_lots: 500,
-            max_quote_lots_including_fees: 500,
-            client_order_id: 0,
+            max_base_lots: 2,
+            max_quote_lots_including_fees: 100_000,
+
+            client_order_id: 61,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
@@ -16411,93 +22151,58 @@ async fn test_place_order_taker_fees() -> Result<(), TransportError> {
     )
     .await
     .unwrap();
+    send_tx(
+        solana,
+        ConsumeEventsInstruction {
+            consume_events_admin: None,
+            market,
+            open_orders_accounts: vec![account_1, account_2],
+        },
+    )
+    .await
+    .unwrap();
+    assert_no_orders(solana, account_1).await;
 
-    // Now place place a bid that fills the ask fully and has some remainder go to the book
-    let before_quote_balance = solana.token_account_balance(owner_token_1).await;
+    // restore the oracle to default
+    set_stub_oracle_price(solana, &tokens[0], collect_fee_admin, 1.0).await;
+
+    // TEST: order is cancelled when the price exceeds the peg limit
     send_tx(
         solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_2,
-            open_orders_admin: None,
+        PlaceOrderPeggedInstruction {
+            open_orders_account: account_1,
             market,
             signer: owner,
             user_token_account: owner_token_1,
             market_vault: market_quote_vault,
             side: Side::Bid,
-            price_lots,
-            max_base_lots: 9999999, // unlimited
-            max_quote_lots_including_fees: 1000,
-            client_order_id: 0,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
+            price_offset: -1,
+            peg_limit: price_lots + 2,
+            max_base_lots: 2,
+            max_quote_lots_including_fees: 100_000,
+            client_order_id: 5,
         },
     )
     .await
     .unwrap();
-    let after_quote_balance = solana.token_account_balance(owner_token_1).await;
-
-    // What should have happened is:
-    // - match against the ask, paying 500 quote lots for 500 base lots
-    // - taker fee native is 1.1% * 500 * 1000 = 5500 native
-    // - which is 5.5 quote lots, so only 500 - 6 = 494 quote lots can be placed on the book
-
-    let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-    assert_eq!(open_orders_account_2.position.bids_quote_lots, 494);
-    assert_eq!(open_orders_account_2.position.base_free_native, 500);
-
-    assert_eq!(
-        before_quote_balance - after_quote_balance,
-        // cost of buying 500 base lots
-        500 * 1000
-        // taker fee
-        + 5500
-        // order on the book
-        + 494 * 1000
-    );
-
-    Ok(())
-}
-
-
-// File: openbook-v2/programs/openbook-v2/tests/cases/test_self_trade.rs
-use super::*;
-
-#[tokio::test]
-async fn test_self_trade_decrement_take() -> Result<(), TransportError> {
-    let TestInitialize {
-        context,
-        owner,
-        owner_token_0,
-        owner_token_1,
-        market,
-
-        market_base_vault,
-        market_quote_vault,
-        account_1,
-        account_2,
-        ..
-    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
-    let solana = &context.solana.clone();
-    let owner_quote_ata = context.users[0].token_accounts[1];
-    let owner_base_ata = context.users[0].token_accounts[0];
 
-    // maker (which will be the taker) limit order
+    // order is still matchable when exactly at the peg limit
+    set_stub_oracle_price(solana, &tokens[0], collect_fee_admin, 1.003).await;
     send_tx(
         solana,
         PlaceOrderInstruction {
-            open_orders_account: account_1,
+            open_orders_account: account_2,
             open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_base_ata,
+            user_token_account: owner_token_0,
             market_vault: market_base_vault,
             side: Side::Ask,
-            price_lots: 1000,
-            max_base_lots: 2,
-            max_quote_lots_including_fees: 10000,
-            client_order_id: 1,
+            price_lots: price_lots + 2,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 100_000,
+
+            client_order_id: 62,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
@@ -16507,7 +22212,13 @@ async fn test_self_trade_decrement_take() -> Result<(), TransportError> {
     .await
     .unwrap();
 
-    // maker limit order
+    {
+        let oo = solana.get_open_orders_account(account_2).await;
+        assert!(oo.find_order_with_client_order_id(62).is_none());
+    }
+
+    // but once the adjusted price is > the peg limit, it's gone
+    set_stub_oracle_price(solana, &tokens[0], collect_fee_admin, 1.004).await;
     send_tx(
         solana,
         PlaceOrderInstruction {
@@ -16515,13 +22226,14 @@ async fn test_self_trade_decrement_take() -> Result<(), TransportError> {
             open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_base_ata,
+            user_token_account: owner_token_0,
             market_vault: market_base_vault,
             side: Side::Ask,
-            price_lots: 1000,
-            max_base_lots: 2,
-            max_quote_lots_including_fees: 10000,
-            client_order_id: 2,
+            price_lots: price_lots + 3,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 100_000,
+
+            client_order_id: 63,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
@@ -16530,180 +22242,164 @@ async fn test_self_trade_decrement_take() -> Result<(), TransportError> {
     )
     .await
     .unwrap();
-
-    // taker full self-trade IOC
     send_tx(
         solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_1,
-            open_orders_admin: None,
+        CancelOrderByClientOrderIdInstruction {
+            open_orders_account: account_2,
             market,
             signer: owner,
-            user_token_account: owner_quote_ata,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
-            price_lots: 1000,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-            client_order_id: 3,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::ImmediateOrCancel,
-            self_trade_behavior: SelfTradeBehavior::DecrementTake,
-            remainings: vec![],
+            client_order_id: 63,
         },
     )
     .await
     .unwrap();
-
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 2);
-        assert_eq!(open_orders_account_1.position.base_free_native, 100);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 2);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
-    }
-
     send_tx(
         solana,
-        SettleFundsInstruction {
-            owner,
+        ConsumeEventsInstruction {
+            consume_events_admin: None,
             market,
-            open_orders_account: account_1,
-            market_base_vault,
-            market_quote_vault,
-            user_base_account: owner_token_0,
-            user_quote_account: owner_token_1,
-            referrer_account: None,
+            open_orders_accounts: vec![account_1, account_2],
         },
     )
     .await
     .unwrap();
+    assert_no_orders(solana, account_1).await;
 
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+    Ok(())
+}
 
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 2);
-        assert_eq!(open_orders_account_1.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-    }
+#[tokio::test]
+async fn test_take_peg_invalid_oracle() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        account_1,
+        account_2,
+        price_lots,
+        tokens,
+        collect_fee_admin,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
 
-    // taker partial self-trade limit
     send_tx(
         solana,
-        PlaceOrderInstruction {
+        PlaceOrderPeggedInstruction {
             open_orders_account: account_1,
-            open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_quote_ata,
+            user_token_account: owner_token_1,
             market_vault: market_quote_vault,
             side: Side::Bid,
-            price_lots: 1000,
-            max_base_lots: 2,
-            max_quote_lots_including_fees: 10002,
-            client_order_id: 4,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::DecrementTake,
-            remainings: vec![],
+            price_offset: -1,
+            peg_limit: 100,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 100_000,
+            client_order_id: 0,
         },
     )
     .await
     .unwrap();
 
     {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 2);
-        assert_eq!(open_orders_account_1.position.base_free_native, 200);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 2);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+        let oo = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        assert_eq!(oo.position.bids_base_lots, 1);
     }
 
-    send_tx(
-        solana,
-        ConsumeEventsInstruction {
-            consume_events_admin: None,
-            market,
-            open_orders_accounts: vec![account_1, account_2],
-        },
-    )
-    .await
-    .unwrap();
+    let take_order_ix = PlaceOrderInstruction {
+        open_orders_account: account_2,
+        open_orders_admin: None,
+        market,
+        signer: owner,
+        user_token_account: owner_token_0,
+        market_vault: market_base_vault,
+        side: Side::Ask,
+        price_lots,
+        max_base_lots: 1,
+        max_quote_lots_including_fees: 100_000,
+        client_order_id: 6,
+        expiry_timestamp: 0,
+        order_type: PlaceOrderType::Limit,
+        self_trade_behavior: SelfTradeBehavior::default(),
+        remainings: vec![account_1],
+    };
 
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+    solana.advance_clock(200).await;
 
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 200);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 20000);
+    // stale oracle, order will be posted since matching with the oracle peg component of the book
+    // is not possible
+    send_tx(solana, take_order_ix.clone()).await.unwrap();
+    {
+        let oo_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let oo_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+        assert_eq!(oo_1.position.bids_base_lots, 1);
+        assert_eq!(oo_2.position.asks_base_lots, 1);
+    }
 
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 1);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 10002);
+    // but once the oracle is back, the match will be made
+    set_stub_oracle_price(solana, &tokens[0], collect_fee_admin, 1000.0).await;
+    send_tx(solana, take_order_ix.clone()).await.unwrap();
+    {
+        let oo = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        assert_eq!(oo.position.bids_base_lots, 0);
     }
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_self_trade_cancel_provide() -> Result<(), TransportError> {
+async fn test_take_peg_frozen_oracle() -> Result<(), TransportError> {
     let TestInitialize {
         context,
         owner,
+        owner_token_0,
+        owner_token_1,
         market,
         market_base_vault,
         market_quote_vault,
         account_1,
         account_2,
         ..
-    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        oracle_staleness_policy: OracleStalenessPolicy::Freeze,
+        oracle_freeze_slots: 300,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
     let solana = &context.solana.clone();
-    let owner_quote_ata = context.users[0].token_accounts[1];
-    let owner_base_ata = context.users[0].token_accounts[0];
+    set_account_self_trade_scope(solana, owner, account_2).await;
 
-    // maker (which will be the taker) limit order
-    send_tx(
-        solana,
-        PlaceOrderInstruction {
-            open_orders_account: account_1,
-            open_orders_admin: None,
-            market,
-            signer: owner,
-            user_token_account: owner_base_ata,
-            market_vault: market_base_vault,
-            side: Side::Ask,
-            price_lots: 1000,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-            client_order_id: 1,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
-        },
-    )
-    .await
-    .unwrap();
+    let place_pegged_ix = PlaceOrderPeggedInstruction {
+        open_orders_account: account_1,
+        market,
+        signer: owner,
+        user_token_account: owner_token_1,
+        market_vault: market_quote_vault,
+        side: Side::Bid,
+        price_offset: -1,
+        peg_limit: 100,
+        max_base_lots: 1,
+        max_quote_lots_including_fees: 100_000,
+        client_order_id: 0,
+    };
+    send_tx(solana, place_pegged_ix.clone()).await.unwrap();
+
+    {
+        let market = solana.get_account::<Market>(market).await;
+        assert!(market.last_oracle_slot > 0);
+        assert!(market.last_oracle_price_lots > 0);
+    }
+
+    // stale oracle, but still within the freeze window: the last good price is used and
+    // the pegged bid can be matched
+    solana.advance_by_slots(200).await;
 
-    // maker limit order
     send_tx(
         solana,
         PlaceOrderInstruction {
@@ -16711,212 +22407,208 @@ async fn test_self_trade_cancel_provide() -> Result<(), TransportError> {
             open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_base_ata,
+            user_token_account: owner_token_0,
             market_vault: market_base_vault,
             side: Side::Ask,
-            price_lots: 1000,
-            max_base_lots: 2,
-            max_quote_lots_including_fees: 10000,
-            client_order_id: 2,
+            price_lots: 1,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 100_000,
+            client_order_id: 6,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
+            remainings: vec![account_1],
         },
     )
     .await
     .unwrap();
-
     {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+        let oo_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let oo_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+        assert_eq!(oo_1.position.bids_base_lots, 0);
+        assert_eq!(oo_2.position.asks_base_lots, 0);
+    }
 
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 1);
-        assert_eq!(open_orders_account_1.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+    // once the freeze window is over pegged orders can't be placed anymore
+    solana.advance_by_slots(300).await;
+    assert!(send_tx(solana, place_pegged_ix).await.is_err());
 
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 2);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
-    }
+    Ok(())
+}
 
-    // taker partial self-trade
-    send_tx(
-        solana,
-        PlaceOrderInstruction {
+#[tokio::test]
+async fn test_take_peg_cancel_on_invalid_oracle() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        account_1,
+        account_2,
+        price_lots,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        oracle_staleness_policy: OracleStalenessPolicy::CancelPegged,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
+    let solana = &context.solana.clone();
+
+    send_tx(
+        solana,
+        PlaceOrderPeggedInstruction {
             open_orders_account: account_1,
-            open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_quote_ata,
+            user_token_account: owner_token_1,
             market_vault: market_quote_vault,
             side: Side::Bid,
-            price_lots: 1000,
+            price_offset: -1,
+            peg_limit: 100,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-            client_order_id: 3,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::ImmediateOrCancel,
-            self_trade_behavior: SelfTradeBehavior::CancelProvide,
-            remainings: vec![],
+            max_quote_lots_including_fees: 100_000,
+            client_order_id: 0,
         },
     )
     .await
     .unwrap();
 
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 200);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 2);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
-    }
+    solana.advance_by_slots(200).await;
 
-    // taker with another maker
+    // stale oracle, the pegged bid gets removed from the book instead of being skipped
     send_tx(
         solana,
         PlaceOrderInstruction {
-            open_orders_account: account_1,
+            open_orders_account: account_2,
             open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_quote_ata,
-            market_vault: market_quote_vault,
-            side: Side::Bid,
-            price_lots: 1000,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 10004,
-            client_order_id: 4,
+            max_quote_lots_including_fees: 100_000,
+            client_order_id: 6,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::DecrementTake,
-            remainings: vec![],
-        },
-    )
-    .await
-    .unwrap();
-
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 300);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 2);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
-    }
-
-    send_tx(
-        solana,
-        ConsumeEventsInstruction {
-            consume_events_admin: None,
-            market,
-            open_orders_accounts: vec![account_1, account_2],
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![account_1],
         },
     )
     .await
     .unwrap();
-
     {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 300);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 20004);
+        let oo_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+        assert_eq!(oo_2.position.asks_base_lots, 1);
     }
+    assert_no_orders(solana, account_1).await;
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_self_abort_transaction() -> Result<(), TransportError> {
+async fn test_oracle_peg_limit() -> Result<(), TransportError> {
+    let market_base_lot_size = 10000;
+    let market_quote_lot_size = 10;
+
     let TestInitialize {
         context,
         owner,
+        owner_token_1,
         market,
-        market_base_vault,
         market_quote_vault,
         account_1,
+        bids,
         ..
-    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        quote_lot_size: market_quote_lot_size,
+        base_lot_size: market_base_lot_size,
+        maker_fee: -0,
+        taker_fee: 0,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
     let solana = &context.solana.clone();
-    let owner_quote_ata = context.users[0].token_accounts[1];
-    let owner_base_ata = context.users[0].token_accounts[0];
 
-    // taker limit order
+    let price_lots = {
+        let market = solana.get_account::<Market>(market).await;
+        market.native_price_to_lot(I80F48::ONE).unwrap()
+    };
+    assert_eq!(price_lots, market_base_lot_size / market_quote_lot_size);
+
+    let balance_before = solana.token_account_balance(owner_token_1).await;
+    let max_quote_lots_including_fees = 100_000;
+
+    // TEST: Place a pegged bid, can't post in book due insufficient funds
     send_tx(
         solana,
-        PlaceOrderInstruction {
+        PlaceOrderPeggedInstruction {
             open_orders_account: account_1,
-            open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_base_ata,
-            market_vault: market_base_vault,
-            side: Side::Ask,
-            price_lots: 1000,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-            client_order_id: 1,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_offset: -100,
+            peg_limit: price_lots + 100_000,
+            max_base_lots: 2,
+            max_quote_lots_including_fees,
+            client_order_id: 5,
         },
     )
     .await
     .unwrap();
+    assert_no_orders(solana, account_1).await;
 
-    // taker failing self-trade
-    assert!(send_tx(
+    // Upgrade max quantity
+    let max_quote_lots_including_fees = 101_000;
+
+    send_tx(
         solana,
-        PlaceOrderInstruction {
+        PlaceOrderPeggedInstruction {
             open_orders_account: account_1,
-            open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_quote_ata,
+            user_token_account: owner_token_1,
             market_vault: market_quote_vault,
             side: Side::Bid,
-            price_lots: 1000,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-            client_order_id: 2,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
-            remainings: vec![],
+            price_offset: -100,
+            peg_limit: price_lots + 100_000,
+            max_base_lots: 2,
+            max_quote_lots_including_fees,
+            client_order_id: 5,
         },
     )
     .await
-    .is_err());
+    .unwrap();
+
+    let bids_data = solana.get_book_side(bids).await;
+    assert_eq!(bids_data.roots[1].leaf_count, 1);
+
+    let balance_after = solana.token_account_balance(owner_token_1).await;
 
+    // Max quantity being subtracted from owner is max_quote_lots_including_fees
+    {
+        assert_eq!(
+            balance_before
+                - ((max_quote_lots_including_fees as u64) * (market_quote_lot_size as u64)),
+            balance_after
+        );
+    }
     Ok(())
 }
 
 #[tokio::test]
-async fn test_self_trade_no_fees() -> Result<(), TransportError> {
+async fn test_locked_amounts() -> Result<(), TransportError> {
+    let quote_lot_size = 10;
+    let base_lot_size = 100;
+    let maker_fee = 200;
+    let taker_fee = 400;
+
     let TestInitialize {
         context,
         owner,
@@ -16926,46 +22618,47 @@ async fn test_self_trade_no_fees() -> Result<(), TransportError> {
 
         market_base_vault,
         market_quote_vault,
-        account_1: open_orders_account,
+        account_1,
+        account_2,
         ..
-    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        quote_lot_size,
+        base_lot_size,
+        maker_fee,
+        taker_fee,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
     let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
 
-    let place_bid_ix = PlaceOrderInstruction {
-        open_orders_account,
-        open_orders_admin: None,
+    let place_bid_0_ix = PlaceOrderPeggedInstruction {
+        open_orders_account: account_1,
         market,
         signer: owner,
         user_token_account: owner_quote_ata,
         market_vault: market_quote_vault,
         side: Side::Bid,
-        price_lots: 1000,
-        max_base_lots: 1,
-        max_quote_lots_including_fees: 10000,
-        client_order_id: 1,
-        expiry_timestamp: 0,
-        order_type: PlaceOrderType::Limit,
-        self_trade_behavior: SelfTradeBehavior::default(),
-        remainings: vec![],
+        price_offset: 0,
+        peg_limit: 30,
+        max_base_lots: 1_000,
+        max_quote_lots_including_fees: 100_000_000,
+        client_order_id: 0,
     };
 
-    let place_ask_ix = PlaceOrderInstruction {
+    let place_ask_1_ix = PlaceOrderPeggedInstruction {
         side: Side::Ask,
+        peg_limit: 10,
         market_vault: market_base_vault,
         user_token_account: owner_base_ata,
-        ..place_bid_ix.clone()
-    };
-
-    let consume_events_ix = ConsumeEventsInstruction {
-        consume_events_admin: None,
-        market,
-        open_orders_accounts: vec![open_orders_account],
+        open_orders_account: account_2,
+        ..place_bid_0_ix.clone()
     };
 
-    let settle_funds_ix = SettleFundsInstruction {
+    let settle_funds_0_ix = SettleFundsInstruction {
         owner,
         market,
-        open_orders_account,
+        open_orders_account: account_1,
         market_base_vault,
         market_quote_vault,
         user_base_account: owner_base_ata,
@@ -16973,189 +22666,332 @@ async fn test_self_trade_no_fees() -> Result<(), TransportError> {
         referrer_account: None,
     };
 
-    let balances_before = (
-        solana.token_account_balance(owner_base_ata).await,
-        solana.token_account_balance(owner_quote_ata).await,
-    );
+    let settle_funds_1_ix = SettleFundsInstruction {
+        open_orders_account: account_2,
+        ..settle_funds_0_ix.clone()
+    };
 
-    send_tx(solana, place_bid_ix.clone()).await.unwrap();
-    send_tx(solana, place_ask_ix.clone()).await.unwrap();
-    send_tx(solana, consume_events_ix.clone()).await.unwrap();
-    send_tx(solana, settle_funds_ix.clone()).await.unwrap();
+    let consume_events_ix = ConsumeEventsInstruction {
+        consume_events_admin: None,
+        market,
+        open_orders_accounts: vec![account_1, account_2],
+    };
 
-    let balances_after = (
+    let init_balances = (
         solana.token_account_balance(owner_base_ata).await,
         solana.token_account_balance(owner_quote_ata).await,
     );
 
-    assert_eq!(balances_before, balances_after);
+    // Cancel bid order
+    {
+        send_tx(solana, place_bid_0_ix.clone()).await.unwrap();
 
-    send_tx(solana, place_ask_ix).await.unwrap();
-    send_tx(solana, place_bid_ix).await.unwrap();
-    send_tx(solana, consume_events_ix).await.unwrap();
-    send_tx(solana, settle_funds_ix).await.unwrap();
+        let balances = (
+            solana.token_account_balance(owner_base_ata).await,
+            solana.token_account_balance(owner_quote_ata).await + 300_000 + 60,
+        );
 
-    let balances_after = (
-        solana.token_account_balance(owner_base_ata).await,
-        solana.token_account_balance(owner_quote_ata).await,
-    );
+        assert_eq!(init_balances, balances);
 
-    assert_eq!(balances_before, balances_after);
+        send_tx(
+            solana,
+            CancelAllOrdersInstruction {
+                open_orders_account: account_1,
+                market,
+                signer: owner,
+            },
+        )
+        .await
+        .unwrap();
+        send_tx(solana, settle_funds_0_ix.clone()).await.unwrap();
 
-    Ok(())
-}
+        let balances = (
+            solana.token_account_balance(owner_base_ata).await,
+            solana.token_account_balance(owner_quote_ata).await,
+        );
+
+        assert_eq!(init_balances, balances);
+    }
 
+    // Cancel ask order
+    {
+        send_tx(solana, place_ask_1_ix.clone()).await.unwrap();
 
-// File: openbook-v2/programs/openbook-v2/tests/cases/test_take_order.rs
-use super::*;
+        let balances = (
+            solana.token_account_balance(owner_base_ata).await + 100_000,
+            solana.token_account_balance(owner_quote_ata).await,
+        );
+
+        assert_eq!(init_balances, balances);
+
+        send_tx(
+            solana,
+            CancelAllOrdersInstruction {
+                open_orders_account: account_2,
+                market,
+                signer: owner,
+            },
+        )
+        .await
+        .unwrap();
+        send_tx(solana, settle_funds_1_ix.clone()).await.unwrap();
+
+        let balances = (
+            solana.token_account_balance(owner_base_ata).await,
+            solana.token_account_balance(owner_quote_ata).await,
+        );
+
+        assert_eq!(init_balances, balances);
+    }
+
+    // Place & take a bid
+    {
+        send_tx(solana, place_bid_0_ix.clone()).await.unwrap();
+        send_tx(solana, place_ask_1_ix.clone()).await.unwrap();
+        send_tx(solana, consume_events_ix.clone()).await.unwrap();
+
+        let (position_0, position_1) = {
+            let oo_0 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+            let oo_1 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+            (oo_0.position, oo_1.position)
+        };
+
+        assert_eq!(position_0.quote_free_native, 200_000 + 40);
+        assert_eq!(position_0.base_free_native, 100_000);
+
+        assert_eq!(position_1.quote_free_native, 100_000 - 40);
+        assert_eq!(position_1.base_free_native, 0);
+    }
+
+    Ok(())
+}
 
 #[tokio::test]
-async fn test_take_ask_order() -> Result<(), TransportError> {
+async fn test_bids_quote_lots() -> Result<(), TransportError> {
+    let quote_lot_size = 10;
+    let base_lot_size = 100;
+    let maker_fee = 200;
+    let taker_fee = 400;
+
     let TestInitialize {
         context,
-        collect_fee_admin,
         owner,
-        owner_token_0,
-        owner_token_1,
+        owner_token_0: owner_base_ata,
+        owner_token_1: owner_quote_ata,
         market,
 
         market_base_vault,
         market_quote_vault,
-        price_lots,
-        tokens,
         account_1,
         account_2,
         ..
-    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        quote_lot_size,
+        base_lot_size,
+        maker_fee,
+        taker_fee,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
     let solana = &context.solana.clone();
-
-    // Set the initial oracle price
-    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+    set_account_self_trade_scope(solana, owner, account_2).await;
 
     send_tx(
         solana,
-        PlaceOrderInstruction {
+        PlaceOrderPeggedInstruction {
             open_orders_account: account_1,
-            open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_token_1,
+            user_token_account: owner_quote_ata,
             market_vault: market_quote_vault,
             side: Side::Bid,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-
+            price_offset: 0,
+            peg_limit: 20,
+            max_base_lots: 100,
+            max_quote_lots_including_fees: 100_000_000,
             client_order_id: 0,
-            expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::default(),
-            remainings: vec![],
         },
     )
     .await
     .unwrap();
 
-    let balance_base = solana.token_account_balance(owner_token_0).await;
-    let balance_quote = solana.token_account_balance(owner_token_1).await;
-
+    // first partial match with another oracle peg order
     send_tx(
         solana,
-        PlaceTakeOrderInstruction {
+        PlaceOrderPeggedInstruction {
+            open_orders_account: account_2,
             market,
             signer: owner,
-            user_base_account: owner_token_0,
-            user_quote_account: owner_token_1,
-            market_base_vault,
-            market_quote_vault,
+            user_token_account: owner_base_ata,
+            market_vault: market_base_vault,
             side: Side::Ask,
-            price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10000,
-            open_orders_admin: None,
+            price_offset: 0,
+            peg_limit: 20,
+            max_base_lots: 30,
+            max_quote_lots_including_fees: 100_000_000,
+            client_order_id: 0,
         },
     )
     .await
     .unwrap();
 
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
-
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 0);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
-        assert_eq!(
-            balance_base - 100,
-            solana.token_account_balance(owner_token_0).await
-        );
-        assert_eq!(
-            balance_quote + 99980,
-            solana.token_account_balance(owner_token_1).await
-        );
-    }
+    // not yet unlocked!
+    let oo_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+    assert_eq!(oo_1.position.bids_quote_lots, 2_000);
 
     send_tx(
         solana,
         ConsumeEventsInstruction {
             consume_events_admin: None,
             market,
-            open_orders_accounts: vec![account_1],
+            open_orders_accounts: vec![account_1, account_2],
         },
     )
     .await
     .unwrap();
 
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+    let oo_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+    assert_eq!(oo_1.position.bids_quote_lots, 1_400);
 
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 100);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 20);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
-    }
+    // and fill the rest of the order with a normal ask
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_base_ata,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots: 1,
+            max_base_lots: 70,
+            max_quote_lots_including_fees: 100_000_000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
 
     send_tx(
         solana,
-        SettleFundsInstruction {
-            owner,
+        ConsumeEventsInstruction {
+            consume_events_admin: None,
             market,
-            open_orders_account: account_1,
-            market_base_vault,
-            market_quote_vault,
-            user_base_account: owner_token_0,
-            user_quote_account: owner_token_1,
-            referrer_account: None,
+            open_orders_accounts: vec![account_1, account_2],
         },
     )
     .await
     .unwrap();
 
-    {
-        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+    let oo_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+    assert_eq!(oo_1.position.bids_quote_lots, 0);
 
-        assert_eq!(open_orders_account_1.position.base_free_native, 0);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+    Ok(())
+}
+
+async fn assert_no_orders(solana: &SolanaCookie, account_1: Pubkey) {
+    let open_orders_account = solana.get_account::<OpenOrdersAccount>(account_1).await;
+
+    for oo in open_orders_account.open_orders.iter() {
+        assert!(oo.id == 0);
+        assert!(oo.side_and_tree() == SideAndOrderTree::BidFixed);
+        assert!(oo.client_id == 0);
     }
+}
+
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_order_logs.rs
+use super::*;
+use openbook_v2::logs::{OrderCancelledLog, OrderPlacedLog};
+
+#[tokio::test]
+async fn test_order_placed_and_cancelled_logs() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_1,
+        market,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 2,
+            max_quote_lots_including_fees: 100_000,
+            client_order_id: 7,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    let placed = solana.program_log_events::<OrderPlacedLog>();
+    assert_eq!(placed.len(), 1);
+    assert_eq!(placed[0].market, market);
+    assert_eq!(placed[0].owner, account_1);
+    assert_eq!(placed[0].client_order_id, 7);
+    assert_eq!(placed[0].side_and_tree, SideAndOrderTree::BidFixed as u8);
+    assert_eq!(placed[0].price_lots, price_lots);
+    assert_eq!(placed[0].quantity, 2);
+
+    let order = solana
+        .get_account::<OpenOrdersAccount>(account_1)
+        .await
+        .open_orders[0];
+    assert_eq!(order.id, placed[0].order_id);
+
+    send_tx(
+        solana,
+        CancelOrderInstruction {
+            signer: owner,
+            market,
+            open_orders_account: account_1,
+            order_id: order.id,
+        },
+    )
+    .await
+    .unwrap();
+
+    let cancelled = solana.program_log_events::<OrderCancelledLog>();
+    assert_eq!(cancelled.len(), 1);
+    assert_eq!(cancelled[0].order_id, order.id);
+    assert_eq!(cancelled[0].owner, account_1);
+    assert_eq!(cancelled[0].client_order_id, 7);
+    assert_eq!(cancelled[0].side_and_tree, SideAndOrderTree::BidFixed as u8);
+    assert_eq!(cancelled[0].price_lots, price_lots);
+    assert_eq!(cancelled[0].quantity, 2);
+    assert!(solana.program_log_events::<OrderPlacedLog>().is_empty());
 
     Ok(())
 }
 
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_order_types.rs
+use super::*;
+
 #[tokio::test]
-async fn test_take_bid_order() -> Result<(), TransportError> {
+async fn test_immediate_order() -> Result<(), TransportError> {
     let TestInitialize {
         context,
         collect_fee_admin,
@@ -17163,6 +22999,7 @@ async fn test_take_bid_order() -> Result<(), TransportError> {
         owner_token_0,
         owner_token_1,
         market,
+
         market_base_vault,
         market_quote_vault,
         price_lots,
@@ -17172,6 +23009,7 @@ async fn test_take_bid_order() -> Result<(), TransportError> {
         ..
     } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
     let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
 
     // Set the initial oracle price
     set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
@@ -17183,9 +23021,9 @@ async fn test_take_bid_order() -> Result<(), TransportError> {
             open_orders_admin: None,
             market,
             signer: owner,
-            user_token_account: owner_token_0,
-            market_vault: market_base_vault,
-            side: Side::Ask,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
             price_lots,
             max_base_lots: 1,
             max_quote_lots_including_fees: 10000,
@@ -17205,18 +23043,23 @@ async fn test_take_bid_order() -> Result<(), TransportError> {
 
     send_tx(
         solana,
-        PlaceTakeOrderInstruction {
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
             market,
             signer: owner,
-            user_base_account: owner_token_0,
-            user_quote_account: owner_token_1,
-            market_base_vault,
-            market_quote_vault,
-            side: Side::Bid,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
             price_lots,
-            max_base_lots: 1,
-            max_quote_lots_including_fees: 10040,
-            open_orders_admin: None,
+            max_base_lots: 2,
+            max_quote_lots_including_fees: 20000,
+
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::ImmediateOrCancel,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
         },
     )
     .await
@@ -17226,20 +23069,20 @@ async fn test_take_bid_order() -> Result<(), TransportError> {
         let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
         let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
 
-        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
         assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.asks_base_lots, 1);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
         assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
         assert_eq!(open_orders_account_1.position.base_free_native, 0);
         assert_eq!(open_orders_account_2.position.base_free_native, 0);
         assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 99960);
         assert_eq!(
-            balance_base + 100,
+            balance_base - 100,
             solana.token_account_balance(owner_token_0).await
         );
         assert_eq!(
-            balance_quote - 100020,
+            balance_quote,
             solana.token_account_balance(owner_token_1).await
         );
     }
@@ -17249,7 +23092,7 @@ async fn test_take_bid_order() -> Result<(), TransportError> {
         ConsumeEventsInstruction {
             consume_events_admin: None,
             market,
-            open_orders_accounts: vec![account_1],
+            open_orders_accounts: vec![account_1, account_2],
         },
     )
     .await
@@ -17263,10 +23106,10 @@ async fn test_take_bid_order() -> Result<(), TransportError> {
         assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
         assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
         assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
-        assert_eq!(open_orders_account_1.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 100);
         assert_eq!(open_orders_account_2.position.base_free_native, 0);
-        assert_eq!(open_orders_account_1.position.quote_free_native, 100020);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 20);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 99960);
     }
 
     send_tx(
@@ -17287,38 +23130,11 @@ async fn test_take_bid_order() -> Result<(), TransportError> {
 
     {
         let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
-        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
 
         assert_eq!(open_orders_account_1.position.base_free_native, 0);
-        assert_eq!(open_orders_account_2.position.base_free_native, 0);
         assert_eq!(open_orders_account_1.position.quote_free_native, 0);
-        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
     }
 
-    Ok(())
-}
-
-#[tokio::test]
-async fn test_negative_spread_ask() -> Result<(), TransportError> {
-    let TestInitialize {
-        context,
-        owner,
-        owner_token_0,
-        owner_token_1,
-        market,
-        market_base_vault,
-        market_quote_vault,
-        account_1,
-        account_2,
-        ..
-    } = TestContext::new_with_market(TestNewMarketInitialize {
-        quote_lot_size: 100,
-        base_lot_size: 1_000_000_000,
-        ..TestNewMarketInitialize::default()
-    })
-    .await?;
-    let solana = &context.solana.clone();
-
     send_tx(
         solana,
         PlaceOrderInstruction {
@@ -17329,10 +23145,11 @@ async fn test_negative_spread_ask() -> Result<(), TransportError> {
             user_token_account: owner_token_1,
             market_vault: market_quote_vault,
             side: Side::Bid,
-            price_lots: 10_000,     // $1
-            max_base_lots: 1000000, // wahtever
-            max_quote_lots_including_fees: 10_000,
-            client_order_id: 1,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+
+            client_order_id: 0,
             expiry_timestamp: 0,
             order_type: PlaceOrderType::Limit,
             self_trade_behavior: SelfTradeBehavior::default(),
@@ -17342,7 +23159,10 @@ async fn test_negative_spread_ask() -> Result<(), TransportError> {
     .await
     .unwrap();
 
-    // This order doesn't take any due max_quote_lots_including_fees but it's also don't post in on the book
+    let balance_base = solana.token_account_balance(owner_token_0).await;
+    let balance_quote = solana.token_account_balance(owner_token_1).await;
+
+    // There is a bid in the book, post only doesn't do anything since there is a match
     send_tx(
         solana,
         PlaceOrderInstruction {
@@ -17353,267 +23173,5513 @@ async fn test_negative_spread_ask() -> Result<(), TransportError> {
             user_token_account: owner_token_0,
             market_vault: market_base_vault,
             side: Side::Ask,
-            price_lots: 7_500,
+            price_lots,
             max_base_lots: 1,
-            max_quote_lots_including_fees: 7_500,
-            client_order_id: 25,
+            max_quote_lots_including_fees: 10000,
+
+            client_order_id: 0,
             expiry_timestamp: 0,
-            order_type: PlaceOrderType::Limit,
-            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            order_type: PlaceOrderType::PostOnly,
+            self_trade_behavior: SelfTradeBehavior::default(),
             remainings: vec![],
         },
     )
     .await
     .unwrap();
 
-    let position = solana
-        .get_account::<OpenOrdersAccount>(account_2)
-        .await
-        .position;
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+        assert_eq!(
+            balance_base,
+            solana.token_account_balance(owner_token_0).await
+        );
+        assert_eq!(
+            balance_quote,
+            solana.token_account_balance(owner_token_1).await
+        );
+    }
+
+    // Change the price, so no matching and order posts
+    let price_lots_2 = price_lots - 100;
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots: price_lots_2,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::PostOnly,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+        assert_eq!(
+            balance_base,
+            solana.token_account_balance(owner_token_0).await
+        );
+        assert_eq!(
+            balance_quote,
+            solana.token_account_balance(owner_token_1).await
+        );
+    }
+
+    // PostOnlySlide always post on book
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10040,
+
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::PostOnlySlide,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 1);
+        assert_eq!(open_orders_account_1.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+        assert_eq!(
+            balance_base - 100,
+            solana.token_account_balance(owner_token_0).await
+        );
+        assert_eq!(
+            balance_quote,
+            solana.token_account_balance(owner_token_1).await
+        );
+    }
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_permissioned.rs
+use super::*;
+
+#[tokio::test]
+async fn test_permissioned_open_order() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        collect_fee_admin,
+        owner,
+        owner_token_1,
+        market,
+        market_quote_vault,
+        tokens,
+        account_1,
+        open_orders_admin,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        open_orders_admin_bool: true,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
+    let solana = &context.solana.clone();
+
+    let price_lots = {
+        let market = solana.get_account::<Market>(market).await;
+        market.native_price_to_lot(I80F48::from(1000)).unwrap()
+    };
+
+    // Set the initial oracle price
+    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+
+    // First, send in an order w/o the signature of the open order authority, expect failure
+    let result = send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await;
+
+    assert!(result.is_err());
+
+    // Second, send in an order w/ the signature of the open order authority, expect success
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: Some(open_orders_admin),
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_permissioned_open_take_order() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        collect_fee_admin,
+        open_orders_admin,
+        owner,
+        owner_token_1,
+        market,
+        market_quote_vault,
+        price_lots,
+        tokens,
+        account_1,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        open_orders_admin_bool: true,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
+    let solana = &context.solana.clone();
+
+    // Set the initial oracle price
+    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+
+    let result = send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await;
+
+    assert!(result.is_err());
+
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: Some(open_orders_admin),
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_consume_events_admin() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        collect_fee_admin,
+        consume_events_admin,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        tokens,
+        account_1,
+        account_2,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        consume_events_admin_bool: true,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
+    let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
+
+    // Set the initial oracle price
+    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    let result = send_tx(
+        solana,
+        ConsumeEventsInstruction {
+            consume_events_admin: None,
+            market,
+            open_orders_accounts: vec![account_1, account_2],
+        },
+    )
+    .await;
+
+    assert!(result.is_err());
+
+    send_tx(
+        solana,
+        ConsumeEventsInstruction {
+            consume_events_admin: Some(consume_events_admin),
+            market,
+            open_orders_accounts: vec![account_1, account_2],
+        },
+    )
+    .await
+    .unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_close_market_admin() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        close_market_admin,
+        collect_fee_admin,
+        owner,
+        mints,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        account_2,
+        payer,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        close_market_admin_bool: true,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
+    let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
+
+    let fee_admin_ata = solana
+        .create_associated_token_account(&collect_fee_admin.pubkey(), mints[1].pubkey)
+        .await;
+
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    // Place an order that matches
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    // Place another order
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    let close_ix = CloseMarketInstruction {
+        close_market_admin,
+        market,
+        sol_destination: owner.pubkey(),
+    };
+
+    let settle_funds_expired_ix = SettleFundsExpiredInstruction {
+        close_market_admin,
+        market,
+        owner: payer,
+        open_orders_account: account_2,
+        market_base_vault,
+        market_quote_vault,
+        user_base_account: owner_token_0,
+        user_quote_account: owner_token_1,
+        referrer_account: None,
+    };
+
+    // Can't close yet, market not market as expired
+    assert!(send_tx(solana, close_ix.clone()).await.is_err());
+
+    // also not possible to settle in behalf of the users
+    assert!(send_tx(solana, settle_funds_expired_ix.clone())
+        .await
+        .is_err());
+
+    send_tx(
+        solana,
+        SetMarketExpiredInstruction {
+            close_market_admin,
+            market,
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let market = solana.get_account::<Market>(market).await;
+        assert_eq!(market.time_expiry, -1);
+    }
+
+    // Can't post orders anymore
+    let result = send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await;
+    assert!(result.is_err());
+
+    // Consume events
+    send_tx(
+        solana,
+        ConsumeEventsInstruction {
+            consume_events_admin: None,
+            market,
+            open_orders_accounts: vec![account_1, account_2],
+        },
+    )
+    .await
+    .unwrap();
+
+    // Can't close, have to prune orders first
+    assert!(send_tx(solana, close_ix.clone()).await.is_err());
+
+    send_tx(
+        solana,
+        PruneOrdersInstruction {
+            close_market_admin,
+            market,
+            open_orders_account: account_1,
+        },
+    )
+    .await
+    .unwrap();
+
+    // and wait until users settle funds
+    {
+        let market = solana.get_account::<Market>(market).await;
+        assert!(market.base_deposit_total != 0);
+        assert!(market.quote_deposit_total != 0);
+    }
+    assert!(send_tx(solana, close_ix.clone()).await.is_err());
+
+    send_tx(
+        solana,
+        SettleFundsInstruction {
+            owner,
+            market,
+            open_orders_account: account_1,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            referrer_account: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    // which can be even be called by the close_market_admin once the market is expired so it
+    // doesn't have to wait for the users!
+    send_tx(solana, settle_funds_expired_ix).await.unwrap();
+
+    // but wait! the're still pending fees
+    {
+        let market = solana.get_account::<Market>(market).await;
+        assert!(market.fees_available != 0);
+    }
+    assert!(send_tx(solana, close_ix.clone()).await.is_err());
+
+    send_tx(
+        solana,
+        SweepFeesInstruction {
+            collect_fee_admin,
+            market,
+            market_quote_vault,
+            token_receiver_account: fee_admin_ata,
+        },
+    )
+    .await
+    .unwrap();
+
+    // Boom
+    send_tx(solana, close_ix.clone()).await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_delegate() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        collect_fee_admin,
+        owner,
+        market,
+        market_quote_vault,
+        price_lots,
+        tokens,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
+    let solana = &context.solana.clone();
+
+    let account_3_delegate = context.users[2].key;
+    let account_3 = create_open_orders_account(
+        solana,
+        owner,
+        market,
+        3,
+        &context.users[0],
+        Some(account_3_delegate.pubkey()),
+    )
+    .await;
+    let delegate_token_1 = context.users[2].token_accounts[1];
+
+    // Set the initial oracle price
+    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_3,
+            open_orders_admin: None,
+            market,
+            signer: account_3_delegate,
+            user_token_account: delegate_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+
+            client_order_id: 23,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    send_tx(
+        solana,
+        CancelOrderByClientOrderIdInstruction {
+            signer: account_3_delegate,
+            market,
+            open_orders_account: account_3,
+            client_order_id: 23,
+        },
+    )
+    .await
+    .unwrap();
+
+    send_tx(
+        solana,
+        SetDelegateInstruction {
+            owner,
+            open_orders_account: account_3,
+            delegate_account: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    // No delegate anymore
+    let result = send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_3,
+            open_orders_admin: None,
+            market,
+            signer: account_3_delegate,
+            user_token_account: delegate_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+
+            client_order_id: 23,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await;
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_allowlist() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        allowlist_admin,
+        owner,
+        payer,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        account_2,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        allowlist_admin_bool: true,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
+    let solana = &context.solana.clone();
+
+    let place_bid = |open_orders_account| PlaceOrderInstruction {
+        open_orders_account,
+        open_orders_admin: None,
+        market,
+        signer: owner,
+        user_token_account: owner_token_1,
+        market_vault: market_quote_vault,
+        side: Side::Bid,
+        price_lots,
+        max_base_lots: 1,
+        max_quote_lots_including_fees: 10000,
+        client_order_id: 0,
+        expiry_timestamp: 0,
+        order_type: PlaceOrderType::Limit,
+        self_trade_behavior: SelfTradeBehavior::default(),
+        remainings: vec![],
+    };
+    let add = |entry| AddToAllowlistInstruction {
+        payer,
+        allowlist_admin,
+        market,
+        entry,
+    };
+    let remove = |entry| RemoveFromAllowlistInstruction {
+        allowlist_admin,
+        market,
+        entry,
+        sol_destination: payer.pubkey(),
+    };
+
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            AddToAllowlistInstruction {
+                allowlist_admin: owner,
+                ..add(owner.pubkey())
+            },
+        )
+        .await,
+        OpenBookError::InvalidAllowlistAdmin.error_code(),
+        "only the allowlist admin edits the allowlist".into(),
+    );
+
+    assert_openbook_error(
+        &send_tx(solana, place_bid(account_1)).await,
+        OpenBookError::NotAllowlisted.error_code(),
+        "empty allowlist".into(),
+    );
+
+    // an allowlisted open orders account doesn't let other accounts of the owner in
+    send_tx(solana, add(account_1)).await.unwrap();
+    send_tx(solana, place_bid(account_1)).await.unwrap();
+    assert_openbook_error(
+        &send_tx(solana, place_bid(account_2)).await,
+        OpenBookError::NotAllowlisted.error_code(),
+        "only account_1 is allowlisted".into(),
+    );
+
+    // an allowlisted owner can use all of its accounts
+    send_tx(solana, add(owner.pubkey())).await.unwrap();
+    let logs = solana.program_log_events::<openbook_v2::logs::AddToAllowlistLog>();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].market, market);
+    assert_eq!(logs[0].entry, owner.pubkey());
+
+    send_tx(solana, place_bid(account_2)).await.unwrap();
+    // the entry account of the owner already exists
+    assert!(send_tx(solana, add(owner.pubkey())).await.is_err());
+
+    // takers need to be allowlisted too
+    let taker = context.users[2].key;
+    let take_ask = PlaceTakeOrderInstruction {
+        market,
+        signer: taker,
+        user_base_account: context.users[2].token_accounts[0],
+        user_quote_account: context.users[2].token_accounts[1],
+        market_base_vault,
+        market_quote_vault,
+        side: Side::Ask,
+        price_lots,
+        max_base_lots: 1,
+        max_quote_lots_including_fees: 10000,
+        open_orders_admin: None,
+    };
+    assert_openbook_error(
+        &send_tx(solana, take_ask.clone()).await,
+        OpenBookError::NotAllowlisted.error_code(),
+        "taker is not allowlisted".into(),
+    );
+    send_tx(solana, add(taker.pubkey())).await.unwrap();
+    send_tx(solana, take_ask).await.unwrap();
+
+    send_tx(solana, remove(owner.pubkey())).await.unwrap();
+    assert_openbook_error(
+        &send_tx(solana, place_bid(account_2)).await,
+        OpenBookError::NotAllowlisted.error_code(),
+        "owner was removed".into(),
+    );
+    assert!(send_tx(solana, remove(owner.pubkey())).await.is_err());
+
+    // every entry is its own account
+    for (key, is_allowlisted) in [
+        (account_1, true),
+        (taker.pubkey(), true),
+        (owner.pubkey(), false),
+    ] {
+        let entry = solana
+            .get_account_opt::<MarketListEntry>(get_market_allowlist_entry_address(market, key))
+            .await;
+        assert_eq!(entry.is_some(), is_allowlisted);
+        if let Some(entry) = entry {
+            assert_eq!(entry.market, market);
+            assert_eq!(entry.key, key);
+            assert_eq!(entry.list, MarketList::Allowlist as u8);
+        }
+    }
+
+    Ok(())
+}
+
+
+#[tokio::test]
+async fn test_denylist() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        denylist_admin,
+        owner,
+        payer,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        account_2,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        denylist_admin_bool: true,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
+    let solana = &context.solana.clone();
+
+    let place_bid = |open_orders_account| PlaceOrderInstruction {
+        open_orders_account,
+        open_orders_admin: None,
+        market,
+        signer: owner,
+        user_token_account: owner_token_1,
+        market_vault: market_quote_vault,
+        side: Side::Bid,
+        price_lots,
+        max_base_lots: 1,
+        max_quote_lots_including_fees: 10000,
+        client_order_id: 0,
+        expiry_timestamp: 0,
+        order_type: PlaceOrderType::Limit,
+        self_trade_behavior: SelfTradeBehavior::default(),
+        remainings: vec![],
+    };
+    let settle = SettleFundsInstruction {
+        owner,
+        open_orders_account: account_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        user_base_account: owner_token_0,
+        user_quote_account: owner_token_1,
+        referrer_account: None,
+    };
+    let add = |entry| AddToDenylistInstruction {
+        payer,
+        denylist_admin,
+        market,
+        entry,
+    };
+    let remove = |entry| RemoveFromDenylistInstruction {
+        denylist_admin,
+        market,
+        entry,
+        sol_destination: payer.pubkey(),
+    };
+
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            AddToDenylistInstruction {
+                payer,
+                denylist_admin: owner,
+                market,
+                entry: owner.pubkey(),
+            },
+        )
+        .await,
+        OpenBookError::InvalidDenylistAdmin.error_code(),
+        "only the denylist admin edits the denylist".into(),
+    );
+
+    let taker = context.users[2].key;
+    let take_ask = PlaceTakeOrderInstruction {
+        market,
+        signer: taker,
+        user_base_account: context.users[2].token_accounts[0],
+        user_quote_account: context.users[2].token_accounts[1],
+        market_base_vault,
+        market_quote_vault,
+        side: Side::Ask,
+        price_lots,
+        max_base_lots: 1,
+        max_quote_lots_including_fees: 10000,
+        open_orders_admin: None,
+    };
+
+    send_tx(solana, place_bid(account_1)).await.unwrap();
+
+    // a denylisted owner can't trade, settle, withdraw or open new accounts
+    send_tx(solana, add(owner.pubkey())).await.unwrap();
+    let logs = solana.program_log_events::<openbook_v2::logs::AddToDenylistLog>();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].market, market);
+    assert_eq!(logs[0].entry, owner.pubkey());
+    {
+        let entry = solana
+            .get_account::<MarketListEntry>(get_market_denylist_entry_address(
+                market,
+                owner.pubkey(),
+            ))
+            .await;
+        assert_eq!(entry.market, market);
+        assert_eq!(entry.key, owner.pubkey());
+        assert_eq!(entry.list, MarketList::Denylist as u8);
+    }
+
+    assert_openbook_error(
+        &send_tx(solana, place_bid(account_1)).await,
+        OpenBookError::Denylisted.error_code(),
+        "owner is denylisted".into(),
+    );
+    assert_openbook_error(
+        &send_tx(solana, place_bid(account_2)).await,
+        OpenBookError::Denylisted.error_code(),
+        "every account of the owner is screened".into(),
+    );
+    assert_openbook_error(
+        &send_tx(solana, settle.clone()).await,
+        OpenBookError::Denylisted.error_code(),
+        "owner can't settle".into(),
+    );
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            WithdrawInstruction {
+                owner,
+                open_orders_account: account_1,
+                market,
+                market_base_vault,
+                market_quote_vault,
+                user_base_account: owner_token_0,
+                user_quote_account: owner_token_1,
+                base_amount: 0,
+                quote_amount: 0,
+            },
+        )
+        .await,
+        OpenBookError::Denylisted.error_code(),
+        "owner can't withdraw".into(),
+    );
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            TransferFreeBalanceInstruction {
+                owner,
+                source_open_orders_account: account_1,
+                destination_open_orders_account: account_2,
+                market,
+                base_amount: 0,
+                quote_amount: 0,
+            },
+        )
+        .await,
+        OpenBookError::Denylisted.error_code(),
+        "owner can't move free balances".into(),
+    );
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            CreateOpenOrdersAccountInstruction {
+                account_num: 3,
+                market,
+                owner,
+                payer,
+                delegate: None,
+            },
+        )
+        .await,
+        OpenBookError::Denylisted.error_code(),
+        "owner can't create open orders accounts".into(),
+    );
+    assert!(send_tx(solana, add(owner.pubkey())).await.is_err());
+
+    // the owners of the token accounts of a taker are screened too
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            PlaceTakeOrderInstruction {
+                user_quote_account: owner_token_1,
+                ..take_ask.clone()
+            },
+        )
+        .await,
+        OpenBookError::Denylisted.error_code(),
+        "owner can't receive the proceeds of a taker".into(),
+    );
+    send_tx(solana, add(taker.pubkey())).await.unwrap();
+    assert_openbook_error(
+        &send_tx(solana, take_ask.clone()).await,
+        OpenBookError::Denylisted.error_code(),
+        "taker is denylisted".into(),
+    );
+    send_tx(solana, remove(taker.pubkey())).await.unwrap();
+    let logs = solana.program_log_events::<openbook_v2::logs::RemoveFromDenylistLog>();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].entry, taker.pubkey());
+    send_tx(solana, take_ask).await.unwrap();
+
+    send_tx(solana, remove(owner.pubkey())).await.unwrap();
+    send_tx(solana, settle.clone()).await.unwrap();
+    assert!(send_tx(solana, remove(owner.pubkey())).await.is_err());
+
+    // a denylisted delegate can't act through the account of an allowed owner
+    send_tx(
+        solana,
+        SetDelegateInstruction {
+            owner,
+            open_orders_account: account_1,
+            delegate_account: Some(taker.pubkey()),
+        },
+    )
+    .await
+    .unwrap();
+    send_tx(solana, add(taker.pubkey())).await.unwrap();
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            PlaceOrderInstruction {
+                signer: taker,
+                user_token_account: context.users[2].token_accounts[1],
+                ..place_bid(account_1)
+            },
+        )
+        .await,
+        OpenBookError::Denylisted.error_code(),
+        "delegate can't place orders".into(),
+    );
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            SettleFundsInstruction {
+                owner: taker,
+                ..settle
+            },
+        )
+        .await,
+        OpenBookError::Denylisted.error_code(),
+        "delegate can't settle".into(),
+    );
+    send_tx(solana, remove(taker.pubkey())).await.unwrap();
+
+    for key in [owner.pubkey(), taker.pubkey()] {
+        let entry = solana
+            .get_account_opt::<MarketListEntry>(get_market_denylist_entry_address(market, key))
+            .await;
+        assert!(entry.is_none());
+    }
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_place_order_remaining.rs
+use super::*;
+
+#[tokio::test]
+async fn test_place_cancel_order_remaining() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        collect_fee_admin,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        tokens,
+        account_1,
+        account_2,
+        bids,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
+
+    // Set the initial oracle price
+    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let bids_data = solana.get_book_side(bids).await;
+        assert_eq!(bids_data.roots[0].leaf_count, 1);
+    }
+
+    // Add remainings, no event on event_heap
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10004,
+
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![account_1],
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let bids_data = solana.get_book_side(bids).await;
+        assert_eq!(bids_data.roots[0].leaf_count, 0);
+    }
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 100);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 20);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 99960);
+    }
+
+    // No events on event_heap
+    {
+        let market_acc = solana.get_account::<Market>(market).await;
+        let event_heap = solana.get_event_heap(market_acc.event_heap).await;
+
+        assert_eq!(event_heap.header.count(), 0);
+    }
+
+    // Order with expiry time of 10s
+    let now_ts: u64 = solana.get_clock().await.unix_timestamp as u64;
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+
+            client_order_id: 35,
+            expiry_timestamp: now_ts + 10,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+    {
+        let bids_data = solana.get_book_side(bids).await;
+        assert_eq!(bids_data.roots[0].leaf_count, 1);
+    }
+
+    // Advance clock
+    solana.advance_clock(11).await;
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
+    }
+
+    // Add remainings, no event on event_heap. previous order is canceled
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+
+            client_order_id: 36,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![account_1],
+        },
+    )
+    .await
+    .unwrap();
+    // bid has been canceled
+    {
+        let bids_data = solana.get_book_side(bids).await;
+        assert_eq!(bids_data.roots[0].leaf_count, 0);
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+    }
+
+    // No events on event_heap
+    {
+        let market_acc = solana.get_account_boxed::<Market>(market).await;
+        let event_heap = solana.get_event_heap(market_acc.event_heap).await;
+
+        assert_eq!(event_heap.header.count(), 0);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cancel_order_yourself() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        collect_fee_admin,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        tokens,
+        account_1,
+        account_2,
+        bids,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
+
+    // Set the initial oracle price
+    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+
+    // Order with expiry time of 10s
+    let now_ts: u64 = solana.get_clock().await.unix_timestamp as u64;
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+
+            client_order_id: 0,
+            expiry_timestamp: now_ts + 10,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let bids_data = solana.get_book_side(bids).await;
+        assert_eq!(bids_data.roots[0].leaf_count, 1);
+    }
+
+    // Advance clock
+    solana.advance_clock(11).await;
+
+    // No remainings, same account, previos bid is canceled
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10004,
+
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![account_1],
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let bids_data = solana.get_book_side(bids).await;
+        assert_eq!(bids_data.roots[0].leaf_count, 0);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_place_order_taker_fees() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        collect_fee_admin,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        tokens,
+        account_1,
+        account_2,
+        bids,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        taker_fee: 11000, // 1.1%
+        maker_fee: 0,
+        quote_lot_size: 1000,
+        base_lot_size: 1,
+        ..Default::default()
+    })
+    .await?;
+    let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
+
+    // Set the initial oracle price
+    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 500,
+            max_quote_lots_including_fees: 500,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    // Now place place a bid that fills the ask fully and has some remainder go to the book
+    let before_quote_balance = solana.token_account_balance(owner_token_1).await;
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 9999999, // unlimited
+            max_quote_lots_including_fees: 1000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+    let after_quote_balance = solana.token_account_balance(owner_token_1).await;
+
+    // What should have happened is:
+    // - match against the ask, paying 500 quote lots for 500 base lots
+    // - taker fee native is 1.1% * 500 * 1000 = 5500 native
+    // - which is 5.5 quote lots, so only 500 - 6 = 494 quote lots can be placed on the book
+
+    let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+    assert_eq!(open_orders_account_2.position.bids_quote_lots, 494);
+    assert_eq!(open_orders_account_2.position.base_free_native, 500);
+
+    assert_eq!(
+        before_quote_balance - after_quote_balance,
+        // cost of buying 500 base lots
+        500 * 1000
+        // taker fee
+        + 5500
+        // order on the book
+        + 494 * 1000
+    );
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_resize_open_orders_account.rs
+use super::*;
+
+#[tokio::test]
+async fn test_resize_open_orders_account() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        payer,
+        owner_token_1,
+        market,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+
+    let resize = |capacity| ResizeOpenOrdersAccountInstruction {
+        payer,
+        owner,
+        open_orders_account: account_1,
+        capacity,
+    };
+    let place_bid = |i: i64| PlaceOrderInstruction {
+        open_orders_account: account_1,
+        open_orders_admin: None,
+        market,
+        signer: owner,
+        user_token_account: owner_token_1,
+        market_vault: market_quote_vault,
+        side: Side::Bid,
+        price_lots: price_lots - i,
+        max_base_lots: 1,
+        max_quote_lots_including_fees: 100_000,
+        client_order_id: i as u64,
+        expiry_timestamp: 0,
+        order_type: PlaceOrderType::Limit,
+        self_trade_behavior: SelfTradeBehavior::default(),
+        remainings: vec![],
+    };
+
+    assert_openbook_error(
+        &send_tx(solana, resize(MAX_OPEN_ORDERS_CAPACITY as u16 + 1)).await,
+        OpenBookError::InvalidOpenOrdersCapacity.error_code(),
+        "capacity above the limit".into(),
+    );
+
+    send_tx(solana, resize(30)).await.unwrap();
+    assert_eq!(
+        solana.get_account_data(account_1).await.unwrap().len(),
+        OpenOrdersAccount::space_for_capacity(30)
+    );
+
+    for i in 0..30 {
+        send_tx(solana, place_bid(i)).await.unwrap();
+    }
+    assert_openbook_error(
+        &send_tx(solana, place_bid(30)).await,
+        OpenBookError::OpenOrdersFull.error_code(),
+        "all 30 slots are in use".into(),
+    );
+
+    let oo = solana.get_open_orders_account(account_1).await;
+    assert_eq!(oo.capacity(), 30);
+    assert_eq!(oo.all_orders_in_use().count(), 30);
+    let order_29 = oo.find_order_with_client_order_id(29).unwrap();
+    assert_eq!(oo.dynamic[5].id, order_29.id);
+
+    assert_openbook_error(
+        &send_tx(solana, resize(MAX_OPEN_ORDERS as u16)).await,
+        OpenBookError::OpenOrdersSlotsInUse.error_code(),
+        "dynamic slots still hold orders".into(),
+    );
+
+    // orders in dynamic slots can be cancelled like any other
+    for order in oo.dynamic.iter() {
+        send_tx(
+            solana,
+            CancelOrderInstruction {
+                signer: owner,
+                market,
+                open_orders_account: account_1,
+                order_id: order.id,
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    send_tx(solana, resize(MAX_OPEN_ORDERS as u16)).await.unwrap();
+    assert_eq!(
+        solana.get_account_data(account_1).await.unwrap().len(),
+        OpenOrdersAccount::space()
+    );
+    let oo = solana.get_account::<OpenOrdersAccount>(account_1).await;
+    assert_eq!(oo.extra_order_slots, 0);
+    assert_eq!(oo.position.bids_base_lots, 24);
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_self_trade.rs
+use super::*;
+use openbook_v2::logs::SetSelfTradeScopeLog;
+
+#[tokio::test]
+async fn test_self_trade_decrement_take() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+
+        market_base_vault,
+        market_quote_vault,
+        account_1,
+        account_2,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
+    let owner_quote_ata = context.users[0].token_accounts[1];
+    let owner_base_ata = context.users[0].token_accounts[0];
+
+    // maker (which will be the taker) limit order
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_base_ata,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots: 1000,
+            max_base_lots: 2,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 1,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    // maker limit order
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_base_ata,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots: 1000,
+            max_base_lots: 2,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 2,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    // taker full self-trade IOC
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_quote_ata,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots: 1000,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 3,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::ImmediateOrCancel,
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 2);
+        assert_eq!(open_orders_account_1.position.base_free_native, 100);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 2);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+    }
+
+    send_tx(
+        solana,
+        SettleFundsInstruction {
+            owner,
+            market,
+            open_orders_account: account_1,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            referrer_account: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 2);
+        assert_eq!(open_orders_account_1.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+    }
+
+    // taker partial self-trade limit
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_quote_ata,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots: 1000,
+            max_base_lots: 2,
+            max_quote_lots_including_fees: 10002,
+            client_order_id: 4,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 2);
+        assert_eq!(open_orders_account_1.position.base_free_native, 200);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 2);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+    }
+
+    send_tx(
+        solana,
+        ConsumeEventsInstruction {
+            consume_events_admin: None,
+            market,
+            open_orders_accounts: vec![account_1, account_2],
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 200);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 20000);
+
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 1);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 10002);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_self_trade_cancel_provide() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        account_1,
+        account_2,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
+    let owner_quote_ata = context.users[0].token_accounts[1];
+    let owner_base_ata = context.users[0].token_accounts[0];
+
+    // maker (which will be the taker) limit order
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_base_ata,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots: 1000,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 1,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    // maker limit order
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_base_ata,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots: 1000,
+            max_base_lots: 2,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 2,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 1);
+        assert_eq!(open_orders_account_1.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 2);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+    }
+
+    // taker partial self-trade
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_quote_ata,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots: 1000,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 3,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::ImmediateOrCancel,
+            self_trade_behavior: SelfTradeBehavior::CancelProvide,
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 200);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 2);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+    }
+
+    // taker with another maker
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_quote_ata,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots: 1000,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10004,
+            client_order_id: 4,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 300);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 2);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+    }
+
+    send_tx(
+        solana,
+        ConsumeEventsInstruction {
+            consume_events_admin: None,
+            market,
+            open_orders_accounts: vec![account_1, account_2],
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 300);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 20004);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_self_trade_cancel_both_or_take() -> Result<(), TransportError> {
+    // the own maker order is cancelled with CancelBoth and left on the book with CancelTake
+    for (self_trade_behavior, own_asks_base_lots, own_base_free_native) in [
+        (SelfTradeBehavior::CancelBoth, 0, 200),
+        (SelfTradeBehavior::CancelTake, 1, 100),
+    ] {
+        let TestInitialize {
+            context,
+            owner,
+            owner_token_0: owner_base_ata,
+            owner_token_1: owner_quote_ata,
+            market,
+            market_base_vault,
+            market_quote_vault,
+            account_1,
+            account_2,
+            ..
+        } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+        let solana = &context.solana.clone();
+        set_account_self_trade_scope(solana, owner, account_2).await;
+
+        let place_ask_ix = |open_orders_account, price_lots| PlaceOrderInstruction {
+            open_orders_account,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_base_ata,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 1,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        };
+
+        // another maker, the self-trade maker and another maker behind it
+        send_tx(solana, place_ask_ix(account_2, 999)).await.unwrap();
+        send_tx(solana, place_ask_ix(account_1, 1000)).await.unwrap();
+        send_tx(solana, place_ask_ix(account_2, 1000)).await.unwrap();
+
+        send_tx(
+            solana,
+            PlaceOrderInstruction {
+                open_orders_account: account_1,
+                open_orders_admin: None,
+                market,
+                signer: owner,
+                user_token_account: owner_quote_ata,
+                market_vault: market_quote_vault,
+                side: Side::Bid,
+                price_lots: 1000,
+                max_base_lots: 3,
+                max_quote_lots_including_fees: 10000,
+                client_order_id: 2,
+                expiry_timestamp: 0,
+                order_type: PlaceOrderType::Limit,
+                self_trade_behavior,
+                remainings: vec![account_2],
+            },
+        )
+        .await
+        .unwrap();
+
+        {
+            let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+            let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+            // filled against the other maker only, nothing posted
+            assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+            assert_eq!(
+                open_orders_account_1.position.asks_base_lots,
+                own_asks_base_lots
+            );
+            assert_eq!(
+                open_orders_account_1.position.base_free_native,
+                own_base_free_native
+            );
+
+            assert_eq!(open_orders_account_2.position.asks_base_lots, 1);
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_self_abort_transaction() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        account_1,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+    let owner_quote_ata = context.users[0].token_accounts[1];
+    let owner_base_ata = context.users[0].token_accounts[0];
+
+    // taker limit order
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_base_ata,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots: 1000,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 1,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    // taker failing self-trade
+    assert!(send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_quote_ata,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots: 1000,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 2,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            remainings: vec![],
+        },
+    )
+    .await
+    .is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_self_trade_no_fees() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0: owner_base_ata,
+        owner_token_1: owner_quote_ata,
+        market,
+
+        market_base_vault,
+        market_quote_vault,
+        account_1: open_orders_account,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+
+    let place_bid_ix = PlaceOrderInstruction {
+        open_orders_account,
+        open_orders_admin: None,
+        market,
+        signer: owner,
+        user_token_account: owner_quote_ata,
+        market_vault: market_quote_vault,
+        side: Side::Bid,
+        price_lots: 1000,
+        max_base_lots: 1,
+        max_quote_lots_including_fees: 10000,
+        client_order_id: 1,
+        expiry_timestamp: 0,
+        order_type: PlaceOrderType::Limit,
+        self_trade_behavior: SelfTradeBehavior::default(),
+        remainings: vec![],
+    };
+
+    let place_ask_ix = PlaceOrderInstruction {
+        side: Side::Ask,
+        market_vault: market_base_vault,
+        user_token_account: owner_base_ata,
+        ..place_bid_ix.clone()
+    };
+
+    let consume_events_ix = ConsumeEventsInstruction {
+        consume_events_admin: None,
+        market,
+        open_orders_accounts: vec![open_orders_account],
+    };
+
+    let settle_funds_ix = SettleFundsInstruction {
+        owner,
+        market,
+        open_orders_account,
+        market_base_vault,
+        market_quote_vault,
+        user_base_account: owner_base_ata,
+        user_quote_account: owner_quote_ata,
+        referrer_account: None,
+    };
+
+    let balances_before = (
+        solana.token_account_balance(owner_base_ata).await,
+        solana.token_account_balance(owner_quote_ata).await,
+    );
+
+    send_tx(solana, place_bid_ix.clone()).await.unwrap();
+    send_tx(solana, place_ask_ix.clone()).await.unwrap();
+    send_tx(solana, consume_events_ix.clone()).await.unwrap();
+    send_tx(solana, settle_funds_ix.clone()).await.unwrap();
+
+    let balances_after = (
+        solana.token_account_balance(owner_base_ata).await,
+        solana.token_account_balance(owner_quote_ata).await,
+    );
+
+    assert_eq!(balances_before, balances_after);
+
+    send_tx(solana, place_ask_ix).await.unwrap();
+    send_tx(solana, place_bid_ix).await.unwrap();
+    send_tx(solana, consume_events_ix).await.unwrap();
+    send_tx(solana, settle_funds_ix).await.unwrap();
+
+    let balances_after = (
+        solana.token_account_balance(owner_base_ata).await,
+        solana.token_account_balance(owner_quote_ata).await,
+    );
+
+    assert_eq!(balances_before, balances_after);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_self_trade_across_accounts_of_owner() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0: owner_base_ata,
+        owner_token_1: owner_quote_ata,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        account_1,
+        account_2,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+
+    let set_scope = |open_orders_account, scope| SetSelfTradeScopeInstruction {
+        owner,
+        open_orders_account,
+        scope,
+    };
+
+    let place_ask_ix = PlaceOrderInstruction {
+        open_orders_account: account_2,
+        open_orders_admin: None,
+        market,
+        signer: owner,
+        user_token_account: owner_base_ata,
+        market_vault: market_base_vault,
+        side: Side::Ask,
+        price_lots: 1000,
+        max_base_lots: 1,
+        max_quote_lots_including_fees: 10000,
+        client_order_id: 1,
+        expiry_timestamp: 0,
+        order_type: PlaceOrderType::Limit,
+        self_trade_behavior: SelfTradeBehavior::default(),
+        remainings: vec![],
+    };
+
+    let place_bid_ix = |self_trade_behavior| PlaceOrderInstruction {
+        open_orders_account: account_1,
+        user_token_account: owner_quote_ata,
+        market_vault: market_quote_vault,
+        side: Side::Bid,
+        client_order_id: 2,
+        order_type: PlaceOrderType::ImmediateOrCancel,
+        self_trade_behavior,
+        remainings: vec![account_2],
+        ..place_ask_ix.clone()
+    };
+
+    // both accounts start out in the owner's group
+    send_tx(solana, place_ask_ix.clone()).await.unwrap();
+
+    assert_openbook_error(
+        &send_tx(solana, place_bid_ix(SelfTradeBehavior::AbortTransaction))
+            .await,
+        OpenBookError::WouldSelfTrade.error_code(),
+        "the other account of the owner is in the same group".into(),
+    );
+
+    // matched without fees or rebates
+    send_tx(solana, place_bid_ix(SelfTradeBehavior::DecrementTake))
+        .await
+        .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.base_free_native, 100);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 10000);
+    }
+
+    // the maker order of the other account is cancelled
+    send_tx(solana, place_ask_ix.clone()).await.unwrap();
+    send_tx(solana, place_bid_ix(SelfTradeBehavior::CancelProvide))
+        .await
+        .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.base_free_native, 100);
+
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.base_free_native, 100);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 10000);
+    }
+
+    // both accounts need the owner scope, otherwise it's a regular trade
+    send_tx(solana, set_scope(account_2, SelfTradeScope::Account))
+        .await
+        .unwrap();
+    let logs = solana.program_log_events::<SetSelfTradeScopeLog>();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].open_orders_account, account_2);
+    assert_eq!(logs[0].self_trade_scope, SelfTradeScope::Account as u8);
+
+    send_tx(solana, place_ask_ix.clone()).await.unwrap();
+    send_tx(solana, place_bid_ix(SelfTradeBehavior::DecrementTake))
+        .await
+        .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.base_free_native, 200);
+
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 20002);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_self_trade_with_orders_placed_without_group() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0: owner_base_ata,
+        owner_token_1: owner_quote_ata,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        account_1,
+        account_2,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
+
+    let place_ask_ix = PlaceOrderInstruction {
+        open_orders_account: account_2,
+        open_orders_admin: None,
+        market,
+        signer: owner,
+        user_token_account: owner_base_ata,
+        market_vault: market_base_vault,
+        side: Side::Ask,
+        price_lots: 1000,
+        max_base_lots: 1,
+        max_quote_lots_including_fees: 10000,
+        client_order_id: 1,
+        expiry_timestamp: 0,
+        order_type: PlaceOrderType::Limit,
+        self_trade_behavior: SelfTradeBehavior::default(),
+        remainings: vec![],
+    };
+
+    let place_bid_ix = |open_orders_account| PlaceOrderInstruction {
+        open_orders_account,
+        user_token_account: owner_quote_ata,
+        market_vault: market_quote_vault,
+        side: Side::Bid,
+        client_order_id: 2,
+        order_type: PlaceOrderType::ImmediateOrCancel,
+        self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+        remainings: vec![account_2],
+        ..place_ask_ix.clone()
+    };
+
+    // placed with SelfTradeScope::Account, like all orders from before self-trade groups,
+    // the order has stp_account_num == 0
+    send_tx(solana, place_ask_ix.clone()).await.unwrap();
+    send_tx(
+        solana,
+        SetSelfTradeScopeInstruction {
+            owner,
+            open_orders_account: account_2,
+            scope: SelfTradeScope::Owner,
+        },
+    )
+    .await
+    .unwrap();
+
+    // the maker's own account still can't trade with it
+    assert_openbook_error(
+        &send_tx(solana, place_bid_ix(account_2)).await,
+        OpenBookError::WouldSelfTrade.error_code(),
+        "the order belongs to the taker's account".into(),
+    );
+
+    // other accounts of the owner match it as a regular trade
+    send_tx(solana, place_bid_ix(account_1)).await.unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.base_free_native, 100);
+
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 10002);
+    }
+
+    // orders placed after the change are in the owner's group
+    send_tx(solana, place_ask_ix).await.unwrap();
+    assert_openbook_error(
+        &send_tx(solana, place_bid_ix(account_1)).await,
+        OpenBookError::WouldSelfTrade.error_code(),
+        "the order was placed in the owner's group".into(),
+    );
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_swap.rs
+use super::*;
+
+#[tokio::test]
+async fn test_swap_quote_for_base() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 2,
+            max_quote_lots_including_fees: 20000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    let balance_base = solana.token_account_balance(owner_token_0).await;
+    let balance_quote = solana.token_account_balance(owner_token_1).await;
+
+    // enough for one lot including taker fees, but not for a second one
+    let swap = |min_amount_out: u64| SwapInstruction {
+        market,
+        signer: owner,
+        user_base_account: owner_token_0,
+        user_quote_account: owner_token_1,
+        market_base_vault,
+        market_quote_vault,
+        side: Side::Bid,
+        amount_in: 150_000,
+        min_amount_out,
+    };
+
+    assert_eq!(
+        send_tx_and_get_ix_custom_error(solana, swap(101)).await,
+        Some(OpenBookError::SwapSlippageExceeded.into())
+    );
+    assert_eq!(
+        balance_quote,
+        solana.token_account_balance(owner_token_1).await
+    );
+
+    let amount_out = simulate_tx_and_get_return::<_, u64>(solana, swap(100))
+        .await
+        .unwrap();
+    assert_eq!(amount_out, 100);
+
+    send_tx(solana, swap(100)).await.unwrap();
+
+    assert_eq!(
+        balance_base + 100,
+        solana.token_account_balance(owner_token_0).await
+    );
+    assert_eq!(
+        balance_quote - 100_020,
+        solana.token_account_balance(owner_token_1).await
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_swap_base_for_quote() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 2,
+            max_quote_lots_including_fees: 20000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    let balance_base = solana.token_account_balance(owner_token_0).await;
+    let balance_quote = solana.token_account_balance(owner_token_1).await;
+
+    // amount_in is rounded down to one base lot
+    send_tx(
+        solana,
+        SwapInstruction {
+            market,
+            signer: owner,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            market_base_vault,
+            market_quote_vault,
+            side: Side::Ask,
+            amount_in: 150,
+            min_amount_out: 99_980,
+        },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        balance_base - 100,
+        solana.token_account_balance(owner_token_0).await
+    );
+    assert_eq!(
+        balance_quote + 99_980,
+        solana.token_account_balance(owner_token_1).await
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_swap_exact_base_out() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    let balance_base = solana.token_account_balance(owner_token_0).await;
+    let balance_quote = solana.token_account_balance(owner_token_1).await;
+
+    let swap = |amount_out: u64, max_amount_in: u64| SwapExactOutInstruction {
+        market,
+        signer: owner,
+        user_base_account: owner_token_0,
+        user_quote_account: owner_token_1,
+        market_base_vault,
+        market_quote_vault,
+        side: Side::Bid,
+        amount_out,
+        max_amount_in,
+    };
+
+    // not a whole number of base lots
+    assert_eq!(
+        send_tx_and_get_ix_custom_error(solana, swap(150, 200_000)).await,
+        Some(OpenBookError::InvalidInputLotsSize.into())
+    );
+    // more than the book holds
+    assert_eq!(
+        send_tx_and_get_ix_custom_error(solana, swap(200, 200_000)).await,
+        Some(OpenBookError::WouldExecutePartially.into())
+    );
+    // one lot costs 100_020 including fees
+    assert_eq!(
+        send_tx_and_get_ix_custom_error(solana, swap(100, 100_019)).await,
+        Some(OpenBookError::SwapSlippageExceeded.into())
+    );
+
+    let amount_in = simulate_tx_and_get_return::<_, u64>(solana, swap(100, 100_020))
+        .await
+        .unwrap();
+    assert_eq!(amount_in, 100_020);
+
+    send_tx(solana, swap(100, 100_020)).await.unwrap();
+
+    assert_eq!(
+        balance_base + 100,
+        solana.token_account_balance(owner_token_0).await
+    );
+    assert_eq!(
+        balance_quote - 100_020,
+        solana.token_account_balance(owner_token_1).await
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_swap_exact_quote_out() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 2,
+            max_quote_lots_including_fees: 20000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    let balance_base = solana.token_account_balance(owner_token_0).await;
+    let balance_quote = solana.token_account_balance(owner_token_1).await;
+
+    let swap = |amount_out: u64| SwapExactOutInstruction {
+        market,
+        signer: owner,
+        user_base_account: owner_token_0,
+        user_quote_account: owner_token_1,
+        market_base_vault,
+        market_quote_vault,
+        side: Side::Ask,
+        amount_out,
+        max_amount_in: 1_000,
+    };
+
+    // selling one lot yields 100_000 minus the 20 maker rebate, nothing in between
+    assert_eq!(
+        send_tx_and_get_ix_custom_error(solana, swap(99_981)).await,
+        Some(OpenBookError::WouldExecutePartially.into())
+    );
+
+    send_tx(solana, swap(99_980)).await.unwrap();
+
+    assert_eq!(
+        balance_base - 100,
+        solana.token_account_balance(owner_token_0).await
+    );
+    assert_eq!(
+        balance_quote + 99_980,
+        solana.token_account_balance(owner_token_1).await
+    );
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_swap_route.rs
+use super::*;
+
+#[tokio::test]
+async fn test_swap_route() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        collect_fee_admin,
+        owner,
+        payer,
+        mints,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+    let owner_token_2 = context.users[0].token_accounts[2];
+
+    // Second market sharing the quote mint of the first one
+    let openbook_v2::accounts::CreateMarket {
+        market: second_market,
+        market_base_vault: second_market_base_vault,
+        ..
+    } = send_tx(
+        solana,
+        CreateMarketInstruction {
+            collect_fee_admin: collect_fee_admin.pubkey(),
+            open_orders_admin: None,
+            close_market_admin: None,
+            payer,
+            market: TestKeypair::new(),
+            quote_lot_size: 10,
+            base_lot_size: 100,
+            maker_fee: -200,
+            taker_fee: 400,
+            base_mint: mints[2].pubkey,
+            quote_mint: mints[1].pubkey,
+            ..CreateMarketInstruction::with_new_book_and_heap(solana, None, None).await
+        },
+    )
+    .await
+    .unwrap();
+    let _indexer =
+        create_open_orders_indexer(solana, &context.users[1], owner, second_market).await;
+    let second_account =
+        create_open_orders_account(solana, owner, second_market, 1, &context.users[1], None)
+            .await;
+
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 20000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    // At half the price, the intermediate quote is enough for one base lot but not for two
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: second_account,
+            open_orders_admin: None,
+            market: second_market,
+            signer: owner,
+            user_token_account: owner_token_2,
+            market_vault: second_market_base_vault,
+            side: Side::Ask,
+            price_lots: price_lots / 2,
+            max_base_lots: 2,
+            max_quote_lots_including_fees: 20000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    let swap_route = |user_output_account, min_amount_out| SwapRouteInstruction {
+        signer: owner,
+        first_market: market,
+        second_market,
+        user_input_account: owner_token_0,
+        user_intermediate_account: owner_token_1,
+        user_output_account,
+        amount_in: 100,
+        min_amount_out,
+    };
+
+    assert_openbook_error(
+        &send_tx(solana, swap_route(owner_token_0, 0)).await,
+        OpenBookError::InvalidRoute.error_code(),
+        "output account has the input mint".into(),
+    );
+    assert_openbook_error(
+        &send_tx(solana, swap_route(owner_token_2, 101)).await,
+        OpenBookError::SwapSlippageExceeded.error_code(),
+        "only one base lot of the second market can be bought".into(),
+    );
+
+    let balance_0 = solana.token_account_balance(owner_token_0).await;
+    let balance_1 = solana.token_account_balance(owner_token_1).await;
+    let balance_2 = solana.token_account_balance(owner_token_2).await;
+
+    let amount_out =
+        simulate_tx_and_get_return::<_, u64>(solana, swap_route(owner_token_2, 100))
+            .await
+            .unwrap();
+    assert_eq!(amount_out, 100);
+
+    send_tx(solana, swap_route(owner_token_2, 100)).await.unwrap();
+
+    // 100 base sold for 99_980 quote, of which 50_010 bought 100 of the second base
+    assert_eq!(
+        balance_0 - 100,
+        solana.token_account_balance(owner_token_0).await
+    );
+    assert_eq!(
+        balance_1 + 99_980 - 50_010,
+        solana.token_account_balance(owner_token_1).await
+    );
+    assert_eq!(
+        balance_2 + 100,
+        solana.token_account_balance(owner_token_2).await
+    );
+
+    // The intermediate quote moved straight into the second market's vault
+    let second_market = solana.get_account::<Market>(second_market).await;
+    assert_eq!(second_market.quote_deposit_total, 50_010);
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_take_order.rs
+use super::*;
+
+#[tokio::test]
+async fn test_take_ask_order() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        collect_fee_admin,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        tokens,
+        account_1,
+        account_2,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+
+    // Set the initial oracle price
+    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    let balance_base = solana.token_account_balance(owner_token_0).await;
+    let balance_quote = solana.token_account_balance(owner_token_1).await;
+
+    send_tx(
+        solana,
+        PlaceTakeOrderInstruction {
+            market,
+            signer: owner,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            market_base_vault,
+            market_quote_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            open_orders_admin: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 1);
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+        assert_eq!(
+            balance_base - 100,
+            solana.token_account_balance(owner_token_0).await
+        );
+        assert_eq!(
+            balance_quote + 99980,
+            solana.token_account_balance(owner_token_1).await
+        );
+    }
+
+    send_tx(
+        solana,
+        ConsumeEventsInstruction {
+            consume_events_admin: None,
+            market,
+            open_orders_accounts: vec![account_1],
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 100);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 20);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+    }
+
+    send_tx(
+        solana,
+        SettleFundsInstruction {
+            owner,
+            market,
+            open_orders_account: account_1,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            referrer_account: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_take_bid_order() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        collect_fee_admin,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        tokens,
+        account_1,
+        account_2,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+
+    // Set the initial oracle price
+    set_stub_oracle_price(solana, &tokens[1], collect_fee_admin, 1000.0).await;
+
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    let balance_base = solana.token_account_balance(owner_token_0).await;
+    let balance_quote = solana.token_account_balance(owner_token_1).await;
+
+    send_tx(
+        solana,
+        PlaceTakeOrderInstruction {
+            market,
+            signer: owner,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            market_base_vault,
+            market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10040,
+            open_orders_admin: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 1);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+        assert_eq!(
+            balance_base + 100,
+            solana.token_account_balance(owner_token_0).await
+        );
+        assert_eq!(
+            balance_quote - 100020,
+            solana.token_account_balance(owner_token_1).await
+        );
+    }
+
+    send_tx(
+        solana,
+        ConsumeEventsInstruction {
+            consume_events_admin: None,
+            market,
+            open_orders_accounts: vec![account_1],
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.bids_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_2.position.asks_base_lots, 0);
+        assert_eq!(open_orders_account_1.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 100020);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+    }
+
+    send_tx(
+        solana,
+        SettleFundsInstruction {
+            owner,
+            market,
+            open_orders_account: account_1,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            referrer_account: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let open_orders_account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+
+        assert_eq!(open_orders_account_1.position.base_free_native, 0);
+        assert_eq!(open_orders_account_2.position.base_free_native, 0);
+        assert_eq!(open_orders_account_1.position.quote_free_native, 0);
+        assert_eq!(open_orders_account_2.position.quote_free_native, 0);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_negative_spread_ask() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        account_1,
+        account_2,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize {
+        quote_lot_size: 100,
+        base_lot_size: 1_000_000_000,
+        ..TestNewMarketInitialize::default()
+    })
+    .await?;
+    let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
+
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots: 10_000,     // $1
+            max_base_lots: 1000000, // wahtever
+            max_quote_lots_including_fees: 10_000,
+            client_order_id: 1,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    // This order doesn't take any due max_quote_lots_including_fees but it's also don't post in on the book
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots: 7_500,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 7_500,
+            client_order_id: 25,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    let position = solana
+        .get_account::<OpenOrdersAccount>(account_2)
+        .await
+        .position;
+
+    assert_eq!(position.asks_base_lots, 0);
+    assert_eq!(position.bids_base_lots, 0);
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_transfer_free_balance.rs
+use super::*;
+
+#[tokio::test]
+async fn test_transfer_free_balance() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        payer,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        account_1,
+        account_2,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+
+    let transfer = |destination_open_orders_account, base_amount, quote_amount| {
+        TransferFreeBalanceInstruction {
+            owner,
+            source_open_orders_account: account_1,
+            destination_open_orders_account,
+            market,
+            base_amount,
+            quote_amount,
+        }
+    };
+
+    send_tx(
+        solana,
+        DepositInstruction {
+            owner,
+            market,
+            open_orders_account: account_1,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            base_amount: 1_000,
+            quote_amount: 2_000,
+        },
+    )
+    .await
+    .unwrap();
+
+    assert_openbook_error(
+        &send_tx(solana, transfer(account_1, 100, 0)).await,
+        OpenBookError::SameOpenOrdersAccount.error_code(),
+        "cannot transfer to the source account".into(),
+    );
+
+    assert_openbook_error(
+        &send_tx(solana, transfer(account_2, 0, 2_001)).await,
+        OpenBookError::InsufficientFreeBalance.error_code(),
+        "quote amount above the free balance".into(),
+    );
+
+    let payer_account =
+        create_open_orders_account(solana, payer, market, 3, &context.users[1], None).await;
+    assert!(send_tx(solana, transfer(payer_account, 100, 0)).await.is_err());
+
+    send_tx(solana, transfer(account_2, 300, 2_000)).await.unwrap();
+
+    {
+        let account_1 = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        assert_eq!(account_1.position.base_free_native, 700);
+        assert_eq!(account_1.position.quote_free_native, 0);
+
+        let account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+        assert_eq!(account_2.position.base_free_native, 300);
+        assert_eq!(account_2.position.quote_free_native, 2_000);
+
+        let market = solana.get_account::<Market>(market).await;
+        assert_eq!(market.base_deposit_total, 1_000);
+        assert_eq!(market.quote_deposit_total, 2_000);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_transfer_free_balance_with_unpaid_penalty() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        account_2,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
+
+    let place_order_ix = |open_orders_account, side, user_token_account, market_vault| {
+        PlaceOrderInstruction {
+            open_orders_account,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account,
+            market_vault,
+            side,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        }
+    };
+
+    // the taker owes the penalty for the fill event, test_withdraw covers paying it
+    send_tx(
+        solana,
+        place_order_ix(account_1, Side::Bid, owner_token_1, market_quote_vault),
+    )
+    .await
+    .unwrap();
+    send_tx(
+        solana,
+        place_order_ix(account_2, Side::Ask, owner_token_0, market_base_vault),
+    )
+    .await
+    .unwrap();
+
+    assert_openbook_error(
+        &send_tx(
+            solana,
+            TransferFreeBalanceInstruction {
+                owner,
+                source_open_orders_account: account_2,
+                destination_open_orders_account: account_1,
+                market,
+                base_amount: 0,
+                quote_amount: 1,
+            },
+        )
+        .await,
+        OpenBookError::UnpaidEventHeapPenalty.error_code(),
+        "the penalty is paid before funds leave the account".into(),
+    );
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_view_book.rs
+use super::*;
+use openbook_v2::{BookDepth, BookLevel, BookPrices};
+
+#[tokio::test]
+async fn test_view_book() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+
+    let best_bid_ask = simulate_tx_and_get_return::<_, BookPrices>(
+        solana,
+        GetBestBidAskInstruction { market },
+    )
+    .await
+    .unwrap();
+    assert_eq!(best_bid_ask, BookPrices::default());
+
+    for (side, price_lots, max_base_lots) in [
+        (Side::Bid, price_lots - 10, 2),
+        (Side::Bid, price_lots - 10, 1),
+        (Side::Bid, price_lots - 20, 1),
+        (Side::Ask, price_lots + 10, 1),
+    ] {
+        let (user_token_account, market_vault) = match side {
+            Side::Bid => (owner_token_1, market_quote_vault),
+            Side::Ask => (owner_token_0, market_base_vault),
+        };
+        send_tx(
+            solana,
+            PlaceOrderInstruction {
+                open_orders_account: account_1,
+                open_orders_admin: None,
+                market,
+                signer: owner,
+                user_token_account,
+                market_vault,
+                side,
+                price_lots,
+                max_base_lots,
+                max_quote_lots_including_fees: 100_000,
+                client_order_id: 0,
+                expiry_timestamp: 0,
+                order_type: PlaceOrderType::Limit,
+                self_trade_behavior: SelfTradeBehavior::default(),
+                remainings: vec![],
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    // oracle price is 10 lots, this bid sits at 9
+    send_tx(
+        solana,
+        PlaceOrderPeggedInstruction {
+            open_orders_account: account_1,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_offset: -1,
+            peg_limit: 100,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 100_000,
+            client_order_id: 0,
+        },
+    )
+    .await
+    .unwrap();
+
+    let best_bid_ask = simulate_tx_and_get_return::<_, BookPrices>(
+        solana,
+        GetBestBidAskInstruction { market },
+    )
+    .await
+    .unwrap();
+    assert_eq!(best_bid_ask.bid_price_lots, Some(price_lots - 10));
+    assert_eq!(best_bid_ask.ask_price_lots, Some(price_lots + 10));
+
+    let depth = simulate_tx_and_get_return::<_, BookDepth>(
+        solana,
+        GetDepthInstruction { market, levels: 2 },
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        depth.bids,
+        vec![
+            BookLevel {
+                price_lots: price_lots - 10,
+                base_lots: 3
+            },
+            BookLevel {
+                price_lots: price_lots - 20,
+                base_lots: 1
+            },
+        ]
+    );
+    assert_eq!(
+        depth.asks,
+        vec![BookLevel {
+            price_lots: price_lots + 10,
+            base_lots: 1
+        }]
+    );
+
+    let depth = simulate_tx_and_get_return::<_, BookDepth>(
+        solana,
+        GetDepthInstruction {
+            market,
+            levels: u8::MAX,
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(depth.bids.len(), 3);
+    assert_eq!(
+        depth.bids[2],
+        BookLevel {
+            price_lots: 9,
+            base_lots: 1
+        }
+    );
+
+    for (base_lots, bid_price_lots, ask_price_lots) in [
+        (1, Some(price_lots - 10), Some(price_lots + 10)),
+        (3, Some(price_lots - 10), None),
+        (4, Some(price_lots - 20), None),
+        (5, Some(9), None),
+        (6, None, None),
+    ] {
+        let impact_price = simulate_tx_and_get_return::<_, BookPrices>(
+            solana,
+            GetImpactPriceInstruction { market, base_lots },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            impact_price,
+            BookPrices {
+                bid_price_lots,
+                ask_price_lots
+            }
+        );
+    }
+
+    assert!(simulate_tx_and_get_return::<_, BookPrices>(
+        solana,
+        GetImpactPriceInstruction {
+            market,
+            base_lots: 0
+        },
+    )
+    .await
+    .is_err());
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/tests/cases/test_withdraw.rs
+use super::*;
+use openbook_v2::logs::WithdrawLog;
+
+#[tokio::test]
+async fn test_withdraw() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        account_1,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+
+    let withdraw = |base_amount, quote_amount| WithdrawInstruction {
+        owner,
+        open_orders_account: account_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        user_base_account: owner_token_0,
+        user_quote_account: owner_token_1,
+        base_amount,
+        quote_amount,
+    };
+
+    send_tx(
+        solana,
+        DepositInstruction {
+            owner,
+            market,
+            open_orders_account: account_1,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            base_amount: 1_000,
+            quote_amount: 2_000,
+        },
+    )
+    .await
+    .unwrap();
+
+    let base_balance = solana.token_account_balance(owner_token_0).await;
+    let quote_balance = solana.token_account_balance(owner_token_1).await;
+
+    assert_openbook_error(
+        &send_tx(solana, withdraw(1_001, 0)).await,
+        OpenBookError::InsufficientFreeBalance.error_code(),
+        "base amount above the free balance".into(),
+    );
+
+    send_tx(solana, withdraw(400, 1_500)).await.unwrap();
+
+    assert_eq!(
+        solana.token_account_balance(owner_token_0).await,
+        base_balance + 400
+    );
+    assert_eq!(
+        solana.token_account_balance(owner_token_1).await,
+        quote_balance + 1_500
+    );
+    {
+        let open_orders_account = solana.get_account::<OpenOrdersAccount>(account_1).await;
+        assert_eq!(open_orders_account.position.base_free_native, 600);
+        assert_eq!(open_orders_account.position.quote_free_native, 500);
+
+        let market = solana.get_account::<Market>(market).await;
+        assert_eq!(market.base_deposit_total, 600);
+        assert_eq!(market.quote_deposit_total, 500);
+    }
+
+    let log = solana.program_log_events::<WithdrawLog>().pop().unwrap();
+    assert_eq!(log.open_orders_account, account_1);
+    assert_eq!(log.base_native, 400);
+    assert_eq!(log.quote_native, 1_500);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_withdraw_charges_event_heap_penalty() -> Result<(), TransportError> {
+    let TestInitialize {
+        context,
+        owner,
+        owner_token_0,
+        owner_token_1,
+        market,
+        market_base_vault,
+        market_quote_vault,
+        price_lots,
+        account_1,
+        account_2,
+        ..
+    } = TestContext::new_with_market(TestNewMarketInitialize::default()).await?;
+    let solana = &context.solana.clone();
+    set_account_self_trade_scope(solana, owner, account_2).await;
+
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_1,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_1,
+            market_vault: market_quote_vault,
+            side: Side::Bid,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    // the taker owes the penalty for the fill event
+    send_tx(
+        solana,
+        PlaceOrderInstruction {
+            open_orders_account: account_2,
+            open_orders_admin: None,
+            market,
+            signer: owner,
+            user_token_account: owner_token_0,
+            market_vault: market_base_vault,
+            side: Side::Ask,
+            price_lots,
+            max_base_lots: 1,
+            max_quote_lots_including_fees: 10000,
+            client_order_id: 0,
+            expiry_timestamp: 0,
+            order_type: PlaceOrderType::Limit,
+            self_trade_behavior: SelfTradeBehavior::default(),
+            remainings: vec![],
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        solana
+            .get_account::<OpenOrdersAccount>(account_2)
+            .await
+            .position
+            .penalty_heap_count,
+        1
+    );
+
+    let owner_lamports_before = solana.get_lamports(owner.pubkey()).await;
+    let market_lamports_before = solana.get_lamports(market).await;
+
+    send_tx(
+        solana,
+        WithdrawInstruction {
+            owner,
+            open_orders_account: account_2,
+            market,
+            market_base_vault,
+            market_quote_vault,
+            user_base_account: owner_token_0,
+            user_quote_account: owner_token_1,
+            base_amount: 0,
+            quote_amount: 1,
+        },
+    )
+    .await
+    .unwrap();
+
+    {
+        let open_orders_account_2 = solana.get_account::<OpenOrdersAccount>(account_2).await;
+        assert_eq!(open_orders_account_2.position.penalty_heap_count, 0);
+
+        let market_acc = solana.get_account::<Market>(market).await;
+        assert_eq!(market_acc.crank_reward_pool, PENALTY_EVENT_HEAP);
+    }
+    assert_eq!(
+        solana.get_lamports(owner.pubkey()).await,
+        owner_lamports_before - PENALTY_EVENT_HEAP
+    );
+    assert_eq!(
+        solana.get_lamports(market).await,
+        market_lamports_before + PENALTY_EVENT_HEAP
+    );
+
+    Ok(())
+}
+
+
+// File: openbook-v2/programs/openbook-v2/tests/program_test/client.rs
+#![allow(dead_code)]
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token::{spl_token, Token, TokenAccount},
+};
+use solana_program::instruction::Instruction;
+use solana_program_test::BanksClientError;
+use solana_sdk::instruction;
+use solana_sdk::transport::TransportError;
+use std::sync::Arc;
+
+use super::solana::SolanaCookie;
+use super::utils::TestKeypair;
+use openbook_v2::{
+    state::*, PlaceMultipleOrdersArgs, PlaceOrderArgs, PlaceOrderPeggedArgs, PlaceTakeOrderArgs,
+    SwapArgs, SwapExactOutArgs, SwapRouteArgs,
+};
+
+#[async_trait::async_trait(?Send)]
+pub trait ClientAccountLoader {
+    async fn load_bytes(&self, pubkey: &Pubkey) -> Option<Vec<u8>>;
+    async fn load<T: AccountDeserialize>(&self, pubkey: &Pubkey) -> Option<T> {
+        let bytes = self.load_bytes(pubkey).await?;
+        AccountDeserialize::try_deserialize(&mut &bytes[..]).ok()
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl ClientAccountLoader for &SolanaCookie {
+    async fn load_bytes(&self, pubkey: &Pubkey) -> Option<Vec<u8>> {
+        self.get_account_data(*pubkey).await
+    }
+}
+
+// TODO: report error outwards etc
+pub async fn send_tx<CI: ClientInstruction>(
+    solana: &SolanaCookie,
+    ix: CI,
+) -> std::result::Result<CI::Accounts, TransportError> {
+    let (accounts, instruction) = ix.to_instruction(solana).await;
+    let signers = ix.signers();
+    let instructions = vec![instruction];
+    solana
+        .process_transaction(&instructions, Some(&signers[..]))
+        .await?;
+    Ok(accounts)
+}
+
+/// Simulate a single instruction and deserialize its return value
+pub async fn simulate_tx_and_get_return<CI: ClientInstruction, T: AnchorDeserialize>(
+    solana: &SolanaCookie,
+    ix: CI,
+) -> std::result::Result<T, BanksClientError> {
+    let (_, instruction) = ix.to_instruction(solana).await;
+    let signers = ix.signers();
+    let return_data = solana
+        .simulate_transaction(&[instruction], Some(&signers[..]))
+        .await?
+        .unwrap_or_default();
+    Ok(T::try_from_slice(&return_data).unwrap())
+}
+
+pub async fn send_tx_and_get_ix_custom_error<CI: ClientInstruction>(
+    solana: &SolanaCookie,
+    ix: CI,
+) -> Option<u32> {
+    let tx_result = send_tx(solana, ix).await;
+
+    if let Err(TransportError::TransactionError(
+        solana_sdk::transaction::TransactionError::InstructionError(
+            _,
+            solana_sdk::instruction::InstructionError::Custom(err_num),
+        ),
+    )) = tx_result
+    {
+        Some(err_num)
+    } else {
+        None
+    }
+}
+
+/// Build a transaction from multiple instructions
+pub struct ClientTransaction {
+    solana: Arc<SolanaCookie>,
+    instructions: Vec<instruction::Instruction>,
+    signers: Vec<TestKeypair>,
+}
+
+impl<'a> ClientTransaction {
+    pub fn new(solana: &Arc<SolanaCookie>) -> Self {
+        Self {
+            solana: solana.clone(),
+            instructions: vec![],
+            signers: vec![],
+        }
+    }
+
+    pub async fn add_instruction<CI: ClientInstruction>(&mut self, ix: CI) -> CI::Accounts {
+        let solana: &SolanaCookie = &self.solana;
+        let (accounts, instruction) = ix.to_instruction(solana).await;
+        self.instructions.push(instruction);
+        self.signers.extend(ix.signers());
+        accounts
+    }
+
+    pub fn add_instruction_direct(&mut self, ix: instruction::Instruction) {
+        self.instructions.push(ix);
+    }
+
+    pub fn add_signer(&mut self, keypair: TestKeypair) {
+        self.signers.push(keypair);
+    }
+
+    pub async fn send(&self) -> std::result::Result<(), BanksClientError> {
+        self.solana
+            .process_transaction(&self.instructions, Some(&self.signers))
+            .await
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+pub trait ClientInstruction {
+    type Accounts: anchor_lang::ToAccountMetas;
+    type Instruction: anchor_lang::InstructionData;
+
+    async fn to_instruction(
+        &self,
+        loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction);
+    fn signers(&self) -> Vec<TestKeypair>;
+}
+
+fn make_instruction(
+    program_id: Pubkey,
+    accounts: &impl anchor_lang::ToAccountMetas,
+    data: impl anchor_lang::InstructionData,
+) -> instruction::Instruction {
+    instruction::Instruction {
+        program_id,
+        accounts: anchor_lang::ToAccountMetas::to_account_metas(accounts, None),
+        data: anchor_lang::InstructionData::data(&data),
+    }
+}
+
+pub fn get_market_address(market: TestKeypair) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"Market".as_ref(), market.pubkey().to_bytes().as_ref()],
+        &openbook_v2::id(),
+    )
+    .0
+}
+
+pub fn get_event_authority_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"__event_authority".as_ref()], &openbook_v2::id()).0
+}
+
+pub fn get_open_orders_delegate_address(open_orders_account: Pubkey, delegate: Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"OpenOrdersDelegate".as_ref(),
+            open_orders_account.as_ref(),
+            delegate.as_ref(),
+        ],
+        &openbook_v2::id(),
+    )
+    .0
+}
+
+pub fn get_market_allowlist_entry_address(market: Pubkey, key: Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            MarketList::Allowlist.seed(),
+            market.as_ref(),
+            key.as_ref(),
+        ],
+        &openbook_v2::id(),
+    )
+    .0
+}
+
+/// The allowlist entry of the first allowlisted key, if the market has an allowlist admin
+async fn market_allowlist_entry(
+    account_loader: &impl ClientAccountLoader,
+    market_pk: Pubkey,
+    market: &Market,
+    keys: &[Pubkey],
+) -> Option<Pubkey> {
+    if market.allowlist_admin.is_none() {
+        return None;
+    }
+    for key in keys {
+        let address = get_market_allowlist_entry_address(market_pk, *key);
+        if account_loader.load_bytes(&address).await.is_some() {
+            return Some(address);
+        }
+    }
+    None
+}
+
+async fn open_orders_owner(
+    account_loader: &impl ClientAccountLoader,
+    open_orders_account: Pubkey,
+) -> Pubkey {
+    let account: OpenOrdersAccount = account_loader.load(&open_orders_account).await.unwrap();
+    account.owner
+}
+
+/// The keys checked against the allowlist when placing orders through an open orders account
+async fn open_orders_allowlist_keys(
+    account_loader: &impl ClientAccountLoader,
+    open_orders_account: Pubkey,
+) -> [Pubkey; 2] {
+    [
+        open_orders_owner(account_loader, open_orders_account).await,
+        open_orders_account,
+    ]
+}
+
+pub fn get_market_denylist_entry_address(market: Pubkey, key: Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            MarketList::Denylist.seed(),
+            market.as_ref(),
+            key.as_ref(),
+        ],
+        &openbook_v2::id(),
+    )
+    .0
+}
+
+/// The denylist entry address of `key`, if the market has a denylist admin
+fn market_denylist_entry(market_pk: Pubkey, market: &Market, key: Pubkey) -> Option<Pubkey> {
+    market
+        .denylist_admin
+        .is_some()
+        .then(|| get_market_denylist_entry_address(market_pk, key))
+}
+
+/// The denylist entry address of the owner of `token_account`, if the market has a denylist
+/// admin
+async fn token_owner_denylist_entry(
+    account_loader: &impl ClientAccountLoader,
+    market_pk: Pubkey,
+    market: &Market,
+    token_account: Pubkey,
+) -> Option<Pubkey> {
+    if market.denylist_admin.is_none() {
+        return None;
+    }
+    let account: TokenAccount = account_loader.load(&token_account).await.unwrap();
+    Some(get_market_denylist_entry_address(market_pk, account.owner))
+}
+
+/// The OpenOrdersDelegate of `signer`, if one was added for the account
+async fn open_orders_delegate(
+    account_loader: &impl ClientAccountLoader,
+    open_orders_account: Pubkey,
+    signer: Pubkey,
+) -> Option<Pubkey> {
+    let address = get_open_orders_delegate_address(open_orders_account, signer);
+    account_loader.load_bytes(&address).await.map(|_| address)
+}
+
+pub fn get_native_settle_address(market: Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"NativeSettle".as_ref(), market.as_ref()],
+        &openbook_v2::id(),
+    )
+    .0
+}
+
+/// Passing the wallet itself instead of one of its token accounts moves native SOL
+fn token_account_or_native(account: Pubkey, wallet: Pubkey) -> Option<Pubkey> {
+    (account != wallet).then_some(account)
+}
+
+pub async fn set_stub_oracle_price(
+    solana: &SolanaCookie,
+    token: &super::setup::Token,
+    owner: TestKeypair,
+    price: f64,
+) {
+    send_tx(
+        solana,
+        StubOracleSetInstruction {
+            owner,
+            mint: token.mint.pubkey,
+            price,
+        },
+    )
+    .await
+    .unwrap();
+}
+
+pub struct CreateOpenOrdersIndexerInstruction {
+    pub market: Pubkey,
+    pub owner: TestKeypair,
+    pub payer: TestKeypair,
+}
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for CreateOpenOrdersIndexerInstruction {
+    type Accounts = openbook_v2::accounts::CreateOpenOrdersIndexer;
+    type Instruction = openbook_v2::instruction::CreateOpenOrdersIndexer;
+    async fn to_instruction(
+        &self,
+        _account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = openbook_v2::instruction::CreateOpenOrdersIndexer {};
+
+        let open_orders_indexer = Pubkey::find_program_address(
+            &[b"OpenOrdersIndexer".as_ref(), self.owner.pubkey().as_ref()],
+            &program_id,
+        )
+        .0;
+
+        let accounts = openbook_v2::accounts::CreateOpenOrdersIndexer {
+            payer: self.payer.pubkey(),
+            owner: self.owner.pubkey(),
+            open_orders_indexer,
+            system_program: System::id(),
+        };
+
+        let instruction = make_instruction(program_id, &accounts, instruction);
+        (accounts, instruction)
+    }
+
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![self.owner, self.payer]
+    }
+}
+
+pub struct CreateOpenOrdersAccountInstruction {
+    pub account_num: u32,
+    pub market: Pubkey,
+    pub owner: TestKeypair,
+    pub payer: TestKeypair,
+    pub delegate: Option<Pubkey>,
+}
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for CreateOpenOrdersAccountInstruction {
+    type Accounts = openbook_v2::accounts::CreateOpenOrdersAccount;
+    type Instruction = openbook_v2::instruction::CreateOpenOrdersAccount;
+    async fn to_instruction(
+        &self,
+        account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = openbook_v2::instruction::CreateOpenOrdersAccount {
+            name: "test".to_string(),
+        };
+
+        let open_orders_indexer = Pubkey::find_program_address(
+            &[b"OpenOrdersIndexer".as_ref(), self.owner.pubkey().as_ref()],
+            &program_id,
+        )
+        .0;
+
+        let open_orders_account = Pubkey::find_program_address(
+            &[
+                b"OpenOrders".as_ref(),
+                self.owner.pubkey().as_ref(),
+                &self.account_num.to_le_bytes(),
+            ],
+            &program_id,
+        )
+        .0;
+
+        let market: Market = account_loader.load(&self.market).await.unwrap();
+
+        let accounts = openbook_v2::accounts::CreateOpenOrdersAccount {
+            owner: self.owner.pubkey(),
+            open_orders_indexer,
+            open_orders_account,
+            market: self.market,
+            payer: self.payer.pubkey(),
+            delegate_account: self.delegate,
+            system_program: System::id(),
+            owner_denylist_entry: market_denylist_entry(self.market, &market, self.owner.pubkey()),
+        };
+
+        let instruction = make_instruction(program_id, &accounts, instruction);
+        (accounts, instruction)
+    }
+
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![self.owner, self.payer]
+    }
+}
+
+pub struct CloseOpenOrdersAccountInstruction {
+    pub account_num: u32,
+    pub market: Pubkey,
+    pub owner: TestKeypair,
+    pub sol_destination: Pubkey,
+}
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for CloseOpenOrdersAccountInstruction {
+    type Accounts = openbook_v2::accounts::CloseOpenOrdersAccount;
+    type Instruction = openbook_v2::instruction::CloseOpenOrdersAccount;
+    async fn to_instruction(
+        &self,
+        _account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = openbook_v2::instruction::CloseOpenOrdersAccount {};
+
+        let open_orders_indexer = Pubkey::find_program_address(
+            &[b"OpenOrdersIndexer".as_ref(), self.owner.pubkey().as_ref()],
+            &program_id,
+        )
+        .0;
+
+        let open_orders_account = Pubkey::find_program_address(
+            &[
+                b"OpenOrders".as_ref(),
+                self.owner.pubkey().as_ref(),
+                &self.account_num.to_le_bytes(),
+            ],
+            &program_id,
+        )
+        .0;
+
+        let accounts = openbook_v2::accounts::CloseOpenOrdersAccount {
+            owner: self.owner.pubkey(),
+            open_orders_indexer,
+            open_orders_account,
+            sol_destination: self.sol_destination,
+            system_program: System::id(),
+        };
+
+        let instruction = make_instruction(program_id, &accounts, instruction);
+        (accounts, instruction)
+    }
+
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![self.owner]
+    }
+}
+
+pub struct ResizeOpenOrdersAccountInstruction {
+    pub payer: TestKeypair,
+    pub owner: TestKeypair,
+    pub open_orders_account: Pubkey,
+    pub capacity: u16,
+}
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for ResizeOpenOrdersAccountInstruction {
+    type Accounts = openbook_v2::accounts::ResizeOpenOrdersAccount;
+    type Instruction = openbook_v2::instruction::ResizeOpenOrdersAccount;
+    async fn to_instruction(
+        &self,
+        _account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction {
+            capacity: self.capacity,
+        };
+
+        let accounts = Self::Accounts {
+            payer: self.payer.pubkey(),
+            owner: self.owner.pubkey(),
+            open_orders_account: self.open_orders_account,
+            system_program: System::id(),
+        };
+
+        let instruction = make_instruction(program_id, &accounts, instruction);
+        (accounts, instruction)
+    }
+
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![self.payer, self.owner]
+    }
+}
+
+pub struct MigrateMarketInstruction {
+    pub payer: TestKeypair,
+    pub market: Pubkey,
+}
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for MigrateMarketInstruction {
+    type Accounts = openbook_v2::accounts::MigrateMarket;
+    type Instruction = openbook_v2::instruction::MigrateMarket;
+    async fn to_instruction(
+        &self,
+        _account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction {};
+
+        let accounts = Self::Accounts {
+            payer: self.payer.pubkey(),
+            market: self.market,
+            system_program: System::id(),
+        };
+
+        let instruction = make_instruction(program_id, &accounts, instruction);
+        (accounts, instruction)
+    }
+
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![self.payer]
+    }
+}
+
+#[derive(Default)]
+pub struct CreateMarketInstruction {
+    pub collect_fee_admin: Pubkey,
+    pub open_orders_admin: Option<Pubkey>,
+    pub consume_events_admin: Option<Pubkey>,
+    pub close_market_admin: Option<Pubkey>,
+    pub allowlist_admin: Option<Pubkey>,
+    pub denylist_admin: Option<Pubkey>,
+    pub exposure_admin: Option<Pubkey>,
+    pub oracle_a: Option<Pubkey>,
+    pub oracle_b: Option<Pubkey>,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub name: String,
+    pub bids: Pubkey,
+    pub asks: Pubkey,
+    pub event_heap: Pubkey,
+    pub market: TestKeypair,
+    pub payer: TestKeypair,
+    pub quote_lot_size: i64,
+    pub base_lot_size: i64,
+    pub maker_fee: i64,
+    pub taker_fee: i64,
+    pub event_heap_penalty: u64,
+    pub event_heap_penalty_policy: EventHeapPenaltyPolicy,
+    pub max_bids_quote_lots: i64,
+    pub max_asks_base_lots: i64,
+    pub max_open_orders: u16,
+    pub min_base_lots: i64,
+    pub min_quote_notional: i64,
+    pub posting_bond: u64,
+    pub eviction_policy: EvictionPolicy,
+    pub settle_fee_flat: f32,
+    pub settle_fee_amount_threshold: f32,
+    pub time_expiry: i64,
+    pub oracle_staleness_policy: OracleStalenessPolicy,
+    pub oracle_freeze_slots: u32,
+}
+impl CreateMarketInstruction {
+    pub async fn with_new_book_and_heap(
+        solana: &SolanaCookie,
+        oracle_a: Option<Pubkey>,
+        oracle_b: Option<Pubkey>,
+    ) -> Self {
+        Self::with_new_book_and_heap_capacities(
+            solana,
+            oracle_a,
+            oracle_b,
+            DEFAULT_ORDERTREE_NODES,
+            DEFAULT_NUM_EVENTS as usize,
+        )
+        .await
+    }
+
+    pub async fn with_new_book_and_heap_capacities(
+        solana: &SolanaCookie,
+        oracle_a: Option<Pubkey>,
+        oracle_b: Option<Pubkey>,
+        book_side_capacity: usize,
+        event_heap_capacity: usize,
+    ) -> Self {
+        let book_side_space = BookSide::space_for_capacity(book_side_capacity);
+        CreateMarketInstruction {
+            bids: solana
+                .create_account_from_len(&openbook_v2::id(), book_side_space)
+                .await,
+            asks: solana
+                .create_account_from_len(&openbook_v2::id(), book_side_space)
+                .await,
+            event_heap: solana
+                .create_account_from_len(
+                    &openbook_v2::id(),
+                    EventHeap::space_for_capacity(event_heap_capacity),
+                )
+                .await,
+            oracle_a,
+            oracle_b,
+            event_heap_penalty: PENALTY_EVENT_HEAP,
+            ..CreateMarketInstruction::default()
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for CreateMarketInstruction {
+    type Accounts = openbook_v2::accounts::CreateMarket;
+    type Instruction = openbook_v2::instruction::CreateMarket;
+    async fn to_instruction(
+        &self,
+        _loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction {
+            name: "ONE-TWO".to_string(),
+            oracle_config: OracleConfigParams {
+                conf_filter: 0.1,
+                max_staleness_slots: Some(100),
+                staleness_policy: self.oracle_staleness_policy,
+                freeze_slots: self.oracle_freeze_slots,
+            },
+            quote_lot_size: self.quote_lot_size,
+            base_lot_size: self.base_lot_size,
+            maker_fee: self.maker_fee,
+            taker_fee: self.taker_fee,
+            time_expiry: self.time_expiry,
+            event_heap_penalty: self.event_heap_penalty,
+            event_heap_penalty_policy: self.event_heap_penalty_policy,
+            max_bids_quote_lots: self.max_bids_quote_lots,
+            max_asks_base_lots: self.max_asks_base_lots,
+            max_open_orders: self.max_open_orders,
+            min_base_lots: self.min_base_lots,
+            min_quote_notional: self.min_quote_notional,
+            posting_bond: self.posting_bond,
+            eviction_policy: self.eviction_policy,
+        };
+
+        let market_authority = Pubkey::find_program_address(
+            &[b"Market".as_ref(), self.market.pubkey().to_bytes().as_ref()],
+            &openbook_v2::id(),
+        )
+        .0;
+
+        let market_base_vault = spl_associated_token_account::get_associated_token_address(
+            &market_authority,
+            &self.base_mint,
+        );
+        let market_quote_vault = spl_associated_token_account::get_associated_token_address(
+            &market_authority,
+            &self.quote_mint,
+        );
+
+        let accounts = Self::Accounts {
+            market: self.market.pubkey(),
+            market_authority,
+            bids: self.bids,
+            asks: self.asks,
+            event_heap: self.event_heap,
+            payer: self.payer.pubkey(),
+            market_base_vault,
+            market_quote_vault,
+            quote_mint: self.quote_mint,
+            base_mint: self.base_mint,
+            system_program: System::id(),
+            token_program: Token::id(),
+            associated_token_program: AssociatedToken::id(),
+            collect_fee_admin: self.collect_fee_admin,
+            open_orders_admin: self.open_orders_admin,
+            consume_events_admin: self.consume_events_admin,
+            close_market_admin: self.close_market_admin,
+            allowlist_admin: self.allowlist_admin,
+            denylist_admin: self.denylist_admin,
+            exposure_admin: self.exposure_admin,
+            oracle_a: self.oracle_a,
+            oracle_b: self.oracle_b,
+            event_authority: get_event_authority_address(),
+            program: openbook_v2::id(),
+        };
+
+        let instruction = make_instruction(program_id, &accounts, instruction);
+        (accounts, instruction)
+    }
+
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![self.payer, self.market]
+    }
+}
+
+#[derive(Clone)]
+pub struct PlaceOrderInstruction {
+    pub open_orders_account: Pubkey,
+    pub open_orders_admin: Option<TestKeypair>,
+    pub market: Pubkey,
+    pub signer: TestKeypair,
+    pub market_vault: Pubkey,
+    pub user_token_account: Pubkey,
+    pub side: Side,
+    pub price_lots: i64,
+    pub max_base_lots: i64,
+    pub max_quote_lots_including_fees: i64,
+    pub client_order_id: u64,
+    pub expiry_timestamp: u64,
+    pub order_type: PlaceOrderType,
+    pub self_trade_behavior: SelfTradeBehavior,
+    pub remainings: Vec<Pubkey>,
+}
+
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for PlaceOrderInstruction {
+    type Accounts = openbook_v2::accounts::PlaceOrder;
+    type Instruction = openbook_v2::instruction::PlaceOrder;
+    async fn to_instruction(
+        &self,
+        account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction {
+            args: PlaceOrderArgs {
+                side: self.side,
+                price_lots: self.price_lots,
+                max_base_lots: self.max_base_lots,
+                max_quote_lots_including_fees: self.max_quote_lots_including_fees,
+                client_order_id: self.client_order_id,
+                order_type: self.order_type,
+                expiry_timestamp: self.expiry_timestamp,
+                self_trade_behavior: self.self_trade_behavior,
+                limit: 10,
+            },
+        };
+
+        let market: Market = account_loader.load(&self.market).await.unwrap();
+        let user_token_account =
+            token_account_or_native(self.user_token_account, self.signer.pubkey());
+
+        let accounts = Self::Accounts {
+            open_orders_account: self.open_orders_account,
+            open_orders_admin: self.open_orders_admin.map(|kp| kp.pubkey()),
+            market: self.market,
+            bids: market.bids,
+            asks: market.asks,
+            event_heap: market.event_heap,
+            oracle_a: market.oracle_a.into(),
+            oracle_b: market.oracle_b.into(),
+            signer: self.signer.pubkey(),
+            user_token_account,
+            market_vault: self.market_vault,
+            token_program: Token::id(),
+            open_orders_delegate: open_orders_delegate(
+                &account_loader,
+                self.open_orders_account,
+                self.signer.pubkey(),
+            )
+            .await,
+            system_program: Some(System::id()),
+            market_allowlist_entry: market_allowlist_entry(
+                &account_loader,
+                self.market,
+                &market,
+                &open_orders_allowlist_keys(&account_loader, self.open_orders_account).await,
+            )
+            .await,
+            owner_denylist_entry: market_denylist_entry(
+                self.market,
+                &market,
+                open_orders_owner(&account_loader, self.open_orders_account).await,
+            ),
+            signer_denylist_entry: market_denylist_entry(
+                self.market,
+                &market,
+                self.signer.pubkey(),
+            ),
+            penalty_payer: Some(self.signer.pubkey()),
+            native_payer: user_token_account.is_none().then_some(self.signer.pubkey()),
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
+        };
+        let mut instruction = make_instruction(program_id, &accounts, instruction);
+        let mut vec_remainings: Vec<AccountMeta> = Vec::new();
+        for remaining in &self.remainings {
+            vec_remainings.push(AccountMeta {
+                pubkey: *remaining,
+                is_signer: false,
+                is_writable: true,
+            })
+        }
+        instruction.accounts.append(&mut vec_remainings);
+        (accounts, instruction)
+    }
+
+    fn signers(&self) -> Vec<TestKeypair> {
+        let mut signers = vec![self.signer];
+        if let Some(open_orders_admin) = self.open_orders_admin {
+            signers.push(open_orders_admin);
+        }
+
+        signers
+    }
+}
+
+#[derive(Clone)]
+pub struct PlaceOrderPeggedInstruction {
+    pub open_orders_account: Pubkey,
+    pub market: Pubkey,
+    pub signer: TestKeypair,
+    pub user_token_account: Pubkey,
+    pub market_vault: Pubkey,
+    pub side: Side,
+    pub price_offset: i64,
+    pub max_base_lots: i64,
+    pub max_quote_lots_including_fees: i64,
+    pub client_order_id: u64,
+    pub peg_limit: i64,
+}
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for PlaceOrderPeggedInstruction {
+    type Accounts = openbook_v2::accounts::PlaceOrder;
+    type Instruction = openbook_v2::instruction::PlaceOrderPegged;
+    async fn to_instruction(
+        &self,
+        account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction {
+            args: PlaceOrderPeggedArgs {
+                side: self.side,
+                price_offset_lots: self.price_offset,
+                peg_limit: self.peg_limit,
+                max_base_lots: self.max_base_lots,
+                max_quote_lots_including_fees: self.max_quote_lots_including_fees,
+                client_order_id: self.client_order_id,
+                order_type: PlaceOrderType::Limit,
+                expiry_timestamp: 0,
+                self_trade_behavior: SelfTradeBehavior::default(),
+                limit: 10,
+            },
+        };
+
+        let market: Market = account_loader.load(&self.market).await.unwrap();
+        let user_token_account =
+            token_account_or_native(self.user_token_account, self.signer.pubkey());
+
+        let accounts = Self::Accounts {
+            open_orders_account: self.open_orders_account,
+            open_orders_admin: None,
+            market: self.market,
+            bids: market.bids,
+            asks: market.asks,
+            event_heap: market.event_heap,
+            oracle_a: market.oracle_a.into(),
+            oracle_b: market.oracle_b.into(),
+            signer: self.signer.pubkey(),
+            user_token_account,
+            market_vault: self.market_vault,
+            token_program: Token::id(),
+            open_orders_delegate: open_orders_delegate(
+                &account_loader,
+                self.open_orders_account,
+                self.signer.pubkey(),
+            )
+            .await,
+            system_program: Some(System::id()),
+            market_allowlist_entry: market_allowlist_entry(
+                &account_loader,
+                self.market,
+                &market,
+                &open_orders_allowlist_keys(&account_loader, self.open_orders_account).await,
+            )
+            .await,
+            owner_denylist_entry: market_denylist_entry(
+                self.market,
+                &market,
+                open_orders_owner(&account_loader, self.open_orders_account).await,
+            ),
+            signer_denylist_entry: market_denylist_entry(
+                self.market,
+                &market,
+                self.signer.pubkey(),
+            ),
+            penalty_payer: Some(self.signer.pubkey()),
+            native_payer: user_token_account.is_none().then_some(self.signer.pubkey()),
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
+        };
+        let instruction = make_instruction(program_id, &accounts, instruction);
+
+        (accounts, instruction)
+    }
+
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![self.signer]
+    }
+}
+
+#[derive(Clone)]
+pub struct PlaceTakeOrderInstruction {
+    pub open_orders_admin: Option<TestKeypair>,
+    pub market: Pubkey,
+    pub signer: TestKeypair,
+    pub market_base_vault: Pubkey,
+    pub market_quote_vault: Pubkey,
+    pub user_base_account: Pubkey,
+    pub user_quote_account: Pubkey,
+    pub side: Side,
+    pub price_lots: i64,
+    pub max_base_lots: i64,
+    pub max_quote_lots_including_fees: i64,
+}
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for PlaceTakeOrderInstruction {
+    type Accounts = openbook_v2::accounts::PlaceTakeOrder;
+    type Instruction = openbook_v2::instruction::PlaceTakeOrder;
+    async fn to_instruction(
+        &self,
+        account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction {
+            args: PlaceTakeOrderArgs {
+                side: self.side,
+                price_lots: self.price_lots,
+                max_base_lots: self.max_base_lots,
+                max_quote_lots_including_fees: self.max_quote_lots_including_fees,
+                order_type: PlaceOrderType::ImmediateOrCancel,
+                limit: 10,
+            },
+        };
+
+        let market: Market = account_loader.load(&self.market).await.unwrap();
+
+        let accounts = Self::Accounts {
+            open_orders_admin: self.open_orders_admin.map(|kp| kp.pubkey()),
+            market: self.market,
+            market_authority: market.market_authority,
+            bids: market.bids,
+            asks: market.asks,
+            event_heap: market.event_heap,
+            oracle_a: market.oracle_a.into(),
+            oracle_b: market.oracle_b.into(),
+            signer: self.signer.pubkey(),
+            penalty_payer: self.signer.pubkey(),
+            user_base_account: self.user_base_account,
+            user_quote_account: self.user_quote_account,
+            market_base_vault: self.market_base_vault,
+            market_quote_vault: self.market_quote_vault,
+            token_program: Token::id(),
+            system_program: System::id(),
+            market_allowlist_entry: market_allowlist_entry(
+                &account_loader,
+                self.market,
+                &market,
+                &[self.signer.pubkey()],
+            )
+            .await,
+            signer_denylist_entry: market_denylist_entry(
+                self.market,
+                &market,
+                self.signer.pubkey(),
+            ),
+            base_owner_denylist_entry: token_owner_denylist_entry(
+                &account_loader,
+                self.market,
+                &market,
+                self.user_base_account,
+            )
+            .await,
+            quote_owner_denylist_entry: token_owner_denylist_entry(
+                &account_loader,
+                self.market,
+                &market,
+                self.user_quote_account,
+            )
+            .await,
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
+        };
+
+        let instruction = make_instruction(program_id, &accounts, instruction);
+        (accounts, instruction)
+    }
+
+    fn signers(&self) -> Vec<TestKeypair> {
+        let mut signers = vec![self.signer];
+        if let Some(open_orders_admin) = self.open_orders_admin {
+            signers.push(open_orders_admin);
+        }
+
+        signers
+    }
+}
+
+pub struct SwapInstruction {
+    pub market: Pubkey,
+    pub signer: TestKeypair,
+    pub market_base_vault: Pubkey,
+    pub market_quote_vault: Pubkey,
+    pub user_base_account: Pubkey,
+    pub user_quote_account: Pubkey,
+    pub side: Side,
+    pub amount_in: u64,
+    pub min_amount_out: u64,
+}
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for SwapInstruction {
+    type Accounts = openbook_v2::accounts::PlaceTakeOrder;
+    type Instruction = openbook_v2::instruction::Swap;
+    async fn to_instruction(
+        &self,
+        account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction {
+            args: SwapArgs {
+                side: self.side,
+                amount_in: self.amount_in,
+                min_amount_out: self.min_amount_out,
+                limit: 10,
+            },
+        };
+
+        let market: Market = account_loader.load(&self.market).await.unwrap();
+
+        let accounts = Self::Accounts {
+            open_orders_admin: None,
+            market: self.market,
+            market_authority: market.market_authority,
+            bids: market.bids,
+            asks: market.asks,
+            event_heap: market.event_heap,
+            oracle_a: market.oracle_a.into(),
+            oracle_b: market.oracle_b.into(),
+            signer: self.signer.pubkey(),
+            penalty_payer: self.signer.pubkey(),
+            user_base_account: self.user_base_account,
+            user_quote_account: self.user_quote_account,
+            market_base_vault: self.market_base_vault,
+            market_quote_vault: self.market_quote_vault,
+            token_program: Token::id(),
+            system_program: System::id(),
+            market_allowlist_entry: market_allowlist_entry(
+                &account_loader,
+                self.market,
+                &market,
+                &[self.signer.pubkey()],
+            )
+            .await,
+            signer_denylist_entry: market_denylist_entry(
+                self.market,
+                &market,
+                self.signer.pubkey(),
+            ),
+            base_owner_denylist_entry: token_owner_denylist_entry(
+                &account_loader,
+                self.market,
+                &market,
+                self.user_base_account,
+            )
+            .await,
+            quote_owner_denylist_entry: token_owner_denylist_entry(
+                &account_loader,
+                self.market,
+                &market,
+                self.user_quote_account,
+            )
+            .await,
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
+        };
+
+        let instruction = make_instruction(program_id, &accounts, instruction);
+        (accounts, instruction)
+    }
+
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![self.signer]
+    }
+}
+
+pub struct SwapRouteInstruction {
+    pub signer: TestKeypair,
+    pub first_market: Pubkey,
+    pub second_market: Pubkey,
+    pub user_input_account: Pubkey,
+    pub user_intermediate_account: Pubkey,
+    pub user_output_account: Pubkey,
+    pub amount_in: u64,
+    pub min_amount_out: u64,
+}
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for SwapRouteInstruction {
+    type Accounts = openbook_v2::accounts::SwapRoute;
+    type Instruction = openbook_v2::instruction::SwapRoute;
+    async fn to_instruction(
+        &self,
+        account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction {
+            args: SwapRouteArgs {
+                amount_in: self.amount_in,
+                min_amount_out: self.min_amount_out,
+                limit: 10,
+            },
+        };
+
+        let first_market: Market = account_loader.load(&self.first_market).await.unwrap();
+        let second_market: Market = account_loader.load(&self.second_market).await.unwrap();
+
+        let accounts = Self::Accounts {
+            signer: self.signer.pubkey(),
+            penalty_payer: self.signer.pubkey(),
+            first_market: self.first_market,
+            first_market_authority: first_market.market_authority,
+            first_bids: first_market.bids,
+            first_asks: first_market.asks,
+            first_market_base_vault: first_market.market_base_vault,
+            first_market_quote_vault: first_market.market_quote_vault,
+            first_event_heap: first_market.event_heap,
+            first_oracle_a: first_market.oracle_a.into(),
+            first_oracle_b: first_market.oracle_b.into(),
+            first_open_orders_admin: None,
+            second_market: self.second_market,
+            second_market_authority: second_market.market_authority,
+            second_bids: second_market.bids,
+            second_asks: second_market.asks,
+            second_market_base_vault: second_market.market_base_vault,
+            second_market_quote_vault: second_market.market_quote_vault,
+            second_event_heap: second_market.event_heap,
+            second_oracle_a: second_market.oracle_a.into(),
+            second_oracle_b: second_market.oracle_b.into(),
+            second_open_orders_admin: None,
+            user_input_account: self.user_input_account,
+            user_intermediate_account: self.user_intermediate_account,
+            user_output_account: self.user_output_account,
+            token_program: Token::id(),
+            system_program: System::id(),
+            first_market_allowlist_entry: market_allowlist_entry(
+                &account_loader,
+                self.first_market,
+                &first_market,
+                &[self.signer.pubkey()],
+            )
+            .await,
+            second_market_allowlist_entry: market_allowlist_entry(
+                &account_loader,
+                self.second_market,
+                &second_market,
+                &[self.signer.pubkey()],
+            )
+            .await,
+            first_signer_denylist_entry: market_denylist_entry(
+                self.first_market,
+                &first_market,
+                self.signer.pubkey(),
+            ),
+            first_intermediate_owner_denylist_entry: token_owner_denylist_entry(
+                &account_loader,
+                self.first_market,
+                &first_market,
+                self.user_intermediate_account,
+            )
+            .await,
+            first_output_owner_denylist_entry: token_owner_denylist_entry(
+                &account_loader,
+                self.first_market,
+                &first_market,
+                self.user_output_account,
+            )
+            .await,
+            second_signer_denylist_entry: market_denylist_entry(
+                self.second_market,
+                &second_market,
+                self.signer.pubkey(),
+            ),
+            second_intermediate_owner_denylist_entry: token_owner_denylist_entry(
+                &account_loader,
+                self.second_market,
+                &second_market,
+                self.user_intermediate_account,
+            )
+            .await,
+            second_output_owner_denylist_entry: token_owner_denylist_entry(
+                &account_loader,
+                self.second_market,
+                &second_market,
+                self.user_output_account,
+            )
+            .await,
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
+        };
 
-    assert_eq!(position.asks_base_lots, 0);
-    assert_eq!(position.bids_base_lots, 0);
+        let instruction = make_instruction(program_id, &accounts, instruction);
+        (accounts, instruction)
+    }
 
-    Ok(())
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![self.signer]
+    }
 }
 
+pub struct SwapExactOutInstruction {
+    pub market: Pubkey,
+    pub signer: TestKeypair,
+    pub market_base_vault: Pubkey,
+    pub market_quote_vault: Pubkey,
+    pub user_base_account: Pubkey,
+    pub user_quote_account: Pubkey,
+    pub side: Side,
+    pub amount_out: u64,
+    pub max_amount_in: u64,
+}
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for SwapExactOutInstruction {
+    type Accounts = openbook_v2::accounts::PlaceTakeOrder;
+    type Instruction = openbook_v2::instruction::SwapExactOut;
+    async fn to_instruction(
+        &self,
+        account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction {
+            args: SwapExactOutArgs {
+                side: self.side,
+                amount_out: self.amount_out,
+                max_amount_in: self.max_amount_in,
+                limit: 10,
+            },
+        };
 
-// File: openbook-v2/programs/openbook-v2/tests/program_test/client.rs
-#![allow(dead_code)]
+        let market: Market = account_loader.load(&self.market).await.unwrap();
 
-use anchor_lang::prelude::*;
-use anchor_spl::{associated_token::AssociatedToken, token::Token};
-use solana_program::instruction::Instruction;
-use solana_program_test::BanksClientError;
-use solana_sdk::instruction;
-use solana_sdk::transport::TransportError;
-use std::sync::Arc;
+        let accounts = Self::Accounts {
+            open_orders_admin: None,
+            market: self.market,
+            market_authority: market.market_authority,
+            bids: market.bids,
+            asks: market.asks,
+            event_heap: market.event_heap,
+            oracle_a: market.oracle_a.into(),
+            oracle_b: market.oracle_b.into(),
+            signer: self.signer.pubkey(),
+            penalty_payer: self.signer.pubkey(),
+            user_base_account: self.user_base_account,
+            user_quote_account: self.user_quote_account,
+            market_base_vault: self.market_base_vault,
+            market_quote_vault: self.market_quote_vault,
+            token_program: Token::id(),
+            system_program: System::id(),
+            market_allowlist_entry: market_allowlist_entry(
+                &account_loader,
+                self.market,
+                &market,
+                &[self.signer.pubkey()],
+            )
+            .await,
+            signer_denylist_entry: market_denylist_entry(
+                self.market,
+                &market,
+                self.signer.pubkey(),
+            ),
+            base_owner_denylist_entry: token_owner_denylist_entry(
+                &account_loader,
+                self.market,
+                &market,
+                self.user_base_account,
+            )
+            .await,
+            quote_owner_denylist_entry: token_owner_denylist_entry(
+                &account_loader,
+                self.market,
+                &market,
+                self.user_quote_account,
+            )
+            .await,
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
+        };
 
-use super::solana::SolanaCookie;
-use super::utils::TestKeypair;
-use openbook_v2::{
-    state::*, PlaceMultipleOrdersArgs, PlaceOrderArgs, PlaceOrderPeggedArgs, PlaceTakeOrderArgs,
-};
+        let instruction = make_instruction(program_id, &accounts, instruction);
+        (accounts, instruction)
+    }
 
-#[async_trait::async_trait(?Send)]
-pub trait ClientAccountLoader {
-    async fn load_bytes(&self, pubkey: &Pubkey) -> Option<Vec<u8>>;
-    async fn load<T: AccountDeserialize>(&self, pubkey: &Pubkey) -> Option<T> {
-        let bytes = self.load_bytes(pubkey).await?;
-        AccountDeserialize::try_deserialize(&mut &bytes[..]).ok()
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![self.signer]
     }
 }
 
+pub struct CancelOrderInstruction {
+    pub open_orders_account: Pubkey,
+    pub market: Pubkey,
+    pub signer: TestKeypair,
+    pub order_id: u128,
+}
 #[async_trait::async_trait(?Send)]
-impl ClientAccountLoader for &SolanaCookie {
-    async fn load_bytes(&self, pubkey: &Pubkey) -> Option<Vec<u8>> {
-        self.get_account_data(*pubkey).await
+impl ClientInstruction for CancelOrderInstruction {
+    type Accounts = openbook_v2::accounts::CancelOrder;
+    type Instruction = openbook_v2::instruction::CancelOrder;
+    async fn to_instruction(
+        &self,
+        account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction {
+            order_id: self.order_id,
+        };
+        let market: Market = account_loader.load(&self.market).await.unwrap();
+        let accounts = Self::Accounts {
+            open_orders_account: self.open_orders_account,
+            market: self.market,
+            bids: market.bids,
+            asks: market.asks,
+            signer: self.signer.pubkey(),
+            open_orders_delegate: open_orders_delegate(
+                &account_loader,
+                self.open_orders_account,
+                self.signer.pubkey(),
+            )
+            .await,
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
+        };
+
+        let instruction = make_instruction(program_id, &accounts, instruction);
+        (accounts, instruction)
+    }
+
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![self.signer]
     }
 }
 
-// TODO: report error outwards etc
-pub async fn send_tx<CI: ClientInstruction>(
-    solana: &SolanaCookie,
-    ix: CI,
-) -> std::result::Result<CI::Accounts, TransportError> {
-    let (accounts, instruction) = ix.to_instruction(solana).await;
-    let signers = ix.signers();
-    let instructions = vec![instruction];
-    solana
-        .process_transaction(&instructions, Some(&signers[..]))
-        .await?;
-    Ok(accounts)
+pub struct CancelOrderByClientOrderIdInstruction {
+    pub open_orders_account: Pubkey,
+    pub market: Pubkey,
+    pub signer: TestKeypair,
+    pub client_order_id: u64,
 }
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for CancelOrderByClientOrderIdInstruction {
+    type Accounts = openbook_v2::accounts::CancelOrder;
+    type Instruction = openbook_v2::instruction::CancelOrderByClientOrderId;
+    async fn to_instruction(
+        &self,
+        account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction {
+            client_order_id: self.client_order_id,
+        };
+        let market: Market = account_loader.load(&self.market).await.unwrap();
+        let accounts = Self::Accounts {
+            open_orders_account: self.open_orders_account,
+            market: self.market,
+            bids: market.bids,
+            asks: market.asks,
+            signer: self.signer.pubkey(),
+            open_orders_delegate: open_orders_delegate(
+                &account_loader,
+                self.open_orders_account,
+                self.signer.pubkey(),
+            )
+            .await,
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
+        };
 
-pub async fn send_tx_and_get_ix_custom_error<CI: ClientInstruction>(
-    solana: &SolanaCookie,
-    ix: CI,
-) -> Option<u32> {
-    let tx_result = send_tx(solana, ix).await;
+        let instruction = make_instruction(program_id, &accounts, instruction);
+        (accounts, instruction)
+    }
 
-    if let Err(TransportError::TransactionError(
-        solana_sdk::transaction::TransactionError::InstructionError(
-            _,
-            solana_sdk::instruction::InstructionError::Custom(err_num),
-        ),
-    )) = tx_result
-    {
-        Some(err_num)
-    } else {
-        None
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![self.signer]
     }
 }
 
-/// Build a transaction from multiple instructions
-pub struct ClientTransaction {
-    solana: Arc<SolanaCookie>,
-    instructions: Vec<instruction::Instruction>,
-    signers: Vec<TestKeypair>,
+#[derive(Clone)]
+pub struct CancelAllOrdersInstruction {
+    pub open_orders_account: Pubkey,
+    pub market: Pubkey,
+    pub signer: TestKeypair,
 }
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for CancelAllOrdersInstruction {
+    type Accounts = openbook_v2::accounts::CancelOrder;
+    type Instruction = openbook_v2::instruction::CancelAllOrders;
+    async fn to_instruction(
+        &self,
+        account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction {
+            side_option: None,
+            limit: 5,
+        };
+        let market: Market = account_loader.load(&self.market).await.unwrap();
+        let accounts = Self::Accounts {
+            open_orders_account: self.open_orders_account,
+            market: self.market,
+            bids: market.bids,
+            asks: market.asks,
+            signer: self.signer.pubkey(),
+            open_orders_delegate: open_orders_delegate(
+                &account_loader,
+                self.open_orders_account,
+                self.signer.pubkey(),
+            )
+            .await,
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
+        };
 
-impl<'a> ClientTransaction {
-    pub fn new(solana: &Arc<SolanaCookie>) -> Self {
-        Self {
-            solana: solana.clone(),
-            instructions: vec![],
-            signers: vec![],
-        }
+        let instruction = make_instruction(program_id, &accounts, instruction);
+        (accounts, instruction)
     }
 
-    pub async fn add_instruction<CI: ClientInstruction>(&mut self, ix: CI) -> CI::Accounts {
-        let solana: &SolanaCookie = &self.solana;
-        let (accounts, instruction) = ix.to_instruction(solana).await;
-        self.instructions.push(instruction);
-        self.signers.extend(ix.signers());
-        accounts
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![self.signer]
     }
+}
 
-    pub fn add_instruction_direct(&mut self, ix: instruction::Instruction) {
-        self.instructions.push(ix);
-    }
+#[derive(Clone)]
+pub struct ConsumeEventsInstruction {
+    pub consume_events_admin: Option<TestKeypair>,
+    pub market: Pubkey,
+    pub open_orders_accounts: Vec<Pubkey>,
+}
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for ConsumeEventsInstruction {
+    type Accounts = openbook_v2::accounts::ConsumeEvents;
+    type Instruction = openbook_v2::instruction::ConsumeEvents;
+    async fn to_instruction(
+        &self,
+        account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction { limit: 10 };
 
-    pub fn add_signer(&mut self, keypair: TestKeypair) {
-        self.signers.push(keypair);
+        let market: Market = account_loader.load(&self.market).await.unwrap();
+        let accounts = Self::Accounts {
+            consume_events_admin: self.consume_events_admin.map(|kp| kp.pubkey()),
+            market: self.market,
+            event_heap: market.event_heap,
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
+        };
+
+        let mut instruction = make_instruction(program_id, &accounts, instruction);
+        instruction
+            .accounts
+            .extend(self.open_orders_accounts.iter().map(|ma| AccountMeta {
+                pubkey: *ma,
+                is_signer: false,
+                is_writable: true,
+            }));
+        (accounts, instruction)
     }
 
-    pub async fn send(&self) -> std::result::Result<(), BanksClientError> {
-        self.solana
-            .process_transaction(&self.instructions, Some(&self.signers))
-            .await
+    fn signers(&self) -> Vec<TestKeypair> {
+        match self.consume_events_admin {
+            Some(consume_events_admin) => vec![consume_events_admin],
+            None => vec![],
+        }
     }
 }
 
+/// Like [`ConsumeEventsInstruction`], with a `cranker` receiving the crank reward
+#[derive(Clone)]
+pub struct ConsumeEventsWithRewardInstruction {
+    pub market: Pubkey,
+    pub open_orders_accounts: Vec<Pubkey>,
+    pub cranker: TestKeypair,
+}
 #[async_trait::async_trait(?Send)]
-pub trait ClientInstruction {
-    type Accounts: anchor_lang::ToAccountMetas;
-    type Instruction: anchor_lang::InstructionData;
-
+impl ClientInstruction for ConsumeEventsWithRewardInstruction {
+    type Accounts = openbook_v2::accounts::ConsumeEventsWithReward;
+    type Instruction = openbook_v2::instruction::ConsumeEventsWithReward;
     async fn to_instruction(
         &self,
-        loader: impl ClientAccountLoader + 'async_trait,
-    ) -> (Self::Accounts, instruction::Instruction);
-    fn signers(&self) -> Vec<TestKeypair>;
-}
+        account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction { limit: 10 };
 
-fn make_instruction(
-    program_id: Pubkey,
-    accounts: &impl anchor_lang::ToAccountMetas,
-    data: impl anchor_lang::InstructionData,
-) -> instruction::Instruction {
-    instruction::Instruction {
-        program_id,
-        accounts: anchor_lang::ToAccountMetas::to_account_metas(accounts, None),
-        data: anchor_lang::InstructionData::data(&data),
+        let market: Market = account_loader.load(&self.market).await.unwrap();
+        let accounts = Self::Accounts {
+            consume_events_admin: None,
+            market: self.market,
+            event_heap: market.event_heap,
+            cranker: self.cranker.pubkey(),
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
+        };
+
+        let mut instruction = make_instruction(program_id, &accounts, instruction);
+        instruction
+            .accounts
+            .extend(self.open_orders_accounts.iter().map(|ma| AccountMeta {
+                pubkey: *ma,
+                is_signer: false,
+                is_writable: true,
+            }));
+        (accounts, instruction)
     }
-}
 
-pub fn get_market_address(market: TestKeypair) -> Pubkey {
-    Pubkey::find_program_address(
-        &[b"Market".as_ref(), market.pubkey().to_bytes().as_ref()],
-        &openbook_v2::id(),
-    )
-    .0
-}
-pub async fn set_stub_oracle_price(
-    solana: &SolanaCookie,
-    token: &super::setup::Token,
-    owner: TestKeypair,
-    price: f64,
-) {
-    send_tx(
-        solana,
-        StubOracleSetInstruction {
-            owner,
-            mint: token.mint.pubkey,
-            price,
-        },
-    )
-    .await
-    .unwrap();
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![self.cranker]
+    }
 }
 
-pub struct CreateOpenOrdersIndexerInstruction {
+pub struct ConsumeGivenEventsInstruction {
+    pub consume_events_admin: Option<TestKeypair>,
     pub market: Pubkey,
-    pub owner: TestKeypair,
-    pub payer: TestKeypair,
+    pub open_orders_accounts: Vec<Pubkey>,
+    pub slots: Vec<usize>,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for CreateOpenOrdersIndexerInstruction {
-    type Accounts = openbook_v2::accounts::CreateOpenOrdersIndexer;
-    type Instruction = openbook_v2::instruction::CreateOpenOrdersIndexer;
+impl ClientInstruction for ConsumeGivenEventsInstruction {
+    type Accounts = openbook_v2::accounts::ConsumeEvents;
+    type Instruction = openbook_v2::instruction::ConsumeGivenEvents;
     async fn to_instruction(
         &self,
-        _account_loader: impl ClientAccountLoader + 'async_trait,
+        account_loader: impl ClientAccountLoader + 'async_trait,
     ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
-        let instruction = openbook_v2::instruction::CreateOpenOrdersIndexer {};
-
-        let open_orders_indexer = Pubkey::find_program_address(
-            &[b"OpenOrdersIndexer".as_ref(), self.owner.pubkey().as_ref()],
-            &program_id,
-        )
-        .0;
+        let instruction = Self::Instruction {
+            slots: self.slots.clone(),
+        };
 
-        let accounts = openbook_v2::accounts::CreateOpenOrdersIndexer {
-            payer: self.payer.pubkey(),
-            owner: self.owner.pubkey(),
-            open_orders_indexer,
-            system_program: System::id(),
+        let market: Market = account_loader.load(&self.market).await.unwrap();
+        let accounts = Self::Accounts {
+            consume_events_admin: self.consume_events_admin.map(|kp| kp.pubkey()),
+            market: self.market,
+            event_heap: market.event_heap,
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
 
-        let instruction = make_instruction(program_id, &accounts, instruction);
+        let mut instruction = make_instruction(program_id, &accounts, instruction);
+        instruction
+            .accounts
+            .extend(self.open_orders_accounts.iter().map(|ma| AccountMeta {
+                pubkey: *ma,
+                is_signer: false,
+                is_writable: true,
+            }));
         (accounts, instruction)
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        vec![self.owner, self.payer]
+        match self.consume_events_admin {
+            Some(consume_events_admin) => vec![consume_events_admin],
+            None => vec![],
+        }
     }
 }
 
-pub struct CreateOpenOrdersAccountInstruction {
-    pub account_num: u32,
-    pub market: Pubkey,
+#[derive(Clone)]
+pub struct SettleFundsInstruction {
     pub owner: TestKeypair,
-    pub payer: TestKeypair,
-    pub delegate: Option<Pubkey>,
+    pub open_orders_account: Pubkey,
+    pub market: Pubkey,
+    pub market_base_vault: Pubkey,
+    pub market_quote_vault: Pubkey,
+    pub user_base_account: Pubkey,
+    pub user_quote_account: Pubkey,
+    pub referrer_account: Option<Pubkey>,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for CreateOpenOrdersAccountInstruction {
-    type Accounts = openbook_v2::accounts::CreateOpenOrdersAccount;
-    type Instruction = openbook_v2::instruction::CreateOpenOrdersAccount;
+impl ClientInstruction for SettleFundsInstruction {
+    type Accounts = openbook_v2::accounts::SettleFunds;
+    type Instruction = openbook_v2::instruction::SettleFunds;
     async fn to_instruction(
         &self,
-        _account_loader: impl ClientAccountLoader + 'async_trait,
+        account_loader: impl ClientAccountLoader + 'async_trait,
     ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
-        let instruction = openbook_v2::instruction::CreateOpenOrdersAccount {
-            name: "test".to_string(),
+        let instruction = Self::Instruction {};
+        let market: Market = account_loader.load(&self.market).await.unwrap();
+        let user_base_account =
+            token_account_or_native(self.user_base_account, self.owner.pubkey());
+        let user_quote_account =
+            token_account_or_native(self.user_quote_account, self.owner.pubkey());
+        let native = user_base_account.is_none() || user_quote_account.is_none();
+        let native_destination = if native {
+            Some(open_orders_owner(&account_loader, self.open_orders_account).await)
+        } else {
+            None
         };
-
-        let open_orders_indexer = Pubkey::find_program_address(
-            &[b"OpenOrdersIndexer".as_ref(), self.owner.pubkey().as_ref()],
-            &program_id,
-        )
-        .0;
-
-        let open_orders_account = Pubkey::find_program_address(
-            &[
-                b"OpenOrders".as_ref(),
-                self.owner.pubkey().as_ref(),
-                &self.account_num.to_le_bytes(),
-            ],
-            &program_id,
-        )
-        .0;
-
-        let accounts = openbook_v2::accounts::CreateOpenOrdersAccount {
+        let accounts = Self::Accounts {
             owner: self.owner.pubkey(),
-            open_orders_indexer,
-            open_orders_account,
+            penalty_payer: self.owner.pubkey(),
+            open_orders_account: self.open_orders_account,
             market: self.market,
-            payer: self.payer.pubkey(),
-            delegate_account: self.delegate,
+            market_authority: market.market_authority,
+            market_base_vault: self.market_base_vault,
+            market_quote_vault: self.market_quote_vault,
+            user_base_account,
+            user_quote_account,
+            referrer_account: self.referrer_account,
+            token_program: Token::id(),
             system_program: System::id(),
+            open_orders_delegate: open_orders_delegate(
+                &account_loader,
+                self.open_orders_account,
+                self.owner.pubkey(),
+            )
+            .await,
+            native_mint: native.then_some(spl_token::native_mint::ID),
+            native_settle_account: native.then(|| get_native_settle_address(self.market)),
+            native_destination,
+            owner_denylist_entry: market_denylist_entry(
+                self.market,
+                &market,
+                open_orders_owner(&account_loader, self.open_orders_account).await,
+            ),
+            signer_denylist_entry: market_denylist_entry(self.market, &market, self.owner.pubkey()),
+            base_owner_denylist_entry: match user_base_account {
+                Some(account) => {
+                    token_owner_denylist_entry(&account_loader, self.market, &market, account)
+                        .await
+                }
+                None => None,
+            },
+            quote_owner_denylist_entry: match user_quote_account {
+                Some(account) => {
+                    token_owner_denylist_entry(&account_loader, self.market, &market, account)
+                        .await
+                }
+                None => None,
+            },
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
 
         let instruction = make_instruction(program_id, &accounts, instruction);
@@ -17621,49 +28687,78 @@ impl ClientInstruction for CreateOpenOrdersAccountInstruction {
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        vec![self.owner, self.payer]
+        vec![self.owner]
     }
 }
 
-pub struct CloseOpenOrdersAccountInstruction {
-    pub account_num: u32,
-    pub market: Pubkey,
+#[derive(Clone)]
+pub struct WithdrawInstruction {
     pub owner: TestKeypair,
-    pub sol_destination: Pubkey,
+    pub open_orders_account: Pubkey,
+    pub market: Pubkey,
+    pub market_base_vault: Pubkey,
+    pub market_quote_vault: Pubkey,
+    pub user_base_account: Pubkey,
+    pub user_quote_account: Pubkey,
+    pub base_amount: u64,
+    pub quote_amount: u64,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for CloseOpenOrdersAccountInstruction {
-    type Accounts = openbook_v2::accounts::CloseOpenOrdersAccount;
-    type Instruction = openbook_v2::instruction::CloseOpenOrdersAccount;
+impl ClientInstruction for WithdrawInstruction {
+    type Accounts = openbook_v2::accounts::Withdraw;
+    type Instruction = openbook_v2::instruction::Withdraw;
     async fn to_instruction(
         &self,
-        _account_loader: impl ClientAccountLoader + 'async_trait,
+        account_loader: impl ClientAccountLoader + 'async_trait,
     ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
-        let instruction = openbook_v2::instruction::CloseOpenOrdersAccount {};
-
-        let open_orders_indexer = Pubkey::find_program_address(
-            &[b"OpenOrdersIndexer".as_ref(), self.owner.pubkey().as_ref()],
-            &program_id,
-        )
-        .0;
-
-        let open_orders_account = Pubkey::find_program_address(
-            &[
-                b"OpenOrders".as_ref(),
-                self.owner.pubkey().as_ref(),
-                &self.account_num.to_le_bytes(),
-            ],
-            &program_id,
-        )
-        .0;
-
-        let accounts = openbook_v2::accounts::CloseOpenOrdersAccount {
+        let instruction = Self::Instruction {
+            base_amount: self.base_amount,
+            quote_amount: self.quote_amount,
+        };
+        let market: Market = account_loader.load(&self.market).await.unwrap();
+        let accounts = Self::Accounts {
             owner: self.owner.pubkey(),
-            open_orders_indexer,
-            open_orders_account,
-            sol_destination: self.sol_destination,
+            penalty_payer: self.owner.pubkey(),
+            open_orders_account: self.open_orders_account,
+            market: self.market,
+            market_authority: market.market_authority,
+            market_base_vault: self.market_base_vault,
+            market_quote_vault: self.market_quote_vault,
+            user_base_account: self.user_base_account,
+            user_quote_account: self.user_quote_account,
+            token_program: Token::id(),
             system_program: System::id(),
+            open_orders_delegate: open_orders_delegate(
+                &account_loader,
+                self.open_orders_account,
+                self.owner.pubkey(),
+            )
+            .await,
+            owner_denylist_entry: market_denylist_entry(
+                self.market,
+                &market,
+                open_orders_owner(&account_loader, self.open_orders_account).await,
+            ),
+            signer_denylist_entry: market_denylist_entry(self.market, &market, self.owner.pubkey()),
+            base_owner_denylist_entry: token_owner_denylist_entry(
+                &account_loader,
+                self.market,
+                &market,
+                self.user_base_account,
+            )
+            .await,
+            quote_owner_denylist_entry: token_owner_denylist_entry(
+                &account_loader,
+                self.market,
+                &market,
+                self.user_quote_account,
+            )
+            .await,
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
 
         let instruction = make_instruction(program_id, &accounts, instruction);
@@ -17675,115 +28770,38 @@ impl ClientInstruction for CloseOpenOrdersAccountInstruction {
     }
 }
 
-#[derive(Default)]
-pub struct CreateMarketInstruction {
-    pub collect_fee_admin: Pubkey,
-    pub open_orders_admin: Option<Pubkey>,
-    pub consume_events_admin: Option<Pubkey>,
-    pub close_market_admin: Option<Pubkey>,
-    pub oracle_a: Option<Pubkey>,
-    pub oracle_b: Option<Pubkey>,
-    pub base_mint: Pubkey,
-    pub quote_mint: Pubkey,
-    pub name: String,
-    pub bids: Pubkey,
-    pub asks: Pubkey,
-    pub event_heap: Pubkey,
-    pub market: TestKeypair,
-    pub payer: TestKeypair,
-    pub quote_lot_size: i64,
-    pub base_lot_size: i64,
-    pub maker_fee: i64,
-    pub taker_fee: i64,
-    pub fee_penalty: u64,
-    pub settle_fee_flat: f32,
-    pub settle_fee_amount_threshold: f32,
-    pub time_expiry: i64,
-}
-impl CreateMarketInstruction {
-    pub async fn with_new_book_and_heap(
-        solana: &SolanaCookie,
-        oracle_a: Option<Pubkey>,
-        oracle_b: Option<Pubkey>,
-    ) -> Self {
-        CreateMarketInstruction {
-            bids: solana
-                .create_account_for_type::<BookSide>(&openbook_v2::id())
-                .await,
-            asks: solana
-                .create_account_for_type::<BookSide>(&openbook_v2::id())
-                .await,
-            event_heap: solana
-                .create_account_for_type::<EventHeap>(&openbook_v2::id())
-                .await,
-            oracle_a,
-            oracle_b,
-            ..CreateMarketInstruction::default()
-        }
-    }
+#[derive(Clone)]
+pub struct TransferFreeBalanceInstruction {
+    pub owner: TestKeypair,
+    pub source_open_orders_account: Pubkey,
+    pub destination_open_orders_account: Pubkey,
+    pub market: Pubkey,
+    pub base_amount: u64,
+    pub quote_amount: u64,
 }
-
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for CreateMarketInstruction {
-    type Accounts = openbook_v2::accounts::CreateMarket;
-    type Instruction = openbook_v2::instruction::CreateMarket;
+impl ClientInstruction for TransferFreeBalanceInstruction {
+    type Accounts = openbook_v2::accounts::TransferFreeBalance;
+    type Instruction = openbook_v2::instruction::TransferFreeBalance;
     async fn to_instruction(
         &self,
-        _loader: impl ClientAccountLoader + 'async_trait,
+        account_loader: impl ClientAccountLoader + 'async_trait,
     ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
         let instruction = Self::Instruction {
-            name: "ONE-TWO".to_string(),
-            oracle_config: OracleConfigParams {
-                conf_filter: 0.1,
-                max_staleness_slots: Some(100),
-            },
-            quote_lot_size: self.quote_lot_size,
-            base_lot_size: self.base_lot_size,
-            maker_fee: self.maker_fee,
-            taker_fee: self.taker_fee,
-            time_expiry: self.time_expiry,
+            base_amount: self.base_amount,
+            quote_amount: self.quote_amount,
         };
-
-        let event_authority =
-            Pubkey::find_program_address(&[b"__event_authority".as_ref()], &openbook_v2::id()).0;
-
-        let market_authority = Pubkey::find_program_address(
-            &[b"Market".as_ref(), self.market.pubkey().to_bytes().as_ref()],
-            &openbook_v2::id(),
-        )
-        .0;
-
-        let market_base_vault = spl_associated_token_account::get_associated_token_address(
-            &market_authority,
-            &self.base_mint,
-        );
-        let market_quote_vault = spl_associated_token_account::get_associated_token_address(
-            &market_authority,
-            &self.quote_mint,
-        );
-
+        let market: Market = account_loader.load(&self.market).await.unwrap();
         let accounts = Self::Accounts {
-            market: self.market.pubkey(),
-            market_authority,
-            bids: self.bids,
-            asks: self.asks,
-            event_heap: self.event_heap,
-            payer: self.payer.pubkey(),
-            market_base_vault,
-            market_quote_vault,
-            quote_mint: self.quote_mint,
-            base_mint: self.base_mint,
-            system_program: System::id(),
-            token_program: Token::id(),
-            associated_token_program: AssociatedToken::id(),
-            collect_fee_admin: self.collect_fee_admin,
-            open_orders_admin: self.open_orders_admin,
-            consume_events_admin: self.consume_events_admin,
-            close_market_admin: self.close_market_admin,
-            oracle_a: self.oracle_a,
-            oracle_b: self.oracle_b,
-            event_authority,
+            owner: self.owner.pubkey(),
+            source_open_orders_account: self.source_open_orders_account,
+            destination_open_orders_account: self.destination_open_orders_account,
+            market: self.market,
+            owner_denylist_entry: market_denylist_entry(self.market, &market, self.owner.pubkey()),
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
             program: openbook_v2::id(),
         };
 
@@ -17792,144 +28810,91 @@ impl ClientInstruction for CreateMarketInstruction {
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        vec![self.payer, self.market]
+        vec![self.owner]
     }
 }
 
 #[derive(Clone)]
-pub struct PlaceOrderInstruction {
+pub struct SettleFundsExpiredInstruction {
+    pub close_market_admin: TestKeypair,
+    pub owner: TestKeypair,
     pub open_orders_account: Pubkey,
-    pub open_orders_admin: Option<TestKeypair>,
     pub market: Pubkey,
-    pub signer: TestKeypair,
-    pub market_vault: Pubkey,
-    pub user_token_account: Pubkey,
-    pub side: Side,
-    pub price_lots: i64,
-    pub max_base_lots: i64,
-    pub max_quote_lots_including_fees: i64,
-    pub client_order_id: u64,
-    pub expiry_timestamp: u64,
-    pub order_type: PlaceOrderType,
-    pub self_trade_behavior: SelfTradeBehavior,
-    pub remainings: Vec<Pubkey>,
+    pub market_base_vault: Pubkey,
+    pub market_quote_vault: Pubkey,
+    pub user_base_account: Pubkey,
+    pub user_quote_account: Pubkey,
+    pub referrer_account: Option<Pubkey>,
 }
-
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for PlaceOrderInstruction {
-    type Accounts = openbook_v2::accounts::PlaceOrder;
-    type Instruction = openbook_v2::instruction::PlaceOrder;
+impl ClientInstruction for SettleFundsExpiredInstruction {
+    type Accounts = openbook_v2::accounts::SettleFundsExpired;
+    type Instruction = openbook_v2::instruction::SettleFundsExpired;
     async fn to_instruction(
         &self,
         account_loader: impl ClientAccountLoader + 'async_trait,
     ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
-        let instruction = Self::Instruction {
-            args: PlaceOrderArgs {
-                side: self.side,
-                price_lots: self.price_lots,
-                max_base_lots: self.max_base_lots,
-                max_quote_lots_including_fees: self.max_quote_lots_including_fees,
-                client_order_id: self.client_order_id,
-                order_type: self.order_type,
-                expiry_timestamp: self.expiry_timestamp,
-                self_trade_behavior: self.self_trade_behavior,
-                limit: 10,
-            },
-        };
-
+        let instruction = Self::Instruction {};
         let market: Market = account_loader.load(&self.market).await.unwrap();
-
         let accounts = Self::Accounts {
+            close_market_admin: self.close_market_admin.pubkey(),
+            owner: self.owner.pubkey(),
+            penalty_payer: self.owner.pubkey(),
             open_orders_account: self.open_orders_account,
-            open_orders_admin: self.open_orders_admin.map(|kp| kp.pubkey()),
             market: self.market,
-            bids: market.bids,
-            asks: market.asks,
-            event_heap: market.event_heap,
-            oracle_a: market.oracle_a.into(),
-            oracle_b: market.oracle_b.into(),
-            signer: self.signer.pubkey(),
-            user_token_account: self.user_token_account,
-            market_vault: self.market_vault,
+            market_authority: market.market_authority,
+            market_base_vault: self.market_base_vault,
+            market_quote_vault: self.market_quote_vault,
+            user_base_account: self.user_base_account,
+            user_quote_account: self.user_quote_account,
+            referrer_account: self.referrer_account,
             token_program: Token::id(),
+            system_program: System::id(),
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
-        let mut instruction = make_instruction(program_id, &accounts, instruction);
-        let mut vec_remainings: Vec<AccountMeta> = Vec::new();
-        for remaining in &self.remainings {
-            vec_remainings.push(AccountMeta {
-                pubkey: *remaining,
-                is_signer: false,
-                is_writable: true,
-            })
-        }
-        instruction.accounts.append(&mut vec_remainings);
+
+        let instruction = make_instruction(program_id, &accounts, instruction);
         (accounts, instruction)
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        let mut signers = vec![self.signer];
-        if let Some(open_orders_admin) = self.open_orders_admin {
-            signers.push(open_orders_admin);
-        }
-
-        signers
+        vec![self.close_market_admin, self.owner]
     }
 }
 
-#[derive(Clone)]
-pub struct PlaceOrderPeggedInstruction {
-    pub open_orders_account: Pubkey,
+pub struct SweepFeesInstruction {
+    pub collect_fee_admin: TestKeypair,
     pub market: Pubkey,
-    pub signer: TestKeypair,
-    pub user_token_account: Pubkey,
-    pub market_vault: Pubkey,
-    pub side: Side,
-    pub price_offset: i64,
-    pub max_base_lots: i64,
-    pub max_quote_lots_including_fees: i64,
-    pub client_order_id: u64,
-    pub peg_limit: i64,
+    pub market_quote_vault: Pubkey,
+    pub token_receiver_account: Pubkey,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for PlaceOrderPeggedInstruction {
-    type Accounts = openbook_v2::accounts::PlaceOrder;
-    type Instruction = openbook_v2::instruction::PlaceOrderPegged;
+impl ClientInstruction for SweepFeesInstruction {
+    type Accounts = openbook_v2::accounts::SweepFees;
+    type Instruction = openbook_v2::instruction::SweepFees;
     async fn to_instruction(
         &self,
         account_loader: impl ClientAccountLoader + 'async_trait,
     ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
-        let instruction = Self::Instruction {
-            args: PlaceOrderPeggedArgs {
-                side: self.side,
-                price_offset_lots: self.price_offset,
-                peg_limit: self.peg_limit,
-                max_base_lots: self.max_base_lots,
-                max_quote_lots_including_fees: self.max_quote_lots_including_fees,
-                client_order_id: self.client_order_id,
-                order_type: PlaceOrderType::Limit,
-                expiry_timestamp: 0,
-                self_trade_behavior: SelfTradeBehavior::default(),
-                limit: 10,
-            },
-        };
-
+        let instruction = Self::Instruction {};
         let market: Market = account_loader.load(&self.market).await.unwrap();
 
         let accounts = Self::Accounts {
-            open_orders_account: self.open_orders_account,
-            open_orders_admin: None,
+            collect_fee_admin: self.collect_fee_admin.pubkey(),
             market: self.market,
-            bids: market.bids,
-            asks: market.asks,
-            event_heap: market.event_heap,
-            oracle_a: market.oracle_a.into(),
-            oracle_b: market.oracle_b.into(),
-            signer: self.signer.pubkey(),
-            user_token_account: self.user_token_account,
-            market_vault: self.market_vault,
+            market_authority: market.market_authority,
+            market_quote_vault: self.market_quote_vault,
+            token_receiver_account: self.token_receiver_account,
             token_program: Token::id(),
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
         let instruction = make_instruction(program_id, &accounts, instruction);
 
@@ -17937,103 +28902,96 @@ impl ClientInstruction for PlaceOrderPeggedInstruction {
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        vec![self.signer]
+        vec![self.collect_fee_admin]
     }
 }
 
-pub struct PlaceTakeOrderInstruction {
-    pub open_orders_admin: Option<TestKeypair>,
+pub struct DepositInstruction {
+    pub open_orders_account: Pubkey,
     pub market: Pubkey,
-    pub signer: TestKeypair,
     pub market_base_vault: Pubkey,
     pub market_quote_vault: Pubkey,
     pub user_base_account: Pubkey,
     pub user_quote_account: Pubkey,
-    pub side: Side,
-    pub price_lots: i64,
-    pub max_base_lots: i64,
-    pub max_quote_lots_including_fees: i64,
+    pub owner: TestKeypair,
+    pub base_amount: u64,
+    pub quote_amount: u64,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for PlaceTakeOrderInstruction {
-    type Accounts = openbook_v2::accounts::PlaceTakeOrder;
-    type Instruction = openbook_v2::instruction::PlaceTakeOrder;
+impl ClientInstruction for DepositInstruction {
+    type Accounts = openbook_v2::accounts::Deposit;
+    type Instruction = openbook_v2::instruction::Deposit;
     async fn to_instruction(
         &self,
-        account_loader: impl ClientAccountLoader + 'async_trait,
+        _account_loader: impl ClientAccountLoader + 'async_trait,
     ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
         let instruction = Self::Instruction {
-            args: PlaceTakeOrderArgs {
-                side: self.side,
-                price_lots: self.price_lots,
-                max_base_lots: self.max_base_lots,
-                max_quote_lots_including_fees: self.max_quote_lots_including_fees,
-                order_type: PlaceOrderType::ImmediateOrCancel,
-                limit: 10,
-            },
+            base_amount: self.base_amount,
+            quote_amount: self.quote_amount,
         };
 
-        let market: Market = account_loader.load(&self.market).await.unwrap();
-
+        let user_base_account =
+            token_account_or_native(self.user_base_account, self.owner.pubkey());
+        let user_quote_account =
+            token_account_or_native(self.user_quote_account, self.owner.pubkey());
+        let native = user_base_account.is_none() || user_quote_account.is_none();
         let accounts = Self::Accounts {
-            open_orders_admin: self.open_orders_admin.map(|kp| kp.pubkey()),
+            owner: self.owner.pubkey(),
+            open_orders_account: self.open_orders_account,
             market: self.market,
-            market_authority: market.market_authority,
-            bids: market.bids,
-            asks: market.asks,
-            event_heap: market.event_heap,
-            oracle_a: market.oracle_a.into(),
-            oracle_b: market.oracle_b.into(),
-            signer: self.signer.pubkey(),
-            penalty_payer: self.signer.pubkey(),
-            user_base_account: self.user_base_account,
-            user_quote_account: self.user_quote_account,
             market_base_vault: self.market_base_vault,
             market_quote_vault: self.market_quote_vault,
+            user_base_account,
+            user_quote_account,
             token_program: Token::id(),
-            system_program: System::id(),
+            system_program: Some(System::id()),
+            native_payer: native.then_some(self.owner.pubkey()),
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
-
         let instruction = make_instruction(program_id, &accounts, instruction);
+
         (accounts, instruction)
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        let mut signers = vec![self.signer];
-        if let Some(open_orders_admin) = self.open_orders_admin {
-            signers.push(open_orders_admin);
-        }
-
-        signers
+        vec![self.owner]
     }
 }
 
-pub struct CancelOrderInstruction {
-    pub open_orders_account: Pubkey,
-    pub market: Pubkey,
-    pub signer: TestKeypair,
-    pub order_id: u128,
+pub struct StubOracleSetInstruction {
+    pub mint: Pubkey,
+    pub owner: TestKeypair,
+    pub price: f64,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for CancelOrderInstruction {
-    type Accounts = openbook_v2::accounts::CancelOrder;
-    type Instruction = openbook_v2::instruction::CancelOrder;
+impl ClientInstruction for StubOracleSetInstruction {
+    type Accounts = openbook_v2::accounts::StubOracleSet;
+    type Instruction = openbook_v2::instruction::StubOracleSet;
+
     async fn to_instruction(
         &self,
-        account_loader: impl ClientAccountLoader + 'async_trait,
-    ) -> (Self::Accounts, instruction::Instruction) {
+        _loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, Instruction) {
         let program_id = openbook_v2::id();
-        let instruction = Self::Instruction {
-            order_id: self.order_id,
-        };
-        let market: Market = account_loader.load(&self.market).await.unwrap();
+        let instruction = Self::Instruction { price: self.price };
+
+        let oracle = Pubkey::find_program_address(
+            &[
+                b"StubOracle".as_ref(),
+                self.owner.pubkey().as_ref(),
+                self.mint.as_ref(),
+            ],
+            &program_id,
+        )
+        .0;
+
         let accounts = Self::Accounts {
-            open_orders_account: self.open_orders_account,
-            market: self.market,
-            bids: market.bids,
-            asks: market.asks,
-            signer: self.signer.pubkey(),
+            oracle,
+            owner: self.owner.pubkey(),
         };
 
         let instruction = make_instruction(program_id, &accounts, instruction);
@@ -18041,35 +28999,86 @@ impl ClientInstruction for CancelOrderInstruction {
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        vec![self.signer]
+        vec![self.owner]
     }
 }
 
-pub struct CancelOrderByClientOrderIdInstruction {
-    pub open_orders_account: Pubkey,
-    pub market: Pubkey,
-    pub signer: TestKeypair,
-    pub client_order_id: u64,
+pub struct StubOracleCreate {
+    pub mint: Pubkey,
+    pub owner: TestKeypair,
+    pub payer: TestKeypair,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for CancelOrderByClientOrderIdInstruction {
-    type Accounts = openbook_v2::accounts::CancelOrder;
-    type Instruction = openbook_v2::instruction::CancelOrderByClientOrderId;
+impl ClientInstruction for StubOracleCreate {
+    type Accounts = openbook_v2::accounts::StubOracleCreate;
+    type Instruction = openbook_v2::instruction::StubOracleCreate;
+
     async fn to_instruction(
         &self,
-        account_loader: impl ClientAccountLoader + 'async_trait,
-    ) -> (Self::Accounts, instruction::Instruction) {
+        _loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, Instruction) {
         let program_id = openbook_v2::id();
-        let instruction = Self::Instruction {
-            client_order_id: self.client_order_id,
+        let instruction = Self::Instruction { price: 1.0 };
+
+        let oracle = Pubkey::find_program_address(
+            &[
+                b"StubOracle".as_ref(),
+                self.owner.pubkey().as_ref(),
+                self.mint.as_ref(),
+            ],
+            &program_id,
+        )
+        .0;
+
+        let accounts = Self::Accounts {
+            oracle,
+            mint: self.mint,
+            owner: self.owner.pubkey(),
+            payer: self.payer.pubkey(),
+            system_program: System::id(),
         };
-        let market: Market = account_loader.load(&self.market).await.unwrap();
+
+        let instruction = make_instruction(program_id, &accounts, instruction);
+        (accounts, instruction)
+    }
+
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![self.payer, self.owner]
+    }
+}
+
+pub struct StubOracleCloseInstruction {
+    pub mint: Pubkey,
+    pub owner: TestKeypair,
+    pub sol_destination: Pubkey,
+}
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for StubOracleCloseInstruction {
+    type Accounts = openbook_v2::accounts::StubOracleClose;
+    type Instruction = openbook_v2::instruction::StubOracleClose;
+
+    async fn to_instruction(
+        &self,
+        _loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction {};
+
+        let oracle = Pubkey::find_program_address(
+            &[
+                b"StubOracle".as_ref(),
+                self.owner.pubkey().as_ref(),
+                self.mint.as_ref(),
+            ],
+            &program_id,
+        )
+        .0;
+
         let accounts = Self::Accounts {
-            open_orders_account: self.open_orders_account,
-            market: self.market,
-            bids: market.bids,
-            asks: market.asks,
-            signer: self.signer.pubkey(),
+            owner: self.owner.pubkey(),
+            oracle,
+            sol_destination: self.sol_destination,
+            token_program: Token::id(),
         };
 
         let instruction = make_instruction(program_id, &accounts, instruction);
@@ -18077,36 +29086,36 @@ impl ClientInstruction for CancelOrderByClientOrderIdInstruction {
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        vec![self.signer]
+        vec![self.owner]
     }
 }
 
 #[derive(Clone)]
-pub struct CancelAllOrdersInstruction {
-    pub open_orders_account: Pubkey,
+pub struct CloseMarketInstruction {
+    pub close_market_admin: TestKeypair,
     pub market: Pubkey,
-    pub signer: TestKeypair,
+    pub sol_destination: Pubkey,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for CancelAllOrdersInstruction {
-    type Accounts = openbook_v2::accounts::CancelOrder;
-    type Instruction = openbook_v2::instruction::CancelAllOrders;
+impl ClientInstruction for CloseMarketInstruction {
+    type Accounts = openbook_v2::accounts::CloseMarket;
+    type Instruction = openbook_v2::instruction::CloseMarket;
     async fn to_instruction(
         &self,
         account_loader: impl ClientAccountLoader + 'async_trait,
     ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
-        let instruction = Self::Instruction {
-            side_option: None,
-            limit: 5,
-        };
+        let instruction = Self::Instruction {};
         let market: Market = account_loader.load(&self.market).await.unwrap();
+
         let accounts = Self::Accounts {
-            open_orders_account: self.open_orders_account,
+            close_market_admin: self.close_market_admin.pubkey(),
             market: self.market,
             bids: market.bids,
             asks: market.asks,
-            signer: self.signer.pubkey(),
+            event_heap: market.event_heap,
+            token_program: Token::id(),
+            sol_destination: self.sol_destination,
         };
 
         let instruction = make_instruction(program_id, &accounts, instruction);
@@ -18114,133 +29123,104 @@ impl ClientInstruction for CancelAllOrdersInstruction {
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        vec![self.signer]
+        vec![self.close_market_admin]
     }
 }
 
-#[derive(Clone)]
-pub struct ConsumeEventsInstruction {
-    pub consume_events_admin: Option<TestKeypair>,
+pub struct SetMarketExpiredInstruction {
+    pub close_market_admin: TestKeypair,
     pub market: Pubkey,
-    pub open_orders_accounts: Vec<Pubkey>,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for ConsumeEventsInstruction {
-    type Accounts = openbook_v2::accounts::ConsumeEvents;
-    type Instruction = openbook_v2::instruction::ConsumeEvents;
+impl ClientInstruction for SetMarketExpiredInstruction {
+    type Accounts = openbook_v2::accounts::SetMarketExpired;
+    type Instruction = openbook_v2::instruction::SetMarketExpired;
     async fn to_instruction(
         &self,
-        account_loader: impl ClientAccountLoader + 'async_trait,
+        _account_loader: impl ClientAccountLoader + 'async_trait,
     ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
-        let instruction = Self::Instruction { limit: 10 };
+        let instruction = Self::Instruction {};
 
-        let market: Market = account_loader.load(&self.market).await.unwrap();
         let accounts = Self::Accounts {
-            consume_events_admin: self.consume_events_admin.map(|kp| kp.pubkey()),
+            close_market_admin: self.close_market_admin.pubkey(),
             market: self.market,
-            event_heap: market.event_heap,
         };
 
-        let mut instruction = make_instruction(program_id, &accounts, instruction);
-        instruction
-            .accounts
-            .extend(self.open_orders_accounts.iter().map(|ma| AccountMeta {
-                pubkey: *ma,
-                is_signer: false,
-                is_writable: true,
-            }));
+        let instruction = make_instruction(program_id, &accounts, instruction);
         (accounts, instruction)
     }
 
-    fn signers(&self) -> Vec<TestKeypair> {
-        match self.consume_events_admin {
-            Some(consume_events_admin) => vec![consume_events_admin],
-            None => vec![],
-        }
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![self.close_market_admin]
     }
 }
 
-pub struct ConsumeGivenEventsInstruction {
-    pub consume_events_admin: Option<TestKeypair>,
+pub struct PruneOrdersInstruction {
+    pub close_market_admin: TestKeypair,
     pub market: Pubkey,
-    pub open_orders_accounts: Vec<Pubkey>,
-    pub slots: Vec<usize>,
+    pub open_orders_account: Pubkey,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for ConsumeGivenEventsInstruction {
-    type Accounts = openbook_v2::accounts::ConsumeEvents;
-    type Instruction = openbook_v2::instruction::ConsumeGivenEvents;
+impl ClientInstruction for PruneOrdersInstruction {
+    type Accounts = openbook_v2::accounts::PruneOrders;
+    type Instruction = openbook_v2::instruction::PruneOrders;
     async fn to_instruction(
         &self,
         account_loader: impl ClientAccountLoader + 'async_trait,
     ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
-        let instruction = Self::Instruction {
-            slots: self.slots.clone(),
-        };
-
+        let instruction = Self::Instruction { limit: 255 };
         let market: Market = account_loader.load(&self.market).await.unwrap();
+
         let accounts = Self::Accounts {
-            consume_events_admin: self.consume_events_admin.map(|kp| kp.pubkey()),
+            close_market_admin: self.close_market_admin.pubkey(),
             market: self.market,
-            event_heap: market.event_heap,
+            open_orders_account: self.open_orders_account,
+            bids: market.bids,
+            asks: market.asks,
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
 
-        let mut instruction = make_instruction(program_id, &accounts, instruction);
-        instruction
-            .accounts
-            .extend(self.open_orders_accounts.iter().map(|ma| AccountMeta {
-                pubkey: *ma,
-                is_signer: false,
-                is_writable: true,
-            }));
+        let instruction = make_instruction(program_id, &accounts, instruction);
         (accounts, instruction)
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        match self.consume_events_admin {
-            Some(consume_events_admin) => vec![consume_events_admin],
-            None => vec![],
-        }
+        vec![self.close_market_admin]
     }
 }
 
-#[derive(Clone)]
-pub struct SettleFundsInstruction {
-    pub owner: TestKeypair,
-    pub open_orders_account: Pubkey,
+pub struct PurgeExpiredOrdersInstruction {
+    pub cranker: TestKeypair,
     pub market: Pubkey,
-    pub market_base_vault: Pubkey,
-    pub market_quote_vault: Pubkey,
-    pub user_base_account: Pubkey,
-    pub user_quote_account: Pubkey,
-    pub referrer_account: Option<Pubkey>,
+    pub limit: u8,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for SettleFundsInstruction {
-    type Accounts = openbook_v2::accounts::SettleFunds;
-    type Instruction = openbook_v2::instruction::SettleFunds;
+impl ClientInstruction for PurgeExpiredOrdersInstruction {
+    type Accounts = openbook_v2::accounts::PurgeExpiredOrders;
+    type Instruction = openbook_v2::instruction::PurgeExpiredOrders;
     async fn to_instruction(
         &self,
         account_loader: impl ClientAccountLoader + 'async_trait,
     ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
-        let instruction = Self::Instruction {};
+        let instruction = Self::Instruction { limit: self.limit };
         let market: Market = account_loader.load(&self.market).await.unwrap();
+
         let accounts = Self::Accounts {
-            owner: self.owner.pubkey(),
-            penalty_payer: self.owner.pubkey(),
-            open_orders_account: self.open_orders_account,
+            cranker: self.cranker.pubkey(),
             market: self.market,
-            market_authority: market.market_authority,
-            market_base_vault: self.market_base_vault,
-            market_quote_vault: self.market_quote_vault,
-            user_base_account: self.user_base_account,
-            user_quote_account: self.user_quote_account,
-            referrer_account: self.referrer_account,
-            token_program: Token::id(),
-            system_program: System::id(),
+            bids: market.bids,
+            asks: market.asks,
+            event_heap: market.event_heap,
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
 
         let instruction = make_instruction(program_id, &accounts, instruction);
@@ -18248,47 +29228,41 @@ impl ClientInstruction for SettleFundsInstruction {
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        vec![self.owner]
+        vec![self.cranker]
     }
 }
 
-#[derive(Clone)]
-pub struct SettleFundsExpiredInstruction {
-    pub close_market_admin: TestKeypair,
-    pub owner: TestKeypair,
-    pub open_orders_account: Pubkey,
+pub struct SetExposureLimitsInstruction {
+    pub exposure_admin: TestKeypair,
     pub market: Pubkey,
-    pub market_base_vault: Pubkey,
-    pub market_quote_vault: Pubkey,
-    pub user_base_account: Pubkey,
-    pub user_quote_account: Pubkey,
-    pub referrer_account: Option<Pubkey>,
+    pub open_orders_account: Pubkey,
+    pub max_bids_quote_lots: i64,
+    pub max_asks_base_lots: i64,
+    pub max_open_orders: u16,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for SettleFundsExpiredInstruction {
-    type Accounts = openbook_v2::accounts::SettleFundsExpired;
-    type Instruction = openbook_v2::instruction::SettleFundsExpired;
+impl ClientInstruction for SetExposureLimitsInstruction {
+    type Accounts = openbook_v2::accounts::SetExposureLimits;
+    type Instruction = openbook_v2::instruction::SetExposureLimits;
     async fn to_instruction(
         &self,
-        account_loader: impl ClientAccountLoader + 'async_trait,
+        _account_loader: impl ClientAccountLoader + 'async_trait,
     ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
-        let instruction = Self::Instruction {};
-        let market: Market = account_loader.load(&self.market).await.unwrap();
+        let instruction = Self::Instruction {
+            max_bids_quote_lots: self.max_bids_quote_lots,
+            max_asks_base_lots: self.max_asks_base_lots,
+            max_open_orders: self.max_open_orders,
+        };
+
         let accounts = Self::Accounts {
-            close_market_admin: self.close_market_admin.pubkey(),
-            owner: self.owner.pubkey(),
-            penalty_payer: self.owner.pubkey(),
-            open_orders_account: self.open_orders_account,
+            exposure_admin: self.exposure_admin.pubkey(),
             market: self.market,
-            market_authority: market.market_authority,
-            market_base_vault: self.market_base_vault,
-            market_quote_vault: self.market_quote_vault,
-            user_base_account: self.user_base_account,
-            user_quote_account: self.user_quote_account,
-            referrer_account: self.referrer_account,
-            token_program: Token::id(),
-            system_program: System::id(),
+            open_orders_account: self.open_orders_account,
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
 
         let instruction = make_instruction(program_id, &accounts, instruction);
@@ -18296,121 +29270,123 @@ impl ClientInstruction for SettleFundsExpiredInstruction {
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        vec![self.close_market_admin, self.owner]
+        vec![self.exposure_admin]
     }
 }
 
-pub struct SweepFeesInstruction {
-    pub collect_fee_admin: TestKeypair,
-    pub market: Pubkey,
-    pub market_quote_vault: Pubkey,
-    pub token_receiver_account: Pubkey,
+pub struct SetDelegateInstruction {
+    pub delegate_account: Option<Pubkey>,
+    pub owner: TestKeypair,
+    pub open_orders_account: Pubkey,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for SweepFeesInstruction {
-    type Accounts = openbook_v2::accounts::SweepFees;
-    type Instruction = openbook_v2::instruction::SweepFees;
+impl ClientInstruction for SetDelegateInstruction {
+    type Accounts = openbook_v2::accounts::SetDelegate;
+    type Instruction = openbook_v2::instruction::SetDelegate;
     async fn to_instruction(
         &self,
-        account_loader: impl ClientAccountLoader + 'async_trait,
+        _account_loader: impl ClientAccountLoader + 'async_trait,
     ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
         let instruction = Self::Instruction {};
-        let market: Market = account_loader.load(&self.market).await.unwrap();
 
         let accounts = Self::Accounts {
-            collect_fee_admin: self.collect_fee_admin.pubkey(),
-            market: self.market,
-            market_authority: market.market_authority,
-            market_quote_vault: self.market_quote_vault,
-            token_receiver_account: self.token_receiver_account,
-            token_program: Token::id(),
+            owner: self.owner.pubkey(),
+            open_orders_account: self.open_orders_account,
+            delegate_account: self.delegate_account,
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
-        let instruction = make_instruction(program_id, &accounts, instruction);
 
+        let instruction = make_instruction(program_id, &accounts, instruction);
         (accounts, instruction)
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        vec![self.collect_fee_admin]
+        vec![self.owner]
     }
 }
 
-pub struct DepositInstruction {
-    pub open_orders_account: Pubkey,
-    pub market: Pubkey,
-    pub market_base_vault: Pubkey,
-    pub market_quote_vault: Pubkey,
-    pub user_base_account: Pubkey,
-    pub user_quote_account: Pubkey,
+pub struct AddDelegateInstruction {
+    pub payer: TestKeypair,
     pub owner: TestKeypair,
-    pub base_amount: u64,
-    pub quote_amount: u64,
+    pub open_orders_account: Pubkey,
+    pub delegate: Pubkey,
+    pub label: String,
+    pub permissions: u8,
+    pub expiry_timestamp: u64,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for DepositInstruction {
-    type Accounts = openbook_v2::accounts::Deposit;
-    type Instruction = openbook_v2::instruction::Deposit;
+impl ClientInstruction for AddDelegateInstruction {
+    type Accounts = openbook_v2::accounts::AddDelegate;
+    type Instruction = openbook_v2::instruction::AddDelegate;
     async fn to_instruction(
         &self,
         _account_loader: impl ClientAccountLoader + 'async_trait,
     ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
         let instruction = Self::Instruction {
-            base_amount: self.base_amount,
-            quote_amount: self.quote_amount,
+            label: self.label.clone(),
+            permissions: self.permissions,
+            expiry_timestamp: self.expiry_timestamp,
         };
 
         let accounts = Self::Accounts {
+            payer: self.payer.pubkey(),
             owner: self.owner.pubkey(),
             open_orders_account: self.open_orders_account,
-            market: self.market,
-            market_base_vault: self.market_base_vault,
-            market_quote_vault: self.market_quote_vault,
-            user_base_account: self.user_base_account,
-            user_quote_account: self.user_quote_account,
-            token_program: Token::id(),
+            delegate_account: self.delegate,
+            open_orders_delegate: get_open_orders_delegate_address(
+                self.open_orders_account,
+                self.delegate,
+            ),
+            system_program: System::id(),
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
-        let instruction = make_instruction(program_id, &accounts, instruction);
 
+        let instruction = make_instruction(program_id, &accounts, instruction);
         (accounts, instruction)
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        vec![self.owner]
+        vec![self.payer, self.owner]
     }
 }
 
-pub struct StubOracleSetInstruction {
-    pub mint: Pubkey,
+pub struct RemoveDelegateInstruction {
     pub owner: TestKeypair,
-    pub price: f64,
+    pub open_orders_account: Pubkey,
+    pub delegate: Pubkey,
+    pub sol_destination: Pubkey,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for StubOracleSetInstruction {
-    type Accounts = openbook_v2::accounts::StubOracleSet;
-    type Instruction = openbook_v2::instruction::StubOracleSet;
-
+impl ClientInstruction for RemoveDelegateInstruction {
+    type Accounts = openbook_v2::accounts::RemoveDelegate;
+    type Instruction = openbook_v2::instruction::RemoveDelegate;
     async fn to_instruction(
         &self,
-        _loader: impl ClientAccountLoader + 'async_trait,
-    ) -> (Self::Accounts, Instruction) {
+        _account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
-        let instruction = Self::Instruction { price: self.price };
-
-        let oracle = Pubkey::find_program_address(
-            &[
-                b"StubOracle".as_ref(),
-                self.owner.pubkey().as_ref(),
-                self.mint.as_ref(),
-            ],
-            &program_id,
-        )
-        .0;
+        let instruction = Self::Instruction {};
 
         let accounts = Self::Accounts {
-            oracle,
             owner: self.owner.pubkey(),
+            open_orders_account: self.open_orders_account,
+            open_orders_delegate: get_open_orders_delegate_address(
+                self.open_orders_account,
+                self.delegate,
+            ),
+            sol_destination: self.sol_destination,
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
 
         let instruction = make_instruction(program_id, &accounts, instruction);
@@ -18422,39 +29398,33 @@ impl ClientInstruction for StubOracleSetInstruction {
     }
 }
 
-pub struct StubOracleCreate {
-    pub mint: Pubkey,
-    pub owner: TestKeypair,
+pub struct AddToAllowlistInstruction {
     pub payer: TestKeypair,
+    pub allowlist_admin: TestKeypair,
+    pub market: Pubkey,
+    pub entry: Pubkey,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for StubOracleCreate {
-    type Accounts = openbook_v2::accounts::StubOracleCreate;
-    type Instruction = openbook_v2::instruction::StubOracleCreate;
-
+impl ClientInstruction for AddToAllowlistInstruction {
+    type Accounts = openbook_v2::accounts::AddToAllowlist;
+    type Instruction = openbook_v2::instruction::AddToAllowlist;
     async fn to_instruction(
         &self,
-        _loader: impl ClientAccountLoader + 'async_trait,
-    ) -> (Self::Accounts, Instruction) {
+        _account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
-        let instruction = Self::Instruction { price: 1.0 };
-
-        let oracle = Pubkey::find_program_address(
-            &[
-                b"StubOracle".as_ref(),
-                self.owner.pubkey().as_ref(),
-                self.mint.as_ref(),
-            ],
-            &program_id,
-        )
-        .0;
+        let instruction = Self::Instruction { entry: self.entry };
 
         let accounts = Self::Accounts {
-            oracle,
-            mint: self.mint,
-            owner: self.owner.pubkey(),
             payer: self.payer.pubkey(),
+            allowlist_admin: self.allowlist_admin.pubkey(),
+            market: self.market,
+            market_allowlist_entry: get_market_allowlist_entry_address(self.market, self.entry),
             system_program: System::id(),
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
 
         let instruction = make_instruction(program_id, &accounts, instruction);
@@ -18462,42 +29432,36 @@ impl ClientInstruction for StubOracleCreate {
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        vec![self.payer, self.owner]
+        vec![self.payer, self.allowlist_admin]
     }
 }
 
-pub struct StubOracleCloseInstruction {
-    pub mint: Pubkey,
-    pub owner: TestKeypair,
+pub struct RemoveFromAllowlistInstruction {
+    pub allowlist_admin: TestKeypair,
+    pub market: Pubkey,
+    pub entry: Pubkey,
     pub sol_destination: Pubkey,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for StubOracleCloseInstruction {
-    type Accounts = openbook_v2::accounts::StubOracleClose;
-    type Instruction = openbook_v2::instruction::StubOracleClose;
-
-    async fn to_instruction(
-        &self,
-        _loader: impl ClientAccountLoader + 'async_trait,
-    ) -> (Self::Accounts, Instruction) {
-        let program_id = openbook_v2::id();
-        let instruction = Self::Instruction {};
-
-        let oracle = Pubkey::find_program_address(
-            &[
-                b"StubOracle".as_ref(),
-                self.owner.pubkey().as_ref(),
-                self.mint.as_ref(),
-            ],
-            &program_id,
-        )
-        .0;
+impl ClientInstruction for RemoveFromAllowlistInstruction {
+    type Accounts = openbook_v2::accounts::RemoveFromAllowlist;
+    type Instruction = openbook_v2::instruction::RemoveFromAllowlist;
+    async fn to_instruction(
+        &self,
+        _account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction { entry: self.entry };
 
         let accounts = Self::Accounts {
-            owner: self.owner.pubkey(),
-            oracle,
+            allowlist_admin: self.allowlist_admin.pubkey(),
+            market: self.market,
+            market_allowlist_entry: get_market_allowlist_entry_address(self.market, self.entry),
             sol_destination: self.sol_destination,
-            token_program: Token::id(),
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
 
         let instruction = make_instruction(program_id, &accounts, instruction);
@@ -18505,36 +29469,37 @@ impl ClientInstruction for StubOracleCloseInstruction {
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        vec![self.owner]
+        vec![self.allowlist_admin]
     }
 }
 
-#[derive(Clone)]
-pub struct CloseMarketInstruction {
-    pub close_market_admin: TestKeypair,
+pub struct AddToDenylistInstruction {
+    pub payer: TestKeypair,
+    pub denylist_admin: TestKeypair,
     pub market: Pubkey,
-    pub sol_destination: Pubkey,
+    pub entry: Pubkey,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for CloseMarketInstruction {
-    type Accounts = openbook_v2::accounts::CloseMarket;
-    type Instruction = openbook_v2::instruction::CloseMarket;
+impl ClientInstruction for AddToDenylistInstruction {
+    type Accounts = openbook_v2::accounts::AddToDenylist;
+    type Instruction = openbook_v2::instruction::AddToDenylist;
     async fn to_instruction(
         &self,
-        account_loader: impl ClientAccountLoader + 'async_trait,
+        _account_loader: impl ClientAccountLoader + 'async_trait,
     ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
-        let instruction = Self::Instruction {};
-        let market: Market = account_loader.load(&self.market).await.unwrap();
+        let instruction = Self::Instruction { entry: self.entry };
 
         let accounts = Self::Accounts {
-            close_market_admin: self.close_market_admin.pubkey(),
+            payer: self.payer.pubkey(),
+            denylist_admin: self.denylist_admin.pubkey(),
             market: self.market,
-            bids: market.bids,
-            asks: market.asks,
-            event_heap: market.event_heap,
-            token_program: Token::id(),
-            sol_destination: self.sol_destination,
+            market_denylist_entry: get_market_denylist_entry_address(self.market, self.entry),
+            system_program: System::id(),
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
 
         let instruction = make_instruction(program_id, &accounts, instruction);
@@ -18542,28 +29507,36 @@ impl ClientInstruction for CloseMarketInstruction {
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        vec![self.close_market_admin]
+        vec![self.payer, self.denylist_admin]
     }
 }
 
-pub struct SetMarketExpiredInstruction {
-    pub close_market_admin: TestKeypair,
+pub struct RemoveFromDenylistInstruction {
+    pub denylist_admin: TestKeypair,
     pub market: Pubkey,
+    pub entry: Pubkey,
+    pub sol_destination: Pubkey,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for SetMarketExpiredInstruction {
-    type Accounts = openbook_v2::accounts::SetMarketExpired;
-    type Instruction = openbook_v2::instruction::SetMarketExpired;
+impl ClientInstruction for RemoveFromDenylistInstruction {
+    type Accounts = openbook_v2::accounts::RemoveFromDenylist;
+    type Instruction = openbook_v2::instruction::RemoveFromDenylist;
     async fn to_instruction(
         &self,
         _account_loader: impl ClientAccountLoader + 'async_trait,
     ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
-        let instruction = Self::Instruction {};
+        let instruction = Self::Instruction { entry: self.entry };
 
         let accounts = Self::Accounts {
-            close_market_admin: self.close_market_admin.pubkey(),
+            denylist_admin: self.denylist_admin.pubkey(),
             market: self.market,
+            market_denylist_entry: get_market_denylist_entry_address(self.market, self.entry),
+            sol_destination: self.sol_destination,
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
 
         let instruction = make_instruction(program_id, &accounts, instruction);
@@ -18571,33 +29544,37 @@ impl ClientInstruction for SetMarketExpiredInstruction {
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        vec![self.close_market_admin]
+        vec![self.denylist_admin]
     }
 }
 
-pub struct PruneOrdersInstruction {
-    pub close_market_admin: TestKeypair,
-    pub market: Pubkey,
+pub struct SetDelegatePermissionsInstruction {
+    pub owner: TestKeypair,
     pub open_orders_account: Pubkey,
+    pub permissions: u8,
+    pub expiry_timestamp: u64,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for PruneOrdersInstruction {
-    type Accounts = openbook_v2::accounts::PruneOrders;
-    type Instruction = openbook_v2::instruction::PruneOrders;
+impl ClientInstruction for SetDelegatePermissionsInstruction {
+    type Accounts = openbook_v2::accounts::SetDelegatePermissions;
+    type Instruction = openbook_v2::instruction::SetDelegatePermissions;
     async fn to_instruction(
         &self,
-        account_loader: impl ClientAccountLoader + 'async_trait,
+        _account_loader: impl ClientAccountLoader + 'async_trait,
     ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
-        let instruction = Self::Instruction { limit: 255 };
-        let market: Market = account_loader.load(&self.market).await.unwrap();
+        let instruction = Self::Instruction {
+            permissions: self.permissions,
+            expiry_timestamp: self.expiry_timestamp,
+        };
 
         let accounts = Self::Accounts {
-            close_market_admin: self.close_market_admin.pubkey(),
-            market: self.market,
+            owner: self.owner.pubkey(),
             open_orders_account: self.open_orders_account,
-            bids: market.bids,
-            asks: market.asks,
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
 
         let instruction = make_instruction(program_id, &accounts, instruction);
@@ -18605,30 +29582,33 @@ impl ClientInstruction for PruneOrdersInstruction {
     }
 
     fn signers(&self) -> Vec<TestKeypair> {
-        vec![self.close_market_admin]
+        vec![self.owner]
     }
 }
 
-pub struct SetDelegateInstruction {
-    pub delegate_account: Option<Pubkey>,
+pub struct SetSelfTradeScopeInstruction {
     pub owner: TestKeypair,
     pub open_orders_account: Pubkey,
+    pub scope: SelfTradeScope,
 }
 #[async_trait::async_trait(?Send)]
-impl ClientInstruction for SetDelegateInstruction {
-    type Accounts = openbook_v2::accounts::SetDelegate;
-    type Instruction = openbook_v2::instruction::SetDelegate;
+impl ClientInstruction for SetSelfTradeScopeInstruction {
+    type Accounts = openbook_v2::accounts::SetSelfTradeScope;
+    type Instruction = openbook_v2::instruction::SetSelfTradeScope;
     async fn to_instruction(
         &self,
         _account_loader: impl ClientAccountLoader + 'async_trait,
     ) -> (Self::Accounts, instruction::Instruction) {
         let program_id = openbook_v2::id();
-        let instruction = Self::Instruction {};
+        let instruction = Self::Instruction { scope: self.scope };
 
         let accounts = Self::Accounts {
             owner: self.owner.pubkey(),
             open_orders_account: self.open_orders_account,
-            delegate_account: self.delegate_account,
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
 
         let instruction = make_instruction(program_id, &accounts, instruction);
@@ -18686,6 +29666,8 @@ impl ClientInstruction for EditOrderInstruction {
         };
 
         let market: Market = account_loader.load(&self.market).await.unwrap();
+        let user_token_account =
+            token_account_or_native(self.user_token_account, self.signer.pubkey());
 
         let accounts = Self::Accounts {
             open_orders_account: self.open_orders_account,
@@ -18697,9 +29679,39 @@ impl ClientInstruction for EditOrderInstruction {
             oracle_a: market.oracle_a.into(),
             oracle_b: market.oracle_b.into(),
             signer: self.signer.pubkey(),
-            user_token_account: self.user_token_account,
+            user_token_account,
             market_vault: self.market_vault,
             token_program: Token::id(),
+            open_orders_delegate: open_orders_delegate(
+                &account_loader,
+                self.open_orders_account,
+                self.signer.pubkey(),
+            )
+            .await,
+            system_program: Some(System::id()),
+            market_allowlist_entry: market_allowlist_entry(
+                &account_loader,
+                self.market,
+                &market,
+                &open_orders_allowlist_keys(&account_loader, self.open_orders_account).await,
+            )
+            .await,
+            owner_denylist_entry: market_denylist_entry(
+                self.market,
+                &market,
+                open_orders_owner(&account_loader, self.open_orders_account).await,
+            ),
+            signer_denylist_entry: market_denylist_entry(
+                self.market,
+                &market,
+                self.signer.pubkey(),
+            ),
+            penalty_payer: Some(self.signer.pubkey()),
+            native_payer: user_token_account.is_none().then_some(self.signer.pubkey()),
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
         let mut instruction = make_instruction(program_id, &accounts, instruction);
         let mut vec_remainings: Vec<AccountMeta> = Vec::new();
@@ -18770,6 +29782,35 @@ impl ClientInstruction for CancelAllAndPlaceOrdersInstruction {
             market_base_vault: market.market_base_vault,
             market_quote_vault: market.market_quote_vault,
             token_program: Token::id(),
+            open_orders_delegate: open_orders_delegate(
+                &account_loader,
+                self.open_orders_account,
+                self.signer.pubkey(),
+            )
+            .await,
+            market_allowlist_entry: market_allowlist_entry(
+                &account_loader,
+                self.market,
+                &market,
+                &open_orders_allowlist_keys(&account_loader, self.open_orders_account).await,
+            )
+            .await,
+            owner_denylist_entry: market_denylist_entry(
+                self.market,
+                &market,
+                open_orders_owner(&account_loader, self.open_orders_account).await,
+            ),
+            signer_denylist_entry: market_denylist_entry(
+                self.market,
+                &market,
+                self.signer.pubkey(),
+            ),
+            system_program: Some(System::id()),
+            penalty_payer: Some(self.signer.pubkey()),
+            #[cfg(feature = "cpi-events")]
+            event_authority: get_event_authority_address(),
+            #[cfg(feature = "cpi-events")]
+            program: openbook_v2::id(),
         };
         let instruction = make_instruction(program_id, &accounts, instruction);
         (accounts, instruction)
@@ -18785,6 +29826,108 @@ impl ClientInstruction for CancelAllAndPlaceOrdersInstruction {
     }
 }
 
+pub struct GetBestBidAskInstruction {
+    pub market: Pubkey,
+}
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for GetBestBidAskInstruction {
+    type Accounts = openbook_v2::accounts::ViewBook;
+    type Instruction = openbook_v2::instruction::GetBestBidAsk;
+    async fn to_instruction(
+        &self,
+        account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction {};
+        let market: Market = account_loader.load(&self.market).await.unwrap();
+
+        let accounts = Self::Accounts {
+            market: self.market,
+            bids: market.bids,
+            asks: market.asks,
+            oracle_a: market.oracle_a.into(),
+            oracle_b: market.oracle_b.into(),
+        };
+
+        let instruction = make_instruction(program_id, &accounts, instruction);
+        (accounts, instruction)
+    }
+
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![]
+    }
+}
+
+pub struct GetDepthInstruction {
+    pub market: Pubkey,
+    pub levels: u8,
+}
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for GetDepthInstruction {
+    type Accounts = openbook_v2::accounts::ViewBook;
+    type Instruction = openbook_v2::instruction::GetDepth;
+    async fn to_instruction(
+        &self,
+        account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction {
+            levels: self.levels,
+        };
+        let market: Market = account_loader.load(&self.market).await.unwrap();
+
+        let accounts = Self::Accounts {
+            market: self.market,
+            bids: market.bids,
+            asks: market.asks,
+            oracle_a: market.oracle_a.into(),
+            oracle_b: market.oracle_b.into(),
+        };
+
+        let instruction = make_instruction(program_id, &accounts, instruction);
+        (accounts, instruction)
+    }
+
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![]
+    }
+}
+
+pub struct GetImpactPriceInstruction {
+    pub market: Pubkey,
+    pub base_lots: i64,
+}
+#[async_trait::async_trait(?Send)]
+impl ClientInstruction for GetImpactPriceInstruction {
+    type Accounts = openbook_v2::accounts::ViewBook;
+    type Instruction = openbook_v2::instruction::GetImpactPrice;
+    async fn to_instruction(
+        &self,
+        account_loader: impl ClientAccountLoader + 'async_trait,
+    ) -> (Self::Accounts, instruction::Instruction) {
+        let program_id = openbook_v2::id();
+        let instruction = Self::Instruction {
+            base_lots: self.base_lots,
+        };
+        let market: Market = account_loader.load(&self.market).await.unwrap();
+
+        let accounts = Self::Accounts {
+            market: self.market,
+            bids: market.bids,
+            asks: market.asks,
+            oracle_a: market.oracle_a.into(),
+            oracle_b: market.oracle_b.into(),
+        };
+
+        let instruction = make_instruction(program_id, &accounts, instruction);
+        (accounts, instruction)
+    }
+
+    fn signers(&self) -> Vec<TestKeypair> {
+        vec![]
+    }
+}
+
 
 // File: openbook-v2/programs/openbook-v2/tests/program_test/cookies.rs
 use solana_program::pubkey::*;
@@ -18817,7 +29960,10 @@ use std::{sync::Arc, sync::RwLock};
 
 use fixed::types::I80F48;
 use log::*;
-use openbook_v2::state::Market;
+use openbook_v2::state::{
+    EventHeapPenaltyPolicy, EvictionPolicy, Market, OracleStalenessPolicy, DEFAULT_NUM_EVENTS,
+    DEFAULT_ORDERTREE_NODES, PENALTY_EVENT_HEAP,
+};
 use solana_program::{program_option::COption, program_pack::Pack};
 use solana_program_test::*;
 use solana_sdk::pubkey::Pubkey;
@@ -18843,6 +29989,9 @@ pub struct TestInitialize {
     pub open_orders_admin: TestKeypair,
     pub close_market_admin: TestKeypair,
     pub consume_events_admin: TestKeypair,
+    pub allowlist_admin: TestKeypair,
+    pub denylist_admin: TestKeypair,
+    pub exposure_admin: TestKeypair,
     pub owner: TestKeypair,
     pub payer: TestKeypair,
     pub mints: Vec<MintCookie>,
@@ -18944,6 +30093,15 @@ impl TestContextBuilder {
             accounts: &[anchor_lang::prelude::AccountInfo],
             data: &[u8],
         ) -> anchor_lang::solana_program::entrypoint::ProgramResult {
+            // Events sent through a self-CPI never show up as "Program data:" log lines,
+            // capture them here so program_log_events() can decode both kinds alike
+            if let Some(event) = data.strip_prefix(&anchor_lang::event::EVENT_IX_TAG_LE[..]) {
+                LOGGER_CAPTURE
+                    .write()
+                    .unwrap()
+                    .push(base64::encode(event));
+            }
+
             let extended_lifetime_accs = unsafe {
                 core::mem::transmute::<_, &[anchor_lang::prelude::AccountInfo<'_>]>(accounts)
             };
@@ -19091,7 +30249,16 @@ pub struct TestContext {
 }
 
 pub struct TestNewMarketInitialize {
-    pub fee_penalty: u64,
+    pub event_heap_penalty: u64,
+    pub event_heap_penalty_policy: EventHeapPenaltyPolicy,
+    pub max_bids_quote_lots: i64,
+    pub max_asks_base_lots: i64,
+    pub max_open_orders: u16,
+    pub min_base_lots: i64,
+    pub min_quote_notional: i64,
+    pub posting_bond: u64,
+    pub eviction_policy: EvictionPolicy,
+    pub book_side_capacity: usize,
     pub quote_lot_size: i64,
     pub base_lot_size: i64,
     pub maker_fee: i64,
@@ -19099,15 +30266,29 @@ pub struct TestNewMarketInitialize {
     pub open_orders_admin_bool: bool,
     pub close_market_admin_bool: bool,
     pub consume_events_admin_bool: bool,
+    pub allowlist_admin_bool: bool,
+    pub denylist_admin_bool: bool,
+    pub exposure_admin_bool: bool,
     pub time_expiry: i64,
     pub with_oracle: bool,
     pub payer_as_delegate: bool,
+    pub oracle_staleness_policy: OracleStalenessPolicy,
+    pub oracle_freeze_slots: u32,
 }
 
 impl Default for TestNewMarketInitialize {
     fn default() -> TestNewMarketInitialize {
         TestNewMarketInitialize {
-            fee_penalty: 0,
+            event_heap_penalty: PENALTY_EVENT_HEAP,
+            event_heap_penalty_policy: EventHeapPenaltyPolicy::Deferred,
+            max_bids_quote_lots: 0,
+            max_asks_base_lots: 0,
+            max_open_orders: 0,
+            min_base_lots: 0,
+            min_quote_notional: 0,
+            posting_bond: 0,
+            eviction_policy: EvictionPolicy::WorstPrice,
+            book_side_capacity: DEFAULT_ORDERTREE_NODES,
             quote_lot_size: 10,
             base_lot_size: 100,
             maker_fee: -200,
@@ -19115,9 +30296,14 @@ impl Default for TestNewMarketInitialize {
             open_orders_admin_bool: false,
             close_market_admin_bool: false,
             consume_events_admin_bool: false,
+            allowlist_admin_bool: false,
+            denylist_admin_bool: false,
+            exposure_admin_bool: false,
             time_expiry: 0,
             with_oracle: true,
             payer_as_delegate: false,
+            oracle_staleness_policy: OracleStalenessPolicy::Skip,
+            oracle_freeze_slots: 0,
         }
     }
 }
@@ -19151,6 +30337,24 @@ impl TestContext {
         } else {
             None
         };
+        let allowlist_admin_acc = TestKeypair::new();
+        let allowlist_admin = if args.allowlist_admin_bool {
+            Some(allowlist_admin_acc.pubkey())
+        } else {
+            None
+        };
+        let denylist_admin_acc = TestKeypair::new();
+        let denylist_admin = if args.denylist_admin_bool {
+            Some(denylist_admin_acc.pubkey())
+        } else {
+            None
+        };
+        let exposure_admin_acc = TestKeypair::new();
+        let exposure_admin = if args.exposure_admin_bool {
+            Some(exposure_admin_acc.pubkey())
+        } else {
+            None
+        };
 
         let owner = context.users[0].key;
         let payer = context.users[1].key;
@@ -19184,6 +30388,9 @@ impl TestContext {
                 open_orders_admin,
                 close_market_admin,
                 consume_events_admin,
+                allowlist_admin,
+                denylist_admin,
+                exposure_admin,
                 payer,
                 market,
                 quote_lot_size: args.quote_lot_size,
@@ -19192,9 +30399,26 @@ impl TestContext {
                 taker_fee: args.taker_fee,
                 base_mint: mints[0].pubkey,
                 quote_mint: mints[1].pubkey,
-                fee_penalty: args.fee_penalty,
+                event_heap_penalty: args.event_heap_penalty,
+                event_heap_penalty_policy: args.event_heap_penalty_policy,
+                max_bids_quote_lots: args.max_bids_quote_lots,
+                max_asks_base_lots: args.max_asks_base_lots,
+                max_open_orders: args.max_open_orders,
+                min_base_lots: args.min_base_lots,
+                min_quote_notional: args.min_quote_notional,
+                posting_bond: args.posting_bond,
+                eviction_policy: args.eviction_policy,
                 time_expiry: args.time_expiry,
-                ..CreateMarketInstruction::with_new_book_and_heap(solana, oracle, None).await
+                oracle_staleness_policy: args.oracle_staleness_policy,
+                oracle_freeze_slots: args.oracle_freeze_slots,
+                ..CreateMarketInstruction::with_new_book_and_heap_capacities(
+                    solana,
+                    oracle,
+                    None,
+                    args.book_side_capacity,
+                    DEFAULT_NUM_EVENTS as usize,
+                )
+                .await
             },
         )
         .await
@@ -19228,6 +30452,9 @@ impl TestContext {
             open_orders_admin: open_orders_admin_acc,
             close_market_admin: close_market_admin_acc,
             consume_events_admin: consume_events_admin_acc,
+            allowlist_admin: allowlist_admin_acc,
+            denylist_admin: denylist_admin_acc,
+            exposure_admin: exposure_admin_acc,
             owner,
             payer,
             mints,
@@ -19251,6 +30478,7 @@ impl TestContext {
 #![allow(dead_code)]
 
 use anchor_lang::prelude::*;
+use openbook_v2::state::SelfTradeScope;
 
 use super::client::*;
 use super::solana::SolanaCookie;
@@ -19349,6 +30577,25 @@ pub async fn create_open_orders_account(
     .open_orders_account
 }
 
+/// Lets the account trade with the owner's other accounts, which the default
+/// `SelfTradeScope::Owner` treats as self-trades
+pub async fn set_account_self_trade_scope(
+    solana: &SolanaCookie,
+    owner: TestKeypair,
+    open_orders_account: Pubkey,
+) {
+    send_tx(
+        solana,
+        SetSelfTradeScopeInstruction {
+            owner,
+            open_orders_account,
+            scope: SelfTradeScope::Account,
+        },
+    )
+    .await
+    .unwrap();
+}
+
 
 // File: openbook-v2/programs/openbook-v2/tests/program_test/solana.rs
 #![allow(dead_code)]
@@ -19436,6 +30683,42 @@ impl SolanaCookie {
         result
     }
 
+    /// Simulate a transaction without committing it and return the data set via
+    /// `set_return_data`, if any.
+    pub async fn simulate_transaction(
+        &self,
+        instructions: &[Instruction],
+        signers: Option<&[TestKeypair]>,
+    ) -> Result<Option<Vec<u8>>, BanksClientError> {
+        let mut context = self.context.borrow_mut();
+
+        let mut transaction =
+            Transaction::new_with_payer(instructions, Some(&context.payer.pubkey()));
+
+        let mut all_signers = vec![&context.payer];
+        let signer_keypairs =
+            signers.map(|signers| signers.iter().map(|s| s.into()).collect::<Vec<Keypair>>());
+        if let Some(signer_keypairs) = signer_keypairs.as_ref() {
+            all_signers.extend(signer_keypairs.iter());
+        }
+
+        transaction.sign(&all_signers, context.last_blockhash);
+
+        let simulation = context
+            .banks_client
+            .simulate_transaction(transaction)
+            .await?;
+
+        if let Some(Err(err)) = simulation.result {
+            return Err(BanksClientError::TransactionError(err));
+        }
+
+        Ok(simulation
+            .simulation_details
+            .and_then(|details| details.return_data)
+            .map(|return_data| return_data.data))
+    }
+
     pub async fn get_clock(&self) -> solana_program::clock::Clock {
         self.context
             .borrow_mut()
@@ -19612,10 +30895,48 @@ impl SolanaCookie {
         self.get_account_opt(address).await.unwrap()
     }
 
+    // Includes the order slots of resized accounts, which get_account can't decode
+    pub async fn get_open_orders_account(
+        &self,
+        address: Pubkey,
+    ) -> openbook_v2::state::OpenOrdersAccountValue {
+        let data = self.get_account_data(address).await.unwrap();
+        openbook_v2::state::OpenOrdersAccountValue::from_bytes(&data).unwrap()
+    }
+
+    // Book sides and event heaps have nodes after the fixed part, which get_account can't decode
+    pub async fn get_book_side(&self, address: Pubkey) -> openbook_v2::state::BookSideValue {
+        let data = self.get_account_data(address).await.unwrap();
+        openbook_v2::state::BookSideValue::from_bytes(&data).unwrap()
+    }
+
+    pub async fn get_event_heap(&self, address: Pubkey) -> openbook_v2::state::EventHeapValue {
+        let data = self.get_account_data(address).await.unwrap();
+        openbook_v2::state::EventHeapValue::from_bytes(&data).unwrap()
+    }
+
     pub async fn token_account_balance(&self, address: Pubkey) -> u64 {
         self.get_account::<TokenAccount>(address).await.amount
     }
 
+    pub async fn get_lamports(&self, address: Pubkey) -> u64 {
+        self.context
+            .borrow_mut()
+            .banks_client
+            .get_balance(address)
+            .await
+            .unwrap()
+    }
+
+    pub async fn transfer_lamports(&self, from: TestKeypair, to: Pubkey, lamports: u64) {
+        self.process_transaction(
+            &[solana_sdk::system_instruction::transfer(&from.pubkey(), &to, lamports)],
+            Some(&[from]),
+        )
+        .await
+        .unwrap();
+    }
+
     pub async fn set_account_balance(&self, address: Pubkey, amount: u64) {
         let mut account = self
             .context
@@ -19635,10 +30956,31 @@ impl SolanaCookie {
             .set_account(&address, &account.into());
     }
 
+    // Keeps the account exactly rent exempt for the new data
+    pub async fn set_account_data(&self, address: Pubkey, data: Vec<u8>) {
+        let mut account = self
+            .context
+            .borrow_mut()
+            .banks_client
+            .get_account(address)
+            .await
+            .unwrap()
+            .unwrap();
+
+        account.lamports = self.rent.minimum_balance(data.len());
+        account.data = data;
+
+        self.context
+            .borrow_mut()
+            .set_account(&address, &account.into());
+    }
+
     pub fn program_log(&self) -> Vec<String> {
         self.last_transaction_log.borrow().clone()
     }
 
+    /// Decodes the events of the last transaction, whether they were written to the log
+    /// with `sol_log_data` or sent through a self-CPI
     pub fn program_log_events<T: anchor_lang::Event + anchor_lang::AnchorDeserialize>(
         &self,
     ) -> Vec<T> {